@@ -1,21 +1,69 @@
-use adborc::market::{request::*, DeviceFilter, DeviceFilterVec, SysState};
-use adborc::net::TCPClient;
+use adborc::market::{
+    request::*, DeviceFilter, DeviceFilterExpr, DeviceFilterVec, EventTopic, ParseFilterError,
+    Quota, QuotaTarget, Role, SysState, WaitTarget,
+};
+use adborc::net::{split_host_port, TCPClient};
 use adborc::util::{
     adb_utils::{self, ScrcpyCliArgs, SCRCPY_SHORTCUT_HELP},
-    SysStateDefaultConfig, ADBORC_VERSION,
+    config::{NodeConfig, Profile},
+    ListenerConfig, SysStateDefaultConfig, ADBORC_VERSION,
 };
-use clap::{Parser, Subcommand};
+use adborc::Error;
+use clap::{Parser, Subcommand, ValueEnum};
 use log::error;
 use std::collections::HashSet;
+use std::fs;
 use std::io;
-use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream};
+use std::path::PathBuf;
+use std::process::ExitCode;
 use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
 
 #[derive(Parser)]
 #[clap(name="adborc", author, version, about, long_about = None)]
 pub struct Cli {
     #[clap(subcommand)]
     pub command: Commands,
+    /// Host address of the system listener. Used by `init` to bind the system listener.
+    /// Overrides `ADBORC_LISTEN_HOST` and the node config file.
+    /// Defaults to 0.0.0.0 (all interfaces).
+    #[clap(long, global = true, value_parser)]
+    pub listen_host: Option<String>,
+    /// Port of the system listener. Used by `init` to bind the system listener, and by
+    /// all other commands to connect to it.
+    /// Overrides `ADBORC_LISTEN_PORT` and the node config file.
+    /// Defaults to 16063.
+    #[clap(long, global = true, value_parser)]
+    pub listen_port: Option<u16>,
+    /// Path of the node config file. Defaults to `config.toml` in the adborc data directory.
+    #[clap(long, global = true, value_parser)]
+    pub config: Option<PathBuf>,
+    /// Profile of the node config file to use. Overrides `ADBORC_PROFILE` and
+    /// the `default_profile` of the config file. On `init`, the modes declared
+    /// by the profile are started automatically.
+    #[clap(long, global = true, value_parser)]
+    pub profile: Option<String>,
+    /// Address of a remote MarketMaker, as `host` or `host:port`. `marketmaker` commands
+    /// are sent to it instead of the MarketMaker on the system, with the network_id
+    /// of the system. Requires the `admin` role on the remote MarketMaker, or the
+    /// `observer` role for `marketmaker status`.
+    #[clap(long, global = true, value_parser = parse_market_maker_addr)]
+    pub market_maker: Option<String>,
+    /// Format of the output. `table` prints human readable text. `json` and `yaml`
+    /// print the response as an object with the name of the response in `response`,
+    /// along with its fields. The exit code is non-zero if the command fails.
+    #[clap(short, long, global = true, value_enum, default_value_t = OutputFormat::Table)]
+    pub output: OutputFormat,
+}
+
+/// Format of the output of the commands.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    Table,
+    Json,
+    Yaml,
 }
 
 #[derive(Subcommand)]
@@ -29,6 +77,26 @@ pub enum Commands {
     Shutdown,
     /// Get the network_id of system.
     GetNetworkId,
+    /// Export the network_id of system to a file.
+    /// The file can be shared with a MarketMaker to add the system to its whitelist.
+    ExportNetworkId {
+        /// Path of the file to write the network_id to.
+        #[clap(value_parser)]
+        path: String,
+    },
+    /// Generate a new network key for the system. The network_id of the system
+    /// will change, and will have to be whitelisted again on MarketMakers that use whitelisting.
+    /// All active modes (MarketMaker/Supplier/Consumer) must be stopped first.
+    RotateNetworkKey,
+    /// Import an existing network key (private key) for the system.
+    /// The specified file must contain the base64 encoded private key, as found in the
+    /// `network_key` file of the adborc data directory of another system.
+    /// All active modes (MarketMaker/Supplier/Consumer) must be stopped first.
+    ImportNetworkKey {
+        /// Path of the file containing the private key.
+        #[clap(value_parser)]
+        path: String,
+    },
     /// Check if `adb` and `scrcpy` are installed and compatible.
     /// Outputs which modes (MarketMaker/Supplier/Consumer) are
     /// available to be run on the system.
@@ -78,7 +146,17 @@ pub enum MarketMakerCommands {
     /// Get the current status of MarketMaker.
     Status,
     /// Start a network by running MarketMaker mode on the system.
-    Start,
+    /// With `--standby-of`, the MarketMaker is started as a standby of the primary
+    /// MarketMaker of an existing network instead. The standby keeps a replica of
+    /// the state of the primary, and takes over as the primary if the primary stops
+    /// sending heartbeats. The `network_id` of the standby must first be added
+    /// to the primary with `adborc marketmaker add-standby`.
+    Start {
+        /// Address of the primary MarketMaker, as `host` or `host:port`.
+        /// IPv6 addresses with a port must be enclosed in brackets, e.g. `[2001:db8::1]:15555`.
+        #[clap(long, value_parser = parse_market_maker_addr)]
+        standby_of: Option<String>,
+    },
     /// Terminate the MarketMaker on the system.
     /// WARNING: This will terminate the entire network of Suppliers and Consumers
     /// connected to the MarketMaker.
@@ -117,6 +195,146 @@ pub enum MarketMakerCommands {
         /// The `network_id` of Consumer.
         peer_id: String,
     },
+    /// Allow a MarketMaker to connect as the standby of this MarketMaker.
+    /// Standbys are always required to be whitelisted, as they receive
+    /// the whole state of the network.
+    AddStandby {
+        /// The `network_id` of the standby MarketMaker.
+        peer_id: String,
+    },
+    /// Remove a standby MarketMaker from the whitelist. The standby is
+    /// disconnected and no longer receives the state of the network.
+    RemoveStandby {
+        /// The `network_id` of the standby MarketMaker.
+        peer_id: String,
+    },
+    /// Allow a MarketMaker to peer with this MarketMaker. Peered MarketMakers
+    /// advertise their available devices to each other, so that Consumers
+    /// can reserve devices of the other network. Peers are always required
+    /// to be whitelisted, on both MarketMakers.
+    AddPeer {
+        /// The `network_id` of the peer MarketMaker.
+        peer_id: String,
+    },
+    /// Remove a peer MarketMaker from the whitelist. The peer is disconnected,
+    /// and its devices are no longer offered to Consumers.
+    RemovePeer {
+        /// The `network_id` of the peer MarketMaker.
+        peer_id: String,
+    },
+    /// Connect to a peer MarketMaker. Both MarketMakers must have added
+    /// each other with `adborc marketmaker add-peer` first.
+    ConnectPeer {
+        /// Address of the peer MarketMaker, as `host` or `host:port`.
+        /// IPv6 addresses with a port must be enclosed in brackets, e.g. `[2001:db8::1]:15555`.
+        #[clap(value_parser = parse_market_maker_addr)]
+        remote: String,
+    },
+    /// Grant a role to a network_id. Roles are `admin` (administer the MarketMaker
+    /// remotely with `--market-maker`), `supplier` and `consumer` (connect even if
+    /// not in the whitelist), and `observer` (get the status of the MarketMaker).
+    GrantRole {
+        /// The `network_id` to grant the role to.
+        peer_id: String,
+        /// The role to grant.
+        #[clap(value_parser = Role::from_str)]
+        role: Role,
+    },
+    /// Revoke a role from a network_id.
+    RevokeRole {
+        /// The `network_id` to revoke the role from.
+        peer_id: String,
+        /// The role to revoke.
+        #[clap(value_parser = Role::from_str)]
+        role: Role,
+    },
+    /// Set the reservation quota of a Consumer, or of a group of Consumers.
+    /// Reservations past the quota are rejected by the MarketMaker. The quota of
+    /// a group applies to the reservations of all its members together.
+    SetQuota {
+        /// The `network_id` of the Consumer.
+        #[clap(long, conflicts_with = "group", required_unless_present = "group")]
+        consumer: Option<String>,
+        /// The name of the group.
+        #[clap(long)]
+        group: Option<String>,
+        /// Maximum number of devices reserved at the same time.
+        #[clap(long)]
+        max_devices: Option<usize>,
+        /// Maximum lease duration of a reservation, e.g. `30m` or `2h`.
+        /// Reservations without a lease are rejected.
+        #[clap(long, value_parser = parse_duration)]
+        max_lease: Option<u64>,
+    },
+    /// Remove the reservation quota of a Consumer, or of a group of Consumers.
+    RemoveQuota {
+        /// The `network_id` of the Consumer.
+        #[clap(long, conflicts_with = "group", required_unless_present = "group")]
+        consumer: Option<String>,
+        /// The name of the group.
+        #[clap(long)]
+        group: Option<String>,
+    },
+    /// Add a Consumer to a group. Groups are created when their first member is added.
+    AddToGroup {
+        /// The name of the group.
+        group: String,
+        /// The `network_id` of the Consumer.
+        peer_id: String,
+    },
+    /// Remove a Consumer from a group.
+    RemoveFromGroup {
+        /// The name of the group.
+        group: String,
+        /// The `network_id` of the Consumer.
+        peer_id: String,
+    },
+    /// Assign a device to a pool. Devices in pools are only accessible to
+    /// Consumers granted access to one of the pools.
+    /// Example: adborc marketmaker assign-pool <device_id> release-qa
+    AssignPool {
+        /// The device ID of the device.
+        device_id: String,
+        /// The name of the pool.
+        pool: String,
+    },
+    /// Remove a device from a pool.
+    UnassignPool {
+        /// The device ID of the device.
+        device_id: String,
+        /// The name of the pool.
+        pool: String,
+    },
+    /// Grant a Consumer access to the devices of a pool.
+    GrantPoolAccess {
+        /// The name of the pool.
+        pool: String,
+        /// The `network_id` of the Consumer.
+        peer_id: String,
+    },
+    /// Revoke the access of a Consumer to the devices of a pool.
+    /// Devices already reserved by the Consumer are not released.
+    RevokePoolAccess {
+        /// The name of the pool.
+        pool: String,
+        /// The `network_id` of the Consumer.
+        peer_id: String,
+    },
+    /// Release a reserved device, regardless of the Consumer using it.
+    /// The Consumer is notified and stops using the device.
+    ForceRelease {
+        /// The device ID of the device to release.
+        device_id: String,
+    },
+    /// Stream the events of the MarketMaker on the system, until interrupted.
+    /// Events are printed one per line, or as one JSON object per line with `--output json`.
+    Watch {
+        /// Comma separated topics of the events to stream.
+        /// Possible values: devices, suppliers, consumers, heartbeats.
+        /// All topics are streamed, if not given.
+        #[clap(long, value_parser = EventTopic::from_str, use_value_delimiter = true)]
+        topics: Option<Vec<EventTopic>>,
+    },
 }
 
 #[derive(Subcommand)]
@@ -126,7 +344,8 @@ pub enum SupplierCommands {
     /// Connect to a network (MarketMaker) and start Supplier mode on the system.
     Start {
         /// Hostname or IP address of the MarketMaker.
-        #[clap(value_parser)]
+        /// IPv6 addresses may be enclosed in brackets, e.g. `[2001:db8::1]`.
+        #[clap(value_parser = parse_remote)]
         remote: String,
         /// Port of the MarketMaker.
         #[clap(short, long, value_parser, default_value_t = SysStateDefaultConfig::BIND_PORT)]
@@ -143,6 +362,11 @@ pub enum SupplierCommands {
         /// If specified, encrypted tunnels will be created for device communication.
         #[clap(short, long, action)]
         secure: bool,
+        /// Address of a MarketMaker to fall back to, as `host` or `host:port`,
+        /// if the MarketMaker is unreachable. May be repeated. MarketMakers are
+        /// tried in the order specified, e.g. a standby of the MarketMaker.
+        #[clap(long = "fallback", value_parser = parse_market_maker_addr)]
+        fallbacks: Vec<String>,
     },
     /// Terminate Supplier mode on the system. Supplier will be removed from the
     /// network and all supplied devices will be reclaimed.
@@ -155,6 +379,11 @@ pub enum SupplierCommands {
         /// Example: adborc supply --devices "serial1,serial2,serial3"
         #[clap(long, value_parser, use_value_delimiter = true)]
         devices: Option<Vec<String>>,
+        /// List of pools to assign the supplied devices to. Devices in pools are
+        /// only accessible to Consumers granted access to one of the pools.
+        /// Example: adborc supplier supply --pools "release-qa,perf-lab"
+        #[clap(long, value_parser, use_value_delimiter = true)]
+        pools: Option<Vec<String>>,
     },
     /// Reclaim a device from the network.
     /// If the device is currently in use, reclaim will fail.
@@ -167,6 +396,15 @@ pub enum SupplierCommands {
         #[clap(short, long, action)]
         force: bool,
     },
+    /// Turn auto-supply on or off. When auto-supply is on, newly attached devices
+    /// are supplied to the network and detached devices are reclaimed automatically.
+    /// Consumers using a detached device are notified that the device is no longer available.
+    /// Example: adborc supplier auto-supply true
+    AutoSupply {
+        /// Turn auto-supply on (true) or off (false).
+        #[clap(value_parser)]
+        enable: bool,
+    },
 }
 
 #[derive(Subcommand)]
@@ -176,7 +414,8 @@ pub enum ConsumerCommands {
     /// Connect to a network (MarketMaker) and start Consumer mode on the system.
     Start {
         /// Hostname or IP address of the MarketMaker.
-        #[clap(value_parser)]
+        /// IPv6 addresses may be enclosed in brackets, e.g. `[2001:db8::1]`.
+        #[clap(value_parser = parse_remote)]
         remote: String,
         /// Port of the MarketMaker.
         #[clap(short, long, value_parser, default_value_t = SysStateDefaultConfig::BIND_PORT)]
@@ -188,6 +427,11 @@ pub enum ConsumerCommands {
         /// functionality of the Consumer. There is no need to specify a unique name.
         #[clap(short, long, value_parser)]
         user: Option<String>,
+        /// Address of a MarketMaker to fall back to, as `host` or `host:port`,
+        /// if the MarketMaker is unreachable. May be repeated. MarketMakers are
+        /// tried in the order specified, e.g. a standby of the MarketMaker.
+        #[clap(long = "fallback", value_parser = parse_market_maker_addr)]
+        fallbacks: Vec<String>,
     },
     /// Terminate Consumer mode on the system. Consumer will be removed from the
     /// network and all reserved devices will be added back to the network.
@@ -200,8 +444,18 @@ pub enum ConsumerCommands {
     Reserve {
         /// `device_id` of the device to reserve.
         /// Use `adborc consumer list-available` for a list of available devices.
-        #[clap(value_parser)]
-        device: String,
+        /// If not specified, available devices matching `--model` are reserved.
+        #[clap(value_parser, required_unless_present = "model", conflicts_with_all = ["model", "count"])]
+        device: Option<String>,
+        /// List of device models to reserve from. Models must be separated by a comma.
+        /// Any available device with one of the models will be reserved.
+        /// Example: adborc consumer reserve --model "Pixel 7,Pixel 7 Pro" --count 2
+        #[clap(long, value_parser, use_value_delimiter = true)]
+        model: Option<Vec<String>>,
+        /// Number of matching devices to reserve when using `--model`.
+        /// If fewer devices are available, all of them are reserved.
+        #[clap(long, value_parser, default_value_t = 1)]
+        count: usize,
         /// Optional flag to not make the device default.
         /// A default device is available on the system using `adb` without specifying
         /// the port number for the device.
@@ -209,7 +463,47 @@ pub enum ConsumerCommands {
         /// will be set as the default device.
         #[clap(long, action)]
         no_default: bool,
+        /// Optional lease duration for the reservation, e.g. `90s`, `30m`, `2h` or `1d`.
+        /// A number without unit is taken as seconds.
+        /// When the lease expires, the device is released automatically.
+        /// Use `adborc consumer renew` to extend the lease.
+        /// If not specified, the reservation does not expire.
+        #[clap(long, value_parser = parse_duration)]
+        lease: Option<u64>,
+    },
+    /// Renew the lease of a reserved device. The new lease starts from now.
+    Renew {
+        /// `device_id` of the device.
+        #[clap(value_parser)]
+        device: String,
+        /// Lease duration, e.g. `90s`, `30m`, `2h` or `1d`.
+        /// A number without unit is taken as seconds.
+        #[clap(long, value_parser = parse_duration)]
+        lease: u64,
     },
+    /// Wait in the queue of the MarketMaker for a device that is currently in use.
+    /// The device is reserved automatically when it is released and the Consumer
+    /// is first in the queue.
+    Enqueue {
+        /// `device_id` of the device to wait for. If not specified, wait for any
+        /// device matching the filters.
+        #[clap(value_parser, conflicts_with_all = ["device_names", "device_models", "supplied_by"])]
+        device: Option<String>,
+        /// List of device names to wait for. Names must be separated by a comma.
+        #[clap(long, value_parser, use_value_delimiter = true)]
+        device_names: Option<Vec<String>>,
+        /// List of device models to wait for. Models must be separated by a comma.
+        #[clap(long, value_parser, use_value_delimiter = true)]
+        device_models: Option<Vec<String>>,
+        /// List of supplier names to wait for devices from. Suppliers must be separated by a comma.
+        #[clap(long, value_parser, use_value_delimiter = true)]
+        supplied_by: Option<Vec<String>>,
+        /// Optional lease duration for the reservation, e.g. `90s`, `30m`, `2h` or `1d`.
+        #[clap(long, value_parser = parse_duration)]
+        lease: Option<u64>,
+    },
+    /// Leave the reservation queue of the MarketMaker.
+    LeaveQueue,
     /// Release a device. If not specified, all reserved devices will be released.
     Release {
         /// `device_id` of the device to be released.
@@ -254,6 +548,43 @@ pub enum ConsumerCommands {
         /// If specified, only devices reserved by the specified consumer(s) in the list will be returned.
         #[clap(long, value_parser, use_value_delimiter = true)]
         reserved_by: Option<Vec<String>>,
+        /// List of Android versions to filter devices by.
+        /// If specified, only devices running one of the Android versions in the list will be returned.
+        /// Example: adborc consumer get-devices --android-versions "12,13"
+        #[clap(long, value_parser, use_value_delimiter = true)]
+        android_versions: Option<Vec<String>>,
+        /// Minimum SDK level of the devices to be returned (inclusive).
+        #[clap(long, value_parser)]
+        min_sdk: Option<u32>,
+        /// Maximum SDK level of the devices to be returned (inclusive).
+        #[clap(long, value_parser)]
+        max_sdk: Option<u32>,
+        /// List of ABIs to filter devices by.
+        /// If specified, only devices supporting at least one of the ABIs in the list will be returned.
+        /// Example: adborc consumer get-devices --abis "arm64-v8a,x86_64"
+        #[clap(long, value_parser, use_value_delimiter = true)]
+        abis: Option<Vec<String>>,
+        /// List of device brands to filter devices by.
+        /// If specified, only devices with brands in the list will be returned.
+        /// Example: adborc consumer get-devices --brands "google,samsung"
+        #[clap(long, value_parser, use_value_delimiter = true)]
+        brands: Option<Vec<String>>,
+        /// List of pools to filter devices by.
+        /// If specified, only devices in at least one of the pools in the list will be returned.
+        /// Example: adborc consumer get-devices --pools "release-qa,perf-lab"
+        #[clap(long, value_parser, use_value_delimiter = true)]
+        pools: Option<Vec<String>>,
+        /// Minimum battery level (in percent) of the devices to be returned.
+        /// Devices that have not reported their health yet are not returned.
+        #[clap(long, value_parser = clap::value_parser!(u32).range(0..=100))]
+        min_battery: Option<u32>,
+        /// Filter query combining filters with `AND`, `OR`, `NOT` and parentheses.
+        /// Used together with the other filters, if specified.
+        /// Supported keys: available, id, name, model, brand, android, abi, sdk, battery,
+        /// supplier, supplier_id, supplier_host, consumer, consumer_id, consumer_host, pool.
+        /// Example: adborc consumer get-devices --query '(model="Pixel 7","Pixel 8" OR brand=samsung) AND NOT supplier=lab-x AND sdk>=31'
+        #[clap(long, value_parser = parse_query)]
+        query: Option<Box<DeviceFilterExpr>>,
     },
     /// Show currently reserved devices.
     ListReserved,
@@ -288,18 +619,205 @@ pub enum ConsumerCommands {
     ScrcpyShortcuts,
 }
 
-fn check_listener() -> bool {
-    let addr = SocketAddr::new(
-        IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
-        SysStateDefaultConfig::BIND_PORT,
-    );
+/// Parse the hostname or IP address of a remote system.
+/// Brackets around IPv6 addresses are removed, e.g. `[2001:db8::1]` becomes `2001:db8::1`.
+fn parse_remote(value: &str) -> Result<String, String> {
+    let host = match value.strip_prefix('[') {
+        Some(host) => host,
+        None if value.ends_with(']') => return Err(format!("Invalid address: {}", value)),
+        None => return Ok(value.to_string()),
+    };
+    let host = host.strip_suffix(']').ok_or_else(|| {
+        if host.contains("]:") {
+            format!(
+                "Invalid address: {}. Specify the port with -p/--port instead",
+                value
+            )
+        } else {
+            format!("Invalid address: {}", value)
+        }
+    })?;
+    host.parse::<Ipv6Addr>()
+        .map(|_| host.to_string())
+        .map_err(|_| format!("Invalid IPv6 address: {}", host))
+}
+
+/// Parse a MarketMaker address of the form `host` or `host:port`.
+fn parse_market_maker_addr(value: &str) -> Result<String, String> {
+    split_host_port(value, SysStateDefaultConfig::BIND_PORT)
+        .map(|_| value.to_string())
+        .map_err(|e| e.to_string())
+}
+
+/// Parse a device filter query. Boxed to keep `ConsumerCommands` small.
+fn parse_query(value: &str) -> Result<Box<DeviceFilterExpr>, ParseFilterError> {
+    DeviceFilterExpr::from_str(value).map(Box::new)
+}
+
+/// Parse a duration like `90s`, `30m`, `2h` or `1d` into seconds.
+fn parse_duration(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let (number, multiplier) = match value.chars().last() {
+        Some('s') => (&value[..value.len() - 1], 1),
+        Some('m') => (&value[..value.len() - 1], 60),
+        Some('h') => (&value[..value.len() - 1], 60 * 60),
+        Some('d') => (&value[..value.len() - 1], 24 * 60 * 60),
+        _ => (value, 1),
+    };
+    let number = number
+        .parse::<u64>()
+        .map_err(|_| format!("Invalid duration: {}", value))?;
+    if number == 0 {
+        return Err("Duration must be greater than zero".to_string());
+    }
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("Duration is too long: {}", value))
+}
+
+/// Get the target of a quota command. Exactly one of `consumer` and `group`
+/// is set, as checked while parsing the arguments.
+fn quota_target(consumer: Option<String>, group: Option<String>) -> QuotaTarget {
+    match consumer {
+        Some(key) => QuotaTarget::Consumer(key),
+        None => QuotaTarget::Group(group.unwrap()),
+    }
+}
+
+/// Format a number of seconds as a human readable duration, e.g. `1h 05m 30s`.
+fn format_duration(secs: u64) -> String {
+    let (hours, minutes, seconds) = (secs / 3600, (secs % 3600) / 60, secs % 60);
+    if hours > 0 {
+        format!("{}h {:02}m {:02}s", hours, minutes, seconds)
+    } else if minutes > 0 {
+        format!("{}m {:02}s", minutes, seconds)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+fn check_listener(port: u16) -> bool {
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), port);
     let stream = TcpStream::connect(addr);
     stream.is_ok()
 }
 
-fn init_listener() -> io::Result<()> {
-    println!("Starting system listener...");
-    SysState::start_system()
+fn init_listener(config: ListenerConfig, restore_modes: bool) -> io::Result<()> {
+    println!(
+        "Starting system listener on {}:{}...",
+        config.host, config.port
+    );
+    SysState::start_system(config, restore_modes)
+}
+
+/// Apply a profile of the node config file, once the system listener on `port` is up.
+/// The profile is applied by running the commands a user would run to set up the node.
+fn apply_profile(name: &str, profile: Profile, port: u16) {
+    // Wait for the system listener to start.
+    let mut attempts = 0;
+    while !check_listener(port) {
+        attempts += 1;
+        if attempts > 50 {
+            error!(
+                "System listener did not start. Profile {} not applied",
+                name
+            );
+            return;
+        }
+        thread::sleep(Duration::from_millis(200));
+    }
+    println!("Applying profile: {}", name);
+
+    let mut commands = Vec::new();
+    if let Some(path) = profile.adb_path {
+        commands.push(Commands::SetAdbPath { path });
+    }
+    if let Some(path) = profile.scrcpy_path {
+        commands.push(Commands::SetScrcpyPath { path });
+    }
+    if let Some(marketmaker) = profile.marketmaker {
+        let standby = marketmaker.standby_of.is_some();
+        commands.push(Commands::Marketmaker(MarketMakerCommands::Start {
+            standby_of: marketmaker.standby_of,
+        }));
+        // A standby receives the whitelists from its primary.
+        if marketmaker.use_whitelist && !standby {
+            commands.push(Commands::Marketmaker(MarketMakerCommands::UseWhitelist));
+        }
+        for peer_id in marketmaker.supplier_whitelist {
+            commands.push(Commands::Marketmaker(MarketMakerCommands::AddSupplier {
+                peer_id,
+            }));
+        }
+        for peer_id in marketmaker.consumer_whitelist {
+            commands.push(Commands::Marketmaker(MarketMakerCommands::AddConsumer {
+                peer_id,
+            }));
+        }
+        for peer_id in marketmaker.standby_whitelist {
+            commands.push(Commands::Marketmaker(MarketMakerCommands::AddStandby {
+                peer_id,
+            }));
+        }
+        for (peer_id, roles) in marketmaker.roles {
+            for role in roles {
+                commands.push(Commands::Marketmaker(MarketMakerCommands::GrantRole {
+                    peer_id: peer_id.clone(),
+                    role,
+                }));
+            }
+        }
+        for peer_id in marketmaker.peer_whitelist {
+            commands.push(Commands::Marketmaker(MarketMakerCommands::AddPeer {
+                peer_id,
+            }));
+        }
+        // A standby receives the peers from its primary.
+        if !standby {
+            for remote in marketmaker.peers {
+                commands.push(Commands::Marketmaker(MarketMakerCommands::ConnectPeer {
+                    remote,
+                }));
+            }
+        }
+    }
+    if let Some(supplier) = profile.supplier {
+        commands.push(Commands::Supplier(SupplierCommands::Start {
+            remote: supplier.remote,
+            port: supplier.port,
+            user: supplier.name,
+            secure: supplier.secure,
+            fallbacks: supplier.fallbacks,
+        }));
+        if supplier.supply {
+            commands.push(Commands::Supplier(SupplierCommands::Supply {
+                devices: supplier.devices,
+                pools: supplier.pools,
+            }));
+        }
+        if supplier.auto_supply {
+            commands.push(Commands::Supplier(SupplierCommands::AutoSupply {
+                enable: true,
+            }));
+        }
+    }
+    if let Some(consumer) = profile.consumer {
+        commands.push(Commands::Consumer(ConsumerCommands::Start {
+            remote: consumer.remote,
+            port: consumer.port,
+            user: consumer.name,
+            fallbacks: consumer.fallbacks,
+        }));
+    }
+
+    for command in commands {
+        match TCPClient::new("127.0.0.1", port) {
+            Ok(client) => {
+                process_command(command, client, OutputFormat::Table);
+            }
+            Err(e) => error!("Failed to connect to system listener: {}", e),
+        }
+    }
 }
 
 fn send_request<T>(request: T, client: &TCPClient) -> Response
@@ -307,33 +825,146 @@ where
     T: ToJson,
 {
     let response = client.send_request(request, None).unwrap_or_else(|e| {
-        SysStateResponse::RequestProcessingError {
-            reason: e.to_string(),
-        }
-        .to_json()
+        SysStateResponse::RequestProcessingError { reason: e.into() }.to_json()
     });
     Response::from_str(&response).unwrap()
 }
 
+/// Convert the response to the object printed by the `json` and `yaml` output formats.
+/// The name of the response is stored in `response`, along with its fields.
+/// Failure responses always have a `reason`, even if the response does not carry one.
+fn output_value(response: &Response) -> serde_json::Value {
+    let value = match response {
+        Response::System(response) => serde_json::to_value(response),
+        Response::MarketMaker(response) => serde_json::to_value(response),
+        Response::Supplier(response) => serde_json::to_value(response),
+        Response::Consumer(response) => serde_json::to_value(response),
+    }
+    .unwrap();
+    let mut value = tag_variant(value, "response");
+    if let (Some(reason), serde_json::Value::Object(fields)) = (response.failure(), &mut value) {
+        fields
+            .entry("reason")
+            .or_insert_with(|| serde_json::to_value(reason).unwrap());
+    }
+    value
+}
+
+/// Flatten a serialized enum variant into an object with the name of the
+/// variant stored in `tag`, along with its fields. If one of the fields is
+/// itself named `tag`, the fields are kept in `fields` instead, so that
+/// neither is lost.
+fn tag_variant(value: serde_json::Value, tag: &str) -> serde_json::Value {
+    let mut output = serde_json::Map::new();
+    match value {
+        serde_json::Value::String(name) => {
+            output.insert(tag.to_string(), serde_json::Value::String(name));
+        }
+        serde_json::Value::Object(variant) => {
+            for (name, fields) in variant {
+                output.insert(tag.to_string(), serde_json::Value::String(name));
+                match fields {
+                    serde_json::Value::Object(fields) if !fields.contains_key(tag) => {
+                        output.extend(fields);
+                    }
+                    fields => {
+                        output.insert("fields".to_string(), fields);
+                    }
+                }
+            }
+        }
+        value => return value,
+    }
+    serde_json::Value::Object(output)
+}
+
+fn print_value(value: &serde_json::Value, output: OutputFormat) {
+    match output {
+        OutputFormat::Table => println!("{}", value),
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(value).unwrap()),
+        OutputFormat::Yaml => print!("{}", serde_yaml::to_string(value).unwrap()),
+    }
+}
+
+/// Print the response in the output format. Returns the exit code of the command.
+fn print_response(response: &Response, output: OutputFormat) -> ExitCode {
+    match output {
+        OutputFormat::Table => println!("{}", response),
+        _ => print_value(&output_value(response), output),
+    }
+    match response.failure() {
+        Some(_) => ExitCode::FAILURE,
+        None => ExitCode::SUCCESS,
+    }
+}
+
+/// Print an error that occurred before a response was received.
+/// The `reason` is serialized like the `reason` of failure responses.
+fn print_error(reason: Error, output: OutputFormat) -> ExitCode {
+    match output {
+        OutputFormat::Table => println!("{}", reason),
+        _ => print_value(
+            &serde_json::json!({ "response": "Error", "reason": reason }),
+            output,
+        ),
+    }
+    ExitCode::FAILURE
+}
+
 impl Cli {
-    pub fn process(self) {
+    pub fn process(self) -> ExitCode {
         #[cfg(feature = "mangen")]
         {
             // Process 'mangen' command separately.
             if let Commands::Mangen { path } = self.command {
                 mangen(path);
-                return;
+                return ExitCode::SUCCESS;
             }
         }
 
+        let output = self.output;
+        let node_config = match NodeConfig::load(self.config.as_deref()) {
+            Ok(node_config) => node_config.unwrap_or_default(),
+            Err(e) => {
+                return print_error(
+                    Error::Io(format!("Failed to load config file\n{}", e)),
+                    output,
+                );
+            }
+        };
+        let profile = match node_config.profile(self.profile.as_deref()) {
+            Ok(profile) => profile,
+            Err(e) => return print_error(Error::InvalidInput(e.to_string()), output),
+        };
+        let mut config = match ListenerConfig::load_for(
+            &node_config,
+            profile.as_ref().map(|(_, profile)| *profile),
+        ) {
+            Ok(config) => config,
+            Err(e) => {
+                return print_error(
+                    Error::Io(format!("Failed to load system listener config\n{}", e)),
+                    output,
+                );
+            }
+        };
+        if let Some(host) = self.listen_host {
+            config.host = host;
+        }
+        if let Some(port) = self.listen_port {
+            config.port = port;
+        }
+
         // Process 'init' command separately.
         if let Commands::Init = self.command {
-            if check_listener() {
-                println!(
-                    "Either the default port ({}) is occupied or system listener is already running",
-                    SysStateDefaultConfig::BIND_PORT
+            if check_listener(config.port) {
+                return print_error(
+                    Error::InvalidState(format!(
+                        "Either the port ({}) is occupied or system listener is already running",
+                        config.port
+                    )),
+                    output,
                 );
-                return;
             } else {
                 // Daemonize the thread, if possible.
                 #[cfg(windows)]
@@ -383,27 +1014,44 @@ impl Cli {
                     }
                 }
 
-                let init_result = init_listener();
+                // Modes declared by the profile replace the modes of the previous run.
+                let restore_modes = !profile.as_ref().is_some_and(|(_, profile)| {
+                    profile.marketmaker.is_some()
+                        || profile.supplier.is_some()
+                        || profile.consumer.is_some()
+                });
+                if let Some((name, profile)) = profile {
+                    let profile = profile.clone();
+                    let port = config.port;
+                    thread::spawn(move || apply_profile(&name, profile, port));
+                }
+
+                let init_result = init_listener(config, restore_modes);
 
                 if init_result.is_ok() {
-                    return;
+                    return ExitCode::SUCCESS;
                 } else {
                     error!("Failed to start system listener");
-                    println!(
-                        "Failed to start system listener\n{}",
-                        init_result.unwrap_err()
+                    return print_error(
+                        Error::Io(format!(
+                            "Failed to start system listener\n{}",
+                            init_result.unwrap_err()
+                        )),
+                        output,
                     );
-                    return;
                 }
             }
-        } else if !check_listener() {
-            println!(
-                "System listener is not running. Use \"adborc init\" to start the system listener."
+        } else if !check_listener(config.port) {
+            return print_error(
+                Error::Unreachable(
+                    "System listener is not running. Use \"adborc init\" to start the system listener."
+                        .to_string(),
+                ),
+                output,
             );
-            return;
         }
 
-        let client = TCPClient::new("127.0.0.1", SysStateDefaultConfig::BIND_PORT).unwrap();
+        let client = TCPClient::new("127.0.0.1", config.port).unwrap();
 
         // Check if the AdbOrc client is compatible with the listener, before proceeding.
         // Note: Pre-0.2.0 versions, this check is not performed. This may lead to unhelpful error
@@ -417,125 +1065,342 @@ impl Cli {
         match response {
             Response::System(SysStateResponse::ClientOk) => {} // Do nothing.
             Response::System(SysStateResponse::ClientError { reason }) => {
-                println!("{}", reason);
-                return;
+                return print_error(reason, output);
             }
             // On server version 0.1.0, the server does not support version check.
             _ => {
-                println!(
-                    "Client version {} is not supported by listener version: 0.1.0",
-                    ADBORC_VERSION
+                return print_error(
+                    Error::InvalidState(format!(
+                        "Client version {} is not supported by listener version: 0.1.0",
+                        ADBORC_VERSION
+                    )),
+                    output,
                 );
-                return;
             }
         }
 
-        process_command(self.command, client);
+        match (self.command, self.market_maker) {
+            (Commands::Marketmaker(cmd), Some(remote)) => {
+                // The address has been validated while parsing the arguments.
+                let remote = split_host_port(&remote, SysStateDefaultConfig::BIND_PORT).unwrap();
+                process_market_maker_command(cmd, client, Some(remote), output)
+            }
+            (command, _) => process_command(command, client, output),
+        }
     }
 }
 
-fn process_command(command: Commands, client: TCPClient) {
+fn process_command(command: Commands, client: TCPClient, output: OutputFormat) -> ExitCode {
     match command {
         Commands::Status => {
             let response = send_request(SysStateRequest::GetState, &client);
-            println!("{}", response);
+            print_response(&response, output)
         }
         Commands::Shutdown => {
             let response = send_request(SysStateRequest::Shutdown, &client);
-            println!("{}", response);
+            print_response(&response, output)
         }
         Commands::GetNetworkId => {
             let response = send_request(SysStateRequest::GetPeerId, &client);
-            println!("{}", response);
+            print_response(&response, output)
+        }
+        Commands::ExportNetworkId { path } => {
+            let response = send_request(SysStateRequest::GetPeerId, &client);
+            match &response {
+                Response::System(SysStateResponse::PeerId { peer_id }) => {
+                    match fs::write(&path, format!("{}\n", peer_id)) {
+                        Ok(_) if output == OutputFormat::Table => {
+                            println!("Exported network_id to {}", path);
+                            ExitCode::SUCCESS
+                        }
+                        Ok(_) => print_response(&response, output),
+                        Err(e) => print_error(
+                            Error::Io(format!("Failed to export network_id to {}\n{}", path, e)),
+                            output,
+                        ),
+                    }
+                }
+                _ => print_response(&response, output),
+            }
+        }
+        Commands::RotateNetworkKey => {
+            let response = send_request(SysStateRequest::RotateNetworkKey, &client);
+            print_response(&response, output)
+        }
+        Commands::ImportNetworkKey { path } => {
+            let private_key = match fs::read_to_string(&path) {
+                Ok(private_key) => private_key,
+                Err(e) => {
+                    return print_error(
+                        Error::Io(format!("Failed to read key file {}\n{}", path, e)),
+                        output,
+                    );
+                }
+            };
+            let response = send_request(SysStateRequest::ImportNetworkKey { private_key }, &client);
+            print_response(&response, output)
         }
         Commands::Check => {
             let response = send_request(SysStateRequest::SystemCheck, &client);
-            println!("{}", response);
+            print_response(&response, output)
         }
         Commands::SetAdbPath { path } => {
             let response = send_request(SysStateRequest::SetAdbPath { adb_path: path }, &client);
-            println!("{}", response);
+            print_response(&response, output)
         }
         Commands::SetScrcpyPath { path } => {
             let response = send_request(
                 SysStateRequest::SetScrcpyPath { scrcpy_path: path },
                 &client,
             );
-            println!("{}", response);
-        }
-        Commands::Marketmaker(cmd) => process_market_maker_command(cmd, client),
-        Commands::Supplier(cmd) => process_supplier_command(cmd, client),
-        Commands::Consumer(cmd) => process_consumer_command(cmd, client),
-        _ => {
-            println!("Not yet implemented");
+            print_response(&response, output)
         }
+        Commands::Marketmaker(cmd) => process_market_maker_command(cmd, client, None, output),
+        Commands::Supplier(cmd) => process_supplier_command(cmd, client, output),
+        Commands::Consumer(cmd) => process_consumer_command(cmd, client, output),
+        _ => print_error(
+            Error::InvalidInput("Not yet implemented".to_string()),
+            output,
+        ),
     }
 }
 
-fn process_market_maker_command(command: MarketMakerCommands, client: TCPClient) {
+fn process_market_maker_command(
+    command: MarketMakerCommands,
+    client: TCPClient,
+    remote: Option<(String, u16)>,
+    output: OutputFormat,
+) -> ExitCode {
+    if remote.is_some()
+        && matches!(
+            command,
+            MarketMakerCommands::Start { .. } | MarketMakerCommands::Stop
+        )
+    {
+        return print_error(
+            Error::InvalidInput(
+                "MarketMaker mode can only be started or stopped on the local system".to_string(),
+            ),
+            output,
+        );
+    }
+    if remote.is_some() && matches!(command, MarketMakerCommands::Watch { .. }) {
+        return print_error(
+            Error::InvalidInput(
+                "Only the events of the MarketMaker on the system can be watched".to_string(),
+            ),
+            output,
+        );
+    }
+    // Requests to a remote MarketMaker are sent through the system listener,
+    // with the network_id of the system.
+    let send = |request: MarketMakerRequest| match &remote {
+        Some((host, port)) => send_request(
+            SysStateRequest::SendToMarketMaker {
+                host: host.clone(),
+                port: *port,
+                request: Box::new(request),
+            },
+            &client,
+        ),
+        None => send_request(request, &client),
+    };
     match command {
         MarketMakerCommands::Status => {
-            let response = send_request(MarketMakerRequest::Status, &client);
-            println!("{}", response);
+            let response = send(MarketMakerRequest::Status);
+            print_response(&response, output)
         }
-        MarketMakerCommands::Start => {
-            let response = send_request(SysStateRequest::StartMarketMaker, &client);
-            println!("{}", response);
+        MarketMakerCommands::Start { standby_of } => {
+            let request = match standby_of {
+                // Addresses from a profile are not validated while parsing the arguments.
+                Some(primary) => {
+                    match split_host_port(&primary, SysStateDefaultConfig::BIND_PORT) {
+                        Ok((primary_host, primary_port)) => {
+                            SysStateRequest::StartStandbyMarketMaker {
+                                primary_host,
+                                primary_port,
+                            }
+                        }
+                        Err(e) => {
+                            return print_error(
+                                Error::InvalidInput(format!(
+                                    "Invalid primary address {}: {}",
+                                    primary, e
+                                )),
+                                output,
+                            )
+                        }
+                    }
+                }
+                None => SysStateRequest::StartMarketMaker,
+            };
+            let response = send_request(request, &client);
+            print_response(&response, output)
         }
         MarketMakerCommands::Stop => {
             let response = send_request(SysStateRequest::StopMarketMaker, &client);
-            println!("{}", response);
+            print_response(&response, output)
         }
         MarketMakerCommands::UseWhitelist => {
-            let response = send_request(MarketMakerRequest::UseWhitelist, &client);
-            println!("{}", response);
+            let response = send(MarketMakerRequest::UseWhitelist);
+            print_response(&response, output)
         }
         MarketMakerCommands::ResetWhitelist => {
-            let response = send_request(MarketMakerRequest::ResetWhitelist, &client);
-            println!("{}", response);
+            let response = send(MarketMakerRequest::ResetWhitelist);
+            print_response(&response, output)
         }
         MarketMakerCommands::AddSupplier { peer_id } => {
-            let response = send_request(
-                MarketMakerRequest::WhitelistSupplier { key: peer_id },
-                &client,
-            );
-            println!("{}", response);
+            let response = send(MarketMakerRequest::WhitelistSupplier { key: peer_id });
+            print_response(&response, output)
         }
         MarketMakerCommands::RemoveSupplier { peer_id } => {
-            let response = send_request(
-                MarketMakerRequest::UnwhitelistSupplier { key: peer_id },
-                &client,
-            );
-            println!("{}", response);
+            let response = send(MarketMakerRequest::UnwhitelistSupplier { key: peer_id });
+            print_response(&response, output)
         }
         MarketMakerCommands::AddConsumer { peer_id } => {
-            let response = send_request(
-                MarketMakerRequest::WhitelistConsumer { key: peer_id },
-                &client,
-            );
-            println!("{}", response);
+            let response = send(MarketMakerRequest::WhitelistConsumer { key: peer_id });
+            print_response(&response, output)
         }
         MarketMakerCommands::RemoveConsumer { peer_id } => {
-            let response = send_request(
-                MarketMakerRequest::UnwhitelistConsumer { key: peer_id },
-                &client,
-            );
-            println!("{}", response);
+            let response = send(MarketMakerRequest::UnwhitelistConsumer { key: peer_id });
+            print_response(&response, output)
+        }
+        MarketMakerCommands::AddStandby { peer_id } => {
+            let response = send(MarketMakerRequest::WhitelistStandby { key: peer_id });
+            print_response(&response, output)
+        }
+        MarketMakerCommands::RemoveStandby { peer_id } => {
+            let response = send(MarketMakerRequest::UnwhitelistStandby { key: peer_id });
+            print_response(&response, output)
+        }
+        MarketMakerCommands::AddPeer { peer_id } => {
+            let response = send(MarketMakerRequest::WhitelistPeer { key: peer_id });
+            print_response(&response, output)
+        }
+        MarketMakerCommands::RemovePeer { peer_id } => {
+            let response = send(MarketMakerRequest::UnwhitelistPeer { key: peer_id });
+            print_response(&response, output)
+        }
+        MarketMakerCommands::ConnectPeer { remote: peer } => {
+            // Addresses from a profile are not validated while parsing the arguments.
+            match split_host_port(&peer, SysStateDefaultConfig::BIND_PORT) {
+                Ok((host, port)) => {
+                    let response = send(MarketMakerRequest::ConnectPeer { host, port });
+                    print_response(&response, output)
+                }
+                Err(e) => print_error(
+                    Error::InvalidInput(format!("Invalid peer address {}: {}", peer, e)),
+                    output,
+                ),
+            }
+        }
+        MarketMakerCommands::GrantRole { peer_id, role } => {
+            let response = send(MarketMakerRequest::GrantRole { key: peer_id, role });
+            print_response(&response, output)
+        }
+        MarketMakerCommands::RevokeRole { peer_id, role } => {
+            let response = send(MarketMakerRequest::RevokeRole { key: peer_id, role });
+            print_response(&response, output)
+        }
+        MarketMakerCommands::SetQuota {
+            consumer,
+            group,
+            max_devices,
+            max_lease,
+        } => {
+            let quota = Quota {
+                max_devices,
+                max_lease_secs: max_lease,
+            };
+            let target = quota_target(consumer, group);
+            let response = send(MarketMakerRequest::SetQuota { target, quota });
+            print_response(&response, output)
+        }
+        MarketMakerCommands::RemoveQuota { consumer, group } => {
+            let target = quota_target(consumer, group);
+            let response = send(MarketMakerRequest::RemoveQuota { target });
+            print_response(&response, output)
+        }
+        MarketMakerCommands::AddToGroup { group, peer_id } => {
+            let response = send(MarketMakerRequest::AddToGroup {
+                group,
+                key: peer_id,
+            });
+            print_response(&response, output)
+        }
+        MarketMakerCommands::RemoveFromGroup { group, peer_id } => {
+            let response = send(MarketMakerRequest::RemoveFromGroup {
+                group,
+                key: peer_id,
+            });
+            print_response(&response, output)
+        }
+        MarketMakerCommands::AssignPool { device_id, pool } => {
+            let response = send(MarketMakerRequest::AssignPool { device_id, pool });
+            print_response(&response, output)
+        }
+        MarketMakerCommands::UnassignPool { device_id, pool } => {
+            let response = send(MarketMakerRequest::UnassignPool { device_id, pool });
+            print_response(&response, output)
+        }
+        MarketMakerCommands::GrantPoolAccess { pool, peer_id } => {
+            let response = send(MarketMakerRequest::GrantPoolAccess { pool, key: peer_id });
+            print_response(&response, output)
+        }
+        MarketMakerCommands::RevokePoolAccess { pool, peer_id } => {
+            let response = send(MarketMakerRequest::RevokePoolAccess { pool, key: peer_id });
+            print_response(&response, output)
+        }
+        MarketMakerCommands::ForceRelease { device_id } => {
+            let response = send(MarketMakerRequest::ForceReleaseDevice { device_id });
+            print_response(&response, output)
+        }
+        MarketMakerCommands::Watch { topics } => {
+            let topics = topics.unwrap_or_default().into_iter().collect();
+            let events = match client.subscribe(topics) {
+                Ok(events) => events,
+                Err(e) => {
+                    return print_error(e.into(), output);
+                }
+            };
+            for event in events {
+                match output {
+                    OutputFormat::Table => println!("{}", event),
+                    OutputFormat::Json => {
+                        let value = tag_variant(serde_json::to_value(&event).unwrap(), "event");
+                        println!("{}", value);
+                    }
+                    OutputFormat::Yaml => {
+                        let value = tag_variant(serde_json::to_value(&event).unwrap(), "event");
+                        print!("---\n{}", serde_yaml::to_string(&value).unwrap());
+                    }
+                }
+            }
+            // The MarketMaker closes the stream when it is stopped.
+            print_error(
+                Error::Unreachable("MarketMaker closed the event stream".to_string()),
+                output,
+            )
         }
     }
 }
 
-fn process_supplier_command(command: SupplierCommands, client: TCPClient) {
+fn process_supplier_command(
+    command: SupplierCommands,
+    client: TCPClient,
+    output: OutputFormat,
+) -> ExitCode {
     match command {
         SupplierCommands::Status => {
             let response = send_request(SupplierRequest::Status, &client);
-            println!("{}", response);
+            print_response(&response, output)
         }
         SupplierCommands::Start {
             remote,
             port,
             user,
             secure,
+            fallbacks,
         } => {
             let response = send_request(
                 SysStateRequest::StartSupplier {
@@ -543,18 +1408,20 @@ fn process_supplier_command(command: SupplierCommands, client: TCPClient) {
                     mm_port: port,
                     name: user,
                     secure_comms: secure,
+                    fallbacks,
                 },
                 &client,
             );
-            println!("{}", response);
+            print_response(&response, output)
         }
         SupplierCommands::Stop => {
             let response = send_request(SysStateRequest::StopSupplier, &client);
-            println!("{}", response);
+            print_response(&response, output)
         }
-        SupplierCommands::Supply { devices } => {
-            let response = send_request(SupplierRequest::SupplyDevices { devices }, &client);
-            println!("{}", response);
+        SupplierCommands::Supply { devices, pools } => {
+            let pools = pools.map(HashSet::from_iter).unwrap_or_default();
+            let response = send_request(SupplierRequest::SupplyDevices { devices, pools }, &client);
+            print_response(&response, output)
         }
         SupplierCommands::Reclaim { device, force } => {
             let response = send_request(
@@ -564,41 +1431,134 @@ fn process_supplier_command(command: SupplierCommands, client: TCPClient) {
                 },
                 &client,
             );
-            println!("{}", response);
+            print_response(&response, output)
+        }
+        SupplierCommands::AutoSupply { enable } => {
+            let response = send_request(
+                SupplierRequest::SetAutoSupply {
+                    auto_supply: enable,
+                },
+                &client,
+            );
+            print_response(&response, output)
         }
     }
 }
 
-fn process_consumer_command(command: ConsumerCommands, client: TCPClient) {
+fn process_consumer_command(
+    command: ConsumerCommands,
+    client: TCPClient,
+    output: OutputFormat,
+) -> ExitCode {
     match command {
         ConsumerCommands::Status => {
             let response = send_request(ConsumerRequest::Status, &client);
-            println!("{}", response);
+            print_response(&response, output)
         }
-        ConsumerCommands::Start { remote, port, user } => {
+        ConsumerCommands::Start {
+            remote,
+            port,
+            user,
+            fallbacks,
+        } => {
             let response = send_request(
                 SysStateRequest::StartConsumer {
                     mm_host: remote,
                     mm_port: port,
                     name: user,
+                    fallbacks,
                 },
                 &client,
             );
-            println!("{}", response);
+            print_response(&response, output)
         }
         ConsumerCommands::Stop => {
             let response = send_request(SysStateRequest::StopConsumer, &client);
-            println!("{}", response);
+            print_response(&response, output)
+        }
+        ConsumerCommands::Reserve {
+            device,
+            model,
+            count,
+            no_default,
+            lease,
+        } => {
+            let response = match device {
+                Some(device) => send_request(
+                    ConsumerRequest::ReserveDevice {
+                        device_id: device,
+                        no_use: no_default,
+                        lease_secs: lease,
+                    },
+                    &client,
+                ),
+                None => {
+                    let mut filters = Vec::new();
+                    if let Some(model) = model {
+                        let models = HashSet::from_iter(model);
+                        filters.push(DeviceFilter::DeviceModels(models));
+                    }
+                    send_request(
+                        ConsumerRequest::ReserveAnyDevice {
+                            filter_vec: DeviceFilterVec::new(filters, None),
+                            count,
+                            no_use: no_default,
+                            lease_secs: lease,
+                        },
+                        &client,
+                    )
+                }
+            };
+            print_response(&response, output)
         }
-        ConsumerCommands::Reserve { device, no_default } => {
+        ConsumerCommands::Renew { device, lease } => {
             let response = send_request(
-                ConsumerRequest::ReserveDevice {
+                ConsumerRequest::RenewLease {
                     device_id: device,
-                    no_use: no_default,
+                    lease_secs: lease,
+                },
+                &client,
+            );
+            print_response(&response, output)
+        }
+        ConsumerCommands::Enqueue {
+            device,
+            device_names,
+            device_models,
+            supplied_by,
+            lease,
+        } => {
+            let target = match device {
+                Some(device) => WaitTarget::Device(device),
+                None => {
+                    let mut filters = Vec::new();
+                    if let Some(device_names) = device_names {
+                        let device_names = HashSet::from_iter(device_names);
+                        filters.push(DeviceFilter::DeviceNames(device_names));
+                    }
+                    if let Some(device_models) = device_models {
+                        let device_models = HashSet::from_iter(device_models);
+                        filters.push(DeviceFilter::DeviceModels(device_models));
+                    }
+                    if let Some(supplied_by) = supplied_by {
+                        let supplied_by = HashSet::from_iter(supplied_by);
+                        filters.push(DeviceFilter::SupplierNames(supplied_by));
+                    }
+                    WaitTarget::AnyMatching(DeviceFilterVec::new(filters, None))
+                }
+            };
+            let response = send_request(
+                ConsumerRequest::EnqueueReservation {
+                    target,
+                    lease_secs: lease,
                 },
                 &client,
             );
-            println!("{}", response);
+            print_response(&response, output)
+        }
+        ConsumerCommands::LeaveQueue => {
+            let response = send_request(ConsumerRequest::LeaveQueue, &client);
+            print_response(&response, output)
         }
         ConsumerCommands::Release { device } => {
             let response = if device.is_some() {
@@ -611,11 +1571,11 @@ fn process_consumer_command(command: ConsumerCommands, client: TCPClient) {
             } else {
                 send_request(ConsumerRequest::ReleaseAllDevices, &client)
             };
-            println!("{}", response);
+            print_response(&response, output)
         }
         ConsumerCommands::ListAvailable => {
             let response = send_request(ConsumerRequest::GetAvailableDevices, &client);
-            println!("{}", response);
+            print_response(&response, output)
         }
         ConsumerCommands::GetDevices {
             is_available,
@@ -624,6 +1584,14 @@ fn process_consumer_command(command: ConsumerCommands, client: TCPClient) {
             device_models,
             supplied_by,
             reserved_by,
+            android_versions,
+            min_sdk,
+            max_sdk,
+            abis,
+            brands,
+            pools,
+            min_battery,
+            query,
         } => {
             let mut filters = Vec::new();
 
@@ -651,13 +1619,42 @@ fn process_consumer_command(command: ConsumerCommands, client: TCPClient) {
                 let reserved_by = HashSet::from_iter(reserved_by);
                 filters.push(DeviceFilter::ConsumerNames(reserved_by));
             }
-            let filter_vec = DeviceFilterVec { filters };
+            if let Some(android_versions) = android_versions {
+                let android_versions = HashSet::from_iter(android_versions);
+                filters.push(DeviceFilter::AndroidVersions(android_versions));
+            }
+            if min_sdk.is_some() || max_sdk.is_some() {
+                filters.push(DeviceFilter::SdkRange {
+                    min: min_sdk,
+                    max: max_sdk,
+                });
+            }
+            if let Some(abis) = abis {
+                let abis = HashSet::from_iter(abis);
+                filters.push(DeviceFilter::Abis(abis));
+            }
+            if let Some(brands) = brands {
+                let brands = HashSet::from_iter(brands);
+                filters.push(DeviceFilter::Brands(brands));
+            }
+            if let Some(pools) = pools {
+                let pools = HashSet::from_iter(pools);
+                filters.push(DeviceFilter::Pools(pools));
+            }
+            if let Some(min_battery) = min_battery {
+                filters.push(DeviceFilter::MinBatteryLevel(min_battery));
+            }
+            let filter_vec = DeviceFilterVec::new(filters, query.map(|query| *query));
             let response =
                 send_request(ConsumerRequest::GetDevicesByFilter { filter_vec }, &client);
-            println!("{}", response);
+            print_response(&response, output)
         }
         ConsumerCommands::ListReserved => {
             let response = send_request(ConsumerRequest::Status, &client);
+            if output != OutputFormat::Table {
+                // The state of the Consumer holds the reserved devices.
+                return print_response(&response, output);
+            }
             match response {
                 Response::Consumer(ConsumerResponse::Status { state }) => {
                     let reserved_devices = state.devices;
@@ -665,6 +1662,13 @@ fn process_consumer_command(command: ConsumerCommands, client: TCPClient) {
                     println!("Reserved devices:");
                     for (_, device) in reserved_devices.iter() {
                         println!("{}", device);
+                        if let Some(remaining) = device.lease_remaining() {
+                            println!(
+                                r"        |
+    |----> Lease expires in: {}",
+                                format_duration(remaining)
+                            );
+                        }
                         if device.device_id == using_device {
                             println!(
                                 r"        |
@@ -679,13 +1683,17 @@ fn process_consumer_command(command: ConsumerCommands, client: TCPClient) {
                         }
                     }
                     println!();
+                    ExitCode::SUCCESS
                 }
-                _ => println!("Unexpected response: {}", response),
+                _ => print_error(
+                    Error::Protocol(format!("Unexpected response: {}", response)),
+                    output,
+                ),
             }
         }
         ConsumerCommands::SetDefault { device } => {
             let response = send_request(ConsumerRequest::UseDevice { device_id: device }, &client);
-            println!("{}", response);
+            print_response(&response, output)
         }
         ConsumerCommands::Scrcpy { device, args } => {
             let scrcpy_args = adb_utils::get_scrcpy_args(args);
@@ -696,24 +1704,25 @@ fn process_consumer_command(command: ConsumerCommands, client: TCPClient) {
                 },
                 &client,
             );
-            println!("{}", response);
+            print_response(&response, output)
         }
         ConsumerCommands::StopScrcpy { device } => {
             let response = send_request(ConsumerRequest::StopScrCpy { device_id: device }, &client);
-            println!("{}", response);
+            print_response(&response, output)
         }
         ConsumerCommands::SetScrcpyArgs(args) => {
             let scrcpy_args = adb_utils::get_scrcpy_args(args);
             let response =
                 send_request(ConsumerRequest::SetScrCpyDefaults { scrcpy_args }, &client);
-            println!("{}", response);
+            print_response(&response, output)
         }
         ConsumerCommands::GetScrcpyArgs => {
             let response = send_request(ConsumerRequest::GetScrCpyDefaults, &client);
-            println!("{}", response);
+            print_response(&response, output)
         }
         ConsumerCommands::ScrcpyShortcuts => {
             println!("{}", SCRCPY_SHORTCUT_HELP);
+            ExitCode::SUCCESS
         }
     }
 }
@@ -850,3 +1859,84 @@ fn mangen(path: Option<String>) {
     println!("Wrote man page to {}", path.join("adborc.man").display());
     return;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_output_value() {
+        let response = Response::System(SysStateResponse::ClientOk);
+        assert_eq!(output_value(&response), json!({ "response": "ClientOk" }));
+
+        let response = Response::System(SysStateResponse::StartMarketMakerFailed {
+            reason: Error::InvalidState("MarketMaker is already running".to_string()),
+        });
+        assert_eq!(
+            output_value(&response),
+            json!({
+                "response": "StartMarketMakerFailed",
+                "reason": { "InvalidState": "MarketMaker is already running" },
+            })
+        );
+
+        // Failure responses without a reason are printed with the reason of the failure.
+        let response = Response::Consumer(ConsumerResponse::RequestNotAllowed);
+        assert_eq!(
+            output_value(&response),
+            json!({
+                "response": "RequestNotAllowed",
+                "reason": { "NotAuthorized": "Request not allowed" },
+            })
+        );
+    }
+
+    #[test]
+    fn test_tag_variant() {
+        assert_eq!(
+            tag_variant(json!("Supplied"), "event"),
+            json!({ "event": "Supplied" })
+        );
+        assert_eq!(
+            tag_variant(json!({ "Supplied": { "device_id": "a" } }), "event"),
+            json!({ "event": "Supplied", "device_id": "a" })
+        );
+        // A field named like the tag is not overwritten by the name of the variant.
+        assert_eq!(
+            tag_variant(
+                json!({ "Supplied": { "event": 1, "device_id": "a" } }),
+                "event"
+            ),
+            json!({ "event": "Supplied", "fields": { "event": 1, "device_id": "a" } })
+        );
+        // Tuple variants keep their fields.
+        assert_eq!(
+            tag_variant(json!({ "Adb": "adb not found" }), "reason"),
+            json!({ "reason": "Adb", "fields": "adb not found" })
+        );
+    }
+
+    #[test]
+    fn test_exit_code() {
+        let response = Response::System(SysStateResponse::ClientOk);
+        assert_eq!(
+            print_response(&response, OutputFormat::Json),
+            ExitCode::SUCCESS
+        );
+        let response = Response::System(SysStateResponse::StartMarketMakerFailed {
+            reason: Error::InvalidState("MarketMaker is already running".to_string()),
+        });
+        assert_eq!(
+            print_response(&response, OutputFormat::Json),
+            ExitCode::FAILURE
+        );
+        assert_eq!(
+            print_error(
+                Error::Unreachable("System listener is not running".to_string()),
+                OutputFormat::Json
+            ),
+            ExitCode::FAILURE
+        );
+    }
+}
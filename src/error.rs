@@ -0,0 +1,120 @@
+//! Error type of the library.
+//!
+//! Errors are categorized, so that programmatic callers can tell e.g. a device
+//! that is already reserved from a MarketMaker that cannot be reached. Each
+//! variant carries a human readable message, which is what `Display` prints.
+//! Errors are also carried in the `reason` of the failure responses, so the
+//! category survives the trip over the network.
+
+use serde::{Deserialize, Serialize};
+use std::error;
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, ErrorKind};
+
+/// Error returned by the library, and carried in failure responses.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// `adb` is not found, its version is not supported, or a command of it failed.
+    Adb(String),
+    /// `scrcpy` is not found, its version is not supported, or it failed to start.
+    Scrcpy(String),
+    /// A MarketMaker, Supplier, Consumer or peer could not be reached.
+    Unreachable(String),
+    /// The device is not known to the network, or to the node.
+    DeviceNotFound(String),
+    /// The device is known, but cannot be used for the request,
+    /// e.g. it is already reserved.
+    DeviceUnavailable(String),
+    /// The network_id is not allowed to make the request, e.g. it is not
+    /// in a whitelist, or lacks a role or access to a pool.
+    NotAuthorized(String),
+    /// The request would exceed a quota.
+    QuotaExceeded(String),
+    /// The request, or one of its arguments, is invalid.
+    InvalidInput(String),
+    /// The node is not in a state that allows the request,
+    /// e.g. a mode is already running, or is not running.
+    InvalidState(String),
+    /// A message could not be parsed, or was not expected.
+    Protocol(String),
+    /// Any other I/O failure, e.g. reading or writing a file.
+    Io(String),
+}
+
+impl Error {
+    /// The human readable message of the error.
+    pub fn message(&self) -> &str {
+        match self {
+            Error::Adb(message)
+            | Error::Scrcpy(message)
+            | Error::Unreachable(message)
+            | Error::DeviceNotFound(message)
+            | Error::DeviceUnavailable(message)
+            | Error::NotAuthorized(message)
+            | Error::QuotaExceeded(message)
+            | Error::InvalidInput(message)
+            | Error::InvalidState(message)
+            | Error::Protocol(message)
+            | Error::Io(message) => message,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.message())
+    }
+}
+
+impl error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        // Errors converted into `io::Error` keep their category.
+        if e.get_ref().is_some_and(|inner| inner.is::<Error>()) {
+            return *e.into_inner().unwrap().downcast::<Error>().unwrap();
+        }
+        match e.kind() {
+            ErrorKind::ConnectionRefused
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::NotConnected
+            | ErrorKind::AddrNotAvailable
+            | ErrorKind::BrokenPipe
+            | ErrorKind::TimedOut
+            | ErrorKind::UnexpectedEof => Error::Unreachable(e.to_string()),
+            ErrorKind::InvalidInput => Error::InvalidInput(e.to_string()),
+            ErrorKind::InvalidData => Error::Protocol(e.to_string()),
+            _ => Error::Io(e.to_string()),
+        }
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(e: Error) -> Self {
+        let kind = match e {
+            Error::Unreachable(_) => ErrorKind::NotConnected,
+            Error::InvalidInput(_) => ErrorKind::InvalidInput,
+            Error::Protocol(_) => ErrorKind::InvalidData,
+            Error::NotAuthorized(_) => ErrorKind::PermissionDenied,
+            _ => ErrorKind::Other,
+        };
+        io::Error::new(kind, e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn io_error_round_trip() {
+        let error = Error::DeviceUnavailable("Device is being used by a consumer".to_string());
+        let io_error = io::Error::from(error.clone());
+        assert_eq!(io_error.to_string(), "Device is being used by a consumer");
+        assert_eq!(Error::from(io_error), error);
+
+        let refused = io::Error::new(ErrorKind::ConnectionRefused, "Connection refused");
+        assert!(matches!(Error::from(refused), Error::Unreachable(_)));
+    }
+}
@@ -63,11 +63,11 @@
 //!     market::{SysState, request::
 //!             {Request, ToJson, SysStateRequest, SysStateResponse}},
 //!     net::TCPClient,
-//!     util::SysStateDefaultConfig
+//!     util::{ListenerConfig, SysStateDefaultConfig}
 //! };
 //!
 //! // start the system in a separate thread.
-//! thread::spawn(|| SysState::start_system().unwrap());
+//! thread::spawn(|| SysState::start_system(ListenerConfig::default(), false).unwrap());
 //!
 //! let client = TCPClient::new("localhost", SysStateDefaultConfig::BIND_PORT).unwrap();
 //! // Construct a request to start the marketmaker.
@@ -77,6 +77,43 @@
 //! assert_eq!(response, expected_response.to_json());
 //! ```
 //!
+//! `TCPClient` blocks on a runtime of its own, and cannot be used from within
+//! a Tokio runtime. Applications running on Tokio use `AsyncClient` instead,
+//! which also keeps its connection alive between requests.
+//!
+//! ```rust no_run
+//! use adborc::{
+//!     market::request::SysStateRequest, net::AsyncClient, util::SysStateDefaultConfig,
+//! };
+//!
+//! # async fn status() -> std::io::Result<()> {
+//! let client = AsyncClient::new("localhost", SysStateDefaultConfig::BIND_PORT).await?;
+//! let response = client.send_request(SysStateRequest::GetState, None).await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The typed helpers of `TCPClient` return the payload of the response, and
+//! an [`Error`] if the request failed. The variant of the error tells the
+//! category of the failure, e.g. a device that is already reserved.
+//!
+//! ```rust no_run
+//! use adborc::{net::TCPClient, util::SysStateDefaultConfig, Error};
+//!
+//! # fn reserve() -> Result<(), Error> {
+//! let client = TCPClient::new("localhost", SysStateDefaultConfig::BIND_PORT)?;
+//! match client.consumer().reserve("serial1") {
+//!     Ok(device) => println!("Reserved {}", device.device_id),
+//!     Err(Error::DeviceUnavailable(reason)) => println!("Try later: {}", reason),
+//!     Err(e) => return Err(e),
+//! }
+//! for device in client.consumer().list_reserved()? {
+//!     println!("{}", device);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
 
 pub mod net;
 
@@ -84,4 +121,7 @@ pub mod market;
 
 pub mod util;
 
+mod error;
 mod noise;
+
+pub use error::Error;
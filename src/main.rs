@@ -4,11 +4,12 @@ use clap::Parser;
 use cli::Cli;
 use env_logger::{Builder, Env};
 use std::io::Write;
+use std::process::ExitCode;
 
 const FILTER_ENV: &str = "ADBORC_LOG_LEVEL";
 const STYLE_ENV: &str = "ADBORC_LOG_STYLE";
 
-fn main() {
+fn main() -> ExitCode {
     // Initialize the logger.
     let env = Env::default()
         .filter_or(FILTER_ENV, "info")
@@ -29,5 +30,5 @@ fn main() {
         })
         .init();
 
-    Cli::parse().process();
+    Cli::parse().process()
 }
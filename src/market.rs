@@ -4,13 +4,20 @@ pub mod request;
 
 mod consumer;
 mod marketmaker;
+mod modes;
+mod query;
 mod supplier;
 
-use crate::net::{CommandServer, PortForwardMode, PortForwarder, ProcessFn, TCPClient};
+use crate::error::Error;
+use crate::net::{self, CommandServer, PortForwardMode, PortForwarder, ProcessFn, TCPClient};
+use crate::noise::Noise;
 use crate::util::{
-    adb_utils::{self, AdbVersionInfo, DeviceInfo, ScrcpyVersionInfo},
-    SysStateDefaultConfig, ADBORC_VERSION, HEARTBEAT_INTERVAL, MIN_ADB_REV, MIN_ADB_VER,
-    MIN_SCRCPY_VER, UNDERTAKER_INTERVAL,
+    self,
+    adb_utils::{self, AdbVersionInfo, DeviceHealth, DeviceInfo, ScrcpyVersionInfo},
+    ListenerConfig, ReconnectBackoff, SysStateDefaultConfig, ADBORC_VERSION, ADVERTISE_INTERVAL,
+    AUTO_SUPPLY_INTERVAL, HEALTH_TIMEOUT, HEARTBEAT_INTERVAL, KEY_FILE, MIN_ADB_REV, MIN_ADB_VER,
+    MIN_SCRCPY_VER, PEER_HEARTBEAT_INTERVAL, PERSIST_INTERVAL, PRIMARY_HEARTBEAT_INTERVAL,
+    RECONNECT_TIMEOUT, REPLICATION_INTERVAL, STANDBY_TAKEOVER_TIMEOUT, UNDERTAKER_INTERVAL,
 };
 use blake2::{digest::consts::U16, Blake2s, Digest};
 use lazy_static::lazy_static;
@@ -19,17 +26,23 @@ use serde::{Deserialize, Serialize};
 use snow::Keypair;
 use std::collections::{HashMap, HashSet};
 use std::fmt::{self, Display, Formatter, Write};
-use std::io::{self, Error, ErrorKind};
+use std::fs;
+use std::io::{self, ErrorKind};
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::{Arc, Mutex, RwLock};
 use std::thread;
 use std::time::Duration;
 
+pub use consumer::ConsumerStateMin;
 use consumer::{Consumer, ConsumerState};
 use marketmaker::MarketMaker;
+pub use marketmaker::MarketMakerMinState;
+use modes::{SavedConsumer, SavedModes, SavedSupplier};
+pub use query::{DeviceFilterExpr, ParseFilterError};
 use request::*;
+pub use supplier::SupplierStateMin;
 use supplier::{Supplier, SupplierState};
 use tokio::task;
 
@@ -66,6 +79,77 @@ impl SystemKeypair {
             .as_ref()
             .map(|keypair| keypair.public.clone())
     }
+
+    /// Load the keypair from the key file in the data directory.
+    /// If there is no key file yet, a new keypair is generated and saved,
+    /// so that the `network_id` of the system stays the same across restarts.
+    pub fn load_or_generate() -> io::Result<()> {
+        let key_file = util::data_dir().join(KEY_FILE);
+        let keypair = if key_file.exists() {
+            let private_key = read_key_file(&key_file)?;
+            Noise::keypair_from_private_key(&private_key)?
+        } else {
+            info!("No key file found, generating a new network key");
+            let keypair = Noise::generate_keypair()?;
+            write_key_file(&key_file, &keypair.private)?;
+            keypair
+        };
+        debug!("Public key is: {}", base64::encode(&keypair.public));
+        Self::set_keypair(keypair);
+        Ok(())
+    }
+
+    /// Replace the keypair with a newly generated one and save it to the key file.
+    /// Returns the new public key.
+    pub fn rotate() -> io::Result<Vec<u8>> {
+        let keypair = Noise::generate_keypair()?;
+        Self::replace(keypair)
+    }
+
+    /// Replace the keypair with the one derived from `private_key` and save it
+    /// to the key file. Returns the new public key.
+    pub fn import(private_key: &[u8]) -> io::Result<Vec<u8>> {
+        let keypair = Noise::keypair_from_private_key(private_key)?;
+        Self::replace(keypair)
+    }
+
+    fn replace(keypair: Keypair) -> io::Result<Vec<u8>> {
+        let key_file = util::data_dir().join(KEY_FILE);
+        write_key_file(&key_file, &keypair.private)?;
+        let public_key = keypair.public.clone();
+        Self::set_keypair(keypair);
+        Ok(public_key)
+    }
+}
+
+fn read_key_file(path: &Path) -> io::Result<Vec<u8>> {
+    let contents = fs::read_to_string(path)?;
+    base64::decode(contents.trim()).map_err(|e| {
+        io::Error::new(
+            ErrorKind::InvalidData,
+            format!("Invalid key file {}: {}", path.display(), e),
+        )
+    })
+}
+
+/// Write the private key to the key file. The file is readable and writable
+/// only by the current user, on unix systems.
+fn write_key_file(path: &Path, private_key: &[u8]) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let temp_path = path.with_extension("tmp");
+    let mut options = fs::OpenOptions::new();
+    options.create(true).write(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(&temp_path)?;
+    io::Write::write_all(&mut file, base64::encode(private_key).as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&temp_path, path)
 }
 
 pub(crate) type Key = Vec<u8>;
@@ -111,6 +195,8 @@ lazy_static! {
     /// Creates a new uninitialized SysState struct.
     /// This struct is used to persist the system state.
     static ref SYS_STATE: RwLock<SysState> = RwLock::new(SysState::default());
+    /// Address the system listener is bound to. Set when the system listener starts.
+    static ref LISTENER_CONFIG: RwLock<ListenerConfig> = RwLock::new(ListenerConfig::default());
 }
 
 impl Default for SysState {
@@ -136,14 +222,27 @@ impl SysState {
     /// Starts the system listener in uninitialized state.
     /// This function should only be called once.
     /// To initialize the system, send a [`request`] to the system listener
-    /// to start any of the modes.
+    /// to start any of the modes. If `restore_modes` is set, the modes saved by the
+    /// previous run are started again, unless disabled with `ADBORC_RESTORE_MODES`.
     #[inline(always)]
     #[tokio::main]
-    pub async fn start_system() -> io::Result<()> {
+    pub async fn start_system(config: ListenerConfig, restore_modes: bool) -> io::Result<()> {
+        SystemKeypair::load_or_generate()?;
         let mut listener = CommandServer {
-            host: SysStateDefaultConfig::BIND_HOST.to_string(),
-            port: SysStateDefaultConfig::BIND_PORT,
+            host: config.host.clone(),
+            port: config.port,
         };
+        let port = config.port;
+        *LISTENER_CONFIG.write().unwrap() = config;
+        if restore_modes {
+            match SavedModes::load() {
+                Ok(Some(modes)) => {
+                    thread::spawn(move || System::restore_modes(modes, port));
+                }
+                Ok(None) => {}
+                Err(e) => warn!("Unable to load the modes of the previous run: {}", e),
+            }
+        }
         listener.start(System::process_command).await
     }
 
@@ -238,15 +337,21 @@ impl SysState {
     #[inline(always)]
     #[tokio::main]
     async fn stop_system() {
+        let config = Self::get_listener_config();
         let listener = CommandServer {
-            host: SysStateDefaultConfig::BIND_HOST.to_string(),
-            port: SysStateDefaultConfig::BIND_PORT,
+            host: config.host,
+            port: config.port,
         };
         listener.stop().await;
     }
 
     // Read functions...
 
+    #[inline(always)]
+    fn get_listener_config() -> ListenerConfig {
+        LISTENER_CONFIG.read().unwrap().clone()
+    }
+
     #[inline(always)]
     fn get_min_state() -> SysStateMin {
         let state = SYS_STATE.read().unwrap();
@@ -285,12 +390,13 @@ impl SysState {
 
 impl System {
     /// Initializes the system state in a MarketMaker mode.
-    pub fn start_market_maker() -> io::Result<()> {
+    pub fn start_market_maker() -> Result<(), Error> {
         if !SysState::is_initialized() {
             match MarketMaker::new() {
                 Ok(mm) => {
                     SysState::set_market_maker(mm);
                     SysState::set_initialized();
+                    SavedModes::update(|modes| modes.market_maker = true);
                     info!("Market maker started");
                 }
                 Err(e) => {
@@ -300,20 +406,50 @@ impl System {
             }
         } else {
             error!("System is already initialized");
-            return Err(Error::new(
-                ErrorKind::Other,
-                "System is already initialized",
+            return Err(Error::InvalidState(
+                "System is already initialized".to_string(),
             ));
         }
         Ok(())
     }
 
+    /// Initializes the system state in a MarketMaker mode, as a standby of the
+    /// primary MarketMaker at `primary_host:primary_port`.
+    pub fn start_standby_market_maker(primary_host: &str, primary_port: u16) -> Result<(), Error> {
+        if SysState::is_initialized() {
+            error!("System is already initialized");
+            return Err(Error::InvalidState(
+                "System is already initialized".to_string(),
+            ));
+        }
+        match MarketMaker::new_standby(primary_host, primary_port) {
+            Ok(mm) => {
+                SysState::set_market_maker(mm);
+                SysState::set_initialized();
+                SavedModes::update(|modes| {
+                    modes.market_maker = true;
+                    modes.standby_of = Some((primary_host.to_string(), primary_port));
+                });
+                info!("Standby market maker started");
+                Ok(())
+            }
+            Err(e) => {
+                error!("Error starting standby market maker: {}", e);
+                Err(e)
+            }
+        }
+    }
+
     /// Stops the MarketMaker, if currently running.
     /// Returns true if the MarketMaker was stopped, false otherwise.
     pub fn stop_market_maker() -> bool {
         if SysState::market_maker_is_some() {
             MarketMaker::terminate();
             SysState::reset_market_maker();
+            SavedModes::update(|modes| {
+                modes.market_maker = false;
+                modes.standby_of = None;
+            });
             info!("Market maker stopped");
             if !SysState::update_initialized() {
                 info!("System is uninitialized");
@@ -326,20 +462,56 @@ impl System {
     }
 
     /// Initializes the system state in a Supplier mode.
+    /// `fallbacks` are the addresses (`host` or `host:port`) of the MarketMakers
+    /// to connect to, in order, if the MarketMaker at `mm_host:mm_port` is unreachable.
     pub fn start_supplier_and_connect(
         mm_host: &str,
         mm_port: u16,
+        fallbacks: Vec<String>,
+        name: Option<String>,
+        secure_comms: bool,
+    ) -> Result<(), Error> {
+        System::start_supplier(mm_host, mm_port, fallbacks, name, secure_comms, false)
+    }
+
+    /// Start the Supplier mode. If `resume` is set, the Supplier resumes the
+    /// session of the previous run of the system with the MarketMaker.
+    fn start_supplier(
+        mm_host: &str,
+        mm_port: u16,
+        fallbacks: Vec<String>,
         name: Option<String>,
         secure_comms: bool,
-    ) -> io::Result<()> {
+        resume: bool,
+    ) -> Result<(), Error> {
         if SysState::supplier_is_some() {
             error!("Error starting supplier: Supplier is already running");
-            return Err(Error::new(ErrorKind::Other, "Supplier is already running"));
+            return Err(Error::InvalidState(
+                "Supplier is already running".to_string(),
+            ));
         }
-        match Supplier::new(mm_host.to_string(), mm_port, name, secure_comms) {
+        let fallback_addrs = System::parse_fallbacks(&fallbacks)?;
+        match Supplier::new(
+            mm_host.to_string(),
+            mm_port,
+            fallback_addrs,
+            name.clone(),
+            secure_comms,
+            resume,
+        ) {
             Ok(supplier) => {
                 SysState::set_supplier(supplier);
                 SysState::set_initialized();
+                SavedModes::update(|modes| {
+                    modes.supplier = Some(SavedSupplier {
+                        mm_host: mm_host.to_string(),
+                        mm_port,
+                        fallbacks,
+                        name,
+                        secure_comms,
+                        ..SavedSupplier::default()
+                    })
+                });
                 info!("Supplier started");
             }
             Err(e) => {
@@ -358,6 +530,7 @@ impl System {
                 Supplier::market_maker_terminate();
             } else {
                 Supplier::terminate();
+                SavedModes::update(|modes| modes.supplier = None);
             }
             SysState::reset_supplier();
             info!("Supplier stopped");
@@ -372,19 +545,52 @@ impl System {
     }
 
     /// Initializes the system state in a Consumer mode.
+    /// `fallbacks` are the addresses (`host` or `host:port`) of the MarketMakers
+    /// to connect to, in order, if the MarketMaker at `mm_host:mm_port` is unreachable.
     pub fn start_consumer_and_connect(
         mm_host: &str,
         mm_port: u16,
+        fallbacks: Vec<String>,
+        name: Option<String>,
+    ) -> Result<(), Error> {
+        System::start_consumer(mm_host, mm_port, fallbacks, name, false)
+    }
+
+    /// Start the Consumer mode. If `resume` is set, the Consumer resumes the
+    /// session of the previous run of the system with the MarketMaker.
+    fn start_consumer(
+        mm_host: &str,
+        mm_port: u16,
+        fallbacks: Vec<String>,
         name: Option<String>,
-    ) -> io::Result<()> {
+        resume: bool,
+    ) -> Result<(), Error> {
         if SysState::consumer_is_some() {
             error!("Error starting consumer: Consumer is already running");
-            return Err(Error::new(ErrorKind::Other, "Consumer is already running"));
+            return Err(Error::InvalidState(
+                "Consumer is already running".to_string(),
+            ));
         }
-        match Consumer::new(mm_host.to_string(), mm_port, name) {
+        let fallback_addrs = System::parse_fallbacks(&fallbacks)?;
+        match Consumer::new(
+            mm_host.to_string(),
+            mm_port,
+            fallback_addrs,
+            name.clone(),
+            resume,
+        ) {
             Ok(consumer) => {
                 SysState::set_consumer(consumer);
                 SysState::set_initialized();
+                SavedModes::update(|modes| {
+                    modes.consumer = Some(SavedConsumer {
+                        mm_host: mm_host.to_string(),
+                        mm_port,
+                        fallbacks,
+                        name,
+                        ..SavedConsumer::default()
+                    })
+                });
                 info!("Consumer started");
             }
             Err(e) => {
@@ -403,6 +609,7 @@ impl System {
                 Consumer::market_maker_terminate();
             } else {
                 Consumer::terminate();
+                SavedModes::update(|modes| modes.consumer = None);
             }
             SysState::reset_consumer();
             info!("Consumer stopped");
@@ -416,6 +623,133 @@ impl System {
         }
     }
 
+    /// Parse the addresses of fallback MarketMakers. The default port is used
+    /// for addresses without a port.
+    fn parse_fallbacks(fallbacks: &[String]) -> io::Result<Vec<(String, u16)>> {
+        fallbacks
+            .iter()
+            .map(|addr| net::split_host_port(addr, SysStateDefaultConfig::BIND_PORT))
+            .collect()
+    }
+
+    /// Restore the modes saved by the previous run of the system listener on `port`.
+    /// Modes that fail to start are logged and removed from the saved modes.
+    fn restore_modes(modes: SavedModes, port: u16) {
+        // Wait for the system listener to start, as the MarketMaker connects
+        // back to the Suppliers and Consumers joining the network.
+        let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port);
+        let mut attempts = 0;
+        while std::net::TcpStream::connect(addr).is_err() {
+            attempts += 1;
+            if attempts > 50 {
+                error!("System listener did not start. Modes not restored");
+                return;
+            }
+            thread::sleep(Duration::from_millis(200));
+        }
+
+        if let Some((primary_host, primary_port)) = modes.standby_of {
+            info!("Restoring standby MarketMaker mode");
+            if System::start_standby_market_maker(&primary_host, primary_port).is_err() {
+                SavedModes::update(|modes| {
+                    modes.market_maker = false;
+                    modes.standby_of = None;
+                });
+            }
+        } else if modes.market_maker {
+            info!("Restoring MarketMaker mode");
+            if System::start_market_maker().is_err() {
+                SavedModes::update(|modes| modes.market_maker = false);
+            }
+        }
+        if let Some(supplier) = modes.supplier {
+            info!("Restoring Supplier mode");
+            if let Err(e) = System::restore_supplier(supplier) {
+                error!("Unable to restore Supplier mode: {}", e);
+                SavedModes::update(|modes| modes.supplier = None);
+            }
+        }
+        if let Some(consumer) = modes.consumer {
+            info!("Restoring Consumer mode");
+            if let Err(e) = System::restore_consumer(consumer) {
+                error!("Unable to restore Consumer mode: {}", e);
+                SavedModes::update(|modes| modes.consumer = None);
+            }
+        }
+    }
+
+    /// Reconnect to the MarketMaker as a Supplier, and supply the same devices again.
+    /// The MarketMaker may still consider the Supplier of the previous run connected,
+    /// so the Supplier resumes that session, and its devices keep their reservations.
+    fn restore_supplier(supplier: SavedSupplier) -> Result<(), Error> {
+        System::start_supplier(
+            &supplier.mm_host,
+            supplier.mm_port,
+            supplier.fallbacks,
+            supplier.name,
+            supplier.secure_comms,
+            true,
+        )?;
+        if !supplier.android_serials.is_empty() {
+            let devices = supplier.android_serials.into_iter().collect();
+            // The MarketMaker keeps the pools of the devices.
+            match Supplier::supply_devices(Some(devices), HashSet::new()) {
+                Ok((_, failed_devices)) => {
+                    for device in failed_devices {
+                        warn!(
+                            "Device {} could not be supplied again",
+                            device.android_serial
+                        );
+                    }
+                }
+                Err(e) => warn!("Devices could not be supplied again: {}", e),
+            }
+        }
+        if supplier.auto_supply {
+            SupplierState::set_auto_supply(true);
+        }
+        Ok(())
+    }
+
+    /// Reconnect to the MarketMaker as a Consumer, and reserve the same devices again.
+    /// The MarketMaker may still consider the Consumer of the previous run connected,
+    /// so the Consumer resumes that session, and takes its reservations over.
+    /// Devices whose lease has expired are not reserved again.
+    fn restore_consumer(consumer: SavedConsumer) -> Result<(), Error> {
+        System::start_consumer(
+            &consumer.mm_host,
+            consumer.mm_port,
+            consumer.fallbacks,
+            consumer.name,
+            true,
+        )?;
+        let now = util::unix_time_now();
+        for (device_id, lease_expiry) in consumer.devices {
+            let lease_secs = match lease_expiry {
+                Some(expiry) if expiry <= now => {
+                    info!(
+                        "Lease of device {} has expired. Not reserved again",
+                        device_id
+                    );
+                    continue;
+                }
+                Some(expiry) => Some(expiry - now),
+                None => None,
+            };
+            let no_use = consumer.using_device.as_ref() != Some(&device_id);
+            match Consumer::resume_device(device_id.clone(), no_use, lease_secs) {
+                ConsumerResponse::DeviceReserved { .. } => {
+                    info!("Device {} reserved again", device_id);
+                }
+                response => warn!(
+                    "Device {} could not be reserved again: {}",
+                    device_id, response
+                ),
+            }
+        }
+        Ok(())
+    }
+
     fn server_shutdown() {
         info!("Shutting down system state server");
         System::stop_market_maker();
@@ -423,6 +757,30 @@ impl System {
         System::stop_consumer(false);
     }
 
+    /// Change the network key of the system using `change_key`.
+    /// The key is used to identify the system in the network, so it can only
+    /// be changed while no mode is active.
+    fn change_network_key<F>(change_key: F) -> String
+    where
+        F: FnOnce() -> io::Result<Vec<u8>>,
+    {
+        if SysState::is_initialized() {
+            return SysStateResponse::NetworkKeyChangeFailure {
+                reason: Error::InvalidState(
+                    "Stop all active modes before changing the network key".to_string(),
+                ),
+            }
+            .to_json();
+        }
+        match change_key() {
+            Ok(public_key) => SysStateResponse::NetworkKeyChanged {
+                peer_id: base64::encode(public_key),
+            }
+            .to_json(),
+            Err(e) => SysStateResponse::NetworkKeyChangeFailure { reason: e.into() }.to_json(),
+        }
+    }
+
     fn check_system() -> (SupplierCheck, ConsumerCheck) {
         let adb_version = adb_utils::get_adb_version().unwrap_or_else(|e| {
             error!("Error getting adb version: {}", e);
@@ -501,10 +859,10 @@ impl System {
                     SysStateResponse::ClientOk.to_json()
                 } else {
                     SysStateResponse::ClientError {
-                        reason: format!(
+                        reason: Error::InvalidInput(format!(
                             "Client version {} is not supported by listener version: {}",
                             version, ADBORC_VERSION
-                        ),
+                        )),
                     }
                     .to_json()
                 }
@@ -523,6 +881,18 @@ impl System {
                 }
                 .to_json()
             }
+            SysStateRequest::RotateNetworkKey => System::change_network_key(SystemKeypair::rotate),
+            SysStateRequest::ImportNetworkKey { private_key } => {
+                match base64::decode(private_key.trim()) {
+                    Ok(private_key) => {
+                        System::change_network_key(|| SystemKeypair::import(&private_key))
+                    }
+                    Err(_) => SysStateResponse::NetworkKeyChangeFailure {
+                        reason: Error::InvalidInput("Private key is not valid base64".to_string()),
+                    }
+                    .to_json(),
+                }
+            }
             SysStateRequest::SystemCheck => {
                 let (supplier_check, consumer_check) = System::check_system();
                 SysStateResponse::SystemCheck {
@@ -536,7 +906,7 @@ impl System {
                 let result = adb_utils::set_adb_path(adb_path);
                 if result.is_err() {
                     return SysStateResponse::SetAdbPathFailure {
-                        reason: result.err().unwrap().to_string(),
+                        reason: result.err().unwrap(),
                     }
                     .to_json();
                 }
@@ -547,7 +917,7 @@ impl System {
                 let result = adb_utils::set_scrcpy_path(scrcpy_path);
                 if result.is_err() {
                     return SysStateResponse::SetScrcpyPathFailure {
-                        reason: result.err().unwrap().to_string(),
+                        reason: result.err().unwrap(),
                     }
                     .to_json();
                 }
@@ -566,10 +936,14 @@ impl System {
             }
             SysStateRequest::StartMarketMaker => match System::start_market_maker() {
                 Ok(_) => SysStateResponse::StartMarketMakerSuccess.to_json(),
-                Err(e) => SysStateResponse::StartMarketMakerFailed {
-                    reason: e.to_string(),
-                }
-                .to_json(),
+                Err(e) => SysStateResponse::StartMarketMakerFailed { reason: e }.to_json(),
+            },
+            SysStateRequest::StartStandbyMarketMaker {
+                primary_host,
+                primary_port,
+            } => match System::start_standby_market_maker(&primary_host, primary_port) {
+                Ok(_) => SysStateResponse::StartMarketMakerSuccess.to_json(),
+                Err(e) => SysStateResponse::StartMarketMakerFailed { reason: e }.to_json(),
             },
             SysStateRequest::StopMarketMaker => {
                 if System::stop_market_maker() {
@@ -583,12 +957,16 @@ impl System {
                 mm_port,
                 name,
                 secure_comms,
-            } => match System::start_supplier_and_connect(&mm_host, mm_port, name, secure_comms) {
+                fallbacks,
+            } => match System::start_supplier_and_connect(
+                &mm_host,
+                mm_port,
+                fallbacks,
+                name,
+                secure_comms,
+            ) {
                 Ok(_) => SysStateResponse::StartSupplierSuccess.to_json(),
-                Err(e) => SysStateResponse::StartSupplierFailed {
-                    reason: e.to_string(),
-                }
-                .to_json(),
+                Err(e) => SysStateResponse::StartSupplierFailed { reason: e }.to_json(),
             },
             SysStateRequest::StopSupplier => {
                 if System::stop_supplier(false) {
@@ -601,12 +979,10 @@ impl System {
                 mm_host,
                 mm_port,
                 name,
-            } => match System::start_consumer_and_connect(&mm_host, mm_port, name) {
+                fallbacks,
+            } => match System::start_consumer_and_connect(&mm_host, mm_port, fallbacks, name) {
                 Ok(_) => SysStateResponse::StartConsumerSuccess.to_json(),
-                Err(e) => SysStateResponse::StartConsumerFailed {
-                    reason: e.to_string(),
-                }
-                .to_json(),
+                Err(e) => SysStateResponse::StartConsumerFailed { reason: e }.to_json(),
             },
             SysStateRequest::StopConsumer => {
                 if System::stop_consumer(false) {
@@ -615,13 +991,54 @@ impl System {
                     SysStateResponse::StopConsumerFailed.to_json()
                 }
             }
+            SysStateRequest::SendToMarketMaker {
+                host,
+                port,
+                request,
+            } => {
+                // The response of the MarketMaker is passed on as is.
+                match TCPClient::new(&host, port)
+                    .and_then(|client| client.send_request(*request, None))
+                {
+                    Ok(response) => response,
+                    Err(e) => SysStateResponse::RequestProcessingError {
+                        reason: Error::Unreachable(format!(
+                            "Could not connect to MarketMaker: {}",
+                            e
+                        )),
+                    }
+                    .to_json(),
+                }
+            }
             _ => SysStateResponse::RequestNotAllowed.to_json(),
         }
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub(crate) struct MarketMakerSpec;
+/// Information related to a MarketMaker on the network.
+/// This information is exchanged between a primary MarketMaker and its standby replicas.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MarketMakerSpec {
+    /// The IP address of the MarketMaker on the network.
+    pub bind_host: String,
+    /// The port of the MarketMaker on the network.
+    pub bind_port: u16,
+    /// The public key of the MarketMaker.
+    pub pub_key: String,
+    /// The version of adborc running on the MarketMaker.
+    pub adborc_version: String,
+}
+
+impl Default for MarketMakerSpec {
+    fn default() -> MarketMakerSpec {
+        MarketMakerSpec {
+            bind_host: String::new(),
+            bind_port: SysState::get_listener_config().port,
+            pub_key: SystemKeypair::get_public_key().map_or(String::new(), base64::encode),
+            adborc_version: ADBORC_VERSION.to_string(),
+        }
+    }
+}
 
 /// Information related to a Supplier on the network.
 /// This information is exchanged with the MarketMaker.
@@ -650,7 +1067,7 @@ impl Default for SupplierSpec {
         SupplierSpec {
             name: String::new(),
             bind_host: String::new(),
-            bind_port: SysStateDefaultConfig::BIND_PORT,
+            bind_port: SysState::get_listener_config().port,
             devices: Vec::new(),
             ver_info: SupplierCheck::default(),
             pub_key: SystemKeypair::get_public_key().map_or(String::new(), base64::encode),
@@ -698,7 +1115,7 @@ impl Default for ConsumerSpec {
         ConsumerSpec {
             name: String::new(),
             bind_host: String::new(),
-            bind_port: SysStateDefaultConfig::BIND_PORT,
+            bind_port: SysState::get_listener_config().port,
             ver_info: ConsumerCheck::default(),
             pub_key: SystemKeypair::get_public_key().map_or(String::new(), base64::encode),
             adborc_version: ADBORC_VERSION.to_string(),
@@ -726,9 +1143,8 @@ pub struct DeviceSpec {
     pub device_id: String,
     /// Android Serial of the device.
     pub android_serial: String,
-    /// Details of the device.
-    /// Contains device name, brand and model.
-    pub device_details: String,
+    /// Details of the device, as reported by the device properties.
+    pub device_info: DeviceInfo,
     /// Network ID of the Supplier for the device.
     available_at: String,
     /// Name of the Supplier for the device.
@@ -747,6 +1163,29 @@ pub struct DeviceSpec {
     pub used_by_port: u16,
     /// Whether the device uses secure tunnels for communication.
     pub secure_comms: bool,
+    /// Time (seconds since UNIX epoch) at which the reservation of the device expires.
+    /// `None` if the device is not reserved, or reserved without a lease duration.
+    pub lease_expiry: Option<u64>,
+    /// Health of the device, as last reported by the Supplier.
+    pub health: Option<DeviceHealth>,
+    /// Pools the device is assigned to. A device that is not assigned to
+    /// any pool is accessible to all Consumers.
+    #[serde(default)]
+    pub pools: HashSet<String>,
+}
+
+impl DeviceSpec {
+    /// Get the remaining lease time of the device in seconds, if the device
+    /// is reserved with a lease duration.
+    pub fn lease_remaining(&self) -> Option<u64> {
+        self.lease_expiry
+            .map(|expiry| expiry.saturating_sub(util::unix_time_now()))
+    }
+
+    /// Check whether a Consumer with access to the given pools can access the device.
+    pub(crate) fn is_accessible_with(&self, pools: &HashSet<String>) -> bool {
+        self.pools.is_empty() || !self.pools.is_disjoint(pools)
+    }
 }
 
 impl Display for DeviceSpec {
@@ -754,14 +1193,23 @@ impl Display for DeviceSpec {
         write!(
             f,
             r"device_id: {}   android_serial: {}   {}",
-            self.device_id, self.android_serial, self.device_details
-        )
+            self.device_id, self.android_serial, self.device_info
+        )?;
+        if !self.pools.is_empty() {
+            let mut pools = self.pools.iter().map(String::as_str).collect::<Vec<&str>>();
+            pools.sort_unstable();
+            write!(f, "   pools: {}", pools.join(","))?;
+        }
+        if let Some(health) = &self.health {
+            write!(f, "\n    {}", health)?;
+        }
+        Ok(())
     }
 }
 
 /// Filters available to apply while searching for devices.
 /// Actual filter used is a vector of this enum. See [`DeviceFilterVec`] for more details.
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub enum DeviceFilter {
     // Filter only available devices (or only reserved devices).
     IsAvailable(bool),
@@ -783,6 +1231,20 @@ pub enum DeviceFilter {
     ConsumerNames(HashSet<String>),
     // Filter devices by consumer host.
     ConsumerHosts(HashSet<String>),
+    // Android release version(s) to filter, e.g. `13`.
+    AndroidVersions(HashSet<String>),
+    // Filter devices with SDK level within the range (both inclusive).
+    // Devices with unknown SDK level do not match.
+    SdkRange { min: Option<u32>, max: Option<u32> },
+    // Filter devices supporting any of the ABIs, e.g. `arm64-v8a`.
+    Abis(HashSet<String>),
+    // Device brands to filter.
+    Brands(HashSet<String>),
+    // Filter devices with at least the given battery level (in percent).
+    // Devices with unknown battery level do not match.
+    MinBatteryLevel(u32),
+    // Filter devices assigned to any of the pools.
+    Pools(HashSet<String>),
 }
 
 impl Display for DeviceFilter {
@@ -812,6 +1274,19 @@ impl Display for DeviceFilter {
             Self::ConsumerHosts(consumer_hosts) => {
                 write!(f, "consumer_hosts: {:?}", consumer_hosts)
             }
+            Self::AndroidVersions(android_versions) => {
+                write!(f, "android_versions: {:?}", android_versions)
+            }
+            Self::SdkRange { min, max } => match (min, max) {
+                (Some(min), Some(max)) => write!(f, "sdk_level: {}..={}", min, max),
+                (Some(min), None) => write!(f, "sdk_level: >={}", min),
+                (None, Some(max)) => write!(f, "sdk_level: <={}", max),
+                (None, None) => write!(f, "sdk_level: any"),
+            },
+            Self::Abis(abis) => write!(f, "abis: {:?}", abis),
+            Self::Brands(brands) => write!(f, "brands: {:?}", brands),
+            Self::MinBatteryLevel(level) => write!(f, "battery_level: >={}%", level),
+            Self::Pools(pools) => write!(f, "pools: {:?}", pools),
         }
     }
 }
@@ -821,14 +1296,8 @@ impl DeviceFilter {
         match self {
             Self::IsAvailable(is_available) => *is_available == device.used_by.is_empty(),
             Self::DeviceIds(device_ids) => device_ids.contains(&device.device_id),
-            Self::DeviceNames(device_names) => {
-                let device_name = DeviceInfo::from(device.device_details.clone()).name;
-                device_names.contains(&device_name)
-            }
-            Self::DeviceModels(device_models) => {
-                let device_model = DeviceInfo::from(device.device_details.clone()).model;
-                device_models.contains(&device_model)
-            }
+            Self::DeviceNames(device_names) => device_names.contains(&device.device_info.name),
+            Self::DeviceModels(device_models) => device_models.contains(&device.device_info.model),
             Self::SupplierIds(supplier_ids) => supplier_ids.contains(&device.available_at),
             Self::SupplierNames(supplier_names) => {
                 supplier_names.contains(&device.available_at_name)
@@ -839,6 +1308,27 @@ impl DeviceFilter {
             Self::ConsumerIds(consumer_ids) => consumer_ids.contains(&device.used_by),
             Self::ConsumerNames(consumer_names) => consumer_names.contains(&device.used_by_name),
             Self::ConsumerHosts(consumer_hosts) => consumer_hosts.contains(&device.used_by_host),
+            Self::AndroidVersions(android_versions) => {
+                android_versions.contains(&device.device_info.android_release)
+            }
+            Self::SdkRange { min, max } => match device.device_info.sdk_level {
+                Some(sdk_level) => {
+                    min.iter().all(|min| sdk_level >= *min)
+                        && max.iter().all(|max| sdk_level <= *max)
+                }
+                None => false,
+            },
+            Self::Abis(abis) => device
+                .device_info
+                .abi_list
+                .iter()
+                .any(|abi| abis.contains(abi)),
+            Self::Brands(brands) => brands.contains(&device.device_info.brand),
+            Self::MinBatteryLevel(min_level) => matches!(
+                device.health.as_ref().and_then(|health| health.battery_level),
+                Some(level) if level >= *min_level
+            ),
+            Self::Pools(pools) => !device.pools.is_disjoint(pools),
         }
     }
 }
@@ -846,9 +1336,29 @@ impl DeviceFilter {
 /// A filter composed of one or more [`DeviceFilter`] used to
 /// filter devices on the network. Useful for searching for
 /// devices on the network that satisfy certain properties.
-#[derive(Serialize, Deserialize, Debug, Default)]
+/// All the `filters` must match. If a `query` is specified, it must match too.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+#[non_exhaustive]
 pub struct DeviceFilterVec {
     pub filters: Vec<DeviceFilter>,
+    /// Filter expression, for filters that are not a plain AND of [`DeviceFilter`]s.
+    #[serde(default)]
+    pub query: Option<DeviceFilterExpr>,
+}
+
+impl DeviceFilterVec {
+    /// Create a filter from the `filters`, and an optional `query`.
+    pub fn new(filters: Vec<DeviceFilter>, query: Option<DeviceFilterExpr>) -> Self {
+        DeviceFilterVec { filters, query }
+    }
+
+    /// Check if the device satisfies all the filters and the query.
+    fn matches(&self, device: &DeviceSpec) -> bool {
+        self.filters
+            .iter()
+            .all(|device_filter| device_filter.filter(device))
+            && self.query.iter().all(|query| query.matches(device))
+    }
 }
 
 impl Display for DeviceFilterVec {
@@ -875,6 +1385,14 @@ impl Display for DeviceFilterVec {
             }
         }
 
+        if let Some(query) = &self.query {
+            if filter_str.is_empty() {
+                writeln!(filter_str, "   matching {}", query).unwrap()
+            } else {
+                writeln!(filter_str, "   and matching {}", query).unwrap()
+            }
+        }
+
         write!(
             f,
             "Fetch {}devices in the network\n{}",
@@ -883,6 +1401,258 @@ impl Display for DeviceFilterVec {
     }
 }
 
+/// Device(s) a Consumer waits for in the reservation queue of the MarketMaker.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum WaitTarget {
+    /// Wait for the device with the given device ID.
+    Device(String),
+    /// Wait for any device that satisfies the filter.
+    AnyMatching(DeviceFilterVec),
+}
+
+impl WaitTarget {
+    fn matches(&self, device: &DeviceSpec) -> bool {
+        match self {
+            Self::Device(device_id) => *device_id == device.device_id,
+            Self::AnyMatching(filter_vec) => filter_vec.matches(device),
+        }
+    }
+}
+
+impl Display for WaitTarget {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Device(device_id) => write!(f, "device {}", device_id),
+            Self::AnyMatching(filter_vec) if filter_vec.filters.is_empty() => {
+                write!(f, "any device")
+            }
+            Self::AnyMatching(filter_vec) => {
+                let filters = filter_vec
+                    .filters
+                    .iter()
+                    .map(|filter| filter.to_string())
+                    .collect::<Vec<String>>();
+                write!(f, "any device with {}", filters.join(" and "))
+            }
+        }
+    }
+}
+
+/// Role of a network_id on the MarketMaker.
+/// Roles are granted in addition to the Supplier and Consumer whitelists.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// May administer the MarketMaker remotely, e.g. get its status,
+    /// manage whitelists and roles, and force-release devices.
+    Admin,
+    /// May connect as a Supplier, even if not in the Supplier whitelist.
+    Supplier,
+    /// May connect as a Consumer, even if not in the Consumer whitelist.
+    Consumer,
+    /// May get the status of the MarketMaker.
+    Observer,
+}
+
+impl Display for Role {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Admin => write!(f, "admin"),
+            Self::Supplier => write!(f, "supplier"),
+            Self::Consumer => write!(f, "consumer"),
+            Self::Observer => write!(f, "observer"),
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "admin" => Ok(Self::Admin),
+            "supplier" => Ok(Self::Supplier),
+            "consumer" => Ok(Self::Consumer),
+            "observer" => Ok(Self::Observer),
+            _ => Err(format!(
+                "Unknown role {}. Expected one of: admin, supplier, consumer, observer",
+                s
+            )),
+        }
+    }
+}
+
+/// Limits on the reservations of a Consumer, or of a group of Consumers.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct Quota {
+    /// Maximum number of devices reserved at the same time.
+    pub max_devices: Option<usize>,
+    /// Maximum lease duration of a reservation, in seconds.
+    /// If set, reservations without a lease are rejected.
+    pub max_lease_secs: Option<u64>,
+}
+
+impl Display for Quota {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let max_devices = self
+            .max_devices
+            .map_or("unlimited".to_string(), |max| max.to_string());
+        let max_lease = self
+            .max_lease_secs
+            .map_or("unlimited".to_string(), |secs| format!("{}s", secs));
+        write!(f, "max devices: {}, max lease: {}", max_devices, max_lease)
+    }
+}
+
+/// Consumer or group of Consumers a quota applies to.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum QuotaTarget {
+    /// The Consumer with the given network_id.
+    Consumer(String),
+    /// The group of Consumers with the given name. The limits apply
+    /// to the reservations of all members of the group together.
+    Group(String),
+}
+
+impl Display for QuotaTarget {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Consumer(key) => write!(f, "consumer {}", key),
+            Self::Group(name) => write!(f, "group {}", name),
+        }
+    }
+}
+
+/// Usage of a quota, reported in the MarketMaker status.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct QuotaUsage {
+    pub target: QuotaTarget,
+    pub quota: Quota,
+    /// Number of devices currently reserved.
+    pub reserved: usize,
+}
+
+impl Display for QuotaUsage {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let max_devices = self
+            .quota
+            .max_devices
+            .map_or("unlimited".to_string(), |max| max.to_string());
+        let max_lease = self
+            .quota
+            .max_lease_secs
+            .map_or("unlimited".to_string(), |secs| format!("{}s", secs));
+        write!(
+            f,
+            "{}: {}/{} devices, max lease: {}",
+            self.target, self.reserved, max_devices, max_lease
+        )
+    }
+}
+
+/// Topic of the events streamed by the MarketMaker to its subscribers.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum EventTopic {
+    /// Devices supplied, reserved, released and reclaimed.
+    Devices,
+    /// Suppliers joining and leaving the network.
+    Suppliers,
+    /// Consumers joining and leaving the network.
+    Consumers,
+    /// Suppliers and Consumers whose heartbeat was lost.
+    Heartbeats,
+}
+
+impl Display for EventTopic {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Devices => write!(f, "devices"),
+            Self::Suppliers => write!(f, "suppliers"),
+            Self::Consumers => write!(f, "consumers"),
+            Self::Heartbeats => write!(f, "heartbeats"),
+        }
+    }
+}
+
+impl FromStr for EventTopic {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "devices" => Ok(Self::Devices),
+            "suppliers" => Ok(Self::Suppliers),
+            "consumers" => Ok(Self::Consumers),
+            "heartbeats" => Ok(Self::Heartbeats),
+            _ => Err(format!(
+                "Unknown topic {}. Expected one of: devices, suppliers, consumers, heartbeats",
+                s
+            )),
+        }
+    }
+}
+
+/// Event streamed by the MarketMaker to its subscribers.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum MarketEvent {
+    DeviceSupplied { device: DeviceSpec },
+    DeviceReserved { device: DeviceSpec },
+    DeviceReleased { device_id: String },
+    DeviceReclaimed { device_id: String },
+    SupplierJoined { peer_id: String, name: String },
+    SupplierLeft { peer_id: String },
+    ConsumerJoined { peer_id: String, name: String },
+    ConsumerLeft { peer_id: String },
+    HeartbeatLost { peer_id: String },
+}
+
+impl MarketEvent {
+    /// Topic the event is published on.
+    pub fn topic(&self) -> EventTopic {
+        match self {
+            Self::DeviceSupplied { .. }
+            | Self::DeviceReserved { .. }
+            | Self::DeviceReleased { .. }
+            | Self::DeviceReclaimed { .. } => EventTopic::Devices,
+            Self::SupplierJoined { .. } | Self::SupplierLeft { .. } => EventTopic::Suppliers,
+            Self::ConsumerJoined { .. } | Self::ConsumerLeft { .. } => EventTopic::Consumers,
+            Self::HeartbeatLost { .. } => EventTopic::Heartbeats,
+        }
+    }
+}
+
+impl Display for MarketEvent {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::DeviceSupplied { device } => write!(f, "Device supplied: {}", device),
+            Self::DeviceReserved { device } => write!(
+                f,
+                "Device reserved: {} by {}",
+                device.device_id, device.used_by_name
+            ),
+            Self::DeviceReleased { device_id } => write!(f, "Device released: {}", device_id),
+            Self::DeviceReclaimed { device_id } => write!(f, "Device reclaimed: {}", device_id),
+            Self::SupplierJoined { peer_id, name } => {
+                write!(f, "Supplier joined: {} ({})", name, peer_id)
+            }
+            Self::SupplierLeft { peer_id } => write!(f, "Supplier left: {}", peer_id),
+            Self::ConsumerJoined { peer_id, name } => {
+                write!(f, "Consumer joined: {} ({})", name, peer_id)
+            }
+            Self::ConsumerLeft { peer_id } => write!(f, "Consumer left: {}", peer_id),
+            Self::HeartbeatLost { peer_id } => write!(f, "Heartbeat lost: {}", peer_id),
+        }
+    }
+}
+
+impl FromStr for MarketEvent {
+    type Err = serde_json::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(s)
+    }
+}
+
 /// Support level for `supplier` mode on the system node.
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub enum SupplierCheck {
@@ -1151,10 +1921,15 @@ mod tests {
     #[serial]
     fn init_and_stop_all_modes() {
         test_with_logs();
+        // Do not touch the key file and MarketMaker state in the user's data directory.
+        let data_dir = std::env::temp_dir().join("adborc_test_data");
+        // Start without the modes saved by a previous run of the test.
+        let _ = fs::remove_file(data_dir.join(crate::util::MODES_FILE));
+        std::env::set_var(crate::util::ADBORC_DATA_DIR, data_dir);
 
         assert!(!SysState::is_initialized());
 
-        thread::spawn(|| SysState::start_system().unwrap());
+        thread::spawn(|| SysState::start_system(ListenerConfig::default(), false).unwrap());
 
         let client = TCPClient::new("localhost", SysStateDefaultConfig::BIND_PORT).unwrap();
 
@@ -1182,12 +1957,18 @@ mod tests {
         System::start_supplier_and_connect(
             "localhost",
             SysStateDefaultConfig::BIND_PORT,
+            vec![],
             None,
             false,
         )
         .unwrap();
-        System::start_consumer_and_connect("localhost", SysStateDefaultConfig::BIND_PORT, None)
-            .unwrap();
+        System::start_consumer_and_connect(
+            "localhost",
+            SysStateDefaultConfig::BIND_PORT,
+            vec![],
+            None,
+        )
+        .unwrap();
 
         assert!(SysState::is_initialized());
         assert!(SysState::market_maker_is_some());
@@ -1201,4 +1982,144 @@ mod tests {
         assert!(!SysState::supplier_is_some());
         assert!(!SysState::consumer_is_some());
     }
+
+    #[test]
+    #[serial]
+    fn modes_saved_and_loaded() {
+        use crate::util::{ADBORC_RESTORE_MODES, MODES_FILE};
+        let data_dir = std::env::temp_dir().join("adborc_test_modes");
+        let _ = fs::remove_dir_all(&data_dir);
+        std::env::set_var(crate::util::ADBORC_DATA_DIR, &data_dir);
+        std::env::remove_var(ADBORC_RESTORE_MODES);
+
+        assert!(SavedModes::load().unwrap().is_none());
+
+        SavedModes::update(|modes| {
+            modes.market_maker = true;
+            modes.supplier = Some(SavedSupplier {
+                mm_host: "10.0.0.5".to_string(),
+                mm_port: SysStateDefaultConfig::BIND_PORT,
+                secure_comms: true,
+                ..SavedSupplier::default()
+            });
+        });
+        SavedModes::update_supplier(|supplier| {
+            supplier.android_serials.insert("emulator-5554".to_string());
+        });
+        // No Consumer mode is saved, so there is nothing to update.
+        SavedModes::update_consumer(|consumer| consumer.using_device = Some("1".to_string()));
+
+        let modes = SavedModes::load().unwrap().unwrap();
+        assert!(modes.market_maker);
+        assert!(modes.consumer.is_none());
+        let supplier = modes.supplier.unwrap();
+        assert_eq!(supplier.mm_host, "10.0.0.5");
+        assert!(supplier.secure_comms);
+        assert!(supplier.android_serials.contains("emulator-5554"));
+
+        // Stopped modes are not restored.
+        SavedModes::update(|modes| *modes = SavedModes::default());
+        assert_eq!(SavedModes::load().unwrap(), Some(SavedModes::default()));
+
+        // Restoring can be disabled.
+        assert!(data_dir.join(MODES_FILE).exists());
+        std::env::set_var(ADBORC_RESTORE_MODES, "off");
+        assert!(SavedModes::load().unwrap().is_none());
+
+        std::env::remove_var(ADBORC_RESTORE_MODES);
+        let _ = fs::remove_dir_all(&data_dir);
+    }
+
+    #[test]
+    #[serial]
+    fn network_key_persisted() {
+        test_with_logs();
+        let data_dir = std::env::temp_dir().join("adborc_test_network_key");
+        let _ = fs::remove_dir_all(&data_dir);
+        std::env::set_var(crate::util::ADBORC_DATA_DIR, &data_dir);
+
+        SystemKeypair::load_or_generate().unwrap();
+        assert!(data_dir.join(KEY_FILE).exists());
+        let public_key = SystemKeypair::get_public_key().unwrap();
+        let private_key = SystemKeypair::get_private_key().unwrap();
+
+        // Loading again must give back the same identity.
+        SystemKeypair::load_or_generate().unwrap();
+        assert_eq!(SystemKeypair::get_public_key().unwrap(), public_key);
+
+        let rotated_key = SystemKeypair::rotate().unwrap();
+        assert_ne!(rotated_key, public_key);
+        SystemKeypair::load_or_generate().unwrap();
+        assert_eq!(SystemKeypair::get_public_key().unwrap(), rotated_key);
+
+        assert_eq!(SystemKeypair::import(&private_key).unwrap(), public_key);
+        assert!(SystemKeypair::import(&[0u8; 4]).is_err());
+
+        let _ = fs::remove_dir_all(&data_dir);
+    }
+
+    #[test]
+    #[serial]
+    fn listener_config_loaded() {
+        use crate::util::config::NODE_CONFIG_FILE;
+        use crate::util::{ADBORC_LISTEN_HOST, ADBORC_LISTEN_PORT};
+        let data_dir = std::env::temp_dir().join("adborc_test_listener_config");
+        let _ = fs::remove_dir_all(&data_dir);
+        std::env::set_var(crate::util::ADBORC_DATA_DIR, &data_dir);
+        std::env::remove_var(ADBORC_LISTEN_HOST);
+        std::env::remove_var(ADBORC_LISTEN_PORT);
+
+        assert_eq!(ListenerConfig::load().unwrap(), ListenerConfig::default());
+
+        fs::create_dir_all(&data_dir).unwrap();
+        fs::write(data_dir.join(NODE_CONFIG_FILE), "listen_port = 17000\n").unwrap();
+        let config = ListenerConfig::load().unwrap();
+        assert_eq!(config.host, SysStateDefaultConfig::BIND_HOST);
+        assert_eq!(config.port, 17000);
+
+        // Environment variables override the config file.
+        std::env::set_var(ADBORC_LISTEN_HOST, "192.168.1.10");
+        std::env::set_var(ADBORC_LISTEN_PORT, "17001");
+        let config = ListenerConfig::load().unwrap();
+        assert_eq!(config.host, "192.168.1.10");
+        assert_eq!(config.port, 17001);
+
+        std::env::set_var(ADBORC_LISTEN_PORT, "not-a-port");
+        assert!(ListenerConfig::load().is_err());
+
+        std::env::remove_var(ADBORC_LISTEN_HOST);
+        std::env::remove_var(ADBORC_LISTEN_PORT);
+        let _ = fs::remove_dir_all(&data_dir);
+    }
+
+    #[test]
+    fn device_property_filters() {
+        let device = DeviceSpec {
+            device_info: DeviceInfo {
+                brand: "google".to_string(),
+                android_release: "13".to_string(),
+                sdk_level: Some(33),
+                abi_list: vec!["arm64-v8a".to_string(), "armeabi-v7a".to_string()],
+                ..DeviceInfo::default()
+            },
+            ..DeviceSpec::default()
+        };
+        let set = |values: &[&str]| values.iter().map(|v| v.to_string()).collect();
+
+        assert!(DeviceFilter::AndroidVersions(set(&["12", "13"])).filter(&device));
+        assert!(!DeviceFilter::AndroidVersions(set(&["14"])).filter(&device));
+        assert!(DeviceFilter::Abis(set(&["x86_64", "armeabi-v7a"])).filter(&device));
+        assert!(!DeviceFilter::Abis(set(&["x86_64"])).filter(&device));
+        assert!(DeviceFilter::Brands(set(&["google"])).filter(&device));
+        assert!(!DeviceFilter::Brands(set(&["samsung"])).filter(&device));
+
+        let sdk_range = |min, max| DeviceFilter::SdkRange { min, max };
+        assert!(sdk_range(Some(33), Some(33)).filter(&device));
+        assert!(sdk_range(Some(30), None).filter(&device));
+        assert!(sdk_range(None, Some(34)).filter(&device));
+        assert!(!sdk_range(Some(34), None).filter(&device));
+        assert!(!sdk_range(None, Some(32)).filter(&device));
+        // Devices with unknown SDK level never match a range.
+        assert!(!sdk_range(None, None).filter(&DeviceSpec::default()));
+    }
 }
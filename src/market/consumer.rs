@@ -8,6 +8,7 @@ use request::{ConsumerRequest, ConsumerResponse, MarketMakerRequest, MarketMaker
 use std::default::Default;
 use std::io::{BufRead, BufReader};
 use std::process::Child;
+use std::time::Instant;
 use std::{net::IpAddr, thread};
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -20,6 +21,8 @@ pub(super) struct ConsumerState {
     mm_port: u16,
     // SocketAddr of the market maker it is connected to.
     mm_addr: Option<SocketAddr>,
+    // host, port of the market makers to connect to, in order of preference.
+    market_makers: Vec<(String, u16)>,
     // HashMap of DeviceSpec, hashed by device id.
     devices: HashMap<String, DeviceSpec>,
     // HashMap of PortForwarders, hashed by device id.
@@ -161,6 +164,12 @@ impl ConsumerState {
         *state = ConsumerState::default();
     }
 
+    #[inline(always)]
+    fn set_market_makers(market_makers: Vec<(String, u16)>) {
+        let mut state = CONSUMER_STATE.lock().unwrap();
+        state.market_makers = market_makers;
+    }
+
     #[inline(always)]
     fn update_host_port_name(host: Option<String>, port: Option<u16>, name: Option<String>) {
         let mut state = CONSUMER_STATE.lock().unwrap();
@@ -184,13 +193,20 @@ impl ConsumerState {
     #[inline(always)]
     fn update_using_device(device_id: String) {
         let mut state = CONSUMER_STATE.lock().unwrap();
-        state.using_device = Some(device_id);
+        state.using_device = Some(device_id.clone());
+        drop(state);
+        SavedModes::update_consumer(|consumer| consumer.using_device = Some(device_id));
     }
 
     #[inline(always)]
     fn insert_device(device_id: String, device_spec: DeviceSpec) {
         let mut state = CONSUMER_STATE.lock().unwrap();
-        state.devices.insert(device_id, device_spec);
+        let lease_expiry = device_spec.lease_expiry;
+        state.devices.insert(device_id.clone(), device_spec);
+        drop(state);
+        SavedModes::update_consumer(|consumer| {
+            consumer.devices.insert(device_id, lease_expiry);
+        });
     }
 
     #[inline(always)]
@@ -203,10 +219,30 @@ impl ConsumerState {
     fn remove_device(device_id: &str) {
         let mut state = CONSUMER_STATE.lock().unwrap();
         state.devices.remove(device_id);
-        if state.using_device != Some(device_id.to_owned()) {
-            return;
+        if state.using_device.as_deref() == Some(device_id) {
+            state.using_device = None;
         }
-        state.using_device = None;
+        drop(state);
+        SavedModes::update_consumer(|consumer| {
+            consumer.devices.remove(device_id);
+            if consumer.using_device.as_deref() == Some(device_id) {
+                consumer.using_device = None;
+            }
+        });
+    }
+
+    #[inline(always)]
+    fn update_lease_expiry(device_id: &str, lease_expiry: Option<u64>) {
+        let mut state = CONSUMER_STATE.lock().unwrap();
+        let device = match state.devices.get_mut(device_id) {
+            Some(device) => device,
+            None => return,
+        };
+        device.lease_expiry = lease_expiry;
+        drop(state);
+        SavedModes::update_consumer(|consumer| {
+            consumer.devices.insert(device_id.to_string(), lease_expiry);
+        });
     }
 
     #[inline(always)]
@@ -214,6 +250,11 @@ impl ConsumerState {
         let mut state = CONSUMER_STATE.lock().unwrap();
         state.devices.clear();
         state.using_device = None;
+        drop(state);
+        SavedModes::update_consumer(|consumer| {
+            consumer.devices.clear();
+            consumer.using_device = None;
+        });
     }
 
     #[inline(always)]
@@ -262,6 +303,12 @@ impl ConsumerState {
 
     // Read functions...
 
+    #[inline(always)]
+    fn get_market_makers() -> Vec<(String, u16)> {
+        let state = CONSUMER_STATE.lock().unwrap();
+        state.market_makers.clone()
+    }
+
     #[inline(always)]
     fn is_device_reserved(device_id: &str) -> bool {
         let state = CONSUMER_STATE.lock().unwrap();
@@ -292,6 +339,12 @@ impl ConsumerState {
         state.devices.get(device_id).cloned()
     }
 
+    #[inline(always)]
+    fn get_devices() -> HashMap<String, DeviceSpec> {
+        let state = CONSUMER_STATE.lock().unwrap();
+        state.devices.clone()
+    }
+
     #[inline(always)]
     fn get_number_of_devices() -> usize {
         let state = CONSUMER_STATE.lock().unwrap();
@@ -377,7 +430,51 @@ lazy_static! {
 }
 
 impl Consumer {
-    pub(super) fn new(mm_host: String, mm_port: u16, name: Option<String>) -> io::Result<Consumer> {
+    /// The Market Makers in `fallbacks` are tried in order, if the Market Maker
+    /// at `mm_host:mm_port` cannot be connected to. If `resume` is set, the Consumer
+    /// resumes the session of its previous run, e.g. when its modes are restored
+    /// after a restart of the system.
+    pub(super) fn new(
+        mm_host: String,
+        mm_port: u16,
+        fallbacks: Vec<(String, u16)>,
+        name: Option<String>,
+        resume: bool,
+    ) -> Result<Consumer, Error> {
+        let name = name.unwrap_or_else(|| {
+            hostname::get()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string()
+        });
+        let mut market_makers = vec![(mm_host, mm_port)];
+        market_makers.extend(fallbacks);
+        Consumer::connect_any(&market_makers, &name, resume)?;
+        ConsumerState::set_market_makers(market_makers);
+        Consumer::start_heartbeat_thread();
+        let consumer = Consumer;
+        Ok(consumer)
+    }
+
+    /// Connect to the first Market Maker of `market_makers` that accepts the Consumer.
+    /// Returns the error of the first Market Maker, if none accepts the Consumer.
+    fn connect_any(market_makers: &[(String, u16)], name: &str, resume: bool) -> Result<(), Error> {
+        let mut first_err = None;
+        for (mm_host, mm_port) in market_makers {
+            match Consumer::connect(mm_host.clone(), *mm_port, name.to_string(), resume) {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    warn!("Unable to connect to Market Maker {}: {}", mm_host, e);
+                    first_err.get_or_insert(e);
+                }
+            }
+        }
+        Err(first_err
+            .unwrap_or_else(|| Error::InvalidInput("No Market Maker to connect to.".to_string())))
+    }
+
+    /// Connect to the Market Maker as a Consumer, and update the ConsumerState.
+    fn connect(mm_host: String, mm_port: u16, name: String, resume: bool) -> Result<(), Error> {
         debug!("Checking adb version...");
         let ver_info = ConsumerVerInfo::get();
         debug!("CONSUMER_VER_INFO: {:?}", ver_info);
@@ -387,29 +484,20 @@ impl Consumer {
             debug!("ADB version is compatible.");
         } else {
             error!("ADB version is not compatible.");
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                "ADB version is not compatible.",
-            ));
+            return Err(Error::Adb("ADB version is not compatible.".to_string()));
         }
 
-        let mut consumer_spec = ConsumerSpec {
+        let consumer_spec = ConsumerSpec {
+            name,
             ver_info,
             ..Default::default()
         };
-        if let Some(name) = name {
-            consumer_spec.name = name;
-        } else {
-            consumer_spec.name = hostname::get()
-                .unwrap_or_default()
-                .to_string_lossy()
-                .to_string();
-        }
 
         let client = TCPClient::new(mm_host.as_str(), mm_port)?;
 
         let connect_request = MarketMakerRequest::ConsumerConnect {
             consumer: consumer_spec,
+            resume,
         };
         let response = client.send_request(connect_request, None)?;
         let response = MarketMakerResponse::from_str(&response).unwrap();
@@ -427,55 +515,239 @@ impl Consumer {
             );
             ConsumerState::update_addr(client.addr);
             ConsumerState::set_market_maker_key(mm_pub_key);
-            Consumer::start_heartbeat_thread();
-            let consumer = Consumer;
-            Ok(consumer)
+            Ok(())
         } else if let MarketMakerResponse::ConsumerNotConnected { reason } = response {
             error!("Consumer not connected: {}", reason);
-            Err(io::Error::new(io::ErrorKind::Other, reason))
+            Err(reason)
         } else {
             error!("Unexpected response from Market Maker: {:?}", response);
-            Err(io::Error::new(
-                io::ErrorKind::Other,
-                format!("Unexpected response from Market Maker: {:?}", response),
-            ))
+            Err(Error::Protocol(format!(
+                "Unexpected response from Market Maker: {:?}",
+                response
+            )))
         }
     }
 
+    /// Send a heartbeat to the Market Maker.
+    fn send_heartbeat() -> Result<(), Error> {
+        let mm_addr = ConsumerState::get_addr()
+            .ok_or_else(|| Error::InvalidState("Market Maker address is not set.".to_string()))?;
+        let client = TCPClient::from(mm_addr);
+        let response = client.send_request(MarketMakerRequest::ConsumerHeartBeat, None)?;
+        let response = MarketMakerResponse::from_str(&response).unwrap();
+        if let MarketMakerResponse::HeartBeatResponse = response {
+            Ok(())
+        } else {
+            Err(Error::Protocol(format!(
+                "Unexpected response from Market Maker: {:?}",
+                response
+            )))
+        }
+    }
+
+    /// Send heartbeats to the Market Maker. If the Market Maker is unreachable,
+    /// try to reconnect with exponential backoff, until `RECONNECT_TIMEOUT` elapses.
+    /// Once reconnected, the reservations are reconciled with the Market Maker.
     fn start_heartbeat_thread() {
-        thread::spawn(|| loop {
-            thread::sleep(HEARTBEAT_INTERVAL);
-            // Stop the thread if consumer has terminated.
-            if !SysState::consumer_is_some() {
-                break;
-            }
-            let mm_addr = ConsumerState::get_addr();
-            if mm_addr.is_none() {
-                warn!("Market Maker address is not set. Skipping heartbeat.");
-                continue;
-            }
-            let mm_addr = mm_addr.unwrap();
-            let client = TCPClient::from(mm_addr);
-            let heartbeat_request = MarketMakerRequest::ConsumerHeartBeat;
-            let response = match client.send_request(heartbeat_request, None) {
-                Ok(response) => response,
-                Err(e) => {
-                    error!("Failed to send heartbeat to Market Maker: {}", e);
-                    System::stop_consumer(true);
+        thread::spawn(|| {
+            let mut backoff: Option<ReconnectBackoff> = None;
+            // Deadline for reconciling the reservations, after reconnecting.
+            let mut reconcile_until: Option<Instant> = None;
+            loop {
+                let delay = match backoff.as_mut() {
+                    None => HEARTBEAT_INTERVAL,
+                    Some(backoff) => match backoff.next_delay() {
+                        Some(delay) => delay,
+                        None => {
+                            error!("Could not reconnect to Market Maker. Stopping consumer.");
+                            System::stop_consumer(true);
+                            break;
+                        }
+                    },
+                };
+                thread::sleep(delay);
+                // Stop the thread if consumer has terminated.
+                if !SysState::consumer_is_some() {
                     break;
                 }
-            };
-            let response = MarketMakerResponse::from_str(&response).unwrap();
-            if let MarketMakerResponse::HeartBeatResponse = response {
-                debug!("Heartbeat sent successfully.");
-            } else {
-                error!("Unexpected response from Market Maker: {:?}", response);
-                System::stop_consumer(true);
-                break;
+                if backoff.is_some() {
+                    match Consumer::reconnect() {
+                        Ok(()) => {
+                            info!("Reconnected to Market Maker.");
+                            backoff = None;
+                            reconcile_until = Some(Instant::now() + RECONNECT_TIMEOUT);
+                        }
+                        Err(e) => {
+                            warn!("Failed to reconnect to Market Maker: {}", e);
+                            continue;
+                        }
+                    }
+                } else {
+                    match Consumer::send_heartbeat() {
+                        Ok(()) => debug!("Heartbeat sent successfully."),
+                        Err(e) => {
+                            error!("Failed to send heartbeat to Market Maker: {}", e);
+                            info!("Reconnecting to Market Maker...");
+                            backoff = Some(ReconnectBackoff::new());
+                            continue;
+                        }
+                    }
+                }
+                if let Some(deadline) = reconcile_until {
+                    let give_up = Instant::now() >= deadline;
+                    match Consumer::reconcile_reservations(give_up) {
+                        Ok(true) => reconcile_until = None,
+                        Ok(false) => debug!("Some reservations are not reconciled yet."),
+                        Err(e) => warn!("Failed to reconcile reservations: {}", e),
+                    }
+                }
             }
         });
     }
 
+    /// Resume the session with the Market Maker, after it was unreachable.
+    /// If the Market Maker no longer knows the Consumer, e.g. after a restart,
+    /// the Consumer connects again with the same identity.
+    fn reconnect() -> Result<(), Error> {
+        if Consumer::send_heartbeat().is_ok() {
+            return Ok(());
+        }
+        let state = ConsumerState::get_min_state();
+        // Prefer the Market Maker the Consumer was connected to, e.g. a standby
+        // that took over, before falling back to the others in order.
+        let current = (state.mm_host, state.mm_port);
+        let mut market_makers = ConsumerState::get_market_makers();
+        market_makers.retain(|market_maker| *market_maker != current);
+        market_makers.insert(0, current);
+        Consumer::connect_any(&market_makers, &state.name, false)
+    }
+
+    /// Reconcile the reserved devices with the Market Maker, after reconnecting.
+    /// Reservations that the Market Maker lost are taken again, and devices reserved
+    /// by another Consumer in the meantime are released.
+    /// Devices not known to the Market Maker, e.g. because their Supplier has not
+    /// reconnected yet, are released if `give_up` is set.
+    /// Returns true if all the reserved devices are reconciled.
+    fn reconcile_reservations(give_up: bool) -> Result<bool, Error> {
+        let devices = ConsumerState::get_devices();
+        if devices.is_empty() {
+            return Ok(true);
+        }
+        let mm_addr = ConsumerState::get_addr()
+            .ok_or_else(|| Error::InvalidState("Market Maker address is not set.".to_string()))?;
+        let client = TCPClient::from(mm_addr);
+        let request = MarketMakerRequest::GetDevicesByFilter {
+            filter_vec: DeviceFilterVec {
+                filters: vec![DeviceFilter::DeviceIds(devices.keys().cloned().collect())],
+                query: None,
+            },
+        };
+        let response = client.send_request(request, None)?;
+        let mm_devices = match MarketMakerResponse::from_str(&response).unwrap() {
+            MarketMakerResponse::DevicesByFilter { devices, .. } => devices,
+            response => {
+                return Err(Error::Protocol(format!(
+                    "Unexpected response from Market Maker: {:?}",
+                    response
+                )))
+            }
+        };
+        let consumer_id = SystemKeypair::get_public_key().map_or(String::new(), base64::encode);
+
+        let mut reconciled = true;
+        for (device_id, device) in devices {
+            match mm_devices.iter().find(|d| d.device_id == device_id) {
+                Some(mm_device) if mm_device.used_by == consumer_id => {
+                    ConsumerState::update_lease_expiry(&device_id, mm_device.lease_expiry);
+                }
+                Some(mm_device) if mm_device.used_by.is_empty() => {
+                    if let Err(e) = Consumer::retake_device(&client, device) {
+                        warn!("Could not reserve device {} again: {}", device_id, e);
+                        Consumer::drop_device(&device_id);
+                    }
+                }
+                Some(_) => {
+                    warn!(
+                        "Device {} is reserved by another consumer. Releasing it.",
+                        device_id
+                    );
+                    Consumer::drop_device(&device_id);
+                }
+                None if give_up => {
+                    warn!(
+                        "Device {} is no longer available on the network. Releasing it.",
+                        device_id
+                    );
+                    Consumer::drop_device(&device_id);
+                }
+                None => reconciled = false,
+            }
+        }
+        Ok(reconciled || give_up)
+    }
+
+    /// Reserve a device that the Market Maker lost the reservation of, for the
+    /// remaining lease. The port forwarder of the device is restarted at the same
+    /// port, as the device may now be available at another port of the Supplier.
+    fn retake_device(client: &TCPClient, device: DeviceSpec) -> Result<(), Error> {
+        let now = util::unix_time_now();
+        let lease_secs = match device.lease_expiry {
+            Some(expiry) => match expiry.checked_sub(now) {
+                Some(secs) if secs > 0 => Some(secs),
+                _ => return Err(Error::InvalidState("Lease expired.".to_string())),
+            },
+            None => None,
+        };
+        let request = MarketMakerRequest::ReserveDevice {
+            device_id: device.device_id.clone(),
+            lease_secs,
+        };
+        let response = client.send_request(request, None)?;
+        match MarketMakerResponse::from_str(&response).unwrap() {
+            MarketMakerResponse::DeviceReserved {
+                device: mut mm_device,
+                peer_id,
+            } => {
+                let available_at_host = mm_device.available_at_host.parse::<IpAddr>().unwrap();
+                if available_at_host.is_loopback() {
+                    mm_device.available_at_host = client.addr.ip().to_string();
+                }
+                let port = device.used_by_port;
+                ConsumerState::remove_port_forwarder(&device.device_id);
+                let forwarder = match Consumer::start_port_forwarder(port, &mm_device, peer_id) {
+                    Ok(forwarder) => forwarder,
+                    Err(e) => {
+                        // Inform the market maker to release the device.
+                        let request = MarketMakerRequest::ReleaseDevice {
+                            device_id: device.device_id,
+                        };
+                        client.send_no_wait(request);
+                        return Err(e);
+                    }
+                };
+                mm_device.used_by_port = port;
+                info!("Reserved device {} again.", mm_device.device_id);
+                ConsumerState::insert_device(device.device_id.clone(), mm_device);
+                ConsumerState::insert_port_forwarder(device.device_id, forwarder);
+                Ok(())
+            }
+            MarketMakerResponse::DeviceNotReserved { reason } => Err(reason),
+            response => Err(Error::Protocol(format!(
+                "Unexpected response from Market Maker: {:?}",
+                response
+            ))),
+        }
+    }
+
+    /// Release a reserved device locally, without informing the Market Maker.
+    fn drop_device(device_id: &str) {
+        if ConsumerState::is_device_reserved(device_id) {
+            ConsumerState::remove_device(device_id);
+            ConsumerState::remove_port_forwarder(device_id);
+            ScrCpyState::kill_process(device_id);
+        }
+    }
+
     /// Handle resetting of ConsumerState.
     pub(super) fn terminate() {
         ConsumerState::remove_all_port_forwarders();
@@ -501,14 +773,13 @@ impl Consumer {
         device: DeviceSpec,
         peer_id: Option<String>,
         no_use: bool,
-    ) -> io::Result<()> {
+    ) -> Result<(), Error> {
         // If currently not using a device, start a port forwarder for the device
         // listening on the default adb port (5037) to the remote port.
         // Otherwise, start a portforwarder at any available port.
         if ConsumerState::is_device_reserved(&device_id) {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                "Device already reserved.",
+            return Err(Error::DeviceUnavailable(
+                "Device already reserved.".to_string(),
             ));
         }
 
@@ -518,17 +789,129 @@ impl Consumer {
             let maybe_port = portpicker::pick_unused_port();
             if maybe_port.is_none() {
                 error!("Could not find an available port for port forwarding.");
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    "Could not find an available port for port forwarding.",
+                return Err(Error::Io(
+                    "Could not find an available port for port forwarding.".to_string(),
                 ));
             }
             maybe_port.unwrap()
         };
+        let forwarder = Consumer::start_port_forwarder(port, &device, peer_id)?;
+        if port == 5037 {
+            ConsumerState::update_using_device(device_id.clone());
+        }
+        let mut device = device;
+        device.used_by_port = port;
+        ConsumerState::insert_device(device_id.clone(), device);
+        ConsumerState::insert_port_forwarder(device_id, forwarder);
+        Consumer::update_devices();
+        Ok(())
+    }
+
+    /// Reserve a device from the market maker, and start a port forwarder for it.
+    pub(super) fn request_device(
+        device_id: String,
+        no_use: bool,
+        lease_secs: Option<u64>,
+    ) -> ConsumerResponse {
+        let data = MarketMakerRequest::ReserveDevice {
+            device_id: device_id.clone(),
+            lease_secs,
+        };
+        Consumer::send_reservation(data, device_id, no_use)
+    }
+
+    /// Take over a device reserved by the previous run of the Consumer, and start
+    /// a port forwarder for it. If the market maker no longer holds the reservation,
+    /// the device is reserved again for `lease_secs`.
+    pub(super) fn resume_device(
+        device_id: String,
+        no_use: bool,
+        lease_secs: Option<u64>,
+    ) -> ConsumerResponse {
+        let data = MarketMakerRequest::ResumeReservation {
+            device_id: device_id.clone(),
+        };
+        match Consumer::send_reservation(data, device_id.clone(), no_use) {
+            ConsumerResponse::DeviceNotReserved { .. } => {
+                Consumer::request_device(device_id, no_use, lease_secs)
+            }
+            response => response,
+        }
+    }
+
+    /// Send a reservation request for a device to the market maker, and start a
+    /// port forwarder for the device once it is reserved.
+    fn send_reservation(
+        data: MarketMakerRequest,
+        device_id: String,
+        no_use: bool,
+    ) -> ConsumerResponse {
+        let mm_addr = ConsumerState::get_addr();
+        if mm_addr.is_none() {
+            error!("Could not get marketmaker address.");
+            return ConsumerResponse::DeviceNotReserved {
+                reason: Error::InvalidState("Could not get marketmaker address.".to_string()),
+            };
+        }
+        let mm_addr = mm_addr.unwrap();
+        let client = TCPClient::from(mm_addr);
+        let response = client.send_request(data, None);
+        if response.is_err() {
+            return ConsumerResponse::DeviceNotReserved {
+                reason: Error::Unreachable(format!(
+                    "Could not reserve device from Market Maker: {}",
+                    response.err().unwrap()
+                )),
+            };
+        }
+        let response = response.unwrap();
+        let response = MarketMakerResponse::from_str(&response).unwrap();
+        match response {
+            MarketMakerResponse::DeviceReserved {
+                mut device,
+                peer_id,
+            } => {
+                // This unwrap seems Ok for now because the device is reserved from the Market Maker,
+                // and the Market Maker would have checked/updated the device spec.
+                let available_at_host = device.available_at_host.parse::<IpAddr>().unwrap();
+
+                // If available_at_host is a loopback address, replace it with the address of the marketmaker.
+                if available_at_host.is_loopback() {
+                    device.available_at_host = client.addr.ip().to_string();
+                }
+                // Create a port forwarder for the device.
+                let device_clone = device.clone();
+                if let Err(e) =
+                    Consumer::reserve_device(device_id.clone(), device_clone, peer_id, no_use)
+                {
+                    // Failed to reserve device. Inform the market maker to release the device.
+                    let data = MarketMakerRequest::ReleaseDevice { device_id };
+
+                    client.send_no_wait(data);
+                    // Return error back to client.
+                    ConsumerResponse::DeviceNotReserved { reason: e }
+                } else {
+                    ConsumerResponse::DeviceReserved { device }
+                }
+            }
+            MarketMakerResponse::DeviceNotReserved { reason } => {
+                ConsumerResponse::DeviceNotReserved { reason }
+            }
+            _ => ConsumerResponse::InvalidRequest {
+                request: response.to_json(),
+            },
+        }
+    }
+
+    /// Start a port forwarder from `port` to the device at the Supplier.
+    /// `peer_id` is the network id of the Supplier, if the device uses secure tunnels.
+    fn start_port_forwarder(
+        port: u16,
+        device: &DeviceSpec,
+        peer_id: Option<String>,
+    ) -> Result<PortForwarder, Error> {
         // Kill any existing adb server at the port.
         adb_utils::kill_adb_server_for_port(port);
-        let dst_host = device.available_at_host.clone();
-        let dst_port = device.available_at_port;
         let mode = if peer_id.is_some() {
             PortForwardMode::Encrypt
         } else {
@@ -537,41 +920,29 @@ impl Consumer {
 
         let mut forwarder = PortForwarder::try_new(
             port,
-            &dst_host,
-            dst_port,
+            &device.available_at_host,
+            device.available_at_port,
             mode,
             peer_id.map(|id| base64::decode(&id).unwrap()),
             false,
         )?;
         forwarder.forward()?;
-        if port == 5037 {
-            ConsumerState::update_using_device(device_id.clone());
-        }
-        let mut device = device;
-        device.used_by_port = port;
-        ConsumerState::insert_device(device_id.clone(), device);
-        ConsumerState::insert_port_forwarder(device_id, forwarder);
-        Consumer::update_devices();
-        Ok(())
+        Ok(forwarder)
     }
 
-    fn use_device(device_id: &str) -> io::Result<()> {
+    fn use_device(device_id: &str) -> Result<(), Error> {
         if !ConsumerState::is_device_reserved(device_id) {
-            return Err(io::Error::new(io::ErrorKind::Other, "Device not reserved."));
+            return Err(Error::DeviceNotFound("Device not reserved.".to_string()));
         }
         let device = ConsumerState::get_device(device_id);
         if device.is_none() {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                "Fatal: Error fetching the required device.",
+            return Err(Error::DeviceNotFound(
+                "Fatal: Error fetching the required device.".to_string(),
             ));
         }
         let mut device = device.unwrap();
         if device.used_by_port == 5037 {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                "Already a default device.",
-            ));
+            return Err(Error::InvalidState("Already a default device.".to_string()));
         }
 
         // Check if any other device is currently in use.
@@ -579,9 +950,8 @@ impl Consumer {
         if let Some(device_in_use_id) = device_in_use {
             let device_in_use = ConsumerState::get_device(&device_in_use_id);
             if device_in_use.is_none() {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    "Fatal: Error fetching the device in use.",
+                return Err(Error::DeviceNotFound(
+                    "Fatal: Error fetching the device in use.".to_string(),
                 ));
             }
             let mut device_in_use = device_in_use.unwrap();
@@ -589,9 +959,8 @@ impl Consumer {
             let port = portpicker::pick_unused_port();
             if port.is_none() {
                 error!("Could not find an available port for port forwarding.");
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    "Could not find an available port for port forwarding.",
+                return Err(Error::Io(
+                    "Could not find an available port for port forwarding.".to_string(),
                 ));
             }
             let port = port.unwrap();
@@ -654,10 +1023,10 @@ impl Consumer {
         Ok(())
     }
 
-    fn start_scrcpy(device_id: &str, user_args: Vec<ScrCpyArgs>) -> io::Result<()> {
+    fn start_scrcpy(device_id: &str, user_args: Vec<ScrCpyArgs>) -> Result<(), Error> {
         let device = ConsumerState::get_device(device_id);
         if device.is_none() {
-            return Err(io::Error::new(io::ErrorKind::Other, "Device not reserved."));
+            return Err(Error::DeviceNotFound("Device not reserved.".to_string()));
         }
         let device = device.unwrap();
         let adb_port = device.used_by_port;
@@ -665,9 +1034,8 @@ impl Consumer {
         let scrcpy_port = portpicker::pick_unused_port();
         if scrcpy_port.is_none() {
             error!("Could not find an available port for scrcpy.");
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                "Could not find an available port for scrcpy.",
+            return Err(Error::Io(
+                "Could not find an available port for scrcpy.".to_string(),
             ));
         }
         let scrcpy_port = scrcpy_port.unwrap();
@@ -705,10 +1073,10 @@ impl Consumer {
                 }
                 if line.contains("ERROR: ") {
                     // The scrcpy server has failed to start.
-                    return Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        format!("Scrcpy server failed to start: {}", line),
-                    ));
+                    return Err(Error::Scrcpy(format!(
+                        "Scrcpy server failed to start: {}",
+                        line
+                    )));
                 }
             }
             ScrCpyState::add_process(device_id, child, portforwarder);
@@ -722,14 +1090,13 @@ impl Consumer {
         supplier_id: &str,
         scrcpy_port: u16,
         secure_comms: bool,
-    ) -> io::Result<PortForwarder> {
+    ) -> Result<PortForwarder, Error> {
         debug!("Requesting scrcpy tunnel from supplier.");
         let port = portpicker::pick_unused_port();
         if port.is_none() {
             error!("Could not find an available port for port forwarding.");
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                "Could not find an available port for port forwarding.",
+            return Err(Error::Io(
+                "Could not find an available port for port forwarding.".to_string(),
             ));
         }
         let port = port.unwrap();
@@ -739,9 +1106,8 @@ impl Consumer {
             let peer_key = base64::decode(supplier_id);
             if peer_key.is_err() {
                 error!("Could not decode peer key.");
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    "Could not decode peer key.",
+                return Err(Error::InvalidInput(
+                    "Could not decode peer key.".to_string(),
                 ));
             }
             let peer_key = Some(peer_key.unwrap());
@@ -778,9 +1144,8 @@ impl Consumer {
         let mm_addr = ConsumerState::get_addr();
         if mm_addr.is_none() {
             error!("Could not get marketmaker address.");
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                "Could not get marketmaker address.",
+            return Err(Error::InvalidState(
+                "Could not get marketmaker address.".to_string(),
             ));
         }
         let mm_addr = mm_addr.unwrap();
@@ -788,31 +1153,31 @@ impl Consumer {
         let response = client.send_request(request, None)?;
         let response = MarketMakerResponse::from_str(&response).map_err(|e| {
             error!("Error parsing response from Market Maker: {}", e);
-            io::Error::new(io::ErrorKind::Other, e.to_string())
+            Error::Protocol(e.to_string())
         })?;
         match response {
             MarketMakerResponse::ScrcpyTunnelSuccess => Ok(portforwarder),
             MarketMakerResponse::ScrcpyTunnelFailure { reason } => {
                 error!("Error starting scrcpy tunnel: {}", reason);
-                Err(io::Error::new(io::ErrorKind::Other, reason))
+                Err(reason)
             }
             _ => {
                 error!("Unexpected response from Market Maker: {:?}", response);
-                Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    format!("Unexpected response from Market Maker: {:?}", response),
-                ))
+                Err(Error::Protocol(format!(
+                    "Unexpected response from Market Maker: {:?}",
+                    response
+                )))
             }
         }
     }
 
-    fn stop_scrcpy(device_id: &str) -> io::Result<()> {
+    fn stop_scrcpy(device_id: &str) -> Result<(), Error> {
         debug!("Stopping scrcpy for device: {}", device_id);
         if !ScrCpyState::is_mirroring(device_id) {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                format!("Device {} is not being mirrored.", device_id),
-            ));
+            return Err(Error::InvalidState(format!(
+                "Device {} is not being mirrored.",
+                device_id
+            )));
         }
         ScrCpyState::kill_process(device_id);
         Ok(())
@@ -849,7 +1214,9 @@ impl Consumer {
                 if mm_addr.is_none() {
                     error!("Could not get marketmaker address.");
                     return ConsumerResponse::ErrorGettingDevices {
-                        reason: "Could not get marketmaker address.".to_string(),
+                        reason: Error::InvalidState(
+                            "Could not get marketmaker address.".to_string(),
+                        ),
                     }
                     .to_json();
                 }
@@ -858,10 +1225,10 @@ impl Consumer {
                 let response = client.send_request(data, None);
                 if response.is_err() {
                     return ConsumerResponse::ErrorGettingDevices {
-                        reason: format!(
+                        reason: Error::Unreachable(format!(
                             "Could not get available devices from Market Maker: {}",
                             response.err().unwrap()
-                        ),
+                        )),
                     }
                     .to_json();
                 }
@@ -888,7 +1255,9 @@ impl Consumer {
                 if mm_addr.is_none() {
                     error!("Could not get marketmaker address.");
                     return ConsumerResponse::ErrorGettingDevices {
-                        reason: "Could not get marketmaker address.".to_string(),
+                        reason: Error::InvalidState(
+                            "Could not get marketmaker address.".to_string(),
+                        ),
                     }
                     .to_json();
                 }
@@ -897,10 +1266,10 @@ impl Consumer {
                 let response = client.send_request(data, None);
                 if response.is_err() {
                     return ConsumerResponse::ErrorGettingDevices {
-                        reason: format!(
+                        reason: Error::Unreachable(format!(
                             "Could not get available devices from Market Maker: {}",
                             response.err().unwrap()
-                        ),
+                        )),
                     }
                     .to_json();
                 }
@@ -924,18 +1293,32 @@ impl Consumer {
                     .to_json(),
                 }
             }
-            ConsumerRequest::ReserveDevice { device_id, no_use }
-                if peer_addr.ip().is_loopback() =>
-            {
-                // Reserve a device from the market maker.
-                let data = MarketMakerRequest::ReserveDevice {
-                    device_id: device_id.clone(),
+            ConsumerRequest::ReserveDevice {
+                device_id,
+                no_use,
+                lease_secs,
+            } if peer_addr.ip().is_loopback() => {
+                Consumer::request_device(device_id, no_use, lease_secs).to_json()
+            }
+            ConsumerRequest::ReserveAnyDevice {
+                filter_vec,
+                count,
+                no_use,
+                lease_secs,
+            } if peer_addr.ip().is_loopback() => {
+                // Reserve devices matching the filter from the market maker.
+                let data = MarketMakerRequest::ReserveAnyDevice {
+                    filter_vec,
+                    count,
+                    lease_secs,
                 };
                 let mm_addr = ConsumerState::get_addr();
                 if mm_addr.is_none() {
                     error!("Could not get marketmaker address.");
                     return ConsumerResponse::DeviceNotReserved {
-                        reason: "Could not get marketmaker address.".to_string(),
+                        reason: Error::InvalidState(
+                            "Could not get marketmaker address.".to_string(),
+                        ),
                     }
                     .to_json();
                 }
@@ -944,47 +1327,51 @@ impl Consumer {
                 let response = client.send_request(data, None);
                 if response.is_err() {
                     return ConsumerResponse::DeviceNotReserved {
-                        reason: format!(
+                        reason: Error::Unreachable(format!(
                             "Could not reserve device from Market Maker: {}",
                             response.err().unwrap()
-                        ),
+                        )),
                     }
                     .to_json();
                 }
                 let response = response.unwrap();
                 let response = MarketMakerResponse::from_str(&response).unwrap();
                 match response {
-                    MarketMakerResponse::DeviceReserved {
-                        mut device,
-                        peer_id,
-                    } => {
-                        // This unwrap seems Ok for now because the device is reserved from the Market Maker,
-                        // and the Market Maker would have checked/updated the device spec.
-                        let available_at_host = device.available_at_host.parse::<IpAddr>().unwrap();
-
-                        // If available_at_host is a loopback address, replace it with the address of the marketmaker.
-                        if available_at_host.is_loopback() {
-                            device.available_at_host = client.addr.ip().to_string();
+                    MarketMakerResponse::DevicesReserved { devices } => {
+                        let mut reserved = Vec::new();
+                        let mut reasons = Vec::new();
+                        for (mut device, peer_id) in devices {
+                            // See ReserveDevice.
+                            let available_at_host =
+                                device.available_at_host.parse::<IpAddr>().unwrap();
+                            if available_at_host.is_loopback() {
+                                device.available_at_host = client.addr.ip().to_string();
+                            }
+                            let device_id = device.device_id.clone();
+                            if let Err(e) = Consumer::reserve_device(
+                                device_id.clone(),
+                                device.clone(),
+                                peer_id,
+                                no_use,
+                            ) {
+                                // Failed to reserve device. Inform the market maker to release the device.
+                                reasons.push(format!("{}: {}", device_id, e));
+                                let data = MarketMakerRequest::ReleaseDevice { device_id };
+                                client.send_no_wait(data);
+                            } else {
+                                reserved.push(device);
+                            }
                         }
-                        // Create a port forwarder for the device.
-                        let device_clone = device.clone();
-                        if let Err(e) = Consumer::reserve_device(
-                            device_id.clone(),
-                            device_clone,
-                            peer_id,
-                            no_use,
-                        ) {
-                            // Failed to reserve device. Inform the market maker to release the device.
-                            let data = MarketMakerRequest::ReleaseDevice { device_id };
-
-                            client.send_no_wait(data);
-                            // Return error back to client.
+                        if reserved.is_empty() {
                             ConsumerResponse::DeviceNotReserved {
-                                reason: format!("Could not reserve device: {}", e),
+                                reason: Error::DeviceUnavailable(format!(
+                                    "Could not reserve device: {}",
+                                    reasons.join(", ")
+                                )),
                             }
                             .to_json()
                         } else {
-                            ConsumerResponse::DeviceReserved { device }.to_json()
+                            ConsumerResponse::DevicesReserved { devices: reserved }.to_json()
                         }
                     }
                     MarketMakerResponse::DeviceNotReserved { reason } => {
@@ -996,10 +1383,159 @@ impl Consumer {
                     .to_json(),
                 }
             }
+            ConsumerRequest::RenewLease {
+                device_id,
+                lease_secs,
+            } if peer_addr.ip().is_loopback() => {
+                if !ConsumerState::is_device_reserved(&device_id) {
+                    return ConsumerResponse::LeaseNotRenewed {
+                        reason: Error::DeviceNotFound(
+                            "Cannot renew the lease of a device that is not reserved.".to_string(),
+                        ),
+                    }
+                    .to_json();
+                }
+                let data = MarketMakerRequest::RenewLease {
+                    device_id: device_id.clone(),
+                    lease_secs,
+                };
+                let mm_addr = ConsumerState::get_addr();
+                if mm_addr.is_none() {
+                    error!("Could not get marketmaker address.");
+                    return ConsumerResponse::LeaseNotRenewed {
+                        reason: Error::InvalidState(
+                            "Could not get marketmaker address.".to_string(),
+                        ),
+                    }
+                    .to_json();
+                }
+                let mm_addr = mm_addr.unwrap();
+                let client = TCPClient::from(mm_addr);
+                let response = client.send_request(data, None);
+                if response.is_err() {
+                    return ConsumerResponse::LeaseNotRenewed {
+                        reason: Error::Unreachable(format!(
+                            "Could not renew lease from Market Maker: {}",
+                            response.err().unwrap()
+                        )),
+                    }
+                    .to_json();
+                }
+                let response = response.unwrap();
+                let response = MarketMakerResponse::from_str(&response).unwrap();
+                match response {
+                    MarketMakerResponse::LeaseRenewed { device } => {
+                        ConsumerState::update_lease_expiry(&device_id, device.lease_expiry);
+                        match ConsumerState::get_device(&device_id) {
+                            Some(device) => ConsumerResponse::LeaseRenewed { device }.to_json(),
+                            None => ConsumerResponse::LeaseNotRenewed {
+                                reason: Error::DeviceUnavailable(
+                                    "Device was released while renewing the lease.".to_string(),
+                                ),
+                            }
+                            .to_json(),
+                        }
+                    }
+                    MarketMakerResponse::LeaseNotRenewed { reason } => {
+                        ConsumerResponse::LeaseNotRenewed { reason }.to_json()
+                    }
+                    _ => ConsumerResponse::InvalidRequest {
+                        request: response.to_json(),
+                    }
+                    .to_json(),
+                }
+            }
+            ConsumerRequest::EnqueueReservation { target, lease_secs }
+                if peer_addr.ip().is_loopback() =>
+            {
+                if let WaitTarget::Device(device_id) = &target {
+                    if ConsumerState::is_device_reserved(device_id) {
+                        return ConsumerResponse::ReservationNotQueued {
+                            reason: Error::DeviceUnavailable(
+                                "Device already reserved.".to_string(),
+                            ),
+                        }
+                        .to_json();
+                    }
+                }
+                let data = MarketMakerRequest::EnqueueReservation { target, lease_secs };
+                let mm_addr = ConsumerState::get_addr();
+                if mm_addr.is_none() {
+                    error!("Could not get marketmaker address.");
+                    return ConsumerResponse::ReservationNotQueued {
+                        reason: Error::InvalidState(
+                            "Could not get marketmaker address.".to_string(),
+                        ),
+                    }
+                    .to_json();
+                }
+                let mm_addr = mm_addr.unwrap();
+                let client = TCPClient::from(mm_addr);
+                let response = client.send_request(data, None);
+                if response.is_err() {
+                    return ConsumerResponse::ReservationNotQueued {
+                        reason: Error::Unreachable(format!(
+                            "Could not queue reservation with Market Maker: {}",
+                            response.err().unwrap()
+                        )),
+                    }
+                    .to_json();
+                }
+                let response = response.unwrap();
+                let response = MarketMakerResponse::from_str(&response).unwrap();
+                match response {
+                    MarketMakerResponse::ReservationQueued { position } => {
+                        ConsumerResponse::ReservationQueued { position }.to_json()
+                    }
+                    MarketMakerResponse::ReservationNotQueued { reason } => {
+                        ConsumerResponse::ReservationNotQueued { reason }.to_json()
+                    }
+                    _ => ConsumerResponse::InvalidRequest {
+                        request: response.to_json(),
+                    }
+                    .to_json(),
+                }
+            }
+            ConsumerRequest::LeaveQueue if peer_addr.ip().is_loopback() => {
+                let mm_addr = ConsumerState::get_addr();
+                if mm_addr.is_none() {
+                    error!("Could not get marketmaker address.");
+                    return ConsumerResponse::LeaveQueueFailure {
+                        reason: Error::InvalidState(
+                            "Could not get marketmaker address.".to_string(),
+                        ),
+                    }
+                    .to_json();
+                }
+                let client = TCPClient::from(mm_addr.unwrap());
+                let response = client.send_request(MarketMakerRequest::LeaveQueue, None);
+                if response.is_err() {
+                    return ConsumerResponse::LeaveQueueFailure {
+                        reason: Error::Unreachable(format!(
+                            "Could not leave the queue of Market Maker: {}",
+                            response.err().unwrap()
+                        )),
+                    }
+                    .to_json();
+                }
+                let response = response.unwrap();
+                let response = MarketMakerResponse::from_str(&response).unwrap();
+                match response {
+                    MarketMakerResponse::LeftQueue { removed } => {
+                        ConsumerResponse::LeftQueue { removed }.to_json()
+                    }
+                    _ => ConsumerResponse::InvalidRequest {
+                        request: response.to_json(),
+                    }
+                    .to_json(),
+                }
+            }
             ConsumerRequest::ReleaseDevice { device_id } if peer_addr.ip().is_loopback() => {
                 if !ConsumerState::is_device_reserved(&device_id) {
                     return ConsumerResponse::DeviceNotReleased {
-                        reason: "Cannot release a device that is not reserved.".to_string(),
+                        reason: Error::DeviceNotFound(
+                            "Cannot release a device that is not reserved.".to_string(),
+                        ),
                     }
                     .to_json();
                 }
@@ -1011,7 +1547,9 @@ impl Consumer {
                 if mm_addr.is_none() {
                     error!("Could not get marketmaker address.");
                     return ConsumerResponse::DeviceNotReleased {
-                        reason: "Could not get marketmaker address.".to_string(),
+                        reason: Error::InvalidState(
+                            "Could not get marketmaker address.".to_string(),
+                        ),
                     }
                     .to_json();
                 }
@@ -1021,10 +1559,10 @@ impl Consumer {
                 let response = client.send_request(data, None);
                 if response.is_err() {
                     return ConsumerResponse::DeviceNotReleased {
-                        reason: format!(
+                        reason: Error::Unreachable(format!(
                             "Could not release device from Market Maker: {}",
                             response.err().unwrap()
-                        ),
+                        )),
                     }
                     .to_json();
                 }
@@ -1043,7 +1581,7 @@ impl Consumer {
                         ConsumerResponse::DeviceNotReleased { reason }.to_json()
                     }
                     _ => ConsumerResponse::DeviceNotReleased {
-                        reason: "Unknown operation".to_string(),
+                        reason: Error::InvalidInput("Unknown operation".to_string()),
                     }
                     .to_json(),
                 }
@@ -1054,7 +1592,7 @@ impl Consumer {
                 let num_devices = ConsumerState::get_number_of_devices();
                 if num_devices == 0 {
                     return ConsumerResponse::AllDeviceReleaseFailure {
-                        reason: "No devices reserved.".to_string(),
+                        reason: Error::InvalidState("No devices reserved.".to_string()),
                     }
                     .to_json();
                 }
@@ -1063,7 +1601,9 @@ impl Consumer {
                 if mm_addr.is_none() {
                     error!("Could not get marketmaker address.");
                     return ConsumerResponse::AllDeviceReleaseFailure {
-                        reason: "Could not get marketmaker address.".to_string(),
+                        reason: Error::InvalidState(
+                            "Could not get marketmaker address.".to_string(),
+                        ),
                     }
                     .to_json();
                 }
@@ -1073,10 +1613,10 @@ impl Consumer {
                 let response = client.send_request(data, None);
                 if response.is_err() {
                     return ConsumerResponse::AllDeviceReleaseFailure {
-                        reason: format!(
+                        reason: Error::Unreachable(format!(
                             "Could not release device from Market Maker: {}",
                             response.err().unwrap()
-                        ),
+                        )),
                     }
                     .to_json();
                 }
@@ -1095,7 +1635,7 @@ impl Consumer {
                         ConsumerResponse::AllDeviceReleaseFailure { reason }.to_json()
                     }
                     _ => ConsumerResponse::AllDeviceReleaseFailure {
-                        reason: "Unknown operation".to_string(),
+                        reason: Error::InvalidInput("Unknown operation".to_string()),
                     }
                     .to_json(),
                 }
@@ -1103,10 +1643,7 @@ impl Consumer {
             ConsumerRequest::UseDevice { device_id } => {
                 let result = Consumer::use_device(&device_id);
                 if let Err(e) = result {
-                    ConsumerResponse::UseDeviceFailure {
-                        reason: format!("{}", e),
-                    }
-                    .to_json()
+                    ConsumerResponse::UseDeviceFailure { reason: e }.to_json()
                 } else {
                     ConsumerResponse::UseDeviceSuccess { device_id }.to_json()
                 }
@@ -1118,16 +1655,14 @@ impl Consumer {
             } if peer_addr.ip().is_loopback() => {
                 if !ConsumerState::is_device_reserved(&device_id) {
                     return ConsumerResponse::StartScrCpyFailure {
-                        reason: "Cannot start scrcpy for a device that is not reserved."
-                            .to_string(),
+                        reason: Error::DeviceNotFound(
+                            "Cannot start scrcpy for a device that is not reserved.".to_string(),
+                        ),
                     }
                     .to_json();
                 }
                 if let Err(e) = Consumer::start_scrcpy(&device_id, scrcpy_args) {
-                    ConsumerResponse::StartScrCpyFailure {
-                        reason: format!("Could not start scrcpy: {}", e),
-                    }
-                    .to_json()
+                    ConsumerResponse::StartScrCpyFailure { reason: e }.to_json()
                 } else {
                     ConsumerResponse::StartScrCpySuccess { device_id }.to_json()
                 }
@@ -1135,16 +1670,14 @@ impl Consumer {
             ConsumerRequest::StopScrCpy { device_id } if peer_addr.ip().is_loopback() => {
                 if !ConsumerState::is_device_reserved(&device_id) {
                     return ConsumerResponse::StopScrCpyFailure {
-                        reason: "Cannot stop mirroring for a device that is not reserved."
-                            .to_string(),
+                        reason: Error::DeviceNotFound(
+                            "Cannot stop mirroring for a device that is not reserved.".to_string(),
+                        ),
                     }
                     .to_json();
                 }
                 if let Err(e) = Consumer::stop_scrcpy(&device_id) {
-                    ConsumerResponse::StopScrCpyFailure {
-                        reason: format!("Could not stop scrcpy: {}", e),
-                    }
-                    .to_json()
+                    ConsumerResponse::StopScrCpyFailure { reason: e }.to_json()
                 } else {
                     ConsumerResponse::StopScrCpySuccess { device_id }.to_json()
                 }
@@ -1164,15 +1697,39 @@ impl Consumer {
             // Requests from Market Maker.
             ConsumerRequest::SupplierDisconnected { device_id } if is_market_maker() => {
                 let device_id_clone = device_id.clone();
-                thread::spawn(move || {
-                    if ConsumerState::is_device_reserved(&device_id_clone) {
-                        ConsumerState::remove_device(&device_id_clone);
-                        ConsumerState::remove_port_forwarder(&device_id_clone);
-                        ScrCpyState::kill_process(&device_id_clone);
-                    }
-                });
+                thread::spawn(move || Consumer::drop_device(&device_id_clone));
                 ConsumerResponse::DeviceReleased { device_id }.to_json()
             }
+            ConsumerRequest::LeaseExpired { device_id } if is_market_maker() => {
+                info!("Lease expired for device {}", device_id);
+                let device_id_clone = device_id.clone();
+                thread::spawn(move || Consumer::drop_device(&device_id_clone));
+                ConsumerResponse::DeviceReleased { device_id }.to_json()
+            }
+            ConsumerRequest::DeviceForceReleased { device_id } if is_market_maker() => {
+                info!("Device {} released by the MarketMaker", device_id);
+                let device_id_clone = device_id.clone();
+                thread::spawn(move || Consumer::drop_device(&device_id_clone));
+                ConsumerResponse::DeviceReleased { device_id }.to_json()
+            }
+            ConsumerRequest::QueuedDeviceReserved {
+                mut device,
+                peer_id,
+            } if is_market_maker() => {
+                info!("Device {} reserved from the wait queue", device.device_id);
+                // As for ReserveDevice, the Market Maker has checked/updated the device spec.
+                let available_at_host = device.available_at_host.parse::<IpAddr>().unwrap();
+                // If available_at_host is a loopback address, replace it with the address of the marketmaker.
+                if available_at_host.is_loopback() {
+                    device.available_at_host = peer_addr.ip().to_string();
+                }
+                let device_id = device.device_id.clone();
+                let device = *device;
+                match Consumer::reserve_device(device_id, device.clone(), peer_id, false) {
+                    Ok(()) => ConsumerResponse::DeviceReserved { device }.to_json(),
+                    Err(e) => ConsumerResponse::DeviceNotReserved { reason: e }.to_json(),
+                }
+            }
             ConsumerRequest::MarketMakerTerminating if is_market_maker() => {
                 thread::spawn(Consumer::market_maker_terminate);
                 ConsumerResponse::TerminationAcknowledged.to_json()
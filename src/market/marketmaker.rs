@@ -1,9 +1,16 @@
+mod events;
+mod store;
 #[cfg(test)]
 mod tests;
 
 use super::*;
 use request::{MarketMakerRequest, MarketMakerResponse};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread;
+use std::time::Instant;
+pub(super) use store::MarketMakerSnapshot;
+use store::StateStore;
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub(super) struct MarketMaker;
@@ -14,7 +21,7 @@ struct MarketMakerState {
     suppliers: HashMap<String, SupplierSpec>,
     // HashMap of ConsumerSpec, hashed by consumer peer_id.
     consumers: HashMap<String, ConsumerSpec>,
-    // HashMap of MarketMakerSpec, hashed by address.
+    // HashMap of standby MarketMakerSpec, hashed by standby peer_id.
     market_makers: HashMap<String, MarketMakerSpec>,
     // HashMap of DeviceSpec, hashed by device id.
     devices: HashMap<String, DeviceSpec>,
@@ -24,6 +31,55 @@ struct MarketMakerState {
     use_whitelists: bool,
     supplier_whitelist: HashSet<String>,
     consumer_whitelist: HashSet<String>,
+    // Suppliers and Consumers restored from the state store, that have not
+    // reconnected or sent a heartbeat since the MarketMaker was restarted.
+    restored_suppliers: HashSet<String>,
+    restored_consumers: HashSet<String>,
+    // FIFO queue of Consumers waiting for a device to become available.
+    wait_queue: VecDeque<WaitEntry>,
+    // MarketMakers allowed to connect as a standby.
+    standby_whitelist: HashSet<String>,
+    // The primary MarketMaker, if this MarketMaker is a standby.
+    primary: Option<MarketMakerSpec>,
+    // HashMap of peered MarketMakerSpec, hashed by peer_id.
+    peers: HashMap<String, MarketMakerSpec>,
+    // MarketMakers allowed to peer.
+    peer_whitelist: HashSet<String>,
+    // Roles granted to network_ids, hashed by peer_id.
+    roles: HashMap<String, HashSet<Role>>,
+    // Quotas of Consumers, hashed by consumer peer_id.
+    consumer_quotas: HashMap<String, Quota>,
+    // Quotas of groups of Consumers, hashed by group name.
+    group_quotas: HashMap<String, Quota>,
+    // Members of the groups of Consumers, hashed by group name.
+    consumer_groups: HashMap<String, HashSet<String>>,
+    // Pools the devices are assigned to, hashed by device id. Kept for devices
+    // that are not currently supplied, so that they rejoin their pools.
+    device_pools: HashMap<String, HashSet<String>>,
+    // Consumers with access to the pools, hashed by pool name.
+    pool_access: HashMap<String, HashSet<String>>,
+    // Available devices advertised by the peers, hashed by device id, hashed by peer_id.
+    peer_devices: HashMap<String, HashMap<String, DeviceSpec>>,
+    // Devices of peers reserved for Consumers of this MarketMaker, with the peer_id
+    // of the peer, hashed by device id.
+    remote_reservations: HashMap<String, (String, DeviceSpec)>,
+    // peer_id of the peer of Consumers that reserved a device of this MarketMaker
+    // through the peer, hashed by consumer peer_id.
+    peer_consumers: HashMap<String, String>,
+    // peer_id of the Consumers for which a device is being reserved, hashed by
+    // device id. Claimed devices count against the quotas of the Consumer.
+    claims: HashMap<String, String>,
+}
+
+/// A Consumer waiting in the reservation queue of the MarketMaker.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+struct WaitEntry {
+    // Public key of the waiting Consumer.
+    consumer: String,
+    // Device(s) the Consumer is waiting for.
+    target: WaitTarget,
+    // Lease duration in seconds, applied when the device is reserved.
+    lease_secs: Option<u64>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
@@ -46,6 +102,19 @@ pub struct MarketMakerMinState {
     use_whitelists: bool,
     supplier_whitelist: HashSet<String>,
     consumer_whitelist: HashSet<String>,
+    standby_whitelist: HashSet<String>,
+    // The primary MarketMaker, if the MarketMaker is a standby.
+    primary: Option<MarketMakerSpec>,
+    // Vector of peered MarketMakerSpec.
+    peers: Vec<MarketMakerSpec>,
+    peer_whitelist: HashSet<String>,
+    // Number of available devices advertised by the peers.
+    num_peer_devices: usize,
+    roles: HashMap<String, HashSet<Role>>,
+    // Usage of the quotas of Consumers and groups.
+    quotas: Vec<QuotaUsage>,
+    // Consumers with access to the pools, hashed by pool name.
+    pool_access: HashMap<String, HashSet<String>>,
 }
 
 #[derive(Debug, Default)]
@@ -54,6 +123,9 @@ struct HeartBeatState {
     consumers: HashMap<String, u8>,
     dead_suppliers: HashSet<String>,
     dead_consumers: HashSet<String>,
+    // Last heartbeat received from the primary MarketMaker, on a standby.
+    primary: Option<Instant>,
+    peers: HashMap<String, u8>,
 }
 
 lazy_static! {
@@ -68,6 +140,8 @@ impl HeartBeatState {
         state.consumers.clear();
         state.dead_suppliers.clear();
         state.dead_consumers.clear();
+        state.primary = None;
+        state.peers.clear();
     }
 
     #[inline(always)]
@@ -132,6 +206,53 @@ impl HeartBeatState {
         }
     }
 
+    /// A peer is considered alive for three undertaker intervals after its last heartbeat.
+    #[inline(always)]
+    fn peer_heartbeat(peer_id: &str) {
+        let mut state = HEARTBEAT_STATE.write().unwrap();
+        state.peers.insert(peer_id.to_string(), 3);
+    }
+
+    #[inline(always)]
+    fn remove_peer(peer_id: &str) {
+        let mut state = HEARTBEAT_STATE.write().unwrap();
+        state.peers.remove(peer_id);
+    }
+
+    /// Decrement the heartbeats of the peers.
+    /// Returns the peers that missed their heartbeats, which are no longer tracked.
+    #[inline(always)]
+    fn decrement_peer_heartbeats() -> Vec<String> {
+        let mut state = HEARTBEAT_STATE.write().unwrap();
+        let mut dead_peers = Vec::new();
+        state.peers.retain(|peer_id, heartbeat| {
+            if *heartbeat == 0 {
+                dead_peers.push(peer_id.to_string());
+                false
+            } else {
+                *heartbeat -= 1;
+                true
+            }
+        });
+        dead_peers
+    }
+
+    #[inline(always)]
+    fn primary_heartbeat() {
+        let mut state = HEARTBEAT_STATE.write().unwrap();
+        state.primary = Some(Instant::now());
+    }
+
+    /// Time elapsed since the last heartbeat from the primary MarketMaker.
+    #[inline(always)]
+    fn since_primary_heartbeat() -> Duration {
+        let state = HEARTBEAT_STATE.read().unwrap();
+        state
+            .primary
+            .map(|heartbeat| heartbeat.elapsed())
+            .unwrap_or_default()
+    }
+
     #[inline(always)]
     fn decrement_heartbeats() {
         let mut state = HEARTBEAT_STATE.write().unwrap();
@@ -174,12 +295,16 @@ impl HeartBeatState {
 
 lazy_static! {
     /// Creates a new uninitialized MarketMakerState struct.
-    /// This struct is used to persist the MarketMaker state.
-
-    // TODO: Replace this static variable with a database.
+    /// This struct holds the live MarketMaker state. Changes to it are
+    /// written to the attached STATE_STORE, if any.
     static ref MARKET_MAKER_STATE: RwLock<MarketMakerState> = RwLock::new(MarketMakerState::default());
+    /// Store used to persist MARKET_MAKER_STATE. Attached when the MarketMaker starts.
+    static ref STATE_STORE: RwLock<Option<Arc<dyn StateStore>>> = RwLock::new(None);
 }
 
+/// Set when MARKET_MAKER_STATE has changed since the last snapshot was written.
+static STATE_CHANGED: AtomicBool = AtomicBool::new(false);
+
 impl Display for MarketMakerState {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         write!(
@@ -210,7 +335,69 @@ impl Display for MarketMakerMinState {
             self.num_consumers,
             self.devices.len(),
             self.available_devices.len()
-        )
+        )?;
+        match &self.primary {
+            Some(primary) => write!(
+                f,
+                "\n    Standby of MarketMaker       : {}",
+                primary.bind_host
+            )?,
+            None => write!(
+                f,
+                "\n    Standby MarketMakers         : {}",
+                self.num_market_makers
+            )?,
+        }
+        if !self.peers.is_empty() {
+            write!(
+                f,
+                "\n    Peered MarketMakers          : {}\n    Devices of peers             : {}",
+                self.peers.len(),
+                self.num_peer_devices
+            )?;
+        }
+        if !self.quotas.is_empty() {
+            write!(f, "\n    Quotas:")?;
+            for usage in self.quotas.iter() {
+                write!(f, "\n        {}", usage)?;
+            }
+        }
+        if !self.pool_access.is_empty() {
+            write!(f, "\n    Pools:")?;
+            let mut pools = self.pool_access.iter().collect::<Vec<_>>();
+            pools.sort_by(|a, b| a.0.cmp(b.0));
+            for (pool, consumers) in pools {
+                let mut consumers = consumers.iter().cloned().collect::<Vec<_>>();
+                consumers.sort();
+                write!(f, "\n        {}: {}", pool, consumers.join(", "))?;
+            }
+        }
+        if !self.roles.is_empty() {
+            write!(f, "\n    Roles:")?;
+            let mut roles = self.roles.iter().collect::<Vec<_>>();
+            roles.sort_by(|a, b| a.0.cmp(b.0));
+            for (peer_id, roles) in roles {
+                let mut roles = roles.iter().map(Role::to_string).collect::<Vec<_>>();
+                roles.sort();
+                write!(f, "\n        {}: {}", peer_id, roles.join(", "))?;
+            }
+        }
+        let mut device_health = self
+            .devices
+            .iter()
+            .filter_map(|(device_id, device)| {
+                device.health.as_ref().map(|health| (device_id, health))
+            })
+            .collect::<Vec<_>>();
+        if device_health.is_empty() {
+            return Ok(());
+        }
+        device_health.sort_by(|a, b| a.0.cmp(b.0));
+        write!(f, "\nDevice Health:")?;
+        for (device_id, health) in device_health {
+            write!(f, "\n    {}: {}", device_id, health)?;
+        }
+        Ok(())
     }
 }
 
@@ -228,16 +415,176 @@ impl MarketMakerState {
         *state = MarketMakerState::default();
     }
 
+    #[inline(always)]
+    fn restore(snapshot: MarketMakerSnapshot) {
+        let mut state = MARKET_MAKER_STATE.write().unwrap();
+        *state = MarketMakerState {
+            restored_suppliers: snapshot.suppliers.keys().cloned().collect(),
+            restored_consumers: snapshot.consumers.keys().cloned().collect(),
+            suppliers: snapshot.suppliers,
+            consumers: snapshot.consumers,
+            devices: snapshot.devices,
+            available_devices: snapshot.available_devices,
+            use_whitelists: snapshot.use_whitelists,
+            supplier_whitelist: snapshot.supplier_whitelist,
+            consumer_whitelist: snapshot.consumer_whitelist,
+            wait_queue: snapshot.wait_queue,
+            standby_whitelist: snapshot.standby_whitelist,
+            peers: snapshot.peers,
+            peer_whitelist: snapshot.peer_whitelist,
+            roles: snapshot.roles,
+            consumer_quotas: snapshot.consumer_quotas,
+            group_quotas: snapshot.group_quotas,
+            consumer_groups: snapshot.consumer_groups,
+            device_pools: snapshot.device_pools,
+            pool_access: snapshot.pool_access,
+            ..MarketMakerState::default()
+        };
+    }
+
+    /// Replace the state with a snapshot replicated from the primary MarketMaker.
+    #[inline(always)]
+    fn replicate(snapshot: MarketMakerSnapshot) {
+        let mut state = MARKET_MAKER_STATE.write().unwrap();
+        let primary = state.primary.take();
+        *state = MarketMakerState {
+            suppliers: snapshot.suppliers,
+            consumers: snapshot.consumers,
+            devices: snapshot.devices,
+            available_devices: snapshot.available_devices,
+            use_whitelists: snapshot.use_whitelists,
+            supplier_whitelist: snapshot.supplier_whitelist,
+            consumer_whitelist: snapshot.consumer_whitelist,
+            wait_queue: snapshot.wait_queue,
+            standby_whitelist: snapshot.standby_whitelist,
+            peers: snapshot.peers,
+            peer_whitelist: snapshot.peer_whitelist,
+            roles: snapshot.roles,
+            consumer_quotas: snapshot.consumer_quotas,
+            group_quotas: snapshot.group_quotas,
+            consumer_groups: snapshot.consumer_groups,
+            device_pools: snapshot.device_pools,
+            pool_access: snapshot.pool_access,
+            primary,
+            ..MarketMakerState::default()
+        };
+    }
+
+    /// Take over as the primary MarketMaker. The replicated Suppliers and Consumers
+    /// are handled like peers restored from the state store.
+    #[inline(always)]
+    fn promote() {
+        let mut state = MARKET_MAKER_STATE.write().unwrap();
+        state.primary = None;
+        state.restored_suppliers = state.suppliers.keys().cloned().collect();
+        state.restored_consumers = state.consumers.keys().cloned().collect();
+        Self::persist();
+    }
+
+    #[inline(always)]
+    fn set_primary(primary: MarketMakerSpec) {
+        let mut state = MARKET_MAKER_STATE.write().unwrap();
+        state.primary = Some(primary);
+    }
+
+    #[inline(always)]
+    fn insert_standby(market_maker: MarketMakerSpec) {
+        let mut state = MARKET_MAKER_STATE.write().unwrap();
+        state
+            .market_makers
+            .insert(market_maker.pub_key.clone(), market_maker);
+    }
+
+    #[inline(always)]
+    fn remove_standby(pub_key: &str) {
+        let mut state = MARKET_MAKER_STATE.write().unwrap();
+        state.market_makers.remove(pub_key);
+    }
+
+    /// Attach the state store, and start writing the state to it.
+    /// The current state is written first.
+    fn attach_store(store: Box<dyn StateStore>) {
+        let store: Arc<dyn StateStore> = Arc::from(store);
+        *STATE_STORE.write().unwrap() = Some(store.clone());
+        STATE_CHANGED.store(true, Ordering::SeqCst);
+        MarketMakerState::start_persist_thread(store);
+    }
+
+    /// Write a snapshot of the state to `store` whenever the state changes,
+    /// at most once every `PERSIST_INTERVAL`, until the store is detached.
+    /// The snapshot is taken under the read lock, and written after releasing it.
+    fn start_persist_thread(store: Arc<dyn StateStore>) {
+        thread::spawn(move || loop {
+            thread::sleep(PERSIST_INTERVAL);
+            // Hold the store, so that it is not cleared while the snapshot is written.
+            let state_store = STATE_STORE.read().unwrap();
+            match state_store.as_ref() {
+                Some(attached) if Arc::ptr_eq(attached, &store) => {}
+                _ => break,
+            }
+            if !STATE_CHANGED.swap(false, Ordering::SeqCst) {
+                continue;
+            }
+            let snapshot = MarketMakerState::get_snapshot();
+            if let Err(e) = store.save(&snapshot) {
+                error!("Failed to persist MarketMaker state: {}", e);
+            }
+        });
+    }
+
+    /// Detach the state store and remove the snapshot persisted in it.
+    #[inline(always)]
+    fn detach_store() {
+        let store = STATE_STORE.write().unwrap().take();
+        if let Some(store) = store {
+            if let Err(e) = store.clear() {
+                error!("Failed to clear persisted MarketMaker state: {}", e);
+            }
+        }
+    }
+
+    /// Mark the state as changed, for it to be written to the attached store.
+    /// Called while holding the MARKET_MAKER_STATE write lock, after a change.
+    #[inline(always)]
+    fn persist() {
+        STATE_CHANGED.store(true, Ordering::SeqCst);
+    }
+
+    #[inline(always)]
+    fn clear_restored_supplier(supplier_pub_key: &str) {
+        let mut state = MARKET_MAKER_STATE.write().unwrap();
+        state.restored_suppliers.remove(supplier_pub_key);
+    }
+
+    #[inline(always)]
+    fn clear_restored_consumer(consumer_pub_key: &str) {
+        let mut state = MARKET_MAKER_STATE.write().unwrap();
+        state.restored_consumers.remove(consumer_pub_key);
+    }
+
+    #[inline(always)]
+    fn update_consumer_host(consumer_pub_key: &str, host: &str) {
+        let mut state = MARKET_MAKER_STATE.write().unwrap();
+        for device in state.devices.values_mut() {
+            if device.used_by == consumer_pub_key {
+                device.used_by_host = host.to_owned();
+            }
+        }
+        Self::persist();
+    }
+
     #[inline(always)]
     fn set_whitelists() {
         let mut state = MARKET_MAKER_STATE.write().unwrap();
         state.use_whitelists = true;
+        Self::persist();
     }
 
     #[inline(always)]
     fn reset_whitelists() {
         let mut state = MARKET_MAKER_STATE.write().unwrap();
         state.use_whitelists = false;
+        Self::persist();
     }
 
     #[inline(always)]
@@ -252,62 +599,186 @@ impl MarketMakerState {
             .map(|device| (device.device_id.clone(), device))
             .collect();
         state.available_devices = available_devices;
+        Self::persist();
     }
 
     #[inline(always)]
-    fn insert_device(device: DeviceSpec) {
+    fn insert_device(mut device: DeviceSpec) {
         let mut state = MARKET_MAKER_STATE.write().unwrap();
+        // The device rejoins the pools it was assigned to before.
+        let pools = state
+            .device_pools
+            .entry(device.device_id.clone())
+            .or_default();
+        pools.extend(device.pools.drain());
+        device.pools = pools.clone();
+        if device.pools.is_empty() {
+            state.device_pools.remove(&device.device_id);
+        }
         state.devices.insert(device.device_id.clone(), device);
+        Self::persist();
     }
 
     #[inline(always)]
     fn insert_supplier(supplier: SupplierSpec) {
         let pub_key = supplier.pub_key.clone();
         let mut state = MARKET_MAKER_STATE.write().unwrap();
+        state.restored_suppliers.remove(&pub_key);
         state.suppliers.insert(pub_key, supplier);
+        Self::persist();
     }
 
     #[inline(always)]
     fn insert_consumer(consumer: ConsumerSpec) {
         let pub_key = consumer.pub_key.clone();
         let mut state = MARKET_MAKER_STATE.write().unwrap();
+        state.restored_consumers.remove(&pub_key);
         state.consumers.insert(pub_key, consumer);
+        Self::persist();
+    }
+
+    /// Claim up to `count` of the given devices for the Consumer, by removing them
+    /// from the available devices. Devices that are no longer available are skipped.
+    /// The quotas are checked under the same lock, so that concurrent reservations
+    /// cannot exceed them. Returns the claimed devices, or the reason the quotas
+    /// allow no more reservations. The claims are released with `release_claim`.
+    #[inline(always)]
+    fn claim_devices_for(
+        consumer_pub_key: &str,
+        device_ids: &[String],
+        count: usize,
+        lease_secs: Option<u64>,
+    ) -> Result<Vec<DeviceSpec>, Error> {
+        let mut state = MARKET_MAKER_STATE.write().unwrap();
+        let device_ids = device_ids
+            .iter()
+            .filter(|device_id| state.available_devices.contains_key(*device_id))
+            .cloned()
+            .collect::<Vec<String>>();
+        if device_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let count = count.min(state.check_quota(consumer_pub_key, lease_secs)?);
+        let mut claimed = Vec::new();
+        for device_id in device_ids.into_iter().take(count) {
+            if let Some(device) = state.available_devices.remove(&device_id) {
+                state.claims.insert(device_id, consumer_pub_key.to_owned());
+                claimed.push(device);
+            }
+        }
+        Self::persist();
+        Ok(claimed)
     }
 
+    /// Release the claim on the device, once its reservation is completed or failed.
     #[inline(always)]
-    fn remove_device_from_available(device_id: &str) -> Option<DeviceSpec> {
+    fn release_claim(device_id: &str) {
         let mut state = MARKET_MAKER_STATE.write().unwrap();
-        state.available_devices.remove(device_id)
+        state.claims.remove(device_id);
     }
 
     #[inline(always)]
     fn remove_device(device_id: &str) {
         let mut state = MARKET_MAKER_STATE.write().unwrap();
         state.devices.remove(device_id);
+        Self::persist();
     }
 
     #[inline(always)]
     fn remove_supplier(supplier_pub_key: &str) -> Option<SupplierSpec> {
         let mut state = MARKET_MAKER_STATE.write().unwrap();
-        state.suppliers.remove(supplier_pub_key)
+        state.restored_suppliers.remove(supplier_pub_key);
+        let supplier = state.suppliers.remove(supplier_pub_key);
+        Self::persist();
+        supplier
     }
 
     #[inline(always)]
     fn remove_consumer(consumer_pub_key: &str) -> Option<ConsumerSpec> {
         let mut state = MARKET_MAKER_STATE.write().unwrap();
-        state.consumers.remove(consumer_pub_key)
+        state.restored_consumers.remove(consumer_pub_key);
+        state
+            .wait_queue
+            .retain(|entry| entry.consumer != consumer_pub_key);
+        let consumer = state.consumers.remove(consumer_pub_key);
+        Self::persist();
+        consumer
+    }
+
+    /// Add the entry to the end of the wait queue.
+    /// Returns the position of the entry in the queue, or `None` if the
+    /// Consumer is already waiting for the same target.
+    #[inline(always)]
+    fn enqueue_waiting(entry: WaitEntry) -> Option<usize> {
+        let mut state = MARKET_MAKER_STATE.write().unwrap();
+        if state
+            .wait_queue
+            .iter()
+            .any(|queued| queued.consumer == entry.consumer && queued.target == entry.target)
+        {
+            return None;
+        }
+        state.wait_queue.push_back(entry);
+        let position = state.wait_queue.len();
+        Self::persist();
+        Some(position)
+    }
+
+    /// Remove all entries of the Consumer from the wait queue.
+    /// Returns the number of entries removed.
+    #[inline(always)]
+    fn dequeue_waiting(consumer_pub_key: &str) -> usize {
+        let mut state = MARKET_MAKER_STATE.write().unwrap();
+        let queue_len = state.wait_queue.len();
+        state
+            .wait_queue
+            .retain(|entry| entry.consumer != consumer_pub_key);
+        let removed = queue_len - state.wait_queue.len();
+        Self::persist();
+        removed
+    }
+
+    /// Pop the first entry in the wait queue for which an available device exists,
+    /// along with the matching device. The device is claimed for the Consumer of the
+    /// entry. Entries of Consumers whose quotas allow no more reservations are kept
+    /// in the queue, until the Consumer releases a device.
+    #[inline(always)]
+    fn pop_waiting_with_device() -> Option<(WaitEntry, DeviceSpec)> {
+        let mut state = MARKET_MAKER_STATE.write().unwrap();
+        let (index, device_id) = state.wait_queue.iter().enumerate().find_map(|(i, entry)| {
+            state.check_quota(&entry.consumer, entry.lease_secs).ok()?;
+            let pools = state.pools_of(&entry.consumer);
+            state
+                .available_devices
+                .values()
+                .find(|device| entry.target.matches(device) && device.is_accessible_with(&pools))
+                .map(|device| (i, device.device_id.clone()))
+        })?;
+        let entry = state.wait_queue.remove(index)?;
+        let device = state.available_devices.remove(&device_id)?;
+        state.claims.insert(device_id, entry.consumer.clone());
+        Self::persist();
+        Some((entry, device))
     }
 
     #[inline(always)]
     fn reclaim_devices_used_by(consumer_pub_key: &str) {
         let mut state = MARKET_MAKER_STATE.write().unwrap();
+        let mut released = Vec::new();
         for device in state.devices.values_mut() {
             if device.used_by == consumer_pub_key {
                 device.used_by = Default::default();
                 device.used_by_host = Default::default();
                 device.used_by_port = Default::default();
+                device.lease_expiry = Default::default();
+                released.push(device.device_id.clone());
             }
         }
+        Self::persist();
+        drop(state);
+        for device_id in released {
+            events::publish(MarketEvent::DeviceReleased { device_id });
+        }
     }
 
     #[inline(always)]
@@ -319,6 +790,45 @@ impl MarketMakerState {
             device.used_by_name = Default::default();
             device.used_by_host = Default::default();
             device.used_by_port = Default::default();
+            device.lease_expiry = Default::default();
+        }
+        Self::persist();
+    }
+
+    #[inline(always)]
+    fn renew_lease(device_id: &str, consumer_pub_key: &str, expiry: u64) -> Option<DeviceSpec> {
+        let mut state = MARKET_MAKER_STATE.write().unwrap();
+        let device = state
+            .devices
+            .get_mut(device_id)
+            .filter(|device| device.used_by == consumer_pub_key)?;
+        device.lease_expiry = Some(expiry);
+        let device = device.clone();
+        Self::persist();
+        Some(device)
+    }
+
+    /// Update the health of the devices supplied by the Supplier.
+    /// Health reported for devices of other Suppliers is ignored.
+    /// The health is reported with every heartbeat, so it is not persisted.
+    #[inline(always)]
+    fn update_device_health(supplier_pub_key: &str, device_health: HashMap<String, DeviceHealth>) {
+        let mut state = MARKET_MAKER_STATE.write().unwrap();
+        for (device_id, health) in device_health {
+            if let Some(device) = state
+                .devices
+                .get_mut(&device_id)
+                .filter(|device| device.available_at == supplier_pub_key)
+            {
+                device.health = Some(health.clone());
+            }
+            if let Some(device) = state
+                .available_devices
+                .get_mut(&device_id)
+                .filter(|device| device.available_at == supplier_pub_key)
+            {
+                device.health = Some(health);
+            }
         }
     }
 
@@ -326,162 +836,777 @@ impl MarketMakerState {
     fn add_to_supplier_whitelist(key: &str) {
         let mut state = MARKET_MAKER_STATE.write().unwrap();
         state.supplier_whitelist.insert(key.to_owned());
+        Self::persist();
     }
 
     #[inline(always)]
     fn add_to_consumer_whitelist(key: &str) {
         let mut state = MARKET_MAKER_STATE.write().unwrap();
         state.consumer_whitelist.insert(key.to_owned());
+        Self::persist();
     }
 
     #[inline(always)]
     fn remove_from_supplier_whitelist(key: &str) {
         let mut state = MARKET_MAKER_STATE.write().unwrap();
         state.supplier_whitelist.remove(key);
+        Self::persist();
     }
 
     #[inline(always)]
     fn remove_from_consumer_whitelist(key: &str) {
         let mut state = MARKET_MAKER_STATE.write().unwrap();
         state.consumer_whitelist.remove(key);
+        Self::persist();
     }
 
     #[inline(always)]
-    fn update_supplier_ip(supplier_id: &str, ip: &str) {
+    fn add_to_standby_whitelist(key: &str) {
         let mut state = MARKET_MAKER_STATE.write().unwrap();
-        if let Some(supplier) = state.suppliers.get_mut(supplier_id) {
-            supplier.bind_host = ip.to_owned();
-        }
+        state.standby_whitelist.insert(key.to_owned());
+        Self::persist();
     }
 
     #[inline(always)]
-    fn update_device_supplier(device_id: &str, supplier_ip: &str) {
+    fn remove_from_standby_whitelist(key: &str) {
         let mut state = MARKET_MAKER_STATE.write().unwrap();
-        if let Some(device) = state.devices.get_mut(device_id) {
-            device.available_at_host = supplier_ip.to_owned();
-        }
+        state.standby_whitelist.remove(key);
+        Self::persist();
     }
 
-    // Read functions...
-
     #[inline(always)]
-    fn get_state() -> MarketMakerState {
-        let state = MARKET_MAKER_STATE.read().unwrap();
-        state.clone()
+    fn add_to_peer_whitelist(key: &str) {
+        let mut state = MARKET_MAKER_STATE.write().unwrap();
+        state.peer_whitelist.insert(key.to_owned());
+        Self::persist();
     }
 
     #[inline(always)]
-    fn get_min_state() -> MarketMakerMinState {
-        let state = MARKET_MAKER_STATE.read().unwrap();
-        MarketMakerMinState {
-            num_suppliers: state.suppliers.len(),
-            suppliers: state.suppliers.values().cloned().collect(),
-            num_consumers: state.consumers.len(),
-            consumers: state.consumers.values().cloned().collect(),
-            num_market_makers: state.market_makers.len(),
-            devices: state.devices.clone(),
-            use_whitelists: state.use_whitelists,
-            available_devices: state.available_devices.clone(),
-            supplier_whitelist: state
-                .supplier_whitelist
-                .iter()
-                .map(base64::encode)
-                .collect(),
-            consumer_whitelist: state
-                .consumer_whitelist
-                .iter()
-                .map(base64::encode)
-                .collect(),
-        }
+    fn remove_from_peer_whitelist(key: &str) {
+        let mut state = MARKET_MAKER_STATE.write().unwrap();
+        state.peer_whitelist.remove(key);
+        Self::persist();
     }
 
     #[inline(always)]
-    fn verify_supplier_whitelist(key: &str) -> bool {
-        let state = MARKET_MAKER_STATE.read().unwrap();
-        if state.use_whitelists {
-            state.supplier_whitelist.contains(key)
-        } else {
-            true
-        }
+    fn grant_role(key: &str, role: Role) {
+        let mut state = MARKET_MAKER_STATE.write().unwrap();
+        state.roles.entry(key.to_owned()).or_default().insert(role);
+        Self::persist();
     }
 
     #[inline(always)]
-    fn verify_consumer_whitelist(key: &str) -> bool {
-        let state = MARKET_MAKER_STATE.read().unwrap();
-        if state.use_whitelists {
-            state.consumer_whitelist.contains(key)
-        } else {
-            true
+    fn revoke_role(key: &str, role: Role) {
+        let mut state = MARKET_MAKER_STATE.write().unwrap();
+        if let Some(roles) = state.roles.get_mut(key) {
+            roles.remove(&role);
+            if roles.is_empty() {
+                state.roles.remove(key);
+            }
         }
+        Self::persist();
     }
 
     #[inline(always)]
-    fn supplier_exists(supplier_pub_key: &str) -> bool {
-        let state = MARKET_MAKER_STATE.read().unwrap();
-        state.suppliers.contains_key(supplier_pub_key)
+    fn set_quota(target: QuotaTarget, quota: Quota) {
+        let mut state = MARKET_MAKER_STATE.write().unwrap();
+        match target {
+            QuotaTarget::Consumer(key) => state.consumer_quotas.insert(key, quota),
+            QuotaTarget::Group(name) => state.group_quotas.insert(name, quota),
+        };
+        Self::persist();
     }
 
     #[inline(always)]
-    fn get_consumer(consumer_pub_key: &str) -> Option<ConsumerSpec> {
-        let state = MARKET_MAKER_STATE.read().unwrap();
-        state.consumers.get(consumer_pub_key).cloned()
+    fn remove_quota(target: &QuotaTarget) {
+        let mut state = MARKET_MAKER_STATE.write().unwrap();
+        match target {
+            QuotaTarget::Consumer(key) => state.consumer_quotas.remove(key),
+            QuotaTarget::Group(name) => state.group_quotas.remove(name),
+        };
+        Self::persist();
     }
+
     #[inline(always)]
-    fn consumer_exists(consumer_pub_key: &str) -> bool {
-        let state = MARKET_MAKER_STATE.read().unwrap();
-        state.consumers.contains_key(consumer_pub_key)
+    fn add_to_group(group: &str, key: &str) {
+        let mut state = MARKET_MAKER_STATE.write().unwrap();
+        state
+            .consumer_groups
+            .entry(group.to_owned())
+            .or_default()
+            .insert(key.to_owned());
+        Self::persist();
     }
 
     #[inline(always)]
-    fn get_available_devices() -> Vec<DeviceSpec> {
-        let state = MARKET_MAKER_STATE.read().unwrap();
-        state.available_devices.values().cloned().collect()
+    fn remove_from_group(group: &str, key: &str) {
+        let mut state = MARKET_MAKER_STATE.write().unwrap();
+        if let Some(members) = state.consumer_groups.get_mut(group) {
+            members.remove(key);
+            if members.is_empty() {
+                state.consumer_groups.remove(group);
+            }
+        }
+        Self::persist();
     }
 
+    /// Assign the device to the pool. Returns `false` if the device is not found.
     #[inline(always)]
-    fn get_consumers_affected_by(supplier_pub_key: &str) -> Vec<(String, String, String)> {
-        let state = MARKET_MAKER_STATE.read().unwrap();
-        // iterate through all the devices and find the ones that are affected by the supplier.
-        state
-            .devices
-            .values()
-            .filter_map(|device| {
-                if device.available_at == supplier_pub_key {
-                    Some((
-                        device.used_by.clone(),
-                        device.used_by_host.clone(),
-                        device.device_id.clone(),
-                    ))
-                } else {
-                    None
-                }
-            })
-            .collect()
+    fn assign_pool(device_id: &str, pool: &str) -> bool {
+        let mut state = MARKET_MAKER_STATE.write().unwrap();
+        let pools = match state.devices.get_mut(device_id) {
+            Some(device) => {
+                device.pools.insert(pool.to_owned());
+                device.pools.clone()
+            }
+            None => return false,
+        };
+        if let Some(device) = state.available_devices.get_mut(device_id) {
+            device.pools = pools.clone();
+        }
+        state.device_pools.insert(device_id.to_owned(), pools);
+        Self::persist();
+        true
     }
 
     #[inline(always)]
-    fn get_consumer_port(consumer_pub_key: &str) -> Option<u16> {
-        let state = MARKET_MAKER_STATE.read().unwrap();
-        state
-            .consumers
-            .get(consumer_pub_key)
-            .map(|consumer| consumer.bind_port)
+    fn unassign_pool(device_id: &str, pool: &str) {
+        let mut state = MARKET_MAKER_STATE.write().unwrap();
+        if let Some(device) = state.devices.get_mut(device_id) {
+            device.pools.remove(pool);
+        }
+        if let Some(device) = state.available_devices.get_mut(device_id) {
+            device.pools.remove(pool);
+        }
+        if let Some(pools) = state.device_pools.get_mut(device_id) {
+            pools.remove(pool);
+            if pools.is_empty() {
+                state.device_pools.remove(device_id);
+            }
+        }
+        Self::persist();
     }
 
     #[inline(always)]
-    fn get_consumer_name(consumer_pub_key: &str) -> Option<String> {
-        let state = MARKET_MAKER_STATE.read().unwrap();
+    fn grant_pool_access(pool: &str, key: &str) {
+        let mut state = MARKET_MAKER_STATE.write().unwrap();
         state
-            .consumers
-            .get(consumer_pub_key)
-            .map(|consumer| consumer.name.clone())
+            .pool_access
+            .entry(pool.to_owned())
+            .or_default()
+            .insert(key.to_owned());
+        Self::persist();
     }
 
     #[inline(always)]
-    fn is_device_used_by(device_id: &str, consumer_pub_key: &str) -> bool {
-        let state = MARKET_MAKER_STATE.read().unwrap();
-        let device = state.devices.get(device_id);
-        device.map_or(false, |device| device.used_by == consumer_pub_key)
+    fn revoke_pool_access(pool: &str, key: &str) {
+        let mut state = MARKET_MAKER_STATE.write().unwrap();
+        if let Some(consumers) = state.pool_access.get_mut(pool) {
+            consumers.remove(key);
+            if consumers.is_empty() {
+                state.pool_access.remove(pool);
+            }
+        }
+        Self::persist();
+    }
+
+    #[inline(always)]
+    fn insert_peer(market_maker: MarketMakerSpec) {
+        let mut state = MARKET_MAKER_STATE.write().unwrap();
+        state
+            .peers
+            .insert(market_maker.pub_key.clone(), market_maker);
+        Self::persist();
+    }
+
+    /// Remove the peer, along with the devices it advertised.
+    #[inline(always)]
+    fn remove_peer(pub_key: &str) {
+        let mut state = MARKET_MAKER_STATE.write().unwrap();
+        state.peers.remove(pub_key);
+        state.peer_devices.remove(pub_key);
+        Self::persist();
+    }
+
+    /// Replace the devices advertised by the peer. Reservations of the advertised
+    /// devices have ended on the peer, e.g. because their lease expired.
+    #[inline(always)]
+    fn set_peer_devices(pub_key: &str, devices: Vec<DeviceSpec>) {
+        let mut state = MARKET_MAKER_STATE.write().unwrap();
+        for device in devices.iter() {
+            state.remote_reservations.remove(&device.device_id);
+        }
+        let devices = devices
+            .into_iter()
+            .map(|device| (device.device_id.clone(), device))
+            .collect();
+        state.peer_devices.insert(pub_key.to_owned(), devices);
+    }
+
+    #[inline(always)]
+    fn clear_peer_devices(pub_key: &str) {
+        let mut state = MARKET_MAKER_STATE.write().unwrap();
+        state.peer_devices.remove(pub_key);
+    }
+
+    /// Claim a device advertised by the peers for the Consumer, by removing it from
+    /// the devices advertised by the peers. The quotas are checked under the same lock.
+    /// Returns the peer_id of the peer along with the device, if the device is
+    /// advertised, or the reason the quotas allow no more reservations.
+    /// The claim is released with `release_claim`.
+    #[inline(always)]
+    fn claim_peer_device_for(
+        device_id: &str,
+        consumer_pub_key: &str,
+        lease_secs: Option<u64>,
+    ) -> Result<Option<(String, DeviceSpec)>, Error> {
+        let mut state = MARKET_MAKER_STATE.write().unwrap();
+        let peer_pub_key = state
+            .peer_devices
+            .iter()
+            .find(|(_, devices)| devices.contains_key(device_id))
+            .map(|(pub_key, _)| pub_key.clone());
+        let peer_pub_key = match peer_pub_key {
+            Some(peer_pub_key) => peer_pub_key,
+            None => return Ok(None),
+        };
+        state.check_quota(consumer_pub_key, lease_secs)?;
+        let device = state
+            .peer_devices
+            .get_mut(&peer_pub_key)
+            .and_then(|devices| devices.remove(device_id));
+        if device.is_some() {
+            state
+                .claims
+                .insert(device_id.to_owned(), consumer_pub_key.to_owned());
+        }
+        Ok(device.map(|device| (peer_pub_key, device)))
+    }
+
+    #[inline(always)]
+    fn insert_remote_reservation(peer_pub_key: &str, device: DeviceSpec) {
+        let mut state = MARKET_MAKER_STATE.write().unwrap();
+        state
+            .remote_reservations
+            .insert(device.device_id.clone(), (peer_pub_key.to_owned(), device));
+    }
+
+    #[inline(always)]
+    fn remove_remote_reservation(device_id: &str) -> Option<(String, DeviceSpec)> {
+        let mut state = MARKET_MAKER_STATE.write().unwrap();
+        state.remote_reservations.remove(device_id)
+    }
+
+    #[inline(always)]
+    fn insert_peer_consumer(consumer_pub_key: &str, peer_pub_key: &str) {
+        let mut state = MARKET_MAKER_STATE.write().unwrap();
+        state
+            .peer_consumers
+            .insert(consumer_pub_key.to_owned(), peer_pub_key.to_owned());
+    }
+
+    /// Remove the Consumers that reserved devices through the peer.
+    /// Returns the peer_id of the removed Consumers.
+    #[inline(always)]
+    fn remove_peer_consumers(peer_pub_key: &str) -> Vec<String> {
+        let mut state = MARKET_MAKER_STATE.write().unwrap();
+        let mut consumers = Vec::new();
+        state.peer_consumers.retain(|consumer, peer| {
+            if peer == peer_pub_key {
+                consumers.push(consumer.clone());
+                false
+            } else {
+                true
+            }
+        });
+        consumers
+    }
+
+    #[inline(always)]
+    fn update_supplier_ip(supplier_id: &str, ip: &str) {
+        let mut state = MARKET_MAKER_STATE.write().unwrap();
+        if let Some(supplier) = state.suppliers.get_mut(supplier_id) {
+            supplier.bind_host = ip.to_owned();
+        }
+        Self::persist();
+    }
+
+    /// Update a device that its Supplier supplies again, e.g. after the Supplier
+    /// restarted. The device keeps its pools and its reservation.
+    #[inline(always)]
+    fn update_supplied_device(device: DeviceSpec) {
+        let mut state = MARKET_MAKER_STATE.write().unwrap();
+        if let Some(known) = state.devices.get_mut(&device.device_id) {
+            known.available_at_host = device.available_at_host;
+            known.available_at_port = device.available_at_port;
+            known.available_at_name = device.available_at_name;
+            known.device_info = device.device_info;
+            known.secure_comms = device.secure_comms;
+        }
+        Self::persist();
+    }
+
+    #[inline(always)]
+    fn update_device_supplier(device_id: &str, supplier_ip: &str) {
+        let mut state = MARKET_MAKER_STATE.write().unwrap();
+        if let Some(device) = state.devices.get_mut(device_id) {
+            device.available_at_host = supplier_ip.to_owned();
+        }
+        Self::persist();
+    }
+
+    // Read functions...
+
+    #[inline(always)]
+    fn get_state() -> MarketMakerState {
+        let state = MARKET_MAKER_STATE.read().unwrap();
+        state.clone()
+    }
+
+    #[inline(always)]
+    fn get_min_state() -> MarketMakerMinState {
+        let state = MARKET_MAKER_STATE.read().unwrap();
+        MarketMakerMinState {
+            num_suppliers: state.suppliers.len(),
+            suppliers: state.suppliers.values().cloned().collect(),
+            num_consumers: state.consumers.len(),
+            consumers: state.consumers.values().cloned().collect(),
+            num_market_makers: state.market_makers.len(),
+            devices: state.devices.clone(),
+            use_whitelists: state.use_whitelists,
+            available_devices: state.available_devices.clone(),
+            supplier_whitelist: state
+                .supplier_whitelist
+                .iter()
+                .map(base64::encode)
+                .collect(),
+            consumer_whitelist: state
+                .consumer_whitelist
+                .iter()
+                .map(base64::encode)
+                .collect(),
+            standby_whitelist: state.standby_whitelist.clone(),
+            primary: state.primary.clone(),
+            peers: state.peers.values().cloned().collect(),
+            peer_whitelist: state.peer_whitelist.clone(),
+            num_peer_devices: state.peer_devices.values().map(HashMap::len).sum(),
+            roles: state.roles.clone(),
+            quotas: state.get_quota_usage(),
+            pool_access: state.pool_access.clone(),
+        }
+    }
+
+    #[inline(always)]
+    fn get_snapshot() -> MarketMakerSnapshot {
+        let state = MARKET_MAKER_STATE.read().unwrap();
+        MarketMakerSnapshot::from(&*state)
+    }
+
+    #[inline(always)]
+    fn is_standby() -> bool {
+        let state = MARKET_MAKER_STATE.read().unwrap();
+        state.primary.is_some()
+    }
+
+    #[inline(always)]
+    fn get_primary() -> Option<MarketMakerSpec> {
+        let state = MARKET_MAKER_STATE.read().unwrap();
+        state.primary.clone()
+    }
+
+    #[inline(always)]
+    fn verify_primary(key: &str) -> bool {
+        let state = MARKET_MAKER_STATE.read().unwrap();
+        matches!(&state.primary, Some(primary) if primary.pub_key == key)
+    }
+
+    #[inline(always)]
+    fn get_standbys() -> Vec<MarketMakerSpec> {
+        let state = MARKET_MAKER_STATE.read().unwrap();
+        state.market_makers.values().cloned().collect()
+    }
+
+    /// Check if a MarketMaker other than `key` is connected as the standby.
+    #[inline(always)]
+    fn has_other_standby(key: &str) -> bool {
+        let state = MARKET_MAKER_STATE.read().unwrap();
+        state.market_makers.keys().any(|standby| standby != key)
+    }
+
+    #[inline(always)]
+    fn verify_peer(key: &str) -> bool {
+        let state = MARKET_MAKER_STATE.read().unwrap();
+        state.peers.contains_key(key)
+    }
+
+    #[inline(always)]
+    fn verify_peer_whitelist(key: &str) -> bool {
+        let state = MARKET_MAKER_STATE.read().unwrap();
+        state.peer_whitelist.contains(key)
+    }
+
+    #[inline(always)]
+    fn get_peer(pub_key: &str) -> Option<MarketMakerSpec> {
+        let state = MARKET_MAKER_STATE.read().unwrap();
+        state.peers.get(pub_key).cloned()
+    }
+
+    #[inline(always)]
+    fn get_peers() -> Vec<MarketMakerSpec> {
+        let state = MARKET_MAKER_STATE.read().unwrap();
+        state.peers.values().cloned().collect()
+    }
+
+    /// Get the available devices advertised by the peers. Devices with the same
+    /// device id as a device of this MarketMaker are left out.
+    #[inline(always)]
+    fn get_peer_devices() -> Vec<DeviceSpec> {
+        let state = MARKET_MAKER_STATE.read().unwrap();
+        state
+            .peer_devices
+            .values()
+            .flat_map(HashMap::values)
+            .filter(|device| !state.devices.contains_key(&device.device_id))
+            .cloned()
+            .collect()
+    }
+
+    /// Filter the devices of the peers, both the available devices advertised by
+    /// the peers and the devices reserved through the peers.
+    #[inline(always)]
+    fn filter_peer_devices(filter_vec: &DeviceFilterVec) -> Vec<DeviceSpec> {
+        let state = MARKET_MAKER_STATE.read().unwrap();
+        state
+            .peer_devices
+            .values()
+            .flat_map(HashMap::values)
+            .chain(state.remote_reservations.values().map(|(_, device)| device))
+            .filter(|device| !state.devices.contains_key(&device.device_id))
+            .filter(|device| filter_vec.matches(device))
+            .cloned()
+            .collect()
+    }
+
+    #[inline(always)]
+    fn get_remote_reservation(device_id: &str) -> Option<(String, DeviceSpec)> {
+        let state = MARKET_MAKER_STATE.read().unwrap();
+        state.remote_reservations.get(device_id).cloned()
+    }
+
+    /// Get the devices reserved by the Consumer through the peers, along with
+    /// the peer_id of the peer.
+    #[inline(always)]
+    fn get_remote_reservations_of(consumer_pub_key: &str) -> Vec<(String, String)> {
+        let state = MARKET_MAKER_STATE.read().unwrap();
+        state
+            .remote_reservations
+            .iter()
+            .filter(|(_, (_, device))| device.used_by == consumer_pub_key)
+            .map(|(device_id, (peer_pub_key, _))| (device_id.clone(), peer_pub_key.clone()))
+            .collect()
+    }
+
+    #[inline(always)]
+    fn get_peer_consumer(consumer_pub_key: &str) -> Option<String> {
+        let state = MARKET_MAKER_STATE.read().unwrap();
+        state.peer_consumers.get(consumer_pub_key).cloned()
+    }
+
+    #[inline(always)]
+    fn verify_standby_whitelist(key: &str) -> bool {
+        let state = MARKET_MAKER_STATE.read().unwrap();
+        state.standby_whitelist.contains(key)
+    }
+
+    #[inline(always)]
+    fn verify_supplier_whitelist(key: &str) -> bool {
+        let state = MARKET_MAKER_STATE.read().unwrap();
+        if state.use_whitelists {
+            state.supplier_whitelist.contains(key) || state.has_role_of(key, Role::Supplier)
+        } else {
+            true
+        }
+    }
+
+    #[inline(always)]
+    fn verify_consumer_whitelist(key: &str) -> bool {
+        let state = MARKET_MAKER_STATE.read().unwrap();
+        if state.use_whitelists {
+            state.consumer_whitelist.contains(key) || state.has_role_of(key, Role::Consumer)
+        } else {
+            true
+        }
+    }
+
+    #[inline(always)]
+    fn has_role(key: &str, role: Role) -> bool {
+        let state = MARKET_MAKER_STATE.read().unwrap();
+        state.has_role_of(key, role)
+    }
+
+    /// Get the maximum lease duration allowed for the Consumer by its quotas.
+    #[inline(always)]
+    fn get_max_lease(consumer_pub_key: &str) -> Option<u64> {
+        let state = MARKET_MAKER_STATE.read().unwrap();
+        state
+            .quotas_of(consumer_pub_key)
+            .into_iter()
+            .filter_map(|(_, quota)| quota.max_lease_secs)
+            .min()
+    }
+
+    /// Get the quotas that apply to the Consumer: its own quota, and the quotas
+    /// of the groups it is a member of.
+    fn quotas_of(&self, consumer_pub_key: &str) -> Vec<(QuotaTarget, &Quota)> {
+        let consumer_quota = self
+            .consumer_quotas
+            .get(consumer_pub_key)
+            .map(|quota| (QuotaTarget::Consumer(consumer_pub_key.to_owned()), quota));
+        let group_quotas = self
+            .consumer_groups
+            .iter()
+            .filter(|(_, members)| members.contains(consumer_pub_key))
+            .filter_map(|(group, _)| {
+                let quota = self.group_quotas.get(group)?;
+                Some((QuotaTarget::Group(group.clone()), quota))
+            });
+        consumer_quota.into_iter().chain(group_quotas).collect()
+    }
+
+    /// Check whether the quotas of the Consumer, and of its groups, allow more
+    /// reservations with the given lease. Returns the number of devices the Consumer
+    /// may still reserve, or the reason, if none.
+    fn check_quota(&self, consumer_pub_key: &str, lease_secs: Option<u64>) -> Result<usize, Error> {
+        let mut remaining = usize::MAX;
+        for (target, quota) in self.quotas_of(consumer_pub_key) {
+            if let Some(max_lease_secs) = quota.max_lease_secs {
+                if !matches!(lease_secs, Some(secs) if secs <= max_lease_secs) {
+                    return Err(Error::QuotaExceeded(format!(
+                        "Quota of {} requires a lease of at most {}s",
+                        target, max_lease_secs
+                    )));
+                }
+            }
+            if let Some(max_devices) = quota.max_devices {
+                let reserved = self.count_reserved(&target);
+                if reserved >= max_devices {
+                    return Err(Error::QuotaExceeded(format!(
+                        "Quota of {} exceeded: {} of {} devices reserved",
+                        target, reserved, max_devices
+                    )));
+                }
+                remaining = remaining.min(max_devices - reserved);
+            }
+        }
+        Ok(remaining)
+    }
+
+    /// Count the devices reserved by the Consumer, or by the members of the group,
+    /// including the devices reserved through the peers, and the devices being reserved.
+    fn count_reserved(&self, target: &QuotaTarget) -> usize {
+        let reserved_by = |key: &str| {
+            let local = self
+                .devices
+                .values()
+                .filter(|device| device.used_by == key)
+                .count();
+            let remote = self
+                .remote_reservations
+                .values()
+                .filter(|(_, device)| device.used_by == key)
+                .count();
+            let claimed = self.claims.values().filter(|claim| *claim == key).count();
+            local + remote + claimed
+        };
+        match target {
+            QuotaTarget::Consumer(key) => reserved_by(key),
+            QuotaTarget::Group(name) => self.consumer_groups.get(name).map_or(0, |members| {
+                members.iter().map(|key| reserved_by(key)).sum()
+            }),
+        }
+    }
+
+    fn get_quota_usage(&self) -> Vec<QuotaUsage> {
+        let consumer_targets = self
+            .consumer_quotas
+            .iter()
+            .map(|(key, quota)| (QuotaTarget::Consumer(key.clone()), quota));
+        let group_targets = self
+            .group_quotas
+            .iter()
+            .map(|(name, quota)| (QuotaTarget::Group(name.clone()), quota));
+        let mut usage = consumer_targets
+            .chain(group_targets)
+            .map(|(target, quota)| QuotaUsage {
+                reserved: self.count_reserved(&target),
+                target,
+                quota: quota.clone(),
+            })
+            .collect::<Vec<QuotaUsage>>();
+        usage.sort_by_key(|usage| usage.target.to_string());
+        usage
+    }
+
+    fn has_role_of(&self, key: &str, role: Role) -> bool {
+        matches!(self.roles.get(key), Some(roles) if roles.contains(&role))
+    }
+
+    /// Get the pools the Consumer has access to.
+    #[inline(always)]
+    fn get_accessible_pools(consumer_pub_key: &str) -> HashSet<String> {
+        let state = MARKET_MAKER_STATE.read().unwrap();
+        state.pools_of(consumer_pub_key)
+    }
+
+    /// Check whether the device exists, but is in pools the Consumer has no access to.
+    #[inline(always)]
+    fn is_device_restricted_for(device_id: &str, consumer_pub_key: &str) -> bool {
+        let state = MARKET_MAKER_STATE.read().unwrap();
+        let pools = state.pools_of(consumer_pub_key);
+        matches!(state.devices.get(device_id), Some(device) if !device.is_accessible_with(&pools))
+    }
+
+    /// Check whether the device may be seen by the peer: the Supplier of the device,
+    /// or a Consumer with access to the pools of the device.
+    #[inline(always)]
+    fn is_device_visible_to(device: &DeviceSpec, peer_id: &str) -> bool {
+        let state = MARKET_MAKER_STATE.read().unwrap();
+        device.available_at == peer_id || device.is_accessible_with(&state.pools_of(peer_id))
+    }
+
+    fn pools_of(&self, consumer_pub_key: &str) -> HashSet<String> {
+        self.pool_access
+            .iter()
+            .filter(|(_, consumers)| consumers.contains(consumer_pub_key))
+            .map(|(pool, _)| pool.clone())
+            .collect()
+    }
+
+    #[inline(always)]
+    fn is_restored_supplier(supplier_pub_key: &str) -> bool {
+        let state = MARKET_MAKER_STATE.read().unwrap();
+        state.restored_suppliers.contains(supplier_pub_key)
+    }
+
+    #[inline(always)]
+    fn is_restored_consumer(consumer_pub_key: &str) -> bool {
+        let state = MARKET_MAKER_STATE.read().unwrap();
+        state.restored_consumers.contains(consumer_pub_key)
+    }
+
+    #[inline(always)]
+    fn supplier_exists(supplier_pub_key: &str) -> bool {
+        let state = MARKET_MAKER_STATE.read().unwrap();
+        state.suppliers.contains_key(supplier_pub_key)
+    }
+
+    #[inline(always)]
+    fn get_consumer(consumer_pub_key: &str) -> Option<ConsumerSpec> {
+        let state = MARKET_MAKER_STATE.read().unwrap();
+        state.consumers.get(consumer_pub_key).cloned()
+    }
+    #[inline(always)]
+    fn consumer_exists(consumer_pub_key: &str) -> bool {
+        let state = MARKET_MAKER_STATE.read().unwrap();
+        state.consumers.contains_key(consumer_pub_key)
+    }
+
+    #[cfg(test)]
+    #[inline(always)]
+    fn get_available_devices() -> Vec<DeviceSpec> {
+        let state = MARKET_MAKER_STATE.read().unwrap();
+        state.available_devices.values().cloned().collect()
+    }
+
+    /// Get the available devices to advertise to peer MarketMakers, sorted by device_id.
+    /// Devices in pools are only accessible to the Consumers of this MarketMaker.
+    #[inline(always)]
+    fn get_advertised_devices() -> Vec<DeviceSpec> {
+        let state = MARKET_MAKER_STATE.read().unwrap();
+        let mut devices = state
+            .available_devices
+            .values()
+            .filter(|device| device.pools.is_empty())
+            .cloned()
+            .collect::<Vec<_>>();
+        devices.sort_by(|a, b| a.device_id.cmp(&b.device_id));
+        devices
+    }
+
+    /// Get the available devices the Consumer has access to, by its pools.
+    #[inline(always)]
+    fn get_available_devices_for(consumer_pub_key: &str) -> Vec<DeviceSpec> {
+        let state = MARKET_MAKER_STATE.read().unwrap();
+        let pools = state.pools_of(consumer_pub_key);
+        state
+            .available_devices
+            .values()
+            .filter(|device| device.is_accessible_with(&pools))
+            .cloned()
+            .collect()
+    }
+
+    #[inline(always)]
+    fn get_consumers_affected_by(supplier_pub_key: &str) -> Vec<(String, String, String)> {
+        let state = MARKET_MAKER_STATE.read().unwrap();
+        // iterate through all the devices and find the ones that are affected by the supplier.
+        state
+            .devices
+            .values()
+            .filter_map(|device| {
+                if device.available_at == supplier_pub_key {
+                    Some((
+                        device.used_by.clone(),
+                        device.used_by_host.clone(),
+                        device.device_id.clone(),
+                    ))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    #[inline(always)]
+    fn get_expired_leases(now: u64) -> Vec<(String, String, String)> {
+        let state = MARKET_MAKER_STATE.read().unwrap();
+        state
+            .devices
+            .values()
+            .filter(|device| matches!(device.lease_expiry, Some(expiry) if expiry <= now))
+            .map(|device| {
+                (
+                    device.used_by.clone(),
+                    device.used_by_host.clone(),
+                    device.device_id.clone(),
+                )
+            })
+            .collect()
+    }
+
+    #[inline(always)]
+    fn get_consumer_port(consumer_pub_key: &str) -> Option<u16> {
+        let state = MARKET_MAKER_STATE.read().unwrap();
+        state
+            .consumers
+            .get(consumer_pub_key)
+            .map(|consumer| consumer.bind_port)
+    }
+
+    #[inline(always)]
+    fn get_consumer_name(consumer_pub_key: &str) -> Option<String> {
+        let state = MARKET_MAKER_STATE.read().unwrap();
+        state
+            .consumers
+            .get(consumer_pub_key)
+            .map(|consumer| consumer.name.clone())
+    }
+
+    #[inline(always)]
+    fn is_device_used_by(device_id: &str, consumer_pub_key: &str) -> bool {
+        let state = MARKET_MAKER_STATE.read().unwrap();
+        let device = state.devices.get(device_id);
+        device.map_or(false, |device| device.used_by == consumer_pub_key)
     }
 
     #[inline(always)]
@@ -496,70 +1621,446 @@ impl MarketMakerState {
         state.consumers.contains_key(key)
     }
 
-    #[inline(always)]
-    fn verify_supplier(key: &str) -> bool {
-        let state = MARKET_MAKER_STATE.read().unwrap();
-        state.suppliers.contains_key(key)
+    #[inline(always)]
+    fn verify_supplier(key: &str) -> bool {
+        let state = MARKET_MAKER_STATE.read().unwrap();
+        state.suppliers.contains_key(key)
+    }
+
+    #[inline(always)]
+    fn get_supplier(supplier_pub_key: &str) -> Option<SupplierSpec> {
+        let state = MARKET_MAKER_STATE.read().unwrap();
+        state.suppliers.get(supplier_pub_key).cloned()
+    }
+
+    #[inline(always)]
+    fn filter_devices(filter_vec: &DeviceFilterVec) -> Vec<DeviceSpec> {
+        let state = MARKET_MAKER_STATE.read().unwrap();
+        state
+            .devices
+            .values()
+            .filter(|device| filter_vec.matches(device))
+            .cloned()
+            .collect()
+    }
+
+    /// Filter the devices the Consumer has access to, by its pools.
+    #[inline(always)]
+    fn filter_devices_for(filter_vec: &DeviceFilterVec, consumer_pub_key: &str) -> Vec<DeviceSpec> {
+        let pools = MarketMakerState::get_accessible_pools(consumer_pub_key);
+        MarketMakerState::filter_devices(filter_vec)
+            .into_iter()
+            .filter(|device| device.is_accessible_with(&pools))
+            .collect()
+    }
+
+    #[inline(always)]
+    fn supplier_supported(supplier_version: &str) -> bool {
+        debug!(
+            "Checking if supplier version {} is supported",
+            supplier_version
+        );
+        // We neither establish nor support any backwards compatibility
+        // till we reach 1.0. What this means in practice is that,
+        // for now, we only accept connections from suppliers that
+        // have the same version as the market maker.
+        ADBORC_VERSION == supplier_version
+    }
+
+    #[inline(always)]
+    fn consumer_supported(consumer_version: &str) -> bool {
+        debug!(
+            "Checking if consumer version {} is supported",
+            consumer_version
+        );
+        // We neither establish nor support any backwards compatibility
+        // till we reach 1.0. What this means in practice is that,
+        // for now, we only accept connections from consumers that
+        // have the same version as the market maker.
+        ADBORC_VERSION == consumer_version
+    }
+}
+
+impl MarketMaker {
+    /// Construct a new MarketMaker and start the listen server.
+    /// If a state store is configured, the state persisted by a previous
+    /// MarketMaker instance is restored before accepting requests.
+    pub(super) fn new() -> Result<MarketMaker, Error> {
+        if let Some(store) = store::configured_store() {
+            MarketMaker::restore_state(store.as_ref());
+            MarketMakerState::attach_store(store);
+        }
+        MarketMaker::start_undertaker_thread();
+        MarketMaker::start_replication_thread();
+        MarketMaker::start_peering_thread();
+        let market_maker = MarketMaker;
+        Ok(market_maker)
+    }
+
+    /// Construct a new MarketMaker as a standby of the primary MarketMaker at
+    /// `primary_host:primary_port`. The standby keeps a replica of the state of
+    /// the primary, and takes over as the primary if the primary stops sending
+    /// heartbeats.
+    pub(super) fn new_standby(primary_host: &str, primary_port: u16) -> Result<MarketMaker, Error> {
+        MarketMaker::connect_to_primary(primary_host, primary_port)?;
+        MarketMaker::start_undertaker_thread();
+        MarketMaker::start_replication_thread();
+        MarketMaker::start_peering_thread();
+        MarketMaker::start_standby_thread();
+        let market_maker = MarketMaker;
+        Ok(market_maker)
+    }
+
+    /// Connect to the primary MarketMaker as its standby, and replicate its state.
+    fn connect_to_primary(primary_host: &str, primary_port: u16) -> Result<(), Error> {
+        let client = TCPClient::new(primary_host, primary_port)?;
+        let request = MarketMakerRequest::StandbyConnect {
+            market_maker: MarketMakerSpec::default(),
+        };
+        let response = client.send_request(request, None)?;
+
+        let response = MarketMakerResponse::from_str(&response).unwrap();
+        match response {
+            MarketMakerResponse::StandbyConnected { snapshot, pub_key } => {
+                MarketMakerState::set_primary(MarketMakerSpec {
+                    bind_host: primary_host.to_string(),
+                    bind_port: primary_port,
+                    pub_key,
+                    adborc_version: ADBORC_VERSION.to_string(),
+                });
+                MarketMakerState::replicate(*snapshot);
+                HeartBeatState::primary_heartbeat();
+                Ok(())
+            }
+            MarketMakerResponse::StandbyNotConnected { reason } => {
+                error!("Standby not connected: {}", reason);
+                Err(reason)
+            }
+            response => {
+                error!("Unexpected response from Market Maker: {:?}", response);
+                Err(Error::Protocol(format!(
+                    "Unexpected response from Market Maker: {:?}",
+                    response
+                )))
+            }
+        }
+    }
+
+    /// Send the state of the MarketMaker to its standby, whenever the state changes.
+    /// The state is also sent every `PRIMARY_HEARTBEAT_INTERVAL`, as a heartbeat.
+    /// A standby that cannot be reached is dropped. It connects again once it
+    /// misses the heartbeats of the primary.
+    fn start_replication_thread() {
+        thread::spawn(|| {
+            let mut replicated: Option<MarketMakerSnapshot> = None;
+            let mut last_sent = Instant::now();
+            loop {
+                thread::sleep(REPLICATION_INTERVAL);
+                // Stop the thread if the market maker has terminated.
+                if !SysState::market_maker_is_some() {
+                    break;
+                }
+                let standbys = MarketMakerState::get_standbys();
+                if MarketMakerState::is_standby() || standbys.is_empty() {
+                    replicated = None;
+                    continue;
+                }
+                let snapshot = MarketMakerState::get_snapshot();
+                if replicated.as_ref() == Some(&snapshot)
+                    && last_sent.elapsed() < PRIMARY_HEARTBEAT_INTERVAL
+                {
+                    continue;
+                }
+                for standby in standbys {
+                    let request = MarketMakerRequest::ReplicateState {
+                        snapshot: Box::new(snapshot.clone()),
+                    };
+                    let response = TCPClient::new(&standby.bind_host, standby.bind_port)
+                        .and_then(|client| client.send_request(request, None));
+                    match response.map(|response| MarketMakerResponse::from_str(&response)) {
+                        Ok(Ok(MarketMakerResponse::StateReplicated)) => {}
+                        Ok(response) => {
+                            warn!("Standby MarketMaker rejected the state: {:?}", response);
+                            MarketMakerState::remove_standby(&standby.pub_key);
+                        }
+                        Err(e) => {
+                            warn!("Unable to replicate state to standby MarketMaker: {}", e);
+                            MarketMakerState::remove_standby(&standby.pub_key);
+                        }
+                    }
+                }
+                replicated = Some(snapshot);
+                last_sent = Instant::now();
+            }
+        });
+    }
+
+    /// Watch the heartbeats of the primary MarketMaker, on a standby.
+    /// If the primary misses its heartbeats for `STANDBY_TAKEOVER_TIMEOUT`,
+    /// and cannot be connected to again, the standby takes over as the primary.
+    fn start_standby_thread() {
+        thread::spawn(|| loop {
+            thread::sleep(REPLICATION_INTERVAL);
+            // Stop the thread if the market maker has terminated or taken over.
+            let primary = match MarketMakerState::get_primary() {
+                Some(primary) if SysState::market_maker_is_some() => primary,
+                _ => break,
+            };
+            if HeartBeatState::since_primary_heartbeat() < STANDBY_TAKEOVER_TIMEOUT {
+                continue;
+            }
+            warn!("Primary MarketMaker missed its heartbeats");
+            match MarketMaker::connect_to_primary(&primary.bind_host, primary.bind_port) {
+                Ok(()) => info!("Connected to the primary MarketMaker again"),
+                Err(e) => {
+                    warn!("Primary MarketMaker is unreachable: {}", e);
+                    MarketMaker::take_over();
+                    break;
+                }
+            }
+        });
+    }
+
+    /// Connect to the MarketMaker at `host:port` as its peer. Both MarketMakers
+    /// must have whitelisted each other as peers.
+    /// Returns the peer, along with its available devices.
+    fn connect_peer(host: &str, port: u16) -> Result<(MarketMakerSpec, Vec<DeviceSpec>), Error> {
+        let client = TCPClient::new(host, port)?;
+        let request = MarketMakerRequest::PeerConnect {
+            market_maker: MarketMakerSpec::default(),
+        };
+        let response = client.send_request(request, None)?;
+
+        let response = MarketMakerResponse::from_str(&response).unwrap();
+        match response {
+            MarketMakerResponse::PeerConnected {
+                mut market_maker,
+                devices,
+            } => {
+                if !MarketMakerState::verify_peer_whitelist(&market_maker.pub_key) {
+                    return Err(Error::NotAuthorized(
+                        "Peer not in peer whitelist".to_string(),
+                    ));
+                }
+                market_maker.bind_host = host.to_string();
+                market_maker.bind_port = port;
+                let pub_key = market_maker.pub_key.clone();
+                MarketMakerState::insert_peer(market_maker.clone());
+                MarketMakerState::set_peer_devices(&pub_key, devices.clone());
+                HeartBeatState::peer_heartbeat(&pub_key);
+                Ok((market_maker, devices))
+            }
+            MarketMakerResponse::PeerNotConnected { reason } => {
+                error!("Peer not connected: {}", reason);
+                Err(reason)
+            }
+            response => {
+                error!("Unexpected response from Market Maker: {:?}", response);
+                Err(Error::Protocol(format!(
+                    "Unexpected response from Market Maker: {:?}",
+                    response
+                )))
+            }
+        }
+    }
+
+    /// Advertise the available devices of the MarketMaker to its peers, whenever
+    /// they change. The devices are also advertised every `PEER_HEARTBEAT_INTERVAL`,
+    /// as a heartbeat. A peer that rejects the advertisement is removed.
+    fn start_peering_thread() {
+        thread::spawn(|| {
+            let mut advertised: Option<Vec<DeviceSpec>> = None;
+            let mut last_sent = Instant::now();
+            loop {
+                thread::sleep(ADVERTISE_INTERVAL);
+                // Stop the thread if the market maker has terminated.
+                if !SysState::market_maker_is_some() {
+                    break;
+                }
+                let peers = MarketMakerState::get_peers();
+                if MarketMakerState::is_standby() || peers.is_empty() {
+                    advertised = None;
+                    continue;
+                }
+                let devices = MarketMakerState::get_advertised_devices();
+                if advertised.as_ref() == Some(&devices)
+                    && last_sent.elapsed() < PEER_HEARTBEAT_INTERVAL
+                {
+                    continue;
+                }
+                for peer in peers {
+                    let request = MarketMakerRequest::AdvertiseDevices {
+                        devices: devices.clone(),
+                    };
+                    let response = TCPClient::new(&peer.bind_host, peer.bind_port)
+                        .and_then(|client| client.send_request(request, None));
+                    match response.map(|response| MarketMakerResponse::from_str(&response)) {
+                        Ok(Ok(MarketMakerResponse::DevicesAdvertised)) => {}
+                        Ok(response) => {
+                            warn!("Peer MarketMaker rejected the devices: {:?}", response);
+                            MarketMakerState::remove_peer(&peer.pub_key);
+                            HeartBeatState::remove_peer(&peer.pub_key);
+                        }
+                        Err(e) => warn!("Unable to advertise devices to peer MarketMaker: {}", e),
+                    }
+                }
+                advertised = Some(devices);
+                last_sent = Instant::now();
+            }
+        });
     }
 
-    #[inline(always)]
-    fn get_supplier(supplier_pub_key: &str) -> Option<SupplierSpec> {
-        let state = MARKET_MAKER_STATE.read().unwrap();
-        state.suppliers.get(supplier_pub_key).cloned()
+    /// Send the request to the peer, and parse its response.
+    fn send_to_peer(
+        peer_pub_key: &str,
+        request: MarketMakerRequest,
+    ) -> Result<MarketMakerResponse, Error> {
+        let peer = MarketMakerState::get_peer(peer_pub_key)
+            .ok_or_else(|| Error::Unreachable("Peer not found".to_string()))?;
+        let client = TCPClient::new(&peer.bind_host, peer.bind_port)?;
+        let response = client.send_request(request, None)?;
+        MarketMakerResponse::from_str(&response)
+            .map_err(|_| Error::Protocol("Failed to parse peer response".to_string()))
     }
 
-    #[inline(always)]
-    #[allow(dead_code)]
-    fn filter_devices(filter_vec: &DeviceFilterVec) -> Vec<DeviceSpec> {
-        let state = MARKET_MAKER_STATE.read().unwrap();
-        state
-            .devices
-            .values()
-            .filter(|device| {
-                for device_filter in filter_vec.filters.iter() {
-                    if !device_filter.filter(device) {
-                        return false;
-                    }
+    /// Reserve the device advertised by a peer, for the Consumer.
+    /// The peer reserves the device on its network, and the Consumer uses it
+    /// like a device of this MarketMaker.
+    fn broker_reservation(
+        device_id: &str,
+        consumer_pub_key: &str,
+        lease_secs: Option<u64>,
+    ) -> MarketMakerResponse {
+        let consumer = match MarketMakerState::get_consumer(consumer_pub_key) {
+            Some(consumer) => consumer,
+            None => {
+                return MarketMakerResponse::DeviceNotReserved {
+                    reason: Error::InvalidState("Fatal: Consumer not found".to_string()),
                 }
-                true
-            })
-            .cloned()
-            .collect()
+            }
+        };
+        let peer_device =
+            MarketMakerState::claim_peer_device_for(device_id, consumer_pub_key, lease_secs);
+        let (peer_pub_key, _) = match peer_device {
+            Ok(Some(peer_device)) => peer_device,
+            Ok(None) => {
+                return MarketMakerResponse::DeviceNotReserved {
+                    reason: Error::DeviceUnavailable("Device not available".to_string()),
+                }
+            }
+            Err(reason) => return MarketMakerResponse::DeviceNotReserved { reason },
+        };
+        let request = MarketMakerRequest::PeerReserveDevice {
+            device_id: device_id.to_string(),
+            consumer,
+            lease_secs,
+        };
+        let response = match MarketMaker::send_to_peer(&peer_pub_key, request) {
+            Ok(MarketMakerResponse::DeviceReserved { device, peer_id }) => {
+                info!(
+                    "Reserved device {} through peer {}",
+                    device_id, peer_pub_key
+                );
+                MarketMakerState::insert_remote_reservation(&peer_pub_key, device.clone());
+                MarketMakerResponse::DeviceReserved { device, peer_id }
+            }
+            Ok(MarketMakerResponse::DeviceNotReserved { reason }) => {
+                MarketMakerResponse::DeviceNotReserved { reason }
+            }
+            Ok(response) => MarketMakerResponse::DeviceNotReserved {
+                reason: Error::Protocol(format!("Unexpected response from peer: {}", response)),
+            },
+            Err(e) => MarketMakerResponse::DeviceNotReserved {
+                reason: Error::Unreachable(format!("Could not connect to peer: {}", e)),
+            },
+        };
+        MarketMakerState::release_claim(device_id);
+        response
     }
 
-    #[inline(always)]
-    fn supplier_supported(supplier_version: &str) -> bool {
-        debug!(
-            "Checking if supplier version {} is supported",
-            supplier_version
-        );
-        // We neither establish nor support any backwards compatibility
-        // till we reach 1.0. What this means in practice is that,
-        // for now, we only accept connections from suppliers that
-        // have the same version as the market maker.
-        ADBORC_VERSION == supplier_version
+    /// Release the devices reserved by the Consumer through the peers.
+    fn release_remote_devices_of(consumer_pub_key: &str) {
+        for (device_id, peer_pub_key) in
+            MarketMakerState::get_remote_reservations_of(consumer_pub_key)
+        {
+            MarketMakerState::remove_remote_reservation(&device_id);
+            let request = MarketMakerRequest::PeerReleaseDevice {
+                device_id,
+                consumer: consumer_pub_key.to_string(),
+            };
+            if let Some(peer) = MarketMakerState::get_peer(&peer_pub_key) {
+                if let Ok(client) = TCPClient::new(&peer.bind_host, peer.bind_port) {
+                    client.send_no_wait(request);
+                }
+            }
+        }
     }
 
-    #[inline(always)]
-    fn consumer_supported(consumer_version: &str) -> bool {
-        debug!(
-            "Checking if consumer version {} is supported",
-            consumer_version
-        );
-        // We neither establish nor support any backwards compatibility
-        // till we reach 1.0. What this means in practice is that,
-        // for now, we only accept connections from consumers that
-        // have the same version as the market maker.
-        ADBORC_VERSION == consumer_version
+    /// Send the request to the Consumer at `host`. Consumers that reserved the
+    /// device through a peer are notified by the peer.
+    fn notify_consumer(consumer_pub_key: &str, host: &str, request: ConsumerRequest) {
+        if let Some(port) = MarketMakerState::get_consumer_port(consumer_pub_key) {
+            if let Ok(client) = TCPClient::new(host, port) {
+                client.send_no_wait(request);
+            }
+            return;
+        }
+        let peer = MarketMakerState::get_peer_consumer(consumer_pub_key)
+            .and_then(|peer_pub_key| MarketMakerState::get_peer(&peer_pub_key));
+        if let Some(peer) = peer {
+            if let Ok(client) = TCPClient::new(&peer.bind_host, peer.bind_port) {
+                let request = MarketMakerRequest::PeerNotifyConsumer {
+                    consumer: consumer_pub_key.to_string(),
+                    request: Box::new(request),
+                };
+                client.send_no_wait(request);
+            }
+        }
     }
-}
 
-impl MarketMaker {
-    /// Construct a new MarketMaker and start the listen server.
-    pub(super) fn new() -> io::Result<MarketMaker> {
-        MarketMaker::start_undertaker_thread();
-        let market_maker = MarketMaker;
-        Ok(market_maker)
+    /// Take over as the primary MarketMaker, with the state replicated from
+    /// the previous primary. Suppliers and Consumers fall back to this MarketMaker
+    /// and connect again. Those that never do are removed by the undertaker.
+    fn take_over() {
+        info!("Taking over as the primary MarketMaker");
+        let snapshot = MarketMakerState::get_snapshot();
+        for supplier in snapshot.suppliers.keys() {
+            HeartBeatState::add_supplier(supplier);
+        }
+        for consumer in snapshot.consumers.keys() {
+            HeartBeatState::add_consumer(consumer);
+        }
+        if let Some(store) = store::configured_store() {
+            MarketMakerState::attach_store(store);
+        }
+        MarketMakerState::promote();
+        SavedModes::update(|modes| modes.standby_of = None);
+    }
+
+    fn restore_state(store: &dyn StateStore) {
+        let snapshot = match store.load() {
+            Ok(Some(snapshot)) => snapshot,
+            Ok(None) => return,
+            Err(e) => {
+                error!("Failed to load persisted MarketMaker state: {}", e);
+                return;
+            }
+        };
+        info!(
+            "Restoring MarketMaker state: {} supplier(s), {} consumer(s), {} device(s)",
+            snapshot.suppliers.len(),
+            snapshot.consumers.len(),
+            snapshot.devices.len()
+        );
+        // Restored peers are tracked by the undertaker like freshly connected
+        // ones, so that peers which never come back are eventually removed.
+        for supplier in snapshot.suppliers.keys() {
+            HeartBeatState::add_supplier(supplier);
+        }
+        for consumer in snapshot.consumers.keys() {
+            HeartBeatState::add_consumer(consumer);
+        }
+        MarketMakerState::restore(snapshot);
     }
 
     fn start_undertaker_thread() {
@@ -570,41 +2071,88 @@ impl MarketMaker {
                 if !SysState::market_maker_is_some() {
                     break;
                 }
+                // Peers send their heartbeats to the primary, not to the standby.
+                if MarketMakerState::is_standby() {
+                    continue;
+                }
                 HeartBeatState::decrement_heartbeats();
                 let dead_suppliers = HeartBeatState::get_dead_suppliers();
                 let dead_consumers = HeartBeatState::get_dead_consumers();
+                let dead_peers = HeartBeatState::decrement_peer_heartbeats();
 
                 HeartBeatState::remove_dead_suppliers();
                 HeartBeatState::remove_dead_consumers();
 
+                MarketMaker::expire_leases();
+
                 thread::spawn(move || {
                     for supplier in dead_suppliers.iter() {
+                        events::publish(MarketEvent::HeartbeatLost {
+                            peer_id: supplier.clone(),
+                        });
                         MarketMakerState::remove_supplier(supplier);
+                        events::publish(MarketEvent::SupplierLeft {
+                            peer_id: supplier.clone(),
+                        });
                         let consumers = MarketMakerState::get_consumers_affected_by(supplier);
                         for (consumer_pub_key, host, device_id) in consumers {
                             MarketMakerState::remove_device(&device_id);
+                            events::publish(MarketEvent::DeviceReclaimed {
+                                device_id: device_id.clone(),
+                            });
                             if host.is_empty() {
                                 continue;
                             }
-                            let port = MarketMakerState::get_consumer_port(&consumer_pub_key);
-                            if let Some(port) = port {
-                                if let Ok(client) = TCPClient::new(host.as_str(), port) {
-                                    let request =
-                                        ConsumerRequest::SupplierDisconnected { device_id };
-                                    client.send_no_wait(request);
-                                }
-                            }
+                            let request = ConsumerRequest::SupplierDisconnected { device_id };
+                            MarketMaker::notify_consumer(&consumer_pub_key, &host, request);
+                        }
+                    }
+                    // Devices of a peer that stopped advertising can no longer be reserved.
+                    // Devices reserved for the Consumers of the peer are reclaimed, as the
+                    // peer can no longer release them.
+                    for peer in dead_peers.iter() {
+                        warn!("Peer MarketMaker {} missed its heartbeats", peer);
+                        MarketMakerState::clear_peer_devices(peer);
+                        for consumer in MarketMakerState::remove_peer_consumers(peer) {
+                            MarketMakerState::reclaim_devices_used_by(&consumer);
                         }
                     }
                     MarketMakerState::update_available_devices();
                     for consumer in dead_consumers.iter() {
+                        events::publish(MarketEvent::HeartbeatLost {
+                            peer_id: consumer.clone(),
+                        });
                         MarketMakerState::remove_consumer(consumer);
+                        events::publish(MarketEvent::ConsumerLeft {
+                            peer_id: consumer.clone(),
+                        });
+                        MarketMaker::release_remote_devices_of(consumer);
                     }
                 });
             }
         });
     }
 
+    /// Release all devices whose lease has expired.
+    /// The Consumer using the device is notified before the device is released.
+    fn expire_leases() {
+        let expired = MarketMakerState::get_expired_leases(util::unix_time_now());
+        if expired.is_empty() {
+            return;
+        }
+        for (consumer_pub_key, host, device_id) in expired {
+            info!("Lease expired for device {}", device_id);
+            let request = ConsumerRequest::LeaseExpired {
+                device_id: device_id.clone(),
+            };
+            MarketMaker::notify_consumer(&consumer_pub_key, &host, request);
+            MarketMaker::release_device(&device_id);
+        }
+        MarketMakerState::update_available_devices();
+        // Serving the queue involves network requests. Keep them off the undertaker thread.
+        thread::spawn(MarketMaker::serve_wait_queue);
+    }
+
     fn handle_supplier_ip_change(supplier_id: String, new_ip: String) {
         debug!("Supplier {} changed IP to {}", supplier_id, new_ip);
         MarketMakerState::update_supplier_ip(&supplier_id, &new_ip);
@@ -613,20 +2161,16 @@ impl MarketMaker {
             if host.is_empty() {
                 continue;
             }
-            let port = MarketMakerState::get_consumer_port(&consumer_pub_key);
-            if let Some(port) = port {
-                if let Ok(client) = TCPClient::new(host.as_str(), port) {
-                    let request = ConsumerRequest::SupplierDisconnected {
-                        device_id: device_id.clone(),
-                    };
-                    client.send_no_wait(request);
-                }
-            }
+            let request = ConsumerRequest::SupplierDisconnected {
+                device_id: device_id.clone(),
+            };
+            MarketMaker::notify_consumer(&consumer_pub_key, &host, request);
             MarketMaker::release_device(&device_id);
             // Update the device to reflect the new supplier ip.
             MarketMakerState::update_device_supplier(&device_id, &new_ip);
         }
         MarketMakerState::update_available_devices();
+        MarketMaker::serve_wait_queue();
     }
 
     fn release_device(device_id: &str) {
@@ -636,6 +2180,9 @@ impl MarketMaker {
         }
         let device = device.unwrap();
         MarketMakerState::release_device(device_id);
+        events::publish(MarketEvent::DeviceReleased {
+            device_id: device_id.to_string(),
+        });
         // If an encrypted tunnel was used, we need to notify the supplier.
         if device.secure_comms {
             let supplier_id = device.available_at.clone();
@@ -655,9 +2202,216 @@ impl MarketMaker {
         }
     }
 
+    /// Reserve the device, taken from the available devices, for the Consumer.
+    /// If the Supplier of the device uses secure mode, an encrypted tunnel
+    /// is started on the Supplier for the Consumer.
+    fn reserve_device(
+        device: DeviceSpec,
+        consumer_pub_key: &str,
+        consumer_host: String,
+        lease_secs: Option<u64>,
+    ) -> MarketMakerResponse {
+        let consumer_name = MarketMakerState::get_consumer_name(consumer_pub_key);
+        if consumer_name.is_none() {
+            MarketMakerState::release_claim(&device.device_id);
+            return MarketMakerResponse::DeviceNotReserved {
+                reason: Error::InvalidState("Fatal: Consumer not found".to_string()),
+            };
+        }
+        MarketMaker::reserve_device_for(
+            device,
+            consumer_pub_key,
+            consumer_name.unwrap(),
+            consumer_host,
+            lease_secs,
+        )
+    }
+
+    /// Get the expiry of a lease of `lease_secs` seconds, starting now.
+    /// The duration comes from a Consumer or a peer, so a lease whose expiry
+    /// would overflow is rejected.
+    fn lease_expiry(lease_secs: u64) -> Result<u64, Error> {
+        util::unix_time_now()
+            .checked_add(lease_secs)
+            .ok_or_else(|| Error::InvalidInput("Lease duration is too long".to_string()))
+    }
+
+    /// Reserve the device, claimed for the Consumer with the given name and host.
+    /// The Consumer may be connected to a peer of this MarketMaker.
+    /// The claim on the device is released, whether the reservation succeeds or not.
+    fn reserve_device_for(
+        device: DeviceSpec,
+        consumer_pub_key: &str,
+        consumer_name: String,
+        consumer_host: String,
+        lease_secs: Option<u64>,
+    ) -> MarketMakerResponse {
+        let device_id = device.device_id.clone();
+        let response = MarketMaker::reserve_claimed_device(
+            device,
+            consumer_pub_key,
+            consumer_name,
+            consumer_host,
+            lease_secs,
+        );
+        MarketMakerState::release_claim(&device_id);
+        response
+    }
+
+    /// Reserve the claimed device. See `reserve_device_for`.
+    fn reserve_claimed_device(
+        mut device: DeviceSpec,
+        consumer_pub_key: &str,
+        consumer_name: String,
+        consumer_host: String,
+        lease_secs: Option<u64>,
+    ) -> MarketMakerResponse {
+        let lease_expiry = match lease_secs.map(MarketMaker::lease_expiry).transpose() {
+            Ok(lease_expiry) => lease_expiry,
+            Err(reason) => return MarketMakerResponse::DeviceNotReserved { reason },
+        };
+        device.used_by = consumer_pub_key.to_string();
+        device.used_by_name = consumer_name;
+        device.used_by_host = consumer_host;
+        device.lease_expiry = lease_expiry;
+        let device_clone = device.clone();
+
+        // If supplier doesn't require secure connection, we can reserve the device.
+        if !device.secure_comms {
+            MarketMakerState::insert_device(device_clone);
+            events::publish(MarketEvent::DeviceReserved {
+                device: device.clone(),
+            });
+            MarketMakerResponse::DeviceReserved {
+                device,
+                peer_id: None,
+            }
+        } else {
+            let supplier_id = device.available_at.clone();
+            let supplier = MarketMakerState::get_supplier(&supplier_id);
+            if supplier.is_none() {
+                return MarketMakerResponse::DeviceNotReserved {
+                    reason: Error::Unreachable("Supplier not found".to_string()),
+                };
+            }
+            let supplier = supplier.unwrap();
+            let host = supplier.bind_host.as_str();
+            let port = supplier.bind_port;
+            if let Ok(client) = TCPClient::new(host, port) {
+                let request = SupplierRequest::StartSecureTunnel {
+                    device_id: device.device_id.clone(),
+                    port: device.available_at_port,
+                    pub_key: consumer_pub_key.to_string(),
+                };
+                let response = client.send_request(request, None);
+                if response.is_err() {
+                    return MarketMakerResponse::DeviceNotReserved {
+                        reason: Error::Unreachable("Could not connect to supplier".to_string()),
+                    };
+                }
+                let response = response.unwrap();
+                let response = SupplierResponse::from_str(&response);
+                if response.is_err() {
+                    return MarketMakerResponse::DeviceNotReserved {
+                        reason: Error::Protocol("Failed to parse server response".to_string()),
+                    };
+                }
+                let response = response.unwrap();
+                match response {
+                    SupplierResponse::SecureTunnelStarted { port } => {
+                        MarketMakerState::insert_device(device_clone);
+                        events::publish(MarketEvent::DeviceReserved {
+                            device: device.clone(),
+                        });
+                        device.available_at_port = port;
+                        MarketMakerResponse::DeviceReserved {
+                            device,
+                            peer_id: Some(supplier_id),
+                        }
+                    }
+                    SupplierResponse::SecureTunnelStartFailure { reason } => {
+                        MarketMakerResponse::DeviceNotReserved { reason }
+                    }
+                    _ => MarketMakerResponse::DeviceNotReserved {
+                        reason: Error::Protocol("Unexpected response from supplier".to_string()),
+                    },
+                }
+            } else {
+                MarketMakerResponse::DeviceNotReserved {
+                    reason: Error::Unreachable("Could not connect to supplier".to_string()),
+                }
+            }
+        }
+    }
+
+    /// Reserve available devices for the Consumers in the wait queue, in the
+    /// order in which they joined the queue. The Consumer is notified of the
+    /// reservation, and the device is released again if the Consumer cannot use it.
+    fn serve_wait_queue() {
+        while let Some((entry, device)) = MarketMakerState::pop_waiting_with_device() {
+            let device_id = device.device_id.clone();
+            let consumer = MarketMakerState::get_consumer(&entry.consumer);
+            if consumer.is_none() {
+                MarketMakerState::release_claim(&device_id);
+                MarketMakerState::update_available_devices();
+                continue;
+            }
+            let consumer = consumer.unwrap();
+            let host = consumer.bind_host.clone();
+            let response =
+                MarketMaker::reserve_device(device, &entry.consumer, host, entry.lease_secs);
+            let (device, peer_id) = match response {
+                MarketMakerResponse::DeviceReserved { device, peer_id } => (device, peer_id),
+                response => {
+                    warn!(
+                        "Could not reserve device {} for queued consumer {}: {}",
+                        device_id, consumer.name, response
+                    );
+                    MarketMakerState::update_available_devices();
+                    continue;
+                }
+            };
+            info!(
+                "Reserved device {} for queued consumer {}",
+                device_id, consumer.name
+            );
+            let request = ConsumerRequest::QueuedDeviceReserved {
+                device: Box::new(device),
+                peer_id,
+            };
+            let response = TCPClient::new(consumer.bind_host.as_str(), consumer.bind_port)
+                .and_then(|client| client.send_request(request, None));
+            let reserved = matches!(
+                response.as_deref().map(ConsumerResponse::from_str),
+                Ok(Ok(ConsumerResponse::DeviceReserved { .. }))
+            );
+            if !reserved {
+                warn!(
+                    "Queued consumer {} could not use device {}",
+                    consumer.name, device_id
+                );
+                MarketMaker::release_device(&device_id);
+                MarketMakerState::update_available_devices();
+            }
+        }
+    }
+
     /// Handle resetting of MarketMakerState before stopping the MarketMaker listen server.
+    /// A standby only resets its state, as the network belongs to the primary.
     pub(super) fn terminate() {
         let state = MarketMakerState::get_state();
+        events::close_all();
+        if state.primary.is_some() {
+            MarketMakerState::reset_state();
+            HeartBeatState::reset_state();
+            return;
+        }
+
+        for standby in state.market_makers.values() {
+            if let Ok(client) = TCPClient::new(&standby.bind_host, standby.bind_port) {
+                client.send_no_wait(MarketMakerRequest::PrimaryTerminating);
+            }
+        }
 
         let suppliers = state
             .suppliers
@@ -688,6 +2442,8 @@ impl MarketMaker {
             }
         }
         thread::sleep(Duration::from_millis(1000));
+        // The network has been torn down. Nothing is left to restore.
+        MarketMakerState::detach_store();
         MarketMakerState::reset_state();
         HeartBeatState::reset_state();
     }
@@ -708,90 +2464,577 @@ impl MarketMaker {
         // that are known to be serializable/deserializable.
         let is_consumer = || MarketMakerState::verify_consumer(&peer_id_str);
         let is_supplier = || MarketMakerState::verify_supplier(&peer_id_str);
+        let is_peer = || MarketMakerState::verify_peer(&peer_id_str);
+        let has_role = |role| MarketMakerState::has_role(&peer_id_str, role);
+        // Local clients are always admins.
+        let is_admin = || peer_addr.ip().is_loopback() || has_role(Role::Admin);
         match request {
             // Client requests.
             MarketMakerRequest::Test => MarketMakerResponse::Test.to_json(),
-            MarketMakerRequest::Status if peer_addr.ip().is_loopback() => {
+            MarketMakerRequest::Status if is_admin() || has_role(Role::Observer) => {
                 let state = MarketMakerState::get_min_state();
                 MarketMakerResponse::Status { state }.to_json()
             }
-            MarketMakerRequest::UseWhitelist if peer_addr.ip().is_loopback() => {
+
+            // Primary MarketMaker Requests.
+            MarketMakerRequest::ReplicateState { snapshot }
+                if MarketMakerState::verify_primary(&peer_id_str) =>
+            {
+                MarketMakerState::replicate(*snapshot);
+                HeartBeatState::primary_heartbeat();
+                MarketMakerResponse::StateReplicated.to_json()
+            }
+            MarketMakerRequest::PrimaryTerminating
+                if MarketMakerState::verify_primary(&peer_id_str) =>
+            {
+                info!("Primary MarketMaker is terminating. Stopping the standby");
+                thread::spawn(System::stop_market_maker);
+                SysStateResponse::TerminationAcknowledged.to_json()
+            }
+            // A standby only serves the primary, until it takes over.
+            MarketMakerRequest::SupplierConnect { .. } if MarketMakerState::is_standby() => {
+                MarketMakerResponse::SupplierNotConnected {
+                    reason: Error::InvalidState("MarketMaker is a standby".to_string()),
+                }
+                .to_json()
+            }
+            MarketMakerRequest::ConsumerConnect { .. } if MarketMakerState::is_standby() => {
+                MarketMakerResponse::ConsumerNotConnected {
+                    reason: Error::InvalidState("MarketMaker is a standby".to_string()),
+                }
+                .to_json()
+            }
+            MarketMakerRequest::StandbyConnect { .. } if MarketMakerState::is_standby() => {
+                MarketMakerResponse::StandbyNotConnected {
+                    reason: Error::InvalidState("MarketMaker is a standby".to_string()),
+                }
+                .to_json()
+            }
+            _ if MarketMakerState::is_standby() => MarketMakerResponse::RequestNotAllowed.to_json(),
+
+            MarketMakerRequest::UseWhitelist if is_admin() => {
                 MarketMakerState::set_whitelists();
                 MarketMakerResponse::UseWhitelistSuccess.to_json()
             }
-            MarketMakerRequest::ResetWhitelist if peer_addr.ip().is_loopback() => {
-                MarketMakerState::reset_whitelists();
-                MarketMakerResponse::ResetWhitelistSuccess.to_json()
+            MarketMakerRequest::ResetWhitelist if is_admin() => {
+                MarketMakerState::reset_whitelists();
+                MarketMakerResponse::ResetWhitelistSuccess.to_json()
+            }
+            MarketMakerRequest::WhitelistSupplier { key } if is_admin() => {
+                if base64::decode(key.clone()).is_ok() {
+                    MarketMakerState::add_to_supplier_whitelist(&key);
+                    MarketMakerResponse::WhitelistSupplierSuccess.to_json()
+                } else {
+                    MarketMakerResponse::WhitelistSupplierFailure {
+                        reason: Error::InvalidInput("Error processing key".to_string()),
+                    }
+                    .to_json()
+                }
+            }
+            MarketMakerRequest::WhitelistConsumer { key } if is_admin() => {
+                if base64::decode(key.clone()).is_ok() {
+                    MarketMakerState::add_to_consumer_whitelist(&key);
+                    MarketMakerResponse::WhitelistConsumerSuccess.to_json()
+                } else {
+                    MarketMakerResponse::WhitelistConsumerFailure {
+                        reason: Error::InvalidInput("Error processing key".to_string()),
+                    }
+                    .to_json()
+                }
+            }
+            MarketMakerRequest::UnwhitelistSupplier { key } if is_admin() => {
+                if base64::decode(key.clone()).is_ok() {
+                    MarketMakerState::remove_from_supplier_whitelist(&key);
+                    MarketMakerResponse::UnwhitelistSupplierSuccess.to_json()
+                } else {
+                    MarketMakerResponse::UnwhitelistSupplierFailure {
+                        reason: Error::InvalidInput("Error processing key".to_string()),
+                    }
+                    .to_json()
+                }
+            }
+            MarketMakerRequest::UnwhitelistConsumer { key } if is_admin() => {
+                if base64::decode(key.clone()).is_ok() {
+                    MarketMakerState::remove_from_consumer_whitelist(&key);
+                    MarketMakerResponse::UnwhitelistConsumerSuccess.to_json()
+                } else {
+                    MarketMakerResponse::UnwhitelistConsumerFailure {
+                        reason: Error::InvalidInput("Error processing key".to_string()),
+                    }
+                    .to_json()
+                }
+            }
+            MarketMakerRequest::WhitelistStandby { key } if is_admin() => {
+                if base64::decode(key.clone()).is_ok() {
+                    MarketMakerState::add_to_standby_whitelist(&key);
+                    MarketMakerResponse::WhitelistStandbySuccess.to_json()
+                } else {
+                    MarketMakerResponse::WhitelistStandbyFailure {
+                        reason: Error::InvalidInput("Error processing key".to_string()),
+                    }
+                    .to_json()
+                }
+            }
+            MarketMakerRequest::UnwhitelistStandby { key } if is_admin() => {
+                if base64::decode(key.clone()).is_ok() {
+                    MarketMakerState::remove_from_standby_whitelist(&key);
+                    MarketMakerState::remove_standby(&key);
+                    MarketMakerResponse::UnwhitelistStandbySuccess.to_json()
+                } else {
+                    MarketMakerResponse::UnwhitelistStandbyFailure {
+                        reason: Error::InvalidInput("Error processing key".to_string()),
+                    }
+                    .to_json()
+                }
+            }
+            MarketMakerRequest::GrantRole { key, role } if is_admin() => {
+                if base64::decode(key.clone()).is_ok() {
+                    MarketMakerState::grant_role(&key, role);
+                    MarketMakerResponse::GrantRoleSuccess.to_json()
+                } else {
+                    MarketMakerResponse::GrantRoleFailure {
+                        reason: Error::InvalidInput("Error processing key".to_string()),
+                    }
+                    .to_json()
+                }
+            }
+            MarketMakerRequest::RevokeRole { key, role } if is_admin() => {
+                if base64::decode(key.clone()).is_ok() {
+                    MarketMakerState::revoke_role(&key, role);
+                    MarketMakerResponse::RevokeRoleSuccess.to_json()
+                } else {
+                    MarketMakerResponse::RevokeRoleFailure {
+                        reason: Error::InvalidInput("Error processing key".to_string()),
+                    }
+                    .to_json()
+                }
+            }
+            MarketMakerRequest::ForceReleaseDevice { device_id } if is_admin() => {
+                let device = MarketMakerState::get_device(&device_id);
+                if device.is_none() {
+                    return MarketMakerResponse::DeviceNotReleased {
+                        reason: Error::DeviceNotFound("Device not found".to_string()),
+                    }
+                    .to_json();
+                }
+                let device = device.unwrap();
+                if device.used_by.is_empty() {
+                    return MarketMakerResponse::DeviceNotReleased {
+                        reason: Error::InvalidState("Device is not in use".to_string()),
+                    }
+                    .to_json();
+                }
+                info!("Force releasing device {}", device_id);
+                let request = ConsumerRequest::DeviceForceReleased {
+                    device_id: device_id.clone(),
+                };
+                MarketMaker::notify_consumer(&device.used_by, &device.used_by_host, request);
+                thread::spawn(move || {
+                    MarketMaker::release_device(&device_id);
+                    MarketMakerState::update_available_devices();
+                    MarketMaker::serve_wait_queue();
+                });
+                MarketMakerResponse::DeviceReleased.to_json()
+            }
+            MarketMakerRequest::SetQuota { target, quota } if is_admin() => {
+                if quota.max_lease_secs == Some(0) {
+                    return MarketMakerResponse::SetQuotaFailure {
+                        reason: Error::InvalidInput(
+                            "Maximum lease duration must be greater than zero".to_string(),
+                        ),
+                    }
+                    .to_json();
+                }
+                if let QuotaTarget::Consumer(key) = &target {
+                    if base64::decode(key).is_err() {
+                        return MarketMakerResponse::SetQuotaFailure {
+                            reason: Error::InvalidInput("Error processing key".to_string()),
+                        }
+                        .to_json();
+                    }
+                }
+                MarketMakerState::set_quota(target, quota);
+                MarketMakerResponse::SetQuotaSuccess.to_json()
+            }
+            MarketMakerRequest::RemoveQuota { target } if is_admin() => {
+                MarketMakerState::remove_quota(&target);
+                MarketMakerResponse::RemoveQuotaSuccess.to_json()
             }
-            MarketMakerRequest::WhitelistSupplier { key } if peer_addr.ip().is_loopback() => {
+            MarketMakerRequest::AddToGroup { group, key } if is_admin() => {
                 if base64::decode(key.clone()).is_ok() {
-                    MarketMakerState::add_to_supplier_whitelist(&key);
-                    MarketMakerResponse::WhitelistSupplierSuccess.to_json()
+                    MarketMakerState::add_to_group(&group, &key);
+                    MarketMakerResponse::AddToGroupSuccess.to_json()
                 } else {
-                    MarketMakerResponse::WhitelistSupplierFailure {
-                        reason: "Error processing key".to_string(),
+                    MarketMakerResponse::AddToGroupFailure {
+                        reason: Error::InvalidInput("Error processing key".to_string()),
+                    }
+                    .to_json()
+                }
+            }
+            MarketMakerRequest::RemoveFromGroup { group, key } if is_admin() => {
+                MarketMakerState::remove_from_group(&group, &key);
+                MarketMakerResponse::RemoveFromGroupSuccess.to_json()
+            }
+            MarketMakerRequest::AssignPool { device_id, pool } if is_admin() => {
+                if MarketMakerState::assign_pool(&device_id, &pool) {
+                    MarketMakerResponse::AssignPoolSuccess.to_json()
+                } else {
+                    MarketMakerResponse::AssignPoolFailure {
+                        reason: Error::DeviceNotFound("Device not found".to_string()),
                     }
                     .to_json()
                 }
             }
-            MarketMakerRequest::WhitelistConsumer { key } if peer_addr.ip().is_loopback() => {
+            MarketMakerRequest::UnassignPool { device_id, pool } if is_admin() => {
+                MarketMakerState::unassign_pool(&device_id, &pool);
+                // Waiting Consumers may now have access to the device.
+                thread::spawn(MarketMaker::serve_wait_queue);
+                MarketMakerResponse::UnassignPoolSuccess.to_json()
+            }
+            MarketMakerRequest::GrantPoolAccess { pool, key } if is_admin() => {
                 if base64::decode(key.clone()).is_ok() {
-                    MarketMakerState::add_to_consumer_whitelist(&key);
-                    MarketMakerResponse::WhitelistConsumerSuccess.to_json()
+                    MarketMakerState::grant_pool_access(&pool, &key);
+                    // The Consumer may be waiting for a device of the pool.
+                    thread::spawn(MarketMaker::serve_wait_queue);
+                    MarketMakerResponse::GrantPoolAccessSuccess.to_json()
                 } else {
-                    MarketMakerResponse::WhitelistConsumerFailure {
-                        reason: "Error processing key".to_string(),
+                    MarketMakerResponse::GrantPoolAccessFailure {
+                        reason: Error::InvalidInput("Error processing key".to_string()),
                     }
                     .to_json()
                 }
             }
-            MarketMakerRequest::UnwhitelistSupplier { key } if peer_addr.ip().is_loopback() => {
+            MarketMakerRequest::RevokePoolAccess { pool, key } if is_admin() => {
+                MarketMakerState::revoke_pool_access(&pool, &key);
+                MarketMakerResponse::RevokePoolAccessSuccess.to_json()
+            }
+            MarketMakerRequest::Subscribe { topics }
+                if is_admin() || has_role(Role::Observer) || is_consumer() || is_supplier() =>
+            {
+                // The connection is kept open after the response is sent,
+                // and the events are streamed on it. Admins and observers see all
+                // devices, others only the devices visible to them.
+                let restricted_to =
+                    (!is_admin() && !has_role(Role::Observer)).then(|| peer_id_str.clone());
+                events::subscribe(topics, restricted_to, net::open_stream(peer_addr));
+                MarketMakerResponse::Subscribed.to_json()
+            }
+            MarketMakerRequest::WhitelistPeer { key } if is_admin() => {
                 if base64::decode(key.clone()).is_ok() {
-                    MarketMakerState::remove_from_supplier_whitelist(&key);
-                    MarketMakerResponse::UnwhitelistSupplierSuccess.to_json()
+                    MarketMakerState::add_to_peer_whitelist(&key);
+                    MarketMakerResponse::WhitelistPeerSuccess.to_json()
                 } else {
-                    MarketMakerResponse::UnwhitelistSupplierFailure {
-                        reason: "Error processing key".to_string(),
+                    MarketMakerResponse::WhitelistPeerFailure {
+                        reason: Error::InvalidInput("Error processing key".to_string()),
                     }
                     .to_json()
                 }
             }
-            MarketMakerRequest::UnwhitelistConsumer { key } if peer_addr.ip().is_loopback() => {
+            MarketMakerRequest::UnwhitelistPeer { key } if is_admin() => {
                 if base64::decode(key.clone()).is_ok() {
-                    MarketMakerState::remove_from_consumer_whitelist(&key);
-                    MarketMakerResponse::UnwhitelistConsumerSuccess.to_json()
+                    MarketMakerState::remove_from_peer_whitelist(&key);
+                    MarketMakerState::remove_peer(&key);
+                    HeartBeatState::remove_peer(&key);
+                    MarketMakerResponse::UnwhitelistPeerSuccess.to_json()
                 } else {
-                    MarketMakerResponse::UnwhitelistConsumerFailure {
-                        reason: "Error processing key".to_string(),
+                    MarketMakerResponse::UnwhitelistPeerFailure {
+                        reason: Error::InvalidInput("Error processing key".to_string()),
+                    }
+                    .to_json()
+                }
+            }
+            MarketMakerRequest::ConnectPeer { host, port } if is_admin() => {
+                match MarketMaker::connect_peer(&host, port) {
+                    Ok((market_maker, devices)) => {
+                        info!("Connected to peer MarketMaker: {}", market_maker.bind_host);
+                        MarketMakerResponse::PeerConnected {
+                            market_maker,
+                            devices,
+                        }
+                        .to_json()
+                    }
+                    Err(e) => MarketMakerResponse::PeerNotConnected { reason: e }.to_json(),
+                }
+            }
+
+            // Standby MarketMaker Requests.
+            MarketMakerRequest::StandbyConnect { mut market_maker } => {
+                // Standbys receive the whole state of the network. Unlike Suppliers
+                // and Consumers, they are always required to be whitelisted.
+                if !MarketMakerState::verify_standby_whitelist(&peer_id_str) {
+                    return MarketMakerResponse::StandbyNotConnected {
+                        reason: Error::NotAuthorized("Not in standby whitelist".to_string()),
+                    }
+                    .to_json();
+                }
+                if peer_id_str != market_maker.pub_key {
+                    return MarketMakerResponse::StandbyNotConnected {
+                        reason: Error::NotAuthorized(
+                            "Public key does not match peer id".to_string(),
+                        ),
+                    }
+                    .to_json();
+                }
+                if MarketMakerState::has_other_standby(&peer_id_str) {
+                    return MarketMakerResponse::StandbyNotConnected {
+                        reason: Error::InvalidState(
+                            "Another standby is already connected".to_string(),
+                        ),
+                    }
+                    .to_json();
+                }
+                if market_maker.adborc_version != ADBORC_VERSION {
+                    return MarketMakerResponse::StandbyNotConnected {
+                        reason: Error::InvalidInput("Unsupported MarketMaker version".to_string()),
+                    }
+                    .to_json();
+                }
+                market_maker.bind_host = peer_addr_ip;
+                let connected = TCPClient::new(&market_maker.bind_host, market_maker.bind_port)
+                    .and_then(|client| client.test_connect());
+                if connected.is_err() {
+                    return MarketMakerResponse::StandbyNotConnected {
+                        reason: Error::Unreachable("Could not connect to standby".to_string()),
+                    }
+                    .to_json();
+                }
+                info!("Standby MarketMaker connected: {}", market_maker.bind_host);
+                MarketMakerState::insert_standby(market_maker);
+                MarketMakerResponse::StandbyConnected {
+                    snapshot: Box::new(MarketMakerState::get_snapshot()),
+                    pub_key: base64::encode(SystemKeypair::get_public_key().unwrap()),
+                }
+                .to_json()
+            }
+
+            // Peer MarketMaker Requests.
+            MarketMakerRequest::PeerConnect { mut market_maker } => {
+                // Like standbys, peers are always required to be whitelisted.
+                if !MarketMakerState::verify_peer_whitelist(&peer_id_str) {
+                    return MarketMakerResponse::PeerNotConnected {
+                        reason: Error::NotAuthorized("Not in peer whitelist".to_string()),
+                    }
+                    .to_json();
+                }
+                if peer_id_str != market_maker.pub_key {
+                    return MarketMakerResponse::PeerNotConnected {
+                        reason: Error::NotAuthorized(
+                            "Public key does not match peer id".to_string(),
+                        ),
+                    }
+                    .to_json();
+                }
+                if market_maker.adborc_version != ADBORC_VERSION {
+                    return MarketMakerResponse::PeerNotConnected {
+                        reason: Error::InvalidInput("Unsupported MarketMaker version".to_string()),
+                    }
+                    .to_json();
+                }
+                market_maker.bind_host = peer_addr_ip;
+                let connected = TCPClient::new(&market_maker.bind_host, market_maker.bind_port)
+                    .and_then(|client| client.test_connect());
+                if connected.is_err() {
+                    return MarketMakerResponse::PeerNotConnected {
+                        reason: Error::Unreachable("Could not connect to peer".to_string()),
+                    }
+                    .to_json();
+                }
+                info!("Peer MarketMaker connected: {}", market_maker.bind_host);
+                MarketMakerState::insert_peer(market_maker);
+                HeartBeatState::peer_heartbeat(&peer_id_str);
+                MarketMakerResponse::PeerConnected {
+                    market_maker: MarketMakerSpec::default(),
+                    devices: MarketMakerState::get_advertised_devices(),
+                }
+                .to_json()
+            }
+
+            MarketMakerRequest::AdvertiseDevices { devices } if is_peer() => {
+                debug!("Received devices from peer {}", peer_id_str);
+                HeartBeatState::peer_heartbeat(&peer_id_str);
+                MarketMakerState::set_peer_devices(&peer_id_str, devices);
+                MarketMakerResponse::DevicesAdvertised.to_json()
+            }
+
+            MarketMakerRequest::PeerReserveDevice {
+                device_id,
+                consumer,
+                lease_secs,
+            } if is_peer() => {
+                if lease_secs == Some(0) {
+                    return MarketMakerResponse::DeviceNotReserved {
+                        reason: Error::InvalidInput(
+                            "Lease duration must be greater than zero".to_string(),
+                        ),
+                    }
+                    .to_json();
+                }
+                if MarketMakerState::is_device_restricted_for(&device_id, &consumer.pub_key) {
+                    return MarketMakerResponse::DeviceNotReserved {
+                        reason: Error::NotAuthorized(
+                            "No access to the pools of the device".to_string(),
+                        ),
+                    }
+                    .to_json();
+                }
+                let claimed = MarketMakerState::claim_devices_for(
+                    &consumer.pub_key,
+                    &[device_id],
+                    1,
+                    lease_secs,
+                );
+                let device = match claimed.map(|mut devices| devices.pop()) {
+                    Ok(Some(device)) => device,
+                    Ok(None) => {
+                        return MarketMakerResponse::DeviceNotReserved {
+                            reason: Error::DeviceUnavailable("Device not available".to_string()),
+                        }
+                        .to_json()
+                    }
+                    Err(reason) => {
+                        return MarketMakerResponse::DeviceNotReserved { reason }.to_json()
+                    }
+                };
+                MarketMakerState::insert_peer_consumer(&consumer.pub_key, &peer_id_str);
+                let response = MarketMaker::reserve_device_for(
+                    device,
+                    &consumer.pub_key,
+                    consumer.name,
+                    consumer.bind_host,
+                    lease_secs,
+                );
+                if !matches!(response, MarketMakerResponse::DeviceReserved { .. }) {
+                    thread::spawn(MarketMakerState::update_available_devices);
+                }
+                response.to_json()
+            }
+
+            MarketMakerRequest::PeerReleaseDevice {
+                device_id,
+                consumer,
+            } if is_peer() => {
+                let from_peer =
+                    MarketMakerState::get_peer_consumer(&consumer).as_ref() == Some(&peer_id_str);
+                if from_peer && MarketMakerState::is_device_used_by(&device_id, &consumer) {
+                    thread::spawn(move || {
+                        MarketMaker::release_device(&device_id);
+                        MarketMakerState::update_available_devices();
+                        MarketMaker::serve_wait_queue();
+                    });
+                    MarketMakerResponse::DeviceReleased.to_json()
+                } else {
+                    MarketMakerResponse::DeviceNotReleased {
+                        reason: Error::NotAuthorized(
+                            "Device is not used by the specified consumer. Access restricted."
+                                .to_string(),
+                        ),
                     }
                     .to_json()
                 }
             }
 
+            MarketMakerRequest::PeerRenewLease {
+                device_id,
+                consumer,
+                lease_secs,
+            } if is_peer() => {
+                let from_peer =
+                    MarketMakerState::get_peer_consumer(&consumer).as_ref() == Some(&peer_id_str);
+                if !from_peer || lease_secs == 0 {
+                    return MarketMakerResponse::LeaseNotRenewed {
+                        reason: Error::NotAuthorized(
+                            "Device is not used by the specified consumer. Access restricted."
+                                .to_string(),
+                        ),
+                    }
+                    .to_json();
+                }
+                if let Some(max_lease_secs) = MarketMakerState::get_max_lease(&consumer) {
+                    if lease_secs > max_lease_secs {
+                        return MarketMakerResponse::LeaseNotRenewed {
+                            reason: Error::QuotaExceeded(format!(
+                                "Quota allows leases of at most {}s",
+                                max_lease_secs
+                            )),
+                        }
+                        .to_json();
+                    }
+                }
+                let expiry = match MarketMaker::lease_expiry(lease_secs) {
+                    Ok(expiry) => expiry,
+                    Err(reason) => {
+                        return MarketMakerResponse::LeaseNotRenewed { reason }.to_json()
+                    }
+                };
+                match MarketMakerState::renew_lease(&device_id, &consumer, expiry) {
+                    Some(device) => MarketMakerResponse::LeaseRenewed { device }.to_json(),
+                    None => MarketMakerResponse::LeaseNotRenewed {
+                        reason: Error::NotAuthorized(
+                            "Device is not used by the specified consumer. Access restricted."
+                                .to_string(),
+                        ),
+                    }
+                    .to_json(),
+                }
+            }
+
+            MarketMakerRequest::PeerNotifyConsumer { consumer, request } if is_peer() => {
+                // Only notifications about devices reserved through the peer are forwarded.
+                let device_id = match request.as_ref() {
+                    ConsumerRequest::SupplierDisconnected { device_id }
+                    | ConsumerRequest::LeaseExpired { device_id }
+                    | ConsumerRequest::DeviceForceReleased { device_id } => device_id,
+                    _ => return MarketMakerResponse::RequestNotAllowed.to_json(),
+                };
+                match MarketMakerState::get_remote_reservation(device_id) {
+                    Some((peer_pub_key, device))
+                        if peer_pub_key == peer_id_str && device.used_by == consumer =>
+                    {
+                        MarketMakerState::remove_remote_reservation(device_id);
+                        if let Some(spec) = MarketMakerState::get_consumer(&consumer) {
+                            if let Ok(client) = TCPClient::new(&spec.bind_host, spec.bind_port) {
+                                client.send_no_wait(*request);
+                            }
+                        }
+                        MarketMakerResponse::ConsumerNotified.to_json()
+                    }
+                    _ => MarketMakerResponse::RequestNotAllowed.to_json(),
+                }
+            }
+
             // Supplier Requests.
-            MarketMakerRequest::SupplierConnect { mut supplier } => {
+            MarketMakerRequest::SupplierConnect {
+                mut supplier,
+                resume,
+            } => {
                 if !MarketMakerState::verify_supplier_whitelist(&peer_id_str) {
                     return MarketMakerResponse::SupplierNotConnected {
-                        reason: "Not in whitelist".to_string(),
+                        reason: Error::NotAuthorized("Not in whitelist".to_string()),
                     }
                     .to_json();
                 }
                 let pub_key = supplier.pub_key.clone();
                 if peer_id_str != pub_key {
                     return MarketMakerResponse::SupplierNotConnected {
-                        reason: "Public key does not match peer id".to_string(),
+                        reason: Error::NotAuthorized(
+                            "Public key does not match peer id".to_string(),
+                        ),
                     }
                     .to_json();
                 }
-                if MarketMakerState::supplier_exists(&pub_key) {
+                // A Supplier restored from the state store, or resuming its session
+                // after a restart, may connect again, and takes over its previous
+                // entry along with the supplied devices.
+                let reconnecting = resume || MarketMakerState::is_restored_supplier(&pub_key);
+                if MarketMakerState::supplier_exists(&pub_key) && !reconnecting {
                     return MarketMakerResponse::SupplierNotConnected {
-                        reason: "Already connected".to_string(),
+                        reason: Error::InvalidState("Already connected".to_string()),
                     }
                     .to_json();
                 }
                 if !MarketMakerState::supplier_supported(&supplier.adborc_version) {
                     return MarketMakerResponse::SupplierNotConnected {
-                        reason: "Unsupported Supplier version".to_string(),
+                        reason: Error::InvalidInput("Unsupported Supplier version".to_string()),
                     }
                     .to_json();
                 }
@@ -802,23 +3045,34 @@ impl MarketMaker {
                 let client = TCPClient::new(&supplier.bind_host, supplier.bind_port);
                 if client.is_err() {
                     return MarketMakerResponse::SupplierNotConnected {
-                        reason: "Could not connect to supplier.".to_string(),
+                        reason: Error::Unreachable("Could not connect to supplier.".to_string()),
                     }
                     .to_json();
                 }
                 let client = client.unwrap();
                 if client.test_connect().is_err() {
                     return MarketMakerResponse::SupplierNotConnected {
-                        reason: "Could not connect to supplier".to_string(),
+                        reason: Error::Unreachable("Could not connect to supplier".to_string()),
                     }
                     .to_json();
                 }
                 let supplier_clone = supplier.clone();
+                let previous_host = MarketMakerState::get_supplier(&pub_key)
+                    .map(|previous| previous.bind_host)
+                    .unwrap_or_default();
+                MarketMakerState::insert_supplier(supplier_clone);
+                events::publish(MarketEvent::SupplierJoined {
+                    peer_id: pub_key.clone(),
+                    name: supplier.name.clone(),
+                });
+                let new_host = supplier.bind_host.clone();
                 // Update the supplier HeartBeatState in a separate thread.
                 thread::spawn(move || {
                     HeartBeatState::add_supplier(&peer_id_str);
+                    if reconnecting && previous_host != new_host {
+                        MarketMaker::handle_supplier_ip_change(peer_id_str, new_host);
+                    }
                 });
-                MarketMakerState::insert_supplier(supplier_clone);
                 MarketMakerResponse::SupplierConnected {
                     supplier,
                     pub_key: base64::encode(SystemKeypair::get_public_key().unwrap()),
@@ -829,19 +3083,48 @@ impl MarketMaker {
             MarketMakerRequest::SupplyDevices { devices } if is_supplier() => {
                 let mut supplied_devices = Vec::new();
                 let mut failed_devices = Vec::new();
+                // Reserved devices that are served at another address than before.
+                let mut moved_devices = Vec::new();
                 // Add the devices to the network.
                 for mut device in devices {
                     device.available_at_host = peer_addr_ip.clone();
-                    if MarketMakerState::get_device(&device.device_id).is_none() {
-                        MarketMakerState::insert_device(device.clone());
-                        supplied_devices.push(device);
-                    } else {
-                        failed_devices.push(device);
+                    match MarketMakerState::get_device(&device.device_id) {
+                        None => {
+                            MarketMakerState::insert_device(device.clone());
+                            events::publish(MarketEvent::DeviceSupplied {
+                                device: device.clone(),
+                            });
+                            supplied_devices.push(device);
+                        }
+                        // The Supplier supplies the device again, e.g. after a restart.
+                        Some(known) if known.available_at == peer_id_str => {
+                            let moved = known.available_at_host != device.available_at_host
+                                || known.available_at_port != device.available_at_port;
+                            if moved && !known.used_by_host.is_empty() {
+                                moved_devices.push(known);
+                            }
+                            MarketMakerState::update_supplied_device(device.clone());
+                            supplied_devices.push(device);
+                        }
+                        Some(_) => failed_devices.push(device),
                     }
                 }
                 // Update the available devices in state in a separate thread.
-                thread::spawn(|| {
+                thread::spawn(move || {
+                    // The Consumers of moved devices cannot reach them any more.
+                    for device in moved_devices {
+                        let request = ConsumerRequest::SupplierDisconnected {
+                            device_id: device.device_id.clone(),
+                        };
+                        MarketMaker::notify_consumer(
+                            &device.used_by,
+                            &device.used_by_host,
+                            request,
+                        );
+                        MarketMaker::release_device(&device.device_id);
+                    }
                     MarketMakerState::update_available_devices();
+                    MarketMaker::serve_wait_queue();
                 });
                 MarketMakerResponse::DevicesSupplied {
                     supplied_devices,
@@ -855,7 +3138,7 @@ impl MarketMaker {
                 let device = MarketMakerState::get_device(&device_id);
                 if device.is_none() {
                     return MarketMakerResponse::DeviceNotReclaimed {
-                        reason: "Device not found".to_string(),
+                        reason: Error::DeviceNotFound("Device not found".to_string()),
                     }
                     .to_json();
                 }
@@ -864,14 +3147,18 @@ impl MarketMaker {
                 if !force && !device_available {
                     // Device is being used by a consumer and force is not set.
                     return MarketMakerResponse::DeviceNotReclaimed {
-                        reason: "Device is being used by a consumer".to_string(),
+                        reason: Error::DeviceUnavailable(
+                            "Device is being used by a consumer".to_string(),
+                        ),
                     }
                     .to_json();
                 }
                 // Check if the device is supplied by the supplier.
                 if device.available_at_host != peer_addr_ip {
                     return MarketMakerResponse::DeviceNotReclaimed {
-                        reason: "Device not supplied by supplier".to_string(),
+                        reason: Error::DeviceNotFound(
+                            "Device not supplied by supplier".to_string(),
+                        ),
                     }
                     .to_json();
                 }
@@ -879,20 +3166,18 @@ impl MarketMaker {
                 // Can reclaim the device.
 
                 // If the device is being used by a consumer, we need to ask the consumer to stop using the device.
-                let consumer = MarketMakerState::get_consumer(&device.used_by);
-                if let Some(consumer) = consumer {
-                    let host = consumer.bind_host.as_str();
-                    let port = consumer.bind_port;
-                    if let Ok(client) = TCPClient::new(host, port) {
-                        let request = ConsumerRequest::SupplierDisconnected {
-                            device_id: device_id.clone(),
-                        };
-                        client.send_no_wait(request);
-                    }
+                if !device_available {
+                    let request = ConsumerRequest::SupplierDisconnected {
+                        device_id: device_id.clone(),
+                    };
+                    MarketMaker::notify_consumer(&device.used_by, &device.used_by_host, request);
                 }
 
                 MarketMakerState::remove_device(&device_id);
                 MarketMakerState::update_available_devices();
+                events::publish(MarketEvent::DeviceReclaimed {
+                    device_id: device_id.clone(),
+                });
 
                 MarketMakerResponse::DeviceReclaimed { device_id }.to_json()
             }
@@ -904,27 +3189,30 @@ impl MarketMaker {
                 thread::spawn(move || {
                     MarketMakerState::remove_supplier(&peer_id_str);
                     HeartBeatState::remove_supplier(&peer_id_str);
+                    events::publish(MarketEvent::SupplierLeft {
+                        peer_id: peer_id_str.clone(),
+                    });
                     for (consumer_pub_key, host, device_id) in consumers {
                         MarketMakerState::remove_device(&device_id);
+                        events::publish(MarketEvent::DeviceReclaimed {
+                            device_id: device_id.clone(),
+                        });
                         if host.is_empty() {
                             continue;
                         }
-                        let port = MarketMakerState::get_consumer_port(&consumer_pub_key);
-                        if let Some(port) = port {
-                            if let Ok(client) = TCPClient::new(host.as_str(), port) {
-                                let request = ConsumerRequest::SupplierDisconnected { device_id };
-                                client.send_no_wait(request);
-                            }
-                        }
+                        let request = ConsumerRequest::SupplierDisconnected { device_id };
+                        MarketMaker::notify_consumer(&consumer_pub_key, &host, request);
                     }
                     MarketMakerState::update_available_devices();
                 });
                 MarketMakerResponse::SupplierDisconnected.to_json()
             }
 
-            MarketMakerRequest::SupplierHeartBeat if is_supplier() => {
+            MarketMakerRequest::SupplierHeartBeat { device_health } if is_supplier() => {
                 debug!("Received heartbeat from supplier {}", peer_id_str);
                 HeartBeatState::supplier_heartbeat(&peer_id_str);
+                MarketMakerState::update_device_health(&peer_id_str, device_health);
+                MarketMakerState::clear_restored_supplier(&peer_id_str);
                 if peer_addr_ip
                     != MarketMakerState::get_supplier(&peer_id_str)
                         .unwrap()
@@ -938,29 +3226,38 @@ impl MarketMaker {
             }
 
             // Consumer Requests.
-            MarketMakerRequest::ConsumerConnect { mut consumer } => {
+            MarketMakerRequest::ConsumerConnect {
+                mut consumer,
+                resume,
+            } => {
                 if !MarketMakerState::verify_consumer_whitelist(&peer_id_str) {
                     return MarketMakerResponse::ConsumerNotConnected {
-                        reason: "Not in whitelist".to_string(),
+                        reason: Error::NotAuthorized("Not in whitelist".to_string()),
                     }
                     .to_json();
                 }
-                if MarketMakerState::consumer_exists(&peer_id_str) {
+                // A Consumer restored from the state store, or resuming its session
+                // after a restart, may connect again, and takes over its previous
+                // entry along with the reserved devices.
+                let reconnecting = resume || MarketMakerState::is_restored_consumer(&peer_id_str);
+                if MarketMakerState::consumer_exists(&peer_id_str) && !reconnecting {
                     return MarketMakerResponse::ConsumerNotConnected {
-                        reason: "Already connected".to_string(),
+                        reason: Error::InvalidState("Already connected".to_string()),
                     }
                     .to_json();
                 }
                 let pub_key = consumer.pub_key.clone();
                 if pub_key != peer_id_str {
                     return MarketMakerResponse::ConsumerNotConnected {
-                        reason: "Public key does not match peer id".to_string(),
+                        reason: Error::NotAuthorized(
+                            "Public key does not match peer id".to_string(),
+                        ),
                     }
                     .to_json();
                 }
                 if !MarketMakerState::consumer_supported(&consumer.adborc_version) {
                     return MarketMakerResponse::ConsumerNotConnected {
-                        reason: "Unsupported Consumer version".to_string(),
+                        reason: Error::InvalidInput("Unsupported Consumer version".to_string()),
                     }
                     .to_json();
                 }
@@ -971,23 +3268,30 @@ impl MarketMaker {
                 let client = TCPClient::new(&consumer.bind_host, consumer.bind_port);
                 if client.is_err() {
                     return MarketMakerResponse::ConsumerNotConnected {
-                        reason: "Could not connect to consumer.".to_string(),
+                        reason: Error::Unreachable("Could not connect to consumer.".to_string()),
                     }
                     .to_json();
                 }
                 let client = client.unwrap();
                 if client.test_connect().is_err() {
                     return MarketMakerResponse::ConsumerNotConnected {
-                        reason: "Could not connect to consumer.".to_string(),
+                        reason: Error::Unreachable("Could not connect to consumer.".to_string()),
                     }
                     .to_json();
                 }
                 let consumer_clone = consumer.clone();
+                if reconnecting {
+                    MarketMakerState::update_consumer_host(&pub_key, &consumer.bind_host);
+                }
                 // Update the consumer HeartBeatState in a separate thread.
                 thread::spawn(move || {
                     HeartBeatState::add_consumer(&peer_id_str);
                 });
                 MarketMakerState::insert_consumer(consumer_clone);
+                events::publish(MarketEvent::ConsumerJoined {
+                    peer_id: pub_key.clone(),
+                    name: consumer.name.clone(),
+                });
                 MarketMakerResponse::ConsumerConnected {
                     consumer,
                     pub_key: base64::encode(SystemKeypair::get_public_key().unwrap()),
@@ -996,12 +3300,14 @@ impl MarketMaker {
             }
 
             MarketMakerRequest::GetAvailableDevices if is_consumer() => {
-                let devices = MarketMakerState::get_available_devices();
+                let mut devices = MarketMakerState::get_available_devices_for(&peer_id_str);
+                devices.extend(MarketMakerState::get_peer_devices());
                 MarketMakerResponse::AvailableDevices { devices }.to_json()
             }
 
             MarketMakerRequest::GetDevicesByFilter { filter_vec } if is_consumer() => {
-                let devices = MarketMakerState::filter_devices(&filter_vec);
+                let mut devices = MarketMakerState::filter_devices_for(&filter_vec, &peer_id_str);
+                devices.extend(MarketMakerState::filter_peer_devices(&filter_vec));
                 MarketMakerResponse::DevicesByFilter {
                     devices,
                     filter_vec,
@@ -1009,113 +3315,333 @@ impl MarketMaker {
                 .to_json()
             }
 
-            MarketMakerRequest::ReserveDevice { device_id } if is_consumer() => {
-                let device = MarketMakerState::remove_device_from_available(&device_id);
-                if device.is_none() {
+            MarketMakerRequest::ReserveDevice {
+                device_id,
+                lease_secs,
+            } if is_consumer() => {
+                if lease_secs == Some(0) {
                     return MarketMakerResponse::DeviceNotReserved {
-                        reason: "Device not available".to_string(),
+                        reason: Error::InvalidInput(
+                            "Lease duration must be greater than zero".to_string(),
+                        ),
                     }
                     .to_json();
                 }
-                let consumer_name = MarketMakerState::get_consumer_name(&peer_id_str);
-                if consumer_name.is_none() {
+                if MarketMakerState::is_device_restricted_for(&device_id, &peer_id_str) {
                     return MarketMakerResponse::DeviceNotReserved {
-                        reason: "Fatal: Consumer not found".to_string(),
+                        reason: Error::NotAuthorized(
+                            "No access to the pools of the device".to_string(),
+                        ),
                     }
                     .to_json();
                 }
-                let mut device = device.unwrap();
-                device.used_by = peer_id_str.clone();
-                device.used_by_name = consumer_name.unwrap();
-                device.used_by_host = peer_addr_ip;
-                let device_clone = device.clone();
+                let claimed = MarketMakerState::claim_devices_for(
+                    &peer_id_str,
+                    std::slice::from_ref(&device_id),
+                    1,
+                    lease_secs,
+                );
+                match claimed.map(|mut devices| devices.pop()) {
+                    Ok(Some(device)) => {
+                        let response = MarketMaker::reserve_device(
+                            device,
+                            &peer_id_str,
+                            peer_addr_ip,
+                            lease_secs,
+                        );
+                        if !matches!(response, MarketMakerResponse::DeviceReserved { .. }) {
+                            // Make the device available again.
+                            thread::spawn(MarketMakerState::update_available_devices);
+                        }
+                        response
+                    }
+                    // The device may be advertised by a peer.
+                    Ok(None) => {
+                        MarketMaker::broker_reservation(&device_id, &peer_id_str, lease_secs)
+                    }
+                    Err(reason) => MarketMakerResponse::DeviceNotReserved { reason },
+                }
+                .to_json()
+            }
 
-                // If supplier doesn't require secure connection, we can reserve the device.
-                if !device.secure_comms {
-                    // Update the device in state in a separate thread.
-                    thread::spawn(|| {
-                        MarketMakerState::insert_device(device_clone);
-                    });
-                    MarketMakerResponse::DeviceReserved {
+            MarketMakerRequest::ReserveAnyDevice {
+                mut filter_vec,
+                count,
+                lease_secs,
+            } if is_consumer() => {
+                if count == 0 {
+                    return MarketMakerResponse::DeviceNotReserved {
+                        reason: Error::InvalidInput(
+                            "Device count must be greater than zero".to_string(),
+                        ),
+                    }
+                    .to_json();
+                }
+                if lease_secs == Some(0) {
+                    return MarketMakerResponse::DeviceNotReserved {
+                        reason: Error::InvalidInput(
+                            "Lease duration must be greater than zero".to_string(),
+                        ),
+                    }
+                    .to_json();
+                }
+                filter_vec.filters.push(DeviceFilter::IsAvailable(true));
+                let device_ids = MarketMakerState::filter_devices_for(&filter_vec, &peer_id_str)
+                    .into_iter()
+                    .map(|device| device.device_id)
+                    .collect::<Vec<String>>();
+                // No more devices are claimed than the quota allows.
+                let devices = match MarketMakerState::claim_devices_for(
+                    &peer_id_str,
+                    &device_ids,
+                    count,
+                    lease_secs,
+                ) {
+                    Ok(devices) => devices,
+                    Err(reason) => {
+                        return MarketMakerResponse::DeviceNotReserved { reason }.to_json()
+                    }
+                };
+                if devices.is_empty() {
+                    return MarketMakerResponse::DeviceNotReserved {
+                        reason: Error::DeviceUnavailable(
+                            "No matching device available".to_string(),
+                        ),
+                    }
+                    .to_json();
+                }
+                let mut reserved = Vec::new();
+                let mut reasons = Vec::new();
+                for device in devices {
+                    let device_id = device.device_id.clone();
+                    let response = MarketMaker::reserve_device(
                         device,
-                        peer_id: None,
+                        &peer_id_str,
+                        peer_addr_ip.clone(),
+                        lease_secs,
+                    );
+                    match response {
+                        MarketMakerResponse::DeviceReserved { device, peer_id } => {
+                            reserved.push((device, peer_id))
+                        }
+                        response => reasons.push(format!("{}: {}", device_id, response)),
+                    }
+                }
+                if !reasons.is_empty() {
+                    // Make the devices that could not be reserved available again.
+                    thread::spawn(MarketMakerState::update_available_devices);
+                }
+                if reserved.is_empty() {
+                    MarketMakerResponse::DeviceNotReserved {
+                        reason: Error::DeviceUnavailable(reasons.join(", ")),
                     }
                     .to_json()
                 } else {
-                    let supplier_id = device.available_at.clone();
-                    let supplier = MarketMakerState::get_supplier(&supplier_id);
-                    if supplier.is_none() {
-                        return MarketMakerResponse::DeviceNotReserved {
-                            reason: "Supplier not found".to_string(),
+                    MarketMakerResponse::DevicesReserved { devices: reserved }.to_json()
+                }
+            }
+
+            MarketMakerRequest::RenewLease {
+                device_id,
+                lease_secs,
+            } if is_consumer() => {
+                if lease_secs == 0 {
+                    return MarketMakerResponse::LeaseNotRenewed {
+                        reason: Error::InvalidInput(
+                            "Lease duration must be greater than zero".to_string(),
+                        ),
+                    }
+                    .to_json();
+                }
+                if let Some(max_lease_secs) = MarketMakerState::get_max_lease(&peer_id_str) {
+                    if lease_secs > max_lease_secs {
+                        return MarketMakerResponse::LeaseNotRenewed {
+                            reason: Error::QuotaExceeded(format!(
+                                "Quota allows leases of at most {}s",
+                                max_lease_secs
+                            )),
                         }
                         .to_json();
                     }
-                    let supplier = supplier.unwrap();
-                    let host = supplier.bind_host.as_str();
-                    let port = supplier.bind_port;
-                    if let Ok(client) = TCPClient::new(host, port) {
-                        let request = SupplierRequest::StartSecureTunnel {
-                            device_id: device.device_id.clone(),
-                            port: device.available_at_port,
-                            pub_key: peer_id_str.clone(),
+                }
+                let expiry = match MarketMaker::lease_expiry(lease_secs) {
+                    Ok(expiry) => expiry,
+                    Err(reason) => {
+                        return MarketMakerResponse::LeaseNotRenewed { reason }.to_json()
+                    }
+                };
+                if let Some(device) =
+                    MarketMakerState::renew_lease(&device_id, &peer_id_str, expiry)
+                {
+                    return MarketMakerResponse::LeaseRenewed { device }.to_json();
+                }
+                match MarketMakerState::get_remote_reservation(&device_id) {
+                    Some((peer_pub_key, device)) if device.used_by == peer_id_str => {
+                        let request = MarketMakerRequest::PeerRenewLease {
+                            device_id,
+                            consumer: peer_id_str,
+                            lease_secs,
                         };
-                        let response = client.send_request(request, None);
-                        if response.is_err() {
-                            return MarketMakerResponse::DeviceNotReserved {
-                                reason: "Could not connect to supplier".to_string(),
+                        match MarketMaker::send_to_peer(&peer_pub_key, request) {
+                            Ok(MarketMakerResponse::LeaseRenewed { device }) => {
+                                MarketMakerState::insert_remote_reservation(
+                                    &peer_pub_key,
+                                    device.clone(),
+                                );
+                                MarketMakerResponse::LeaseRenewed { device }
                             }
-                            .to_json();
+                            Ok(MarketMakerResponse::LeaseNotRenewed { reason }) => {
+                                MarketMakerResponse::LeaseNotRenewed { reason }
+                            }
+                            Ok(response) => MarketMakerResponse::LeaseNotRenewed {
+                                reason: Error::Protocol(format!(
+                                    "Unexpected response from peer: {}",
+                                    response
+                                )),
+                            },
+                            Err(e) => MarketMakerResponse::LeaseNotRenewed {
+                                reason: Error::Unreachable(format!(
+                                    "Could not connect to peer: {}",
+                                    e
+                                )),
+                            },
                         }
-                        let response = response.unwrap();
-                        let response = SupplierResponse::from_str(&response);
-                        if response.is_err() {
+                        .to_json()
+                    }
+                    _ => MarketMakerResponse::LeaseNotRenewed {
+                        reason: Error::NotAuthorized(
+                            "Device is not used by the specified consumer. Access restricted."
+                                .to_string(),
+                        ),
+                    }
+                    .to_json(),
+                }
+            }
+
+            MarketMakerRequest::ResumeReservation { device_id } if is_consumer() => {
+                let device = MarketMakerState::get_device(&device_id)
+                    .filter(|device| device.used_by == peer_id_str);
+                let device =
+                    match device {
+                        Some(device) => device,
+                        None => return MarketMakerResponse::DeviceNotReserved {
+                            reason: Error::NotAuthorized(
+                                "Device is not used by the specified consumer. Access restricted."
+                                    .to_string(),
+                            ),
+                        }
+                        .to_json(),
+                    };
+                // The device is reserved again for the remaining lease, so that a new
+                // secure tunnel is started for the Consumer, if the Supplier uses one.
+                let now = util::unix_time_now();
+                let lease_secs = match device.lease_expiry {
+                    Some(expiry) => match expiry.checked_sub(now) {
+                        Some(secs) if secs > 0 => Some(secs),
+                        _ => {
                             return MarketMakerResponse::DeviceNotReserved {
-                                reason: "Failed to parse server response".to_string(),
+                                reason: Error::InvalidState("Lease expired".to_string()),
                             }
-                            .to_json();
+                            .to_json()
                         }
-                        let response = response.unwrap();
-                        match response {
-                            SupplierResponse::SecureTunnelStarted { port } => {
-                                // Update the device in state in a separate thread.
-                                thread::spawn(|| {
-                                    MarketMakerState::insert_device(device_clone);
-                                });
-                                device.available_at_port = port;
-                                MarketMakerResponse::DeviceReserved {
-                                    device,
-                                    peer_id: Some(supplier_id),
-                                }
-                                .to_json()
-                            }
-                            SupplierResponse::SecureTunnelStartFailure { reason } => {
-                                MarketMakerResponse::DeviceNotReserved { reason }.to_json()
-                            }
-                            _ => MarketMakerResponse::DeviceNotReserved {
-                                reason: "Unexpected response from supplier".to_string(),
-                            }
-                            .to_json(),
+                    },
+                    None => None,
+                };
+                MarketMaker::reserve_device(device, &peer_id_str, peer_addr_ip, lease_secs)
+                    .to_json()
+            }
+
+            MarketMakerRequest::EnqueueReservation { target, lease_secs } if is_consumer() => {
+                if lease_secs == Some(0) {
+                    return MarketMakerResponse::ReservationNotQueued {
+                        reason: Error::InvalidInput(
+                            "Lease duration must be greater than zero".to_string(),
+                        ),
+                    }
+                    .to_json();
+                }
+                // The number of reserved devices is checked when the reservation is served.
+                if let Some(max_lease_secs) = MarketMakerState::get_max_lease(&peer_id_str) {
+                    if !matches!(lease_secs, Some(secs) if secs <= max_lease_secs) {
+                        return MarketMakerResponse::ReservationNotQueued {
+                            reason: Error::QuotaExceeded(format!(
+                                "Quota requires a lease of at most {}s",
+                                max_lease_secs
+                            )),
                         }
-                    } else {
-                        MarketMakerResponse::DeviceNotReserved {
-                            reason: "Could not connect to supplier".to_string(),
+                        .to_json();
+                    }
+                }
+                if let WaitTarget::Device(device_id) = &target {
+                    if MarketMakerState::get_device(device_id).is_none() {
+                        return MarketMakerResponse::ReservationNotQueued {
+                            reason: Error::DeviceNotFound("Device not found".to_string()),
                         }
-                        .to_json()
+                        .to_json();
+                    }
+                    if MarketMakerState::is_device_restricted_for(device_id, &peer_id_str) {
+                        return MarketMakerResponse::ReservationNotQueued {
+                            reason: Error::NotAuthorized(
+                                "No access to the pools of the device".to_string(),
+                            ),
+                        }
+                        .to_json();
+                    }
+                }
+                let entry = WaitEntry {
+                    consumer: peer_id_str,
+                    target,
+                    lease_secs,
+                };
+                match MarketMakerState::enqueue_waiting(entry) {
+                    Some(position) => {
+                        // The device may already be available.
+                        thread::spawn(MarketMaker::serve_wait_queue);
+                        MarketMakerResponse::ReservationQueued { position }.to_json()
+                    }
+                    None => MarketMakerResponse::ReservationNotQueued {
+                        reason: Error::InvalidState("Already waiting in the queue".to_string()),
                     }
+                    .to_json(),
                 }
             }
 
+            MarketMakerRequest::LeaveQueue if is_consumer() => {
+                let removed = MarketMakerState::dequeue_waiting(&peer_id_str);
+                MarketMakerResponse::LeftQueue { removed }.to_json()
+            }
+
             MarketMakerRequest::ReleaseDevice { device_id } if is_consumer() => {
                 if MarketMakerState::is_device_used_by(&device_id, &peer_id_str) {
                     thread::spawn(move || {
                         MarketMaker::release_device(&device_id);
                         MarketMakerState::update_available_devices();
+                        MarketMaker::serve_wait_queue();
                     });
                     MarketMakerResponse::DeviceReleased.to_json()
+                } else if matches!(
+                    MarketMakerState::get_remote_reservation(&device_id),
+                    Some((_, device)) if device.used_by == peer_id_str
+                ) {
+                    let (peer_pub_key, _) =
+                        MarketMakerState::remove_remote_reservation(&device_id).unwrap();
+                    let request = MarketMakerRequest::PeerReleaseDevice {
+                        device_id,
+                        consumer: peer_id_str,
+                    };
+                    match MarketMaker::send_to_peer(&peer_pub_key, request) {
+                        Ok(response) => response.to_json(),
+                        Err(e) => MarketMakerResponse::DeviceNotReleased {
+                            reason: Error::Unreachable(format!("Could not connect to peer: {}", e)),
+                        }
+                        .to_json(),
+                    }
                 } else {
                     MarketMakerResponse::DeviceNotReleased {
-                        reason: "Device is not used by the specified consumer. Access restricted."
-                            .to_string(),
+                        reason: Error::NotAuthorized(
+                            "Device is not used by the specified consumer. Access restricted."
+                                .to_string(),
+                        ),
                     }
                     .to_json()
                 }
@@ -1123,8 +3649,10 @@ impl MarketMaker {
 
             MarketMakerRequest::ReleaseAllDevices if is_consumer() => {
                 thread::spawn(move || {
+                    MarketMaker::release_remote_devices_of(&peer_id_str);
                     MarketMakerState::reclaim_devices_used_by(&peer_id_str);
                     MarketMakerState::update_available_devices();
+                    MarketMaker::serve_wait_queue();
                 });
                 MarketMakerResponse::AllDeviceReleaseSuccess.to_json()
             }
@@ -1139,7 +3667,7 @@ impl MarketMaker {
                     let supplier = MarketMakerState::get_supplier(&supplier_id);
                     if supplier.is_none() {
                         return MarketMakerResponse::ScrcpyTunnelFailure {
-                            reason: "Supplier not found".to_string(),
+                            reason: Error::Unreachable("Supplier not found".to_string()),
                         }
                         .to_json();
                     }
@@ -1159,7 +3687,9 @@ impl MarketMaker {
                         let response = client.send_request(request, None);
                         if response.is_err() {
                             return MarketMakerResponse::ScrcpyTunnelFailure {
-                                reason: "Could not connect to supplier".to_string(),
+                                reason: Error::Unreachable(
+                                    "Could not connect to supplier".to_string(),
+                                ),
                             }
                             .to_json();
                         }
@@ -1167,7 +3697,9 @@ impl MarketMaker {
                         let response = SupplierResponse::from_str(&response);
                         if response.is_err() {
                             return MarketMakerResponse::ScrcpyTunnelFailure {
-                                reason: "Failed to parse server response".to_string(),
+                                reason: Error::Protocol(
+                                    "Failed to parse server response".to_string(),
+                                ),
                             }
                             .to_json();
                         }
@@ -1180,20 +3712,23 @@ impl MarketMaker {
                                 MarketMakerResponse::ScrcpyTunnelFailure { reason }.to_json()
                             }
                             _ => MarketMakerResponse::ScrcpyTunnelFailure {
-                                reason: "Unexpected response from supplier".to_string(),
+                                reason: Error::Protocol(
+                                    "Unexpected response from supplier".to_string(),
+                                ),
                             }
                             .to_json(),
                         }
                     } else {
                         MarketMakerResponse::ScrcpyTunnelFailure {
-                            reason: "Could not connect to supplier to start scrcpy tunnel"
-                                .to_string(),
+                            reason: Error::Unreachable(
+                                "Could not connect to supplier to start scrcpy tunnel".to_string(),
+                            ),
                         }
                         .to_json()
                     }
                 } else {
                     MarketMakerResponse::ScrcpyTunnelFailure {
-                        reason: "Unauthorised access to device".to_string(),
+                        reason: Error::NotAuthorized("Unauthorised access to device".to_string()),
                     }
                     .to_json()
                 }
@@ -1203,10 +3738,15 @@ impl MarketMaker {
                 // Remove the consumer from the network.
                 thread::spawn(move || {
                     MarketMakerState::remove_consumer(&peer_id_str);
+                    events::publish(MarketEvent::ConsumerLeft {
+                        peer_id: peer_id_str.clone(),
+                    });
                     // Reclaim the devices used by the consumer.
+                    MarketMaker::release_remote_devices_of(&peer_id_str);
                     MarketMakerState::reclaim_devices_used_by(&peer_id_str);
                     MarketMakerState::update_available_devices();
                     HeartBeatState::remove_consumer(&peer_id_str);
+                    MarketMaker::serve_wait_queue();
                 });
                 MarketMakerResponse::ConsumerDisconnected.to_json()
             }
@@ -1214,6 +3754,7 @@ impl MarketMaker {
             MarketMakerRequest::ConsumerHeartBeat if is_consumer() => {
                 debug!("Received heartbeat from consumer {}", peer_id_str);
                 HeartBeatState::consumer_heartbeat(&peer_id_str);
+                MarketMakerState::clear_restored_consumer(&peer_id_str);
                 MarketMakerResponse::HeartBeatResponse.to_json()
             }
 
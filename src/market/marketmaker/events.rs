@@ -0,0 +1,80 @@
+//! Event stream of the MarketMaker.
+//!
+//! Clients subscribe with `MarketMakerRequest::Subscribe`, which keeps their
+//! connection open. Every change to the network that matches the topics of
+//! a subscriber is then sent to it as a JSON encoded `MarketEvent`. Events of
+//! devices in pools are only sent to subscribers that may see the device.
+
+use super::*;
+use net::StreamSender;
+use tokio::sync::mpsc::error::TrySendError;
+
+struct Subscriber {
+    topics: HashSet<EventTopic>,
+    // network_id of the subscriber, if it may only see the devices in the pools
+    // it has access to.
+    restricted_to: Option<String>,
+    sender: StreamSender,
+}
+
+lazy_static! {
+    static ref SUBSCRIBERS: Mutex<Vec<Subscriber>> = Mutex::new(Vec::new());
+}
+
+/// Add a subscriber for the given topics. All topics are sent to the
+/// subscriber, if `topics` is empty. If `restricted_to` is set, events of
+/// devices are only sent if the device is visible to that network_id.
+pub(super) fn subscribe(
+    topics: HashSet<EventTopic>,
+    restricted_to: Option<String>,
+    sender: StreamSender,
+) {
+    SUBSCRIBERS.lock().unwrap().push(Subscriber {
+        topics,
+        restricted_to,
+        sender,
+    });
+}
+
+/// Get the device of the event, for events that carry one.
+fn device_of(event: &MarketEvent) -> Option<&DeviceSpec> {
+    match event {
+        MarketEvent::DeviceSupplied { device } | MarketEvent::DeviceReserved { device } => {
+            Some(device)
+        }
+        _ => None,
+    }
+}
+
+/// Send the event to the subscribers of its topic.
+/// Subscribers whose connection is closed are removed. So are subscribers
+/// that do not keep up with the events, which closes their stream.
+pub(super) fn publish(event: MarketEvent) {
+    let topic = event.topic();
+    let device = device_of(&event);
+    let message = serde_json::to_string(&event).unwrap();
+    SUBSCRIBERS.lock().unwrap().retain(|subscriber| {
+        let sender = &subscriber.sender;
+        if !subscriber.topics.is_empty() && !subscriber.topics.contains(&topic) {
+            return !sender.is_closed();
+        }
+        if let (Some(device), Some(peer_id)) = (device, &subscriber.restricted_to) {
+            if !MarketMakerState::is_device_visible_to(device, peer_id) {
+                return !sender.is_closed();
+            }
+        }
+        match sender.try_send(message.clone()) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                warn!("Event subscriber is not reading its events. Closing its stream.");
+                false
+            }
+            Err(TrySendError::Closed(_)) => false,
+        }
+    });
+}
+
+/// Remove all subscribers, closing their streams.
+pub(super) fn close_all() {
+    SUBSCRIBERS.lock().unwrap().clear();
+}
@@ -0,0 +1,142 @@
+//! Persistence backends for the MarketMaker state.
+//!
+//! The MarketMaker keeps its state in memory and writes a snapshot of it
+//! to a `StateStore` shortly after it changes. When the MarketMaker is started
+//! again, the last snapshot is loaded from the store, so that Suppliers,
+//! Consumers, devices, reservations and whitelists survive a restart.
+
+use super::*;
+use crate::util::{self, ADBORC_MM_STORE, MARKET_MAKER_STATE_FILE};
+use std::fs;
+
+/// Snapshot of the persistent parts of `MarketMakerState`.
+/// Also used to replicate the state of a primary MarketMaker to its standby.
+/// Fields missing from a snapshot written by another version of adborc are
+/// left empty, so that the rest of the snapshot can still be restored.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct MarketMakerSnapshot {
+    // Version of adborc that wrote the snapshot.
+    adborc_version: String,
+    pub(super) suppliers: HashMap<String, SupplierSpec>,
+    pub(super) consumers: HashMap<String, ConsumerSpec>,
+    pub(super) devices: HashMap<String, DeviceSpec>,
+    pub(super) available_devices: HashMap<String, DeviceSpec>,
+    pub(super) use_whitelists: bool,
+    pub(super) supplier_whitelist: HashSet<String>,
+    pub(super) consumer_whitelist: HashSet<String>,
+    pub(super) wait_queue: VecDeque<WaitEntry>,
+    pub(super) standby_whitelist: HashSet<String>,
+    pub(super) peers: HashMap<String, MarketMakerSpec>,
+    pub(super) peer_whitelist: HashSet<String>,
+    pub(super) roles: HashMap<String, HashSet<Role>>,
+    pub(super) consumer_quotas: HashMap<String, Quota>,
+    pub(super) group_quotas: HashMap<String, Quota>,
+    pub(super) consumer_groups: HashMap<String, HashSet<String>>,
+    pub(super) device_pools: HashMap<String, HashSet<String>>,
+    pub(super) pool_access: HashMap<String, HashSet<String>>,
+}
+
+impl From<&MarketMakerState> for MarketMakerSnapshot {
+    fn from(state: &MarketMakerState) -> Self {
+        MarketMakerSnapshot {
+            adborc_version: ADBORC_VERSION.to_string(),
+            suppliers: state.suppliers.clone(),
+            consumers: state.consumers.clone(),
+            devices: state.devices.clone(),
+            available_devices: state.available_devices.clone(),
+            use_whitelists: state.use_whitelists,
+            supplier_whitelist: state.supplier_whitelist.clone(),
+            consumer_whitelist: state.consumer_whitelist.clone(),
+            wait_queue: state.wait_queue.clone(),
+            standby_whitelist: state.standby_whitelist.clone(),
+            peers: state.peers.clone(),
+            peer_whitelist: state.peer_whitelist.clone(),
+            roles: state.roles.clone(),
+            consumer_quotas: state.consumer_quotas.clone(),
+            group_quotas: state.group_quotas.clone(),
+            consumer_groups: state.consumer_groups.clone(),
+            device_pools: state.device_pools.clone(),
+            pool_access: state.pool_access.clone(),
+        }
+    }
+}
+
+/// A backend capable of persisting `MarketMakerSnapshot`s.
+pub(super) trait StateStore: Send + Sync {
+    /// Load the last saved snapshot, if any.
+    fn load(&self) -> io::Result<Option<MarketMakerSnapshot>>;
+    /// Save the snapshot, replacing any previously saved snapshot.
+    fn save(&self, snapshot: &MarketMakerSnapshot) -> io::Result<()>;
+    /// Remove any saved snapshot.
+    fn clear(&self) -> io::Result<()>;
+}
+
+/// Stores the snapshot as a JSON document in a single file.
+/// Writes go to a temporary file first, which is then renamed over
+/// the previous snapshot, so that a crash never leaves a partial file behind.
+pub(super) struct JsonFileStore {
+    path: PathBuf,
+}
+
+impl JsonFileStore {
+    pub(super) fn new(path: PathBuf) -> Self {
+        JsonFileStore { path }
+    }
+}
+
+impl StateStore for JsonFileStore {
+    fn load(&self) -> io::Result<Option<MarketMakerSnapshot>> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+        let contents = fs::read_to_string(&self.path)?;
+        let snapshot: MarketMakerSnapshot = serde_json::from_str(&contents)
+            .map_err(|e| io::Error::new(ErrorKind::InvalidData, e.to_string()))?;
+        if snapshot.adborc_version != ADBORC_VERSION {
+            info!(
+                "Loading MarketMaker state saved by adborc version {}",
+                snapshot.adborc_version
+            );
+        }
+        Ok(Some(snapshot))
+    }
+
+    fn save(&self, snapshot: &MarketMakerSnapshot) -> io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let contents = serde_json::to_string(snapshot)
+            .map_err(|e| io::Error::new(ErrorKind::InvalidData, e.to_string()))?;
+        let temp_path = self.path.with_extension("json.tmp");
+        fs::write(&temp_path, contents)?;
+        fs::rename(&temp_path, &self.path)
+    }
+
+    fn clear(&self) -> io::Result<()> {
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Get the store configured through the `ADBORC_MM_STORE` environment variable.
+/// Returns `None` if persistence is disabled.
+pub(super) fn configured_store() -> Option<Box<dyn StateStore>> {
+    let backend = std::env::var(ADBORC_MM_STORE).unwrap_or_default();
+    match backend.to_lowercase().as_str() {
+        "" | "json" => {
+            let path = util::data_dir().join(MARKET_MAKER_STATE_FILE);
+            Some(Box::new(JsonFileStore::new(path)))
+        }
+        "none" => None,
+        other => {
+            warn!(
+                "Unknown MarketMaker store backend: {}. State will not be persisted",
+                other
+            );
+            None
+        }
+    }
+}
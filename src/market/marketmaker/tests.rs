@@ -32,6 +32,7 @@ async fn test_supplier_connect() {
             pub_key: base64::encode(&peer_pub_key),
             ..SupplierSpec::default()
         },
+        resume: false,
     };
     let (peer_addr, peer_id) = test_utils::get_peer_with_key(&peer_pub_key);
 
@@ -85,6 +86,7 @@ async fn test_supplier_connect_secure_mode() {
             secure_comms: true,
             ..SupplierSpec::default()
         },
+        resume: false,
     };
 
     let (peer_addr, peer_id) = test_utils::get_peer_with_key(&peer_pub_key);
@@ -141,6 +143,7 @@ async fn test_supplier_connect_pub_key_mismatch() {
             pub_key: base64::encode(&peer_pub_key),
             ..SupplierSpec::default()
         },
+        resume: false,
     };
     let (peer_addr, peer_id) = test_utils::get_peer_with_key(&vec![0u8; 32]);
 
@@ -156,7 +159,10 @@ async fn test_supplier_connect_pub_key_mismatch() {
     let response = MarketMakerResponse::from_str(&response).unwrap();
     match response {
         MarketMakerResponse::SupplierNotConnected { reason } => {
-            assert_eq!(reason, "Public key does not match peer id");
+            assert_eq!(
+                reason,
+                Error::NotAuthorized("Public key does not match peer id".to_string())
+            );
             let state = MarketMakerState::get_min_state();
             let expected_state = MarketMakerMinState::default();
             assert_eq!(state, expected_state);
@@ -180,7 +186,10 @@ async fn test_supplier_connect_already_connected() {
         ..SupplierSpec::default()
     };
     MarketMakerState::insert_supplier(supplier.clone());
-    let request = MarketMakerRequest::SupplierConnect { supplier };
+    let request = MarketMakerRequest::SupplierConnect {
+        supplier,
+        resume: false,
+    };
 
     let (peer_addr, peer_id) = test_utils::get_peer_with_key(&peer_pub_key);
 
@@ -196,7 +205,7 @@ async fn test_supplier_connect_already_connected() {
     let response = MarketMakerResponse::from_str(&response).unwrap();
     match response {
         MarketMakerResponse::SupplierNotConnected { reason } => {
-            assert_eq!(reason, "Already connected");
+            assert_eq!(reason, Error::InvalidState("Already connected".to_string()));
             let state = MarketMakerState::get_min_state();
             let expected_state = MarketMakerMinState {
                 suppliers: vec![SupplierSpec {
@@ -228,6 +237,7 @@ async fn test_supplier_connect_not_in_whitelist() {
             pub_key: base64::encode(&peer_pub_key),
             ..SupplierSpec::default()
         },
+        resume: false,
     };
     let (peer_addr, peer_id) = test_utils::get_peer_with_key(&peer_pub_key);
 
@@ -243,7 +253,7 @@ async fn test_supplier_connect_not_in_whitelist() {
     let response = MarketMakerResponse::from_str(&response).unwrap();
     match response {
         MarketMakerResponse::SupplierNotConnected { reason } => {
-            assert_eq!(reason, "Not in whitelist");
+            assert_eq!(reason, Error::NotAuthorized("Not in whitelist".to_string()));
             let state = MarketMakerState::get_min_state();
             let expected_state = MarketMakerMinState {
                 use_whitelists: true,
@@ -270,6 +280,7 @@ async fn test_consumer_connect() {
             pub_key: base64::encode(&peer_pub_key),
             ..ConsumerSpec::default()
         },
+        resume: false,
     };
 
     let (peer_addr, peer_id) = test_utils::get_peer_with_key(&peer_pub_key);
@@ -323,6 +334,7 @@ async fn test_consumer_connect_pub_key_mismatch() {
             pub_key: base64::encode(&peer_pub_key),
             ..ConsumerSpec::default()
         },
+        resume: false,
     };
     let (peer_addr, peer_id) = test_utils::get_peer_with_key(&vec![0u8; 32]);
 
@@ -338,7 +350,10 @@ async fn test_consumer_connect_pub_key_mismatch() {
     let response = MarketMakerResponse::from_str(&response).unwrap();
     match response {
         MarketMakerResponse::ConsumerNotConnected { reason } => {
-            assert_eq!(reason, "Public key does not match peer id");
+            assert_eq!(
+                reason,
+                Error::NotAuthorized("Public key does not match peer id".to_string())
+            );
             let state = MarketMakerState::get_min_state();
             let expected_state = MarketMakerMinState::default();
             assert_eq!(state, expected_state);
@@ -362,7 +377,10 @@ async fn test_consumer_connect_already_connected() {
         ..ConsumerSpec::default()
     };
     MarketMakerState::insert_consumer(consumer.clone());
-    let request = MarketMakerRequest::ConsumerConnect { consumer };
+    let request = MarketMakerRequest::ConsumerConnect {
+        consumer,
+        resume: false,
+    };
 
     let (peer_addr, peer_id) = test_utils::get_peer_with_key(&peer_pub_key);
 
@@ -378,7 +396,7 @@ async fn test_consumer_connect_already_connected() {
     let response = MarketMakerResponse::from_str(&response).unwrap();
     match response {
         MarketMakerResponse::ConsumerNotConnected { reason } => {
-            assert_eq!(reason, "Already connected");
+            assert_eq!(reason, Error::InvalidState("Already connected".to_string()));
             let state = MarketMakerState::get_min_state();
             let expected_state = MarketMakerMinState {
                 consumers: vec![ConsumerSpec {
@@ -410,6 +428,7 @@ async fn test_consumer_connect_not_in_whitelist() {
             pub_key: base64::encode(&peer_pub_key),
             ..ConsumerSpec::default()
         },
+        resume: false,
     };
     let (peer_addr, peer_id) = test_utils::get_peer_with_key(&peer_pub_key);
 
@@ -425,7 +444,7 @@ async fn test_consumer_connect_not_in_whitelist() {
     let response = MarketMakerResponse::from_str(&response).unwrap();
     match response {
         MarketMakerResponse::ConsumerNotConnected { reason } => {
-            assert_eq!(reason, "Not in whitelist");
+            assert_eq!(reason, Error::NotAuthorized("Not in whitelist".to_string()));
             let state = MarketMakerState::get_min_state();
             let expected_state = MarketMakerMinState {
                 use_whitelists: true,
@@ -440,3 +459,1127 @@ async fn test_consumer_connect_not_in_whitelist() {
         }
     }
 }
+
+#[test]
+#[serial]
+fn test_state_persisted_and_restored() {
+    test_with_logs();
+    MarketMakerState::reset_state();
+    let path = std::env::temp_dir().join("adborc_test_marketmaker_state.json");
+    let store = store::JsonFileStore::new(path.clone());
+    store.clear().unwrap();
+    MarketMakerState::attach_store(Box::new(store::JsonFileStore::new(path.clone())));
+
+    let supplier_key = base64::encode(vec![255u8; 32]);
+    MarketMakerState::set_whitelists();
+    MarketMakerState::add_to_supplier_whitelist(&supplier_key);
+    MarketMakerState::insert_supplier(SupplierSpec {
+        pub_key: supplier_key.clone(),
+        ..SupplierSpec::default()
+    });
+    MarketMakerState::insert_device(DeviceSpec {
+        device_id: "device".to_string(),
+        available_at: supplier_key.clone(),
+        ..DeviceSpec::default()
+    });
+    MarketMakerState::update_available_devices();
+    let expected_state = MarketMakerState::get_min_state();
+
+    // The state is written once the changes settle.
+    thread::sleep(PERSIST_INTERVAL * 3);
+    let snapshot = store.load().unwrap().unwrap();
+    MarketMakerState::reset_state();
+    MarketMakerState::restore(snapshot);
+    assert_eq!(MarketMakerState::get_min_state(), expected_state);
+    assert!(MarketMakerState::is_restored_supplier(&supplier_key));
+
+    MarketMakerState::detach_store();
+    assert!(!path.exists());
+    MarketMakerState::reset_state();
+}
+
+#[test]
+fn test_state_from_other_version_loaded() {
+    test_with_logs();
+    let path = std::env::temp_dir().join("adborc_test_marketmaker_state_version.json");
+    let store = store::JsonFileStore::new(path.clone());
+    // A snapshot of an earlier version, without the fields added since.
+    std::fs::write(
+        &path,
+        r#"{"adborc_version": "0.0.1", "use_whitelists": true, "supplier_whitelist": ["key"]}"#,
+    )
+    .unwrap();
+    let snapshot = store.load();
+    store.clear().unwrap();
+    let snapshot = snapshot.unwrap().unwrap();
+    assert!(snapshot.use_whitelists);
+    assert!(snapshot.supplier_whitelist.contains("key"));
+    assert!(snapshot.devices.is_empty());
+}
+
+#[tokio::test]
+#[serial]
+async fn test_supplier_reconnect_after_restore() {
+    test_with_logs();
+    MarketMakerState::reset_state();
+    let peer_pub_key = vec![255u8; 32];
+    let supplier = SupplierSpec {
+        pub_key: base64::encode(&peer_pub_key),
+        ..SupplierSpec::default()
+    };
+    let (peer_addr, peer_id) = test_utils::get_peer_with_key(&peer_pub_key);
+    let device = DeviceSpec {
+        device_id: "device".to_string(),
+        available_at: supplier.pub_key.clone(),
+        available_at_host: peer_addr.ip().to_string(),
+        ..DeviceSpec::default()
+    };
+
+    // Persist a state with a supplied device and restore it, as a restarted
+    // MarketMaker would.
+    MarketMakerState::insert_supplier(SupplierSpec {
+        bind_host: peer_addr.ip().to_string(),
+        ..supplier.clone()
+    });
+    MarketMakerState::insert_device(device);
+    MarketMakerState::update_available_devices();
+    let snapshot = MarketMakerSnapshot::from(&MarketMakerState::get_state());
+    MarketMakerState::reset_state();
+    MarketMakerState::restore(snapshot);
+
+    fn process_command(_: String, _: SocketAddr, _: Arc<Key>) -> String {
+        "OK".to_string()
+    }
+    test_utils::start_dummy_system_server(process_command);
+
+    let request = MarketMakerRequest::SupplierConnect {
+        supplier,
+        resume: false,
+    };
+    let response =
+        task::spawn_blocking(move || MarketMaker::process_request(request, peer_addr, peer_id))
+            .await
+            .unwrap();
+    let response = MarketMakerResponse::from_str(&response).unwrap();
+    let reconnected = matches!(response, MarketMakerResponse::SupplierConnected { .. });
+    let device_kept = MarketMakerState::get_device("device").is_some();
+    let still_restored = MarketMakerState::is_restored_supplier(&base64::encode(&peer_pub_key));
+    MarketMakerState::reset_state();
+    assert!(reconnected, "Unexpected response");
+    assert!(device_kept);
+    assert!(!still_restored);
+}
+
+#[tokio::test]
+#[serial]
+async fn test_consumer_resume_keeps_reservations() {
+    test_with_logs();
+    MarketMakerState::reset_state();
+    let peer_pub_key = vec![255u8; 32];
+    let consumer = ConsumerSpec {
+        pub_key: base64::encode(&peer_pub_key),
+        ..ConsumerSpec::default()
+    };
+    let (peer_addr, peer_id) = test_utils::get_peer_with_key(&peer_pub_key);
+    MarketMakerState::insert_consumer(consumer.clone());
+    MarketMakerState::insert_device(DeviceSpec {
+        device_id: "device".to_string(),
+        available_at_host: peer_addr.ip().to_string(),
+        used_by: consumer.pub_key.clone(),
+        lease_expiry: Some(util::unix_time_now() + 60),
+        ..DeviceSpec::default()
+    });
+
+    fn process_command(_: String, _: SocketAddr, _: Arc<Key>) -> String {
+        "OK".to_string()
+    }
+    test_utils::start_dummy_system_server(process_command);
+
+    let request = MarketMakerRequest::ConsumerConnect {
+        consumer: consumer.clone(),
+        resume: true,
+    };
+    let response = task::spawn_blocking(move || {
+        let connected = MarketMaker::process_request(request, peer_addr, peer_id.clone());
+        let request = MarketMakerRequest::ResumeReservation {
+            device_id: "device".to_string(),
+        };
+        let resumed = MarketMaker::process_request(request, peer_addr, peer_id);
+        (connected, resumed)
+    })
+    .await
+    .unwrap();
+    let connected = MarketMakerResponse::from_str(&response.0).unwrap();
+    let resumed = MarketMakerResponse::from_str(&response.1).unwrap();
+    let device = MarketMakerState::get_device("device").unwrap();
+    MarketMakerState::reset_state();
+    assert!(matches!(
+        connected,
+        MarketMakerResponse::ConsumerConnected { .. }
+    ));
+    let lease = match resumed {
+        MarketMakerResponse::DeviceReserved { device, .. } => device.lease_remaining(),
+        _ => panic!("Unexpected response"),
+    };
+    assert!(matches!(lease, Some(remaining) if remaining <= 60));
+    assert_eq!(device.used_by, consumer.pub_key);
+}
+
+#[test]
+#[serial]
+fn test_lease_renew_and_expiry() {
+    test_with_logs();
+    MarketMakerState::reset_state();
+    let consumer_key = base64::encode(vec![255u8; 32]);
+    let (peer_addr, peer_id) = test_utils::get_peer_with_key(&vec![255u8; 32]);
+    MarketMakerState::insert_consumer(ConsumerSpec {
+        pub_key: consumer_key.clone(),
+        ..ConsumerSpec::default()
+    });
+    MarketMakerState::insert_device(DeviceSpec {
+        device_id: "device".to_string(),
+        used_by: consumer_key.clone(),
+        lease_expiry: Some(util::unix_time_now() + 60),
+        ..DeviceSpec::default()
+    });
+
+    let request = MarketMakerRequest::RenewLease {
+        device_id: "device".to_string(),
+        lease_secs: 3600,
+    };
+    let response = MarketMaker::process_request(request, peer_addr, peer_id);
+    let response = MarketMakerResponse::from_str(&response).unwrap();
+    let renewed = match response {
+        MarketMakerResponse::LeaseRenewed { device } => device.lease_remaining(),
+        _ => None,
+    };
+    assert!(matches!(renewed, Some(remaining) if remaining > 60));
+
+    // Leases that are not yet due are left alone.
+    MarketMaker::expire_leases();
+    assert_eq!(
+        MarketMakerState::get_device("device").unwrap().used_by,
+        consumer_key
+    );
+
+    MarketMakerState::renew_lease("device", &consumer_key, util::unix_time_now() - 1);
+    MarketMaker::expire_leases();
+    let device = MarketMakerState::get_device("device").unwrap();
+    let available = MarketMakerState::get_available_devices();
+    MarketMakerState::reset_state();
+    assert!(device.used_by.is_empty());
+    assert_eq!(device.lease_expiry, None);
+    assert_eq!(available.len(), 1);
+}
+
+#[test]
+#[serial]
+fn test_lease_overflow_rejected() {
+    test_with_logs();
+    MarketMakerState::reset_state();
+    let consumer_key = base64::encode(vec![255u8; 32]);
+    let (peer_addr, peer_id) = test_utils::get_peer_with_key(&vec![255u8; 32]);
+    MarketMakerState::insert_consumer(ConsumerSpec {
+        pub_key: consumer_key.clone(),
+        ..ConsumerSpec::default()
+    });
+    MarketMakerState::insert_device(DeviceSpec {
+        device_id: "reserved".to_string(),
+        used_by: consumer_key,
+        lease_expiry: Some(util::unix_time_now() + 60),
+        ..DeviceSpec::default()
+    });
+    MarketMakerState::insert_device(DeviceSpec {
+        device_id: "available".to_string(),
+        ..DeviceSpec::default()
+    });
+    MarketMakerState::update_available_devices();
+
+    let request = MarketMakerRequest::RenewLease {
+        device_id: "reserved".to_string(),
+        lease_secs: u64::MAX,
+    };
+    let renew = MarketMaker::process_request(request, peer_addr, peer_id.clone());
+    let request = MarketMakerRequest::ReserveDevice {
+        device_id: "available".to_string(),
+        lease_secs: Some(u64::MAX),
+    };
+    let reserve = MarketMaker::process_request(request, peer_addr, peer_id);
+    let lease_expiry = MarketMakerState::get_device("reserved")
+        .unwrap()
+        .lease_expiry;
+    MarketMakerState::reset_state();
+
+    assert!(matches!(
+        MarketMakerResponse::from_str(&renew).unwrap(),
+        MarketMakerResponse::LeaseNotRenewed {
+            reason: Error::InvalidInput(_)
+        }
+    ));
+    assert!(matches!(
+        MarketMakerResponse::from_str(&reserve).unwrap(),
+        MarketMakerResponse::DeviceNotReserved {
+            reason: Error::InvalidInput(_)
+        }
+    ));
+    assert!(matches!(lease_expiry, Some(expiry) if expiry <= util::unix_time_now() + 60));
+}
+
+#[test]
+#[serial]
+fn test_wait_queue_order() {
+    test_with_logs();
+    MarketMakerState::reset_state();
+    let first_key = base64::encode(vec![1u8; 32]);
+    let second_key = base64::encode(vec![2u8; 32]);
+    let (peer_addr, peer_id) = test_utils::get_peer_with_key(&vec![1u8; 32]);
+    for key in [&first_key, &second_key] {
+        MarketMakerState::insert_consumer(ConsumerSpec {
+            pub_key: key.to_string(),
+            ..ConsumerSpec::default()
+        });
+    }
+    MarketMakerState::insert_device(DeviceSpec {
+        device_id: "device".to_string(),
+        used_by: "someone".to_string(),
+        used_by_host: "127.0.0.1".to_string(),
+        ..DeviceSpec::default()
+    });
+
+    let request = MarketMakerRequest::EnqueueReservation {
+        target: WaitTarget::Device("device".to_string()),
+        lease_secs: None,
+    };
+    let response = MarketMaker::process_request(request, peer_addr, peer_id.clone());
+    assert_eq!(
+        response,
+        MarketMakerResponse::ReservationQueued { position: 1 }.to_json()
+    );
+    let second = WaitEntry {
+        consumer: second_key.clone(),
+        target: WaitTarget::AnyMatching(DeviceFilterVec::default()),
+        lease_secs: Some(60),
+    };
+    assert_eq!(MarketMakerState::enqueue_waiting(second.clone()), Some(2));
+    // A Consumer cannot wait twice for the same target.
+    assert_eq!(MarketMakerState::enqueue_waiting(second.clone()), None);
+
+    // Nothing is available yet.
+    assert!(MarketMakerState::pop_waiting_with_device().is_none());
+
+    MarketMakerState::release_device("device");
+    MarketMakerState::update_available_devices();
+    let (entry, device) = MarketMakerState::pop_waiting_with_device().unwrap();
+    assert_eq!(entry.consumer, first_key);
+    assert_eq!(device.device_id, "device");
+    assert!(MarketMakerState::get_available_devices().is_empty());
+
+    // Removing a Consumer drops its queued reservations.
+    MarketMakerState::remove_consumer(&second_key);
+    let request = MarketMakerRequest::LeaveQueue;
+    let response = MarketMaker::process_request(request, peer_addr, peer_id);
+    MarketMakerState::reset_state();
+    assert_eq!(
+        response,
+        MarketMakerResponse::LeftQueue { removed: 0 }.to_json()
+    );
+}
+
+#[test]
+#[serial]
+fn test_reserve_any_device() {
+    test_with_logs();
+    MarketMakerState::reset_state();
+    let consumer_key = base64::encode(vec![255u8; 32]);
+    let (peer_addr, peer_id) = test_utils::get_peer_with_key(&vec![255u8; 32]);
+    MarketMakerState::insert_consumer(ConsumerSpec {
+        pub_key: consumer_key.clone(),
+        ..ConsumerSpec::default()
+    });
+    for (device_id, model) in [("p1", "Pixel 7"), ("p2", "Pixel 7"), ("g1", "Galaxy S23")] {
+        MarketMakerState::insert_device(DeviceSpec {
+            device_id: device_id.to_string(),
+            device_info: DeviceInfo {
+                model: model.to_string(),
+                ..DeviceInfo::default()
+            },
+            ..DeviceSpec::default()
+        });
+    }
+    MarketMakerState::update_available_devices();
+
+    let filter_vec = DeviceFilterVec {
+        filters: vec![DeviceFilter::DeviceModels(HashSet::from([
+            "Pixel 7".to_string()
+        ]))],
+        ..DeviceFilterVec::default()
+    };
+    let request = MarketMakerRequest::ReserveAnyDevice {
+        filter_vec: filter_vec.clone(),
+        count: 3,
+        lease_secs: None,
+    };
+    let response = MarketMaker::process_request(request, peer_addr, peer_id.clone());
+    let response = MarketMakerResponse::from_str(&response).unwrap();
+    let mut reserved = match response {
+        MarketMakerResponse::DevicesReserved { devices } => devices
+            .into_iter()
+            .map(|(device, _)| device.device_id)
+            .collect::<Vec<String>>(),
+        _ => Vec::new(),
+    };
+    reserved.sort();
+
+    // All matching devices are now in use.
+    let request = MarketMakerRequest::ReserveAnyDevice {
+        filter_vec,
+        count: 1,
+        lease_secs: None,
+    };
+    let response = MarketMaker::process_request(request, peer_addr, peer_id);
+    let available = MarketMakerState::get_available_devices();
+    let used_by = MarketMakerState::get_device("p1").unwrap().used_by;
+    MarketMakerState::reset_state();
+    assert_eq!(reserved, vec!["p1".to_string(), "p2".to_string()]);
+    assert_eq!(
+        response,
+        MarketMakerResponse::DeviceNotReserved {
+            reason: Error::DeviceUnavailable("No matching device available".to_string())
+        }
+        .to_json()
+    );
+    assert_eq!(available.len(), 1);
+    assert_eq!(used_by, consumer_key);
+}
+
+#[tokio::test]
+#[serial]
+async fn test_standby_connect() {
+    test_with_logs();
+    MarketMakerState::reset_state();
+    let standby_key = vec![254u8; 32];
+    let other_key = vec![253u8; 32];
+    let standby = |pub_key: &[u8]| MarketMakerRequest::StandbyConnect {
+        market_maker: MarketMakerSpec {
+            pub_key: base64::encode(pub_key),
+            ..MarketMakerSpec::default()
+        },
+    };
+
+    fn process_command(_: String, _: SocketAddr, _: Arc<Key>) -> String {
+        "OK".to_string()
+    }
+    test_utils::start_dummy_system_server(process_command);
+
+    // Standbys are required to be whitelisted, even if whitelisting is not in use.
+    let (peer_addr, peer_id) = test_utils::get_peer_with_key(&standby_key);
+    let request = standby(&standby_key);
+    let response =
+        task::spawn_blocking(move || MarketMaker::process_request(request, peer_addr, peer_id))
+            .await
+            .unwrap();
+    let rejected = matches!(
+        MarketMakerResponse::from_str(&response).unwrap(),
+        MarketMakerResponse::StandbyNotConnected { .. }
+    );
+
+    MarketMakerState::add_to_standby_whitelist(&base64::encode(&standby_key));
+    MarketMakerState::add_to_standby_whitelist(&base64::encode(&other_key));
+    MarketMakerState::insert_supplier(SupplierSpec {
+        pub_key: base64::encode(vec![255u8; 32]),
+        ..SupplierSpec::default()
+    });
+    let (peer_addr, peer_id) = test_utils::get_peer_with_key(&standby_key);
+    let request = standby(&standby_key);
+    let response =
+        task::spawn_blocking(move || MarketMaker::process_request(request, peer_addr, peer_id))
+            .await
+            .unwrap();
+    let replicated_suppliers = match MarketMakerResponse::from_str(&response).unwrap() {
+        MarketMakerResponse::StandbyConnected { snapshot, .. } => Some(snapshot.suppliers.len()),
+        _ => None,
+    };
+
+    // Only one standby may be connected at a time.
+    let (peer_addr, peer_id) = test_utils::get_peer_with_key(&other_key);
+    let request = standby(&other_key);
+    let response =
+        task::spawn_blocking(move || MarketMaker::process_request(request, peer_addr, peer_id))
+            .await
+            .unwrap();
+    let second_rejected = matches!(
+        MarketMakerResponse::from_str(&response).unwrap(),
+        MarketMakerResponse::StandbyNotConnected { .. }
+    );
+    let num_standbys = MarketMakerState::get_min_state().num_market_makers;
+    MarketMakerState::reset_state();
+    assert!(rejected, "Standby connected without being whitelisted");
+    assert_eq!(replicated_suppliers, Some(1));
+    assert!(second_rejected, "Second standby connected");
+    assert_eq!(num_standbys, 1);
+}
+
+#[test]
+#[serial]
+fn test_standby_replicate_and_promote() {
+    test_with_logs();
+    MarketMakerState::reset_state();
+    let primary_key = vec![254u8; 32];
+    let supplier_key = vec![255u8; 32];
+    MarketMakerState::set_primary(MarketMakerSpec {
+        bind_host: "127.0.0.1".to_string(),
+        pub_key: base64::encode(&primary_key),
+        ..MarketMakerSpec::default()
+    });
+    let mut snapshot = MarketMakerSnapshot::default();
+    snapshot.suppliers.insert(
+        base64::encode(&supplier_key),
+        SupplierSpec {
+            pub_key: base64::encode(&supplier_key),
+            ..SupplierSpec::default()
+        },
+    );
+
+    // Only the primary may replicate its state to the standby.
+    let (peer_addr, peer_id) = test_utils::get_peer_with_key(&supplier_key);
+    let request = MarketMakerRequest::ReplicateState {
+        snapshot: Box::new(snapshot.clone()),
+    };
+    let response = MarketMaker::process_request(request, peer_addr, peer_id);
+    assert_eq!(response, MarketMakerResponse::RequestNotAllowed.to_json());
+
+    let (peer_addr, peer_id) = test_utils::get_peer_with_key(&primary_key);
+    let request = MarketMakerRequest::ReplicateState {
+        snapshot: Box::new(snapshot),
+    };
+    let response = MarketMaker::process_request(request, peer_addr, peer_id);
+    assert_eq!(response, MarketMakerResponse::StateReplicated.to_json());
+    assert!(MarketMakerState::is_standby());
+    assert!(MarketMakerState::supplier_exists(&base64::encode(
+        &supplier_key
+    )));
+
+    // Suppliers and Consumers are served by the primary.
+    let (peer_addr, peer_id) = test_utils::get_peer_with_key(&supplier_key);
+    let request = MarketMakerRequest::SupplierConnect {
+        supplier: SupplierSpec {
+            pub_key: base64::encode(&supplier_key),
+            ..SupplierSpec::default()
+        },
+        resume: false,
+    };
+    let response = MarketMaker::process_request(request, peer_addr, peer_id);
+    assert!(matches!(
+        MarketMakerResponse::from_str(&response).unwrap(),
+        MarketMakerResponse::SupplierNotConnected { .. }
+    ));
+
+    // Once promoted, replicated Suppliers may connect again.
+    MarketMakerState::promote();
+    assert!(!MarketMakerState::is_standby());
+    assert!(MarketMakerState::is_restored_supplier(&base64::encode(
+        &supplier_key
+    )));
+    MarketMakerState::reset_state();
+}
+
+#[tokio::test]
+#[serial]
+async fn test_peer_connect() {
+    test_with_logs();
+    MarketMakerState::reset_state();
+    let peer_key = vec![252u8; 32];
+    let peer = |pub_key: &[u8]| MarketMakerRequest::PeerConnect {
+        market_maker: MarketMakerSpec {
+            pub_key: base64::encode(pub_key),
+            ..MarketMakerSpec::default()
+        },
+    };
+
+    fn process_command(_: String, _: SocketAddr, _: Arc<Key>) -> String {
+        "OK".to_string()
+    }
+    test_utils::start_dummy_system_server(process_command);
+
+    // Peers are required to be whitelisted, even if whitelisting is not in use.
+    let (peer_addr, peer_id) = test_utils::get_peer_with_key(&peer_key);
+    let request = peer(&peer_key);
+    let response =
+        task::spawn_blocking(move || MarketMaker::process_request(request, peer_addr, peer_id))
+            .await
+            .unwrap();
+    let rejected = matches!(
+        MarketMakerResponse::from_str(&response).unwrap(),
+        MarketMakerResponse::PeerNotConnected { .. }
+    );
+
+    MarketMakerState::add_to_peer_whitelist(&base64::encode(&peer_key));
+    MarketMakerState::insert_device(DeviceSpec {
+        device_id: "local".to_string(),
+        ..DeviceSpec::default()
+    });
+    // Devices in pools are not offered to peers.
+    MarketMakerState::insert_device(DeviceSpec {
+        device_id: "pooled".to_string(),
+        pools: HashSet::from(["qa".to_string()]),
+        ..DeviceSpec::default()
+    });
+    MarketMakerState::update_available_devices();
+    let (peer_addr, peer_id) = test_utils::get_peer_with_key(&peer_key);
+    let request = peer(&peer_key);
+    let response =
+        task::spawn_blocking(move || MarketMaker::process_request(request, peer_addr, peer_id))
+            .await
+            .unwrap();
+    let advertised = match MarketMakerResponse::from_str(&response).unwrap() {
+        MarketMakerResponse::PeerConnected { devices, .. } => Some(devices.len()),
+        _ => None,
+    };
+    let is_peer = MarketMakerState::verify_peer(&base64::encode(&peer_key));
+    let num_peers = MarketMakerState::get_min_state().peers.len();
+    MarketMakerState::reset_state();
+    HeartBeatState::reset_state();
+    assert!(rejected, "Peer connected without being whitelisted");
+    assert_eq!(advertised, Some(1));
+    assert!(is_peer);
+    assert_eq!(num_peers, 1);
+}
+
+#[test]
+#[serial]
+fn test_peer_devices_offered_to_consumers() {
+    test_with_logs();
+    MarketMakerState::reset_state();
+    let peer_key = vec![252u8; 32];
+    let consumer_key = vec![255u8; 32];
+    MarketMakerState::insert_peer(MarketMakerSpec {
+        bind_host: "127.0.0.1".to_string(),
+        pub_key: base64::encode(&peer_key),
+        ..MarketMakerSpec::default()
+    });
+    MarketMakerState::insert_consumer(ConsumerSpec {
+        pub_key: base64::encode(&consumer_key),
+        ..ConsumerSpec::default()
+    });
+    MarketMakerState::insert_device(DeviceSpec {
+        device_id: "local".to_string(),
+        ..DeviceSpec::default()
+    });
+    MarketMakerState::update_available_devices();
+    let remote = DeviceSpec {
+        device_id: "remote".to_string(),
+        device_info: DeviceInfo {
+            model: "Pixel 7".to_string(),
+            ..DeviceInfo::default()
+        },
+        ..DeviceSpec::default()
+    };
+
+    // Only peers may advertise devices.
+    let (peer_addr, peer_id) = test_utils::get_peer_with_key(&consumer_key);
+    let request = MarketMakerRequest::AdvertiseDevices {
+        devices: vec![remote.clone()],
+    };
+    let response = MarketMaker::process_request(request, peer_addr, peer_id);
+    assert_eq!(response, MarketMakerResponse::RequestNotAllowed.to_json());
+
+    let (peer_addr, peer_id) = test_utils::get_peer_with_key(&peer_key);
+    let request = MarketMakerRequest::AdvertiseDevices {
+        devices: vec![remote],
+    };
+    let response = MarketMaker::process_request(request, peer_addr, peer_id);
+    assert_eq!(response, MarketMakerResponse::DevicesAdvertised.to_json());
+
+    let (peer_addr, peer_id) = test_utils::get_peer_with_key(&consumer_key);
+    let request = MarketMakerRequest::GetAvailableDevices;
+    let response = MarketMaker::process_request(request, peer_addr, peer_id.clone());
+    let mut available = match MarketMakerResponse::from_str(&response).unwrap() {
+        MarketMakerResponse::AvailableDevices { devices } => devices
+            .into_iter()
+            .map(|device| device.device_id)
+            .collect::<Vec<String>>(),
+        _ => Vec::new(),
+    };
+    available.sort();
+
+    let filter_vec = DeviceFilterVec {
+        filters: vec![DeviceFilter::DeviceModels(HashSet::from([
+            "Pixel 7".to_string()
+        ]))],
+        ..DeviceFilterVec::default()
+    };
+    let request = MarketMakerRequest::GetDevicesByFilter { filter_vec };
+    let response = MarketMaker::process_request(request, peer_addr, peer_id);
+    let filtered = match MarketMakerResponse::from_str(&response).unwrap() {
+        MarketMakerResponse::DevicesByFilter { devices, .. } => devices
+            .into_iter()
+            .map(|device| device.device_id)
+            .collect::<Vec<String>>(),
+        _ => Vec::new(),
+    };
+
+    // Notifications are only forwarded for devices reserved through the peer.
+    let (peer_addr, peer_id) = test_utils::get_peer_with_key(&peer_key);
+    let request = MarketMakerRequest::PeerNotifyConsumer {
+        consumer: base64::encode(&consumer_key),
+        request: Box::new(ConsumerRequest::LeaseExpired {
+            device_id: "remote".to_string(),
+        }),
+    };
+    let response = MarketMaker::process_request(request, peer_addr, peer_id);
+    MarketMakerState::reset_state();
+    HeartBeatState::reset_state();
+    assert_eq!(available, vec!["local".to_string(), "remote".to_string()]);
+    assert_eq!(filtered, vec!["remote".to_string()]);
+    assert_eq!(response, MarketMakerResponse::RequestNotAllowed.to_json());
+}
+
+#[test]
+#[serial]
+fn test_roles() {
+    test_with_logs();
+    MarketMakerState::reset_state();
+    let admin_key = vec![251u8; 32];
+    let observer_key = vec![250u8; 32];
+    let consumer_key = vec![255u8; 32];
+    // Remote peers are not admins, unlike local clients.
+    let remote_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 8080);
+    let remote = |key: &Key| (remote_addr, Arc::new(key.clone()));
+
+    let (peer_addr, peer_id) = remote(&observer_key);
+    let response = MarketMaker::process_request(MarketMakerRequest::Status, peer_addr, peer_id);
+    assert_eq!(response, MarketMakerResponse::RequestNotAllowed.to_json());
+
+    let (peer_addr, peer_id) = test_utils::get_peer_with_key(&vec![0u8; 32]);
+    let request = MarketMakerRequest::GrantRole {
+        key: base64::encode(&admin_key),
+        role: Role::Admin,
+    };
+    let response = MarketMaker::process_request(request, peer_addr, peer_id);
+    assert_eq!(response, MarketMakerResponse::GrantRoleSuccess.to_json());
+
+    // Admins manage roles and whitelists remotely.
+    let (peer_addr, peer_id) = remote(&admin_key);
+    let request = MarketMakerRequest::GrantRole {
+        key: base64::encode(&observer_key),
+        role: Role::Observer,
+    };
+    let response = MarketMaker::process_request(request, peer_addr, peer_id);
+    assert_eq!(response, MarketMakerResponse::GrantRoleSuccess.to_json());
+
+    let (peer_addr, peer_id) = remote(&observer_key);
+    let response = MarketMaker::process_request(MarketMakerRequest::Status, peer_addr, peer_id);
+    assert!(matches!(
+        MarketMakerResponse::from_str(&response).unwrap(),
+        MarketMakerResponse::Status { .. }
+    ));
+    let (peer_addr, peer_id) = remote(&observer_key);
+    let response =
+        MarketMaker::process_request(MarketMakerRequest::UseWhitelist, peer_addr, peer_id);
+    assert_eq!(response, MarketMakerResponse::RequestNotAllowed.to_json());
+
+    let (peer_addr, peer_id) = remote(&admin_key);
+    let response =
+        MarketMaker::process_request(MarketMakerRequest::UseWhitelist, peer_addr, peer_id);
+    assert_eq!(response, MarketMakerResponse::UseWhitelistSuccess.to_json());
+
+    // The consumer role grants access like the consumer whitelist.
+    let consumer_id = base64::encode(&consumer_key);
+    let whitelisted_before = MarketMakerState::verify_consumer_whitelist(&consumer_id);
+    MarketMakerState::grant_role(&consumer_id, Role::Consumer);
+    let whitelisted_after = MarketMakerState::verify_consumer_whitelist(&consumer_id);
+
+    // Admins may release devices used by any Consumer.
+    MarketMakerState::insert_consumer(ConsumerSpec {
+        pub_key: consumer_id.clone(),
+        ..ConsumerSpec::default()
+    });
+    MarketMakerState::insert_device(DeviceSpec {
+        device_id: "device".to_string(),
+        used_by: consumer_id,
+        ..DeviceSpec::default()
+    });
+    let (peer_addr, peer_id) = remote(&admin_key);
+    let request = MarketMakerRequest::ForceReleaseDevice {
+        device_id: "device".to_string(),
+    };
+    let response = MarketMaker::process_request(request, peer_addr, peer_id);
+
+    let (peer_addr, peer_id) = remote(&admin_key);
+    let request = MarketMakerRequest::RevokeRole {
+        key: base64::encode(&admin_key),
+        role: Role::Admin,
+    };
+    MarketMaker::process_request(request, peer_addr, peer_id);
+    let roles = MarketMakerState::get_min_state().roles;
+    MarketMakerState::reset_state();
+    assert!(!whitelisted_before);
+    assert!(whitelisted_after);
+    assert_eq!(response, MarketMakerResponse::DeviceReleased.to_json());
+    assert_eq!(roles.len(), 2);
+    assert!(!roles.contains_key(&base64::encode(&admin_key)));
+}
+
+#[test]
+#[serial]
+fn test_reservation_quotas() {
+    test_with_logs();
+    MarketMakerState::reset_state();
+    let consumer_key = vec![255u8; 32];
+    let consumer_id = base64::encode(&consumer_key);
+    MarketMakerState::insert_consumer(ConsumerSpec {
+        pub_key: consumer_id.clone(),
+        ..ConsumerSpec::default()
+    });
+    for device_id in ["d1", "d2", "d3"] {
+        MarketMakerState::insert_device(DeviceSpec {
+            device_id: device_id.to_string(),
+            ..DeviceSpec::default()
+        });
+    }
+    MarketMakerState::update_available_devices();
+    MarketMakerState::set_quota(
+        QuotaTarget::Consumer(consumer_id.clone()),
+        Quota {
+            max_devices: Some(1),
+            max_lease_secs: None,
+        },
+    );
+    MarketMakerState::add_to_group("qa", &consumer_id);
+    MarketMakerState::set_quota(
+        QuotaTarget::Group("qa".to_string()),
+        Quota {
+            max_devices: None,
+            max_lease_secs: Some(3600),
+        },
+    );
+    let reserve = |device_id: &str, lease_secs| {
+        let (peer_addr, peer_id) = test_utils::get_peer_with_key(&consumer_key);
+        let request = MarketMakerRequest::ReserveDevice {
+            device_id: device_id.to_string(),
+            lease_secs,
+        };
+        MarketMakerResponse::from_str(&MarketMaker::process_request(request, peer_addr, peer_id))
+            .unwrap()
+    };
+
+    // The group requires a lease of at most an hour.
+    let without_lease = reserve("d1", None);
+    let too_long = reserve("d1", Some(7200));
+    let reserved = reserve("d1", Some(600));
+    // The consumer may only reserve one device at a time.
+    let past_limit = reserve("d2", Some(600));
+    let usage = MarketMakerState::get_min_state().quotas;
+    MarketMakerState::reset_state();
+
+    assert!(matches!(
+        without_lease,
+        MarketMakerResponse::DeviceNotReserved { reason: Error::QuotaExceeded(reason) }
+            if reason.contains("lease of at most 3600s")
+    ));
+    assert!(matches!(
+        too_long,
+        MarketMakerResponse::DeviceNotReserved { .. }
+    ));
+    assert!(matches!(
+        reserved,
+        MarketMakerResponse::DeviceReserved { .. }
+    ));
+    assert!(matches!(
+        past_limit,
+        MarketMakerResponse::DeviceNotReserved { reason: Error::QuotaExceeded(reason) }
+            if reason == format!("Quota of consumer {} exceeded: 1 of 1 devices reserved", consumer_id)
+    ));
+    assert_eq!(
+        usage,
+        vec![
+            QuotaUsage {
+                target: QuotaTarget::Consumer(consumer_id),
+                quota: Quota {
+                    max_devices: Some(1),
+                    max_lease_secs: None,
+                },
+                reserved: 1,
+            },
+            QuotaUsage {
+                target: QuotaTarget::Group("qa".to_string()),
+                quota: Quota {
+                    max_devices: None,
+                    max_lease_secs: Some(3600),
+                },
+                reserved: 1,
+            },
+        ]
+    );
+}
+
+#[test]
+#[serial]
+fn test_quota_counts_claimed_devices() {
+    test_with_logs();
+    MarketMakerState::reset_state();
+    let consumer_id = base64::encode(vec![251u8; 32]);
+    for device_id in ["d1", "d2"] {
+        MarketMakerState::insert_device(DeviceSpec {
+            device_id: device_id.to_string(),
+            ..DeviceSpec::default()
+        });
+    }
+    MarketMakerState::update_available_devices();
+    MarketMakerState::set_quota(
+        QuotaTarget::Consumer(consumer_id.clone()),
+        Quota {
+            max_devices: Some(1),
+            max_lease_secs: None,
+        },
+    );
+    let claim = |device_id: &str| {
+        MarketMakerState::claim_devices_for(&consumer_id, &[device_id.to_string()], 1, None)
+    };
+
+    let first = claim("d1");
+    // The reservation of the first device has not completed yet.
+    let second = claim("d2");
+    // The queued Consumer waits until it may reserve another device.
+    MarketMakerState::enqueue_waiting(WaitEntry {
+        consumer: consumer_id.clone(),
+        target: WaitTarget::Device("d2".to_string()),
+        lease_secs: None,
+    });
+    let served = MarketMakerState::pop_waiting_with_device();
+    MarketMakerState::release_claim("d1");
+    let served_after_release = MarketMakerState::pop_waiting_with_device();
+    MarketMakerState::reset_state();
+
+    assert_eq!(first.unwrap().len(), 1);
+    assert!(matches!(second, Err(Error::QuotaExceeded(_))));
+    assert!(served.is_none());
+    assert!(matches!(
+        served_after_release,
+        Some((entry, device)) if entry.consumer == consumer_id && device.device_id == "d2"
+    ));
+}
+
+#[test]
+#[serial]
+fn test_device_pools() {
+    test_with_logs();
+    MarketMakerState::reset_state();
+    let consumer_key = vec![254u8; 32];
+    let consumer_id = base64::encode(&consumer_key);
+    MarketMakerState::insert_consumer(ConsumerSpec {
+        pub_key: consumer_id.clone(),
+        ..ConsumerSpec::default()
+    });
+    MarketMakerState::insert_device(DeviceSpec {
+        device_id: "public".to_string(),
+        ..DeviceSpec::default()
+    });
+    // Assigned to a pool by the Supplier.
+    MarketMakerState::insert_device(DeviceSpec {
+        device_id: "qa".to_string(),
+        pools: HashSet::from(["release-qa".to_string()]),
+        ..DeviceSpec::default()
+    });
+    MarketMakerState::insert_device(DeviceSpec {
+        device_id: "perf".to_string(),
+        ..DeviceSpec::default()
+    });
+    MarketMakerState::update_available_devices();
+    // Assigned to a pool by an admin.
+    assert!(MarketMakerState::assign_pool("perf", "perf-lab"));
+    assert!(!MarketMakerState::assign_pool("unknown", "perf-lab"));
+    MarketMakerState::grant_pool_access("release-qa", &consumer_id);
+    let send = |request: MarketMakerRequest| {
+        let (peer_addr, peer_id) = test_utils::get_peer_with_key(&consumer_key);
+        MarketMakerResponse::from_str(&MarketMaker::process_request(request, peer_addr, peer_id))
+            .unwrap()
+    };
+    let device_ids = |response: MarketMakerResponse| {
+        let mut device_ids = match response {
+            MarketMakerResponse::AvailableDevices { devices }
+            | MarketMakerResponse::DevicesByFilter { devices, .. } => devices
+                .into_iter()
+                .map(|device| device.device_id)
+                .collect::<Vec<String>>(),
+            response => panic!("Unexpected response: {}", response),
+        };
+        device_ids.sort();
+        device_ids
+    };
+
+    let available = device_ids(send(MarketMakerRequest::GetAvailableDevices));
+    let in_pools = device_ids(send(MarketMakerRequest::GetDevicesByFilter {
+        filter_vec: DeviceFilterVec {
+            filters: vec![DeviceFilter::Pools(HashSet::from([
+                "release-qa".to_string(),
+                "perf-lab".to_string(),
+            ]))],
+            query: None,
+        },
+    }));
+    let restricted = send(MarketMakerRequest::ReserveDevice {
+        device_id: "perf".to_string(),
+        lease_secs: None,
+    });
+    // The device keeps its pools when supplied again.
+    MarketMakerState::remove_device("perf");
+    MarketMakerState::insert_device(DeviceSpec {
+        device_id: "perf".to_string(),
+        ..DeviceSpec::default()
+    });
+    let resupplied = MarketMakerState::get_device("perf").unwrap();
+    MarketMakerState::reset_state();
+
+    assert_eq!(available, vec!["public", "qa"]);
+    assert_eq!(in_pools, vec!["qa"]);
+    assert!(matches!(
+        restricted,
+        MarketMakerResponse::DeviceNotReserved { reason: Error::NotAuthorized(reason) }
+            if reason == "No access to the pools of the device"
+    ));
+    assert_eq!(resupplied.pools, HashSet::from(["perf-lab".to_string()]));
+}
+
+#[test]
+#[serial]
+fn test_event_subscription() {
+    test_with_logs();
+    MarketMakerState::reset_state();
+    events::close_all();
+    let consumer_key = vec![253u8; 32];
+    let consumer_id = base64::encode(&consumer_key);
+    MarketMakerState::insert_consumer(ConsumerSpec {
+        pub_key: consumer_id.clone(),
+        name: "consumer".to_string(),
+        ..ConsumerSpec::default()
+    });
+    MarketMakerState::insert_device(DeviceSpec {
+        device_id: "device".to_string(),
+        ..DeviceSpec::default()
+    });
+    MarketMakerState::update_available_devices();
+    let (devices_tx, mut devices_rx) = tokio::sync::mpsc::channel(net::STREAM_CAPACITY);
+    let (all_tx, mut all_rx) = tokio::sync::mpsc::channel(net::STREAM_CAPACITY);
+    let (suppliers_tx, mut suppliers_rx) = tokio::sync::mpsc::channel(net::STREAM_CAPACITY);
+    events::subscribe(HashSet::from([EventTopic::Devices]), None, devices_tx);
+    events::subscribe(HashSet::new(), None, all_tx);
+    events::subscribe(HashSet::from([EventTopic::Suppliers]), None, suppliers_tx);
+
+    let send = |request: MarketMakerRequest| {
+        let (peer_addr, peer_id) = test_utils::get_peer_with_key(&consumer_key);
+        MarketMaker::process_request(request, peer_addr, peer_id)
+    };
+    send(MarketMakerRequest::ReserveDevice {
+        device_id: "device".to_string(),
+        lease_secs: None,
+    });
+    send(MarketMakerRequest::ReleaseDevice {
+        device_id: "device".to_string(),
+    });
+    let event = |rx: &mut tokio::sync::mpsc::Receiver<String>| {
+        MarketEvent::from_str(&rx.blocking_recv().unwrap()).unwrap()
+    };
+    let reserved = event(&mut devices_rx);
+    let released = event(&mut devices_rx);
+    let all_reserved = event(&mut all_rx);
+
+    // Only admins, observers, Suppliers and Consumers may subscribe.
+    let unknown = MarketMaker::process_request(
+        MarketMakerRequest::Subscribe {
+            topics: HashSet::new(),
+        },
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 8080),
+        Arc::new(vec![252u8; 32]),
+    );
+    events::close_all();
+    MarketMakerState::reset_state();
+
+    assert!(matches!(
+        reserved,
+        MarketEvent::DeviceReserved { device }
+            if device.device_id == "device" && device.used_by == consumer_id
+    ));
+    assert_eq!(
+        released,
+        MarketEvent::DeviceReleased {
+            device_id: "device".to_string()
+        }
+    );
+    assert_eq!(all_reserved.topic(), EventTopic::Devices);
+    // The stream ends without any events of other topics.
+    assert!(suppliers_rx.blocking_recv().is_none());
+    assert_eq!(unknown, MarketMakerResponse::RequestNotAllowed.to_json());
+}
+
+#[test]
+#[serial]
+fn test_device_events_scoped_to_pools() {
+    test_with_logs();
+    MarketMakerState::reset_state();
+    events::close_all();
+    let consumer_id = base64::encode(vec![253u8; 32]);
+    let (consumer_tx, mut consumer_rx) = tokio::sync::mpsc::channel(net::STREAM_CAPACITY);
+    let (observer_tx, mut observer_rx) = tokio::sync::mpsc::channel(net::STREAM_CAPACITY);
+    events::subscribe(HashSet::new(), Some(consumer_id.clone()), consumer_tx);
+    events::subscribe(HashSet::new(), None, observer_tx);
+
+    let device = |device_id: &str, pools: &[&str]| DeviceSpec {
+        device_id: device_id.to_string(),
+        pools: pools.iter().map(|pool| pool.to_string()).collect(),
+        ..DeviceSpec::default()
+    };
+    events::publish(MarketEvent::DeviceSupplied {
+        device: device("pooled", &["qa"]),
+    });
+    events::publish(MarketEvent::DeviceSupplied {
+        device: device("shared", &[]),
+    });
+    MarketMakerState::grant_pool_access("qa", &consumer_id);
+    events::publish(MarketEvent::DeviceReserved {
+        device: device("pooled", &["qa"]),
+    });
+    events::close_all();
+    MarketMakerState::reset_state();
+
+    let received = |rx: &mut tokio::sync::mpsc::Receiver<String>| {
+        let mut events = Vec::new();
+        while let Some(event) = rx.blocking_recv() {
+            events.push(MarketEvent::from_str(&event).unwrap());
+        }
+        events
+    };
+    let consumer_events = received(&mut consumer_rx);
+    assert_eq!(consumer_events.len(), 2);
+    assert!(matches!(
+        &consumer_events[0],
+        MarketEvent::DeviceSupplied { device } if device.device_id == "shared"
+    ));
+    assert!(matches!(
+        &consumer_events[1],
+        MarketEvent::DeviceReserved { device } if device.device_id == "pooled"
+    ));
+    assert_eq!(received(&mut observer_rx).len(), 3);
+}
+
+#[test]
+#[serial]
+fn test_slow_subscriber_dropped() {
+    test_with_logs();
+    events::close_all();
+    let (tx, mut rx) = tokio::sync::mpsc::channel(1);
+    events::subscribe(HashSet::new(), None, tx);
+    events::publish(MarketEvent::DeviceReleased {
+        device_id: "first".to_string(),
+    });
+    // The subscriber has not read the first event, so it is dropped.
+    events::publish(MarketEvent::DeviceReleased {
+        device_id: "second".to_string(),
+    });
+    let first = rx.blocking_recv();
+    let second = rx.blocking_recv();
+    events::close_all();
+
+    assert!(first.is_some());
+    assert!(second.is_none());
+}
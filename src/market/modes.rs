@@ -0,0 +1,136 @@
+//! Persistence of the modes running on the system.
+//!
+//! The modes started on the system, together with the devices supplied and
+//! reserved, are written to a file in the data directory after every change.
+//! When the system listener is started again, e.g. after a reboot of the host,
+//! the saved modes are restored: the Supplier and Consumer reconnect to their
+//! MarketMaker, the same devices are supplied and reserved again.
+//! A mode is removed from the saved modes when it is stopped by the user.
+
+use super::*;
+use crate::util::{self, ADBORC_RESTORE_MODES, MODES_FILE};
+
+lazy_static! {
+    /// Modes of the system, as written to the modes file.
+    static ref SAVED_MODES: RwLock<SavedModes> = RwLock::new(SavedModes::default());
+}
+
+/// Modes running on the system, and how to start them again.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub(super) struct SavedModes {
+    pub(super) market_maker: bool,
+    // host, port of the primary MarketMaker, if the MarketMaker is a standby.
+    pub(super) standby_of: Option<(String, u16)>,
+    pub(super) supplier: Option<SavedSupplier>,
+    pub(super) consumer: Option<SavedConsumer>,
+}
+
+/// Supplier mode, as started by the user.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub(super) struct SavedSupplier {
+    pub(super) mm_host: String,
+    pub(super) mm_port: u16,
+    pub(super) fallbacks: Vec<String>,
+    pub(super) name: Option<String>,
+    pub(super) secure_comms: bool,
+    // `ANDROID_SERIAL`s of the supplied devices.
+    pub(super) android_serials: HashSet<String>,
+    pub(super) auto_supply: bool,
+}
+
+/// Consumer mode, as started by the user.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub(super) struct SavedConsumer {
+    pub(super) mm_host: String,
+    pub(super) mm_port: u16,
+    pub(super) fallbacks: Vec<String>,
+    pub(super) name: Option<String>,
+    // Lease expiry (seconds since UNIX epoch) of the reserved devices, hashed by device id.
+    pub(super) devices: HashMap<String, Option<u64>>,
+    // Device used on the default adb port.
+    pub(super) using_device: Option<String>,
+}
+
+/// Check if restoring modes is enabled through the `ADBORC_RESTORE_MODES`
+/// environment variable. Enabled by default.
+pub(super) fn restore_enabled() -> bool {
+    let value = std::env::var(ADBORC_RESTORE_MODES).unwrap_or_default();
+    !matches!(value.to_lowercase().as_str(), "off" | "false" | "0")
+}
+
+impl SavedModes {
+    /// Load the modes saved by the previous run of the system listener, if any.
+    /// The loaded modes are kept as the current saved modes.
+    pub(super) fn load() -> io::Result<Option<SavedModes>> {
+        let path = util::data_dir().join(MODES_FILE);
+        if !restore_enabled() || !path.exists() {
+            return Ok(None);
+        }
+        let contents = fs::read_to_string(&path)?;
+        let modes: SavedModes = serde_json::from_str(&contents)
+            .map_err(|e| io::Error::new(ErrorKind::InvalidData, e.to_string()))?;
+        *SAVED_MODES.write().unwrap() = modes.clone();
+        Ok(Some(modes))
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let path = util::data_dir().join(MODES_FILE);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let contents = serde_json::to_string(self)
+            .map_err(|e| io::Error::new(ErrorKind::InvalidData, e.to_string()))?;
+        let temp_path = path.with_extension("json.tmp");
+        fs::write(&temp_path, contents)?;
+        fs::rename(&temp_path, &path)
+    }
+
+    // Write functions...
+
+    /// Apply `update` to the saved modes and write them to the modes file.
+    pub(super) fn update<F>(update: F)
+    where
+        F: FnOnce(&mut SavedModes),
+    {
+        if SAVED_MODES.is_poisoned() {
+            error!("SAVED_MODES lock is poisoned");
+            return;
+        }
+        let mut modes = SAVED_MODES.write().unwrap();
+        let previous = modes.clone();
+        update(&mut modes);
+        if *modes == previous || !restore_enabled() {
+            return;
+        }
+        if let Err(e) = modes.save() {
+            warn!("Unable to save the modes of the system: {}", e);
+        }
+    }
+
+    /// Apply `update` to the saved Supplier mode, if any.
+    pub(super) fn update_supplier<F>(update: F)
+    where
+        F: FnOnce(&mut SavedSupplier),
+    {
+        SavedModes::update(|modes| {
+            if let Some(supplier) = modes.supplier.as_mut() {
+                update(supplier);
+            }
+        });
+    }
+
+    /// Apply `update` to the saved Consumer mode, if any.
+    pub(super) fn update_consumer<F>(update: F)
+    where
+        F: FnOnce(&mut SavedConsumer),
+    {
+        SavedModes::update(|modes| {
+            if let Some(consumer) = modes.consumer.as_mut() {
+                update(consumer);
+            }
+        });
+    }
+}
@@ -0,0 +1,526 @@
+//! Boolean filter expressions over [`DeviceFilter`]s, and a small textual
+//! query syntax to write them.
+//!
+//! A query is made of terms of the form `<key>=<value>[,<value>...]`, combined
+//! with `AND`, `OR`, `NOT` and parentheses. A term matches if the device
+//! property matches any of the values. `NOT` binds tighter than `AND`, which
+//! binds tighter than `OR`. Values containing spaces or special characters
+//! must be quoted using `"`. Within quotes, `"` and `\` are escaped with a `\`.
+//! For example:
+//!
+//! ```text
+//! (model="Pixel 7","Pixel 8" OR brand=samsung) AND NOT supplier=lab-x AND sdk>=31
+//! ```
+//!
+//! Supported keys are: `available`, `id`, `name`, `model`, `brand`, `android`,
+//! `abi`, `sdk`, `supplier`, `supplier_id`, `supplier_host`, `consumer`,
+//! `consumer_id`, `consumer_host` and `pool`. The `sdk` key also supports `>=` and `<=`.
+//! The `battery` key only supports `>=`, e.g. `battery>=20`.
+
+use super::*;
+
+/// A boolean expression over [`DeviceFilter`]s.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum DeviceFilterExpr {
+    /// Matches if the filter matches.
+    Filter(DeviceFilter),
+    /// Matches if all the expressions match.
+    And(Vec<DeviceFilterExpr>),
+    /// Matches if any of the expressions match.
+    Or(Vec<DeviceFilterExpr>),
+    /// Matches if the expression does not match.
+    Not(Box<DeviceFilterExpr>),
+}
+
+impl DeviceFilterExpr {
+    /// Evaluate the expression for the device.
+    pub(super) fn matches(&self, device: &DeviceSpec) -> bool {
+        match self {
+            Self::Filter(device_filter) => device_filter.filter(device),
+            Self::And(exprs) => exprs.iter().all(|expr| expr.matches(device)),
+            Self::Or(exprs) => exprs.iter().any(|expr| expr.matches(device)),
+            Self::Not(expr) => !expr.matches(device),
+        }
+    }
+
+    // Write the expression, wrapped in parentheses if it is composed of other expressions.
+    fn fmt_operand(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::And(_) | Self::Or(_) => write!(f, "({})", self),
+            _ => write!(f, "{}", self),
+        }
+    }
+}
+
+/// Writes the expression in the query syntax.
+impl Display for DeviceFilterExpr {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Filter(device_filter) => fmt_filter(device_filter, f),
+            Self::And(exprs) | Self::Or(exprs) => {
+                let operator = if let Self::And(_) = self {
+                    " AND "
+                } else {
+                    " OR "
+                };
+                for (i, expr) in exprs.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, "{}", operator)?;
+                    }
+                    expr.fmt_operand(f)?;
+                }
+                Ok(())
+            }
+            Self::Not(expr) => {
+                write!(f, "NOT ")?;
+                expr.fmt_operand(f)
+            }
+        }
+    }
+}
+
+fn fmt_filter(device_filter: &DeviceFilter, f: &mut Formatter) -> fmt::Result {
+    let (key, values) = match device_filter {
+        DeviceFilter::IsAvailable(is_available) => {
+            return write!(f, "available={}", is_available);
+        }
+        DeviceFilter::SdkRange { min, max } => {
+            return match (min, max) {
+                (Some(min), Some(max)) if min == max => write!(f, "sdk={}", min),
+                (Some(min), Some(max)) => write!(f, "(sdk>={} AND sdk<={})", min, max),
+                (Some(min), None) => write!(f, "sdk>={}", min),
+                (None, Some(max)) => write!(f, "sdk<={}", max),
+                (None, None) => write!(f, "sdk>=0"),
+            };
+        }
+        DeviceFilter::MinBatteryLevel(level) => {
+            return write!(f, "battery>={}", level);
+        }
+        DeviceFilter::DeviceIds(values) => ("id", values),
+        DeviceFilter::DeviceNames(values) => ("name", values),
+        DeviceFilter::DeviceModels(values) => ("model", values),
+        DeviceFilter::Brands(values) => ("brand", values),
+        DeviceFilter::AndroidVersions(values) => ("android", values),
+        DeviceFilter::Abis(values) => ("abi", values),
+        DeviceFilter::SupplierNames(values) => ("supplier", values),
+        DeviceFilter::SupplierIds(values) => ("supplier_id", values),
+        DeviceFilter::SupplierHosts(values) => ("supplier_host", values),
+        DeviceFilter::ConsumerNames(values) => ("consumer", values),
+        DeviceFilter::ConsumerIds(values) => ("consumer_id", values),
+        DeviceFilter::ConsumerHosts(values) => ("consumer_host", values),
+        DeviceFilter::Pools(values) => ("pool", values),
+    };
+    // Sort the values, so that the output does not depend on the HashSet order.
+    let mut values = values
+        .iter()
+        .map(|value| quote(value))
+        .collect::<Vec<String>>();
+    values.sort();
+    write!(f, "{}={}", key, values.join(","))
+}
+
+// Quote the value, escaping the `"` and `\` in it.
+fn quote(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        if c == '"' || c == '\\' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Error returned when a query cannot be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseFilterError {
+    reason: String,
+}
+
+impl ParseFilterError {
+    fn new(reason: impl Into<String>) -> Self {
+        ParseFilterError {
+            reason: reason.into(),
+        }
+    }
+}
+
+impl Display for ParseFilterError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "Invalid query: {}", self.reason)
+    }
+}
+
+impl std::error::Error for ParseFilterError {}
+
+impl FromStr for DeviceFilterExpr {
+    type Err = ParseFilterError;
+
+    fn from_str(query: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(query)?,
+            pos: 0,
+        };
+        if parser.tokens.is_empty() {
+            return Err(ParseFilterError::new("empty query"));
+        }
+        let expr = parser.parse_or()?;
+        match parser.peek() {
+            Some(token) => Err(ParseFilterError::new(format!("unexpected `{}`", token))),
+            None => Ok(expr),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    LParen,
+    RParen,
+    Comma,
+    // One of `=`, `>=` or `<=`.
+    Op(&'static str),
+    Word(String),
+    Quoted(String),
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Token::LParen => write!(f, "("),
+            Token::RParen => write!(f, ")"),
+            Token::Comma => write!(f, ","),
+            Token::Op(op) => write!(f, "{}", op),
+            Token::Word(word) => write!(f, "{}", word),
+            Token::Quoted(value) => write!(f, "{}", quote(value)),
+        }
+    }
+}
+
+fn tokenize(query: &str) -> Result<Vec<Token>, ParseFilterError> {
+    let mut tokens = Vec::new();
+    let mut chars = query.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '(' => tokens.push(Token::LParen),
+            ')' => tokens.push(Token::RParen),
+            ',' => tokens.push(Token::Comma),
+            '=' => tokens.push(Token::Op("=")),
+            '>' | '<' => {
+                if chars.next_if_eq(&'=').is_none() {
+                    return Err(ParseFilterError::new(format!(
+                        "expected `{}=`, only `=`, `>=` and `<=` are supported",
+                        c
+                    )));
+                }
+                tokens.push(Token::Op(if c == '>' { ">=" } else { "<=" }));
+            }
+            '"' => {
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        // Other characters after a `\` are kept as they are.
+                        Some('\\') => match chars.next_if(|c| *c == '"' || *c == '\\') {
+                            Some(c) => value.push(c),
+                            None => value.push('\\'),
+                        },
+                        Some(c) => value.push(c),
+                        None => return Err(ParseFilterError::new("unterminated `\"`")),
+                    }
+                }
+                tokens.push(Token::Quoted(value));
+            }
+            c => {
+                let mut word = c.to_string();
+                while let Some(c) =
+                    chars.next_if(|c| !c.is_whitespace() && !"()=<>,\"".contains(*c))
+                {
+                    word.push(c);
+                }
+                tokens.push(Token::Word(word));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    // Consume the next token if it is the keyword (case-insensitive).
+    fn next_if_keyword(&mut self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword) => {
+                self.pos += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<DeviceFilterExpr, ParseFilterError> {
+        let mut exprs = vec![self.parse_and()?];
+        while self.next_if_keyword("or") {
+            exprs.push(self.parse_and()?);
+        }
+        Ok(if exprs.len() == 1 {
+            exprs.remove(0)
+        } else {
+            DeviceFilterExpr::Or(exprs)
+        })
+    }
+
+    fn parse_and(&mut self) -> Result<DeviceFilterExpr, ParseFilterError> {
+        let mut exprs = vec![self.parse_not()?];
+        while self.next_if_keyword("and") {
+            exprs.push(self.parse_not()?);
+        }
+        Ok(if exprs.len() == 1 {
+            exprs.remove(0)
+        } else {
+            DeviceFilterExpr::And(exprs)
+        })
+    }
+
+    fn parse_not(&mut self) -> Result<DeviceFilterExpr, ParseFilterError> {
+        if self.next_if_keyword("not") {
+            return Ok(DeviceFilterExpr::Not(Box::new(self.parse_not()?)));
+        }
+        if self.peek() == Some(&Token::LParen) {
+            self.pos += 1;
+            let expr = self.parse_or()?;
+            return match self.next() {
+                Some(Token::RParen) => Ok(expr),
+                _ => Err(ParseFilterError::new("missing `)`")),
+            };
+        }
+        self.parse_term()
+    }
+
+    fn parse_term(&mut self) -> Result<DeviceFilterExpr, ParseFilterError> {
+        let key = match self.next() {
+            Some(Token::Word(key)) => key,
+            Some(token) => {
+                return Err(ParseFilterError::new(format!(
+                    "expected a key, found `{}`",
+                    token
+                )))
+            }
+            None => return Err(ParseFilterError::new("unexpected end of query")),
+        };
+        let op = match self.next() {
+            Some(Token::Op(op)) => op,
+            _ => {
+                return Err(ParseFilterError::new(format!(
+                    "expected `=` after `{}`",
+                    key
+                )))
+            }
+        };
+        let mut values = vec![self.parse_value(&key)?];
+        while self.peek() == Some(&Token::Comma) {
+            self.pos += 1;
+            values.push(self.parse_value(&key)?);
+        }
+        build_filter(&key, op, values).map(DeviceFilterExpr::Filter)
+    }
+
+    fn parse_value(&mut self, key: &str) -> Result<String, ParseFilterError> {
+        match self.next() {
+            Some(Token::Word(value)) | Some(Token::Quoted(value)) => Ok(value),
+            _ => Err(ParseFilterError::new(format!(
+                "expected a value for `{}`",
+                key
+            ))),
+        }
+    }
+}
+
+fn build_filter(
+    key: &str,
+    op: &str,
+    values: Vec<String>,
+) -> Result<DeviceFilter, ParseFilterError> {
+    let key = key.to_lowercase();
+    if key == "sdk" {
+        if values.len() != 1 {
+            return Err(ParseFilterError::new("`sdk` takes a single value"));
+        }
+        let sdk_level = values[0]
+            .parse::<u32>()
+            .map_err(|_| ParseFilterError::new(format!("invalid SDK level `{}`", values[0])))?;
+        let (min, max) = match op {
+            ">=" => (Some(sdk_level), None),
+            "<=" => (None, Some(sdk_level)),
+            _ => (Some(sdk_level), Some(sdk_level)),
+        };
+        return Ok(DeviceFilter::SdkRange { min, max });
+    }
+    if key == "battery" {
+        return match (op, values.as_slice()) {
+            (">=", [value]) => value
+                .parse()
+                .map(DeviceFilter::MinBatteryLevel)
+                .map_err(|_| ParseFilterError::new(format!("invalid battery level `{}`", value))),
+            (">=", _) => Err(ParseFilterError::new("`battery` takes a single value")),
+            _ => Err(ParseFilterError::new("`battery` only supports `>=`")),
+        };
+    }
+    if op != "=" {
+        return Err(ParseFilterError::new(format!(
+            "`{}` is only supported for `sdk` and `battery`",
+            op
+        )));
+    }
+    if key == "available" {
+        return match values.as_slice() {
+            [value] => value.parse().map(DeviceFilter::IsAvailable).map_err(|_| {
+                ParseFilterError::new(format!(
+                    "`available` must be true or false, found `{}`",
+                    value
+                ))
+            }),
+            _ => Err(ParseFilterError::new("`available` takes a single value")),
+        };
+    }
+    let values = HashSet::from_iter(values);
+    let device_filter = match key.as_str() {
+        "id" => DeviceFilter::DeviceIds(values),
+        "name" => DeviceFilter::DeviceNames(values),
+        "model" => DeviceFilter::DeviceModels(values),
+        "brand" => DeviceFilter::Brands(values),
+        "android" => DeviceFilter::AndroidVersions(values),
+        "abi" => DeviceFilter::Abis(values),
+        "supplier" => DeviceFilter::SupplierNames(values),
+        "supplier_id" => DeviceFilter::SupplierIds(values),
+        "supplier_host" => DeviceFilter::SupplierHosts(values),
+        "consumer" => DeviceFilter::ConsumerNames(values),
+        "consumer_id" => DeviceFilter::ConsumerIds(values),
+        "consumer_host" => DeviceFilter::ConsumerHosts(values),
+        "pool" => DeviceFilter::Pools(values),
+        _ => return Err(ParseFilterError::new(format!("unknown key `{}`", key))),
+    };
+    Ok(device_filter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(model: &str, supplier: &str, sdk_level: u32) -> DeviceSpec {
+        DeviceSpec {
+            available_at_name: supplier.to_string(),
+            device_info: DeviceInfo {
+                model: model.to_string(),
+                sdk_level: Some(sdk_level),
+                ..DeviceInfo::default()
+            },
+            ..DeviceSpec::default()
+        }
+    }
+
+    #[test]
+    fn parse_and_evaluate_query() {
+        let query = DeviceFilterExpr::from_str(
+            r#"(model="Pixel 7" or model=Pixel8) AND NOT supplier=lab-x and sdk>=31"#,
+        )
+        .unwrap();
+        assert!(query.matches(&device("Pixel 7", "lab-y", 33)));
+        assert!(query.matches(&device("Pixel8", "lab-y", 31)));
+        assert!(!query.matches(&device("Pixel 7", "lab-x", 33)));
+        assert!(!query.matches(&device("Pixel 7", "lab-y", 30)));
+        assert!(!query.matches(&device("Galaxy", "lab-y", 33)));
+
+        // AND binds tighter than OR.
+        let query = DeviceFilterExpr::from_str("model=a OR model=b AND sdk<=30").unwrap();
+        assert!(query.matches(&device("a", "", 33)));
+        assert!(!query.matches(&device("b", "", 33)));
+
+        // The query can be written back and parsed again.
+        let query = DeviceFilterExpr::from_str(
+            r#"NOT (model="Pixel 7",Pixel8 OR available=true) AND sdk=33"#,
+        )
+        .unwrap();
+        assert_eq!(
+            query.to_string(),
+            r#"NOT (model="Pixel 7","Pixel8" OR available=true) AND sdk=33"#
+        );
+        assert_eq!(DeviceFilterExpr::from_str(&query.to_string()), Ok(query));
+    }
+
+    #[test]
+    fn quoted_values_round_trip() {
+        let query = DeviceFilterExpr::from_str(r#"name="say \"hi\"",C:\lab,"C:\\lab\x""#).unwrap();
+        let names = HashSet::from([
+            r#"say "hi""#.to_string(),
+            r"C:\lab".to_string(),
+            r"C:\lab\x".to_string(),
+        ]);
+        assert_eq!(
+            query,
+            DeviceFilterExpr::Filter(DeviceFilter::DeviceNames(names))
+        );
+        assert_eq!(
+            query.to_string(),
+            r#"name="C:\\lab","C:\\lab\\x","say \"hi\"""#
+        );
+        assert_eq!(DeviceFilterExpr::from_str(&query.to_string()), Ok(query));
+    }
+
+    #[test]
+    fn parse_invalid_query() {
+        for query in [
+            "",
+            "model",
+            "model=",
+            "(model=a",
+            "model=a model=b",
+            "model>=a",
+            "sdk>=thirty",
+            "colour=red",
+            "available=maybe",
+            "model=\"a",
+            "sdk>30",
+        ] {
+            assert!(
+                DeviceFilterExpr::from_str(query).is_err(),
+                "query: {}",
+                query
+            );
+        }
+    }
+
+    #[test]
+    fn filter_vec_with_query() {
+        let filter_vec = DeviceFilterVec::new(
+            vec![DeviceFilter::SupplierNames(HashSet::from([
+                "lab-y".to_string()
+            ]))],
+            Some(DeviceFilterExpr::from_str("model=a OR model=b").unwrap()),
+        );
+        assert!(filter_vec.matches(&device("a", "lab-y", 33)));
+        assert!(!filter_vec.matches(&device("a", "lab-x", 33)));
+        assert!(!filter_vec.matches(&device("c", "lab-y", 33)));
+
+        // Filters sent without a query are still accepted.
+        let filter_vec: DeviceFilterVec =
+            serde_json::from_str(r#"{"filters":[{"IsAvailable":true}]}"#).unwrap();
+        assert_eq!(filter_vec.query, None);
+    }
+}
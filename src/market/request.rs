@@ -2,7 +2,7 @@ use crate::util::adb_utils::ScrCpyArgs;
 
 use super::{supplier::SupplierStateMin, DeviceFilterVec, *};
 use consumer::ConsumerStateMin;
-use marketmaker::MarketMakerMinState;
+use marketmaker::{MarketMakerMinState, MarketMakerSnapshot};
 use serde::Serialize;
 use serde_json;
 use std::str::FromStr;
@@ -43,6 +43,33 @@ impl Display for Response {
     }
 }
 
+impl Response {
+    /// Check whether the response reports that the request failed.
+    pub fn is_failure(&self) -> bool {
+        self.failure().is_some()
+    }
+
+    /// The error reported by the response, if the request failed.
+    pub fn failure(&self) -> Option<Error> {
+        match self {
+            Response::System(response) => response.failure(),
+            Response::MarketMaker(response) => response.failure(),
+            Response::Supplier(response) => response.failure(),
+            Response::Consumer(response) => response.failure(),
+        }
+    }
+}
+
+/// Error of the responses to requests that are not allowed.
+fn request_not_allowed() -> Error {
+    Error::NotAuthorized("Request not allowed".to_string())
+}
+
+/// Error of the responses to requests that are not valid.
+fn invalid_request(request: &str) -> Error {
+    Error::InvalidInput(format!("Invalid request: {}", request))
+}
+
 #[derive(Debug)]
 pub enum ParseResponseError {
     /// Failed to parse the string as a valid JSON `Response`.
@@ -76,6 +103,8 @@ impl Display for ParseResponseError {
         }
     }
 }
+
+impl std::error::Error for ParseResponseError {}
 /// List of valid requests for the SysState listener.
 /// These requests are usually sent to the SysState Listener
 /// from the local TCPClient.
@@ -86,6 +115,10 @@ pub enum SysStateRequest {
     },
     GetState,
     GetPeerId,
+    RotateNetworkKey,
+    ImportNetworkKey {
+        private_key: String,
+    },
     SystemCheck,
     SetAdbPath {
         adb_path: String,
@@ -94,16 +127,26 @@ pub enum SysStateRequest {
         scrcpy_path: String,
     },
     StartMarketMaker,
+    // Start MarketMaker mode as a standby replica of the primary MarketMaker.
+    StartStandbyMarketMaker {
+        primary_host: String,
+        primary_port: u16,
+    },
     StartSupplier {
         mm_host: String,
         mm_port: u16,
         name: Option<String>,
         secure_comms: bool,
+        // Addresses (`host` or `host:port`) of MarketMakers to fall back to, in order.
+        #[serde(default)]
+        fallbacks: Vec<String>,
     },
     StartConsumer {
         mm_host: String,
         mm_port: u16,
         name: Option<String>,
+        #[serde(default)]
+        fallbacks: Vec<String>,
     },
     GetMarketMakerConfig,
     GetSupplierConfig,
@@ -118,6 +161,14 @@ pub enum SysStateRequest {
 
     SupplierMarketMakerTerminating,
     ConsumerMarketMakerTerminating,
+
+    // Send the request to the MarketMaker at `host:port`, with the network_id
+    // of the system. Used to administer a remote MarketMaker.
+    SendToMarketMaker {
+        host: String,
+        port: u16,
+        request: Box<MarketMakerRequest>,
+    },
 }
 
 /// Responses for the SysState listener.
@@ -135,26 +186,32 @@ pub enum SysStateResponse {
     },
     SetAdbPathSuccess,
     SetAdbPathFailure {
-        reason: String,
+        reason: Error,
     },
     SetScrcpyPathSuccess,
     SetScrcpyPathFailure {
-        reason: String,
+        reason: Error,
     },
     GetPeerIdFailure,
+    NetworkKeyChanged {
+        peer_id: String,
+    },
+    NetworkKeyChangeFailure {
+        reason: Error,
+    },
     ShutDownSuccess,
     ShutDownFailure,
     StartMarketMakerSuccess,
     StartMarketMakerFailed {
-        reason: String,
+        reason: Error,
     },
     StartSupplierSuccess,
     StartSupplierFailed {
-        reason: String,
+        reason: Error,
     },
     StartConsumerSuccess,
     StartConsumerFailed {
-        reason: String,
+        reason: Error,
     },
     StopMarketMakerSuccess,
     StopMarketMakerFailed,
@@ -177,20 +234,55 @@ pub enum SysStateResponse {
         request: String,
     },
     RequestProcessingError {
-        reason: String,
+        reason: Error,
     },
     ClientOk,
     ClientError {
-        reason: String,
+        reason: Error,
     },
 }
 
+impl SysStateResponse {
+    /// Check whether the response reports that the request failed.
+    pub fn is_failure(&self) -> bool {
+        self.failure().is_some()
+    }
+
+    /// The error reported by the response, if the request failed.
+    pub fn failure(&self) -> Option<Error> {
+        match self {
+            SysStateResponse::SetAdbPathFailure { reason }
+            | SysStateResponse::SetScrcpyPathFailure { reason }
+            | SysStateResponse::NetworkKeyChangeFailure { reason }
+            | SysStateResponse::StartMarketMakerFailed { reason }
+            | SysStateResponse::StartSupplierFailed { reason }
+            | SysStateResponse::StartConsumerFailed { reason }
+            | SysStateResponse::RequestProcessingError { reason }
+            | SysStateResponse::ClientError { reason } => Some(reason.clone()),
+            SysStateResponse::GetPeerIdFailure
+            | SysStateResponse::ShutDownFailure
+            | SysStateResponse::StopMarketMakerFailed
+            | SysStateResponse::StopSupplierFailed
+            | SysStateResponse::StopConsumerFailed => Some(Error::InvalidState(self.to_string())),
+            SysStateResponse::RequestNotAllowed => Some(request_not_allowed()),
+            SysStateResponse::InvalidRequest { request } => Some(invalid_request(request)),
+            _ => None,
+        }
+    }
+}
+
 impl Display for SysStateResponse {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         match self {
             SysStateResponse::CurrentSysState { state } => write!(f, "{}", state),
             SysStateResponse::PeerId { peer_id } => write!(f, "PeerId: {}", peer_id),
             SysStateResponse::GetPeerIdFailure => write!(f, "Failed to retrieve peer id"),
+            SysStateResponse::NetworkKeyChanged { peer_id } => {
+                write!(f, "Network key changed. New PeerId: {}", peer_id)
+            }
+            SysStateResponse::NetworkKeyChangeFailure { reason } => {
+                write!(f, "Failed to change network key: {}", reason)
+            }
             SysStateResponse::SystemCheck {
                 supplier_check,
                 consumer_check,
@@ -273,15 +365,130 @@ pub enum MarketMakerRequest {
     UnwhitelistConsumer {
         key: String,
     },
+    WhitelistStandby {
+        key: String,
+    },
+    UnwhitelistStandby {
+        key: String,
+    },
+    GrantRole {
+        key: String,
+        role: Role,
+    },
+    RevokeRole {
+        key: String,
+        role: Role,
+    },
+    // Release the device, regardless of the Consumer using it.
+    ForceReleaseDevice {
+        device_id: String,
+    },
+    SetQuota {
+        target: QuotaTarget,
+        quota: Quota,
+    },
+    RemoveQuota {
+        target: QuotaTarget,
+    },
+    AddToGroup {
+        group: String,
+        key: String,
+    },
+    RemoveFromGroup {
+        group: String,
+        key: String,
+    },
+    AssignPool {
+        device_id: String,
+        pool: String,
+    },
+    UnassignPool {
+        device_id: String,
+        pool: String,
+    },
+    GrantPoolAccess {
+        pool: String,
+        key: String,
+    },
+    RevokePoolAccess {
+        pool: String,
+        key: String,
+    },
+
+    // Event subscription Requests.
+    /// Keep the connection open and stream the events of the given topics.
+    /// All topics are streamed, if no topics are given.
+    Subscribe {
+        topics: HashSet<EventTopic>,
+    },
+
+    // Standby MarketMaker Requests.
+    StandbyConnect {
+        market_maker: MarketMakerSpec,
+    },
+
+    // Primary MarketMaker Requests.
+    // Snapshot of the state of the primary. Also sent periodically as a heartbeat.
+    ReplicateState {
+        snapshot: Box<MarketMakerSnapshot>,
+    },
+    PrimaryTerminating,
+
+    // Peer MarketMaker Requests.
+    WhitelistPeer {
+        key: String,
+    },
+    UnwhitelistPeer {
+        key: String,
+    },
+    // Connect to the peer MarketMaker at `host:port`.
+    ConnectPeer {
+        host: String,
+        port: u16,
+    },
+    PeerConnect {
+        market_maker: MarketMakerSpec,
+    },
+    // Available devices of the peer. Also sent periodically as a heartbeat.
+    AdvertiseDevices {
+        devices: Vec<DeviceSpec>,
+    },
+    // Reserve a device on behalf of a Consumer of the peer.
+    PeerReserveDevice {
+        device_id: String,
+        consumer: ConsumerSpec,
+        lease_secs: Option<u64>,
+    },
+    PeerReleaseDevice {
+        device_id: String,
+        consumer: String,
+    },
+    PeerRenewLease {
+        device_id: String,
+        consumer: String,
+        lease_secs: u64,
+    },
+    // Relay a request to a Consumer of the peer.
+    PeerNotifyConsumer {
+        consumer: String,
+        request: Box<ConsumerRequest>,
+    },
 
     // Supplier Requests.
     SupplierConnect {
         supplier: SupplierSpec,
+        // Set when the Supplier restarted and resumes its previous session.
+        // The Supplier then takes over its previous entry, along with its devices.
+        #[serde(default)]
+        resume: bool,
     },
     SupplyDevices {
         devices: Vec<DeviceSpec>,
     },
-    SupplierHeartBeat,
+    SupplierHeartBeat {
+        // Health of the supplied devices, hashed by device id.
+        device_health: HashMap<String, DeviceHealth>,
+    },
     SupplierDisconnect,
     ReclaimDevice {
         device_id: String,
@@ -291,10 +498,38 @@ pub enum MarketMakerRequest {
     // Consumer Requests.
     ConsumerConnect {
         consumer: ConsumerSpec,
+        // Set when the Consumer restarted and resumes its previous session.
+        // The Consumer then takes over its previous entry, along with its reservations.
+        #[serde(default)]
+        resume: bool,
     },
     ReserveDevice {
         device_id: String,
+        // Lease duration in seconds. If `None`, the reservation does not expire.
+        lease_secs: Option<u64>,
+    },
+    // Reserve up to `count` available devices matching the filter.
+    ReserveAnyDevice {
+        filter_vec: DeviceFilterVec,
+        count: usize,
+        lease_secs: Option<u64>,
+    },
+    RenewLease {
+        device_id: String,
+        lease_secs: u64,
+    },
+    // Reserve a device reserved by the Consumer again, for the remaining lease,
+    // after the Consumer resumed its session.
+    ResumeReservation {
+        device_id: String,
     },
+    // Wait in the queue for a device to become available. The device is
+    // reserved for the Consumer when it is its turn in the queue.
+    EnqueueReservation {
+        target: WaitTarget,
+        lease_secs: Option<u64>,
+    },
+    LeaveQueue,
     ReleaseDevice {
         device_id: String,
     },
@@ -333,27 +568,97 @@ pub enum MarketMakerResponse {
     },
     ShutDownSuccess,
     ShutDownFailure {
-        reason: String,
+        reason: Error,
     },
     UseWhitelistSuccess,
     ResetWhitelistSuccess,
 
     WhitelistSupplierSuccess,
     WhitelistSupplierFailure {
-        reason: String,
+        reason: Error,
     },
     WhitelistConsumerSuccess,
     WhitelistConsumerFailure {
-        reason: String,
+        reason: Error,
     },
     UnwhitelistSupplierSuccess,
     UnwhitelistSupplierFailure {
-        reason: String,
+        reason: Error,
     },
     UnwhitelistConsumerSuccess,
     UnwhitelistConsumerFailure {
-        reason: String,
+        reason: Error,
+    },
+    WhitelistStandbySuccess,
+    WhitelistStandbyFailure {
+        reason: Error,
+    },
+    UnwhitelistStandbySuccess,
+    UnwhitelistStandbyFailure {
+        reason: Error,
+    },
+    GrantRoleSuccess,
+    GrantRoleFailure {
+        reason: Error,
+    },
+    RevokeRoleSuccess,
+    RevokeRoleFailure {
+        reason: Error,
+    },
+    SetQuotaSuccess,
+    SetQuotaFailure {
+        reason: Error,
+    },
+    RemoveQuotaSuccess,
+    AddToGroupSuccess,
+    AddToGroupFailure {
+        reason: Error,
+    },
+    RemoveFromGroupSuccess,
+    AssignPoolSuccess,
+    AssignPoolFailure {
+        reason: Error,
+    },
+    UnassignPoolSuccess,
+    GrantPoolAccessSuccess,
+    GrantPoolAccessFailure {
+        reason: Error,
+    },
+    RevokePoolAccessSuccess,
+
+    // Responses to event subscription.
+    Subscribed,
+
+    // Responses to standby MarketMaker.
+    StandbyConnected {
+        snapshot: Box<MarketMakerSnapshot>,
+        pub_key: String,
+    },
+    StandbyNotConnected {
+        reason: Error,
+    },
+
+    // Responses to primary MarketMaker.
+    StateReplicated,
+
+    // Responses to peer MarketMaker.
+    WhitelistPeerSuccess,
+    WhitelistPeerFailure {
+        reason: Error,
+    },
+    UnwhitelistPeerSuccess,
+    UnwhitelistPeerFailure {
+        reason: Error,
     },
+    PeerConnected {
+        market_maker: MarketMakerSpec,
+        devices: Vec<DeviceSpec>,
+    },
+    PeerNotConnected {
+        reason: Error,
+    },
+    DevicesAdvertised,
+    ConsumerNotified,
 
     // Responses to Supplier.
     SupplierConnected {
@@ -361,7 +666,7 @@ pub enum MarketMakerResponse {
         pub_key: String,
     },
     SupplierNotConnected {
-        reason: String,
+        reason: Error,
     },
     DevicesSupplied {
         supplied_devices: Vec<DeviceSpec>,
@@ -375,7 +680,7 @@ pub enum MarketMakerResponse {
         device_id: String,
     },
     DeviceNotReclaimed {
-        reason: String,
+        reason: Error,
     },
 
     // Responses to Consumer.
@@ -384,18 +689,38 @@ pub enum MarketMakerResponse {
         pub_key: String,
     },
     ConsumerNotConnected {
-        reason: String,
+        reason: Error,
     },
     DeviceReserved {
         device: DeviceSpec,
         peer_id: Option<String>,
     },
     DeviceNotReserved {
-        reason: String,
+        reason: Error,
+    },
+    // Devices reserved by `ReserveAnyDevice`, with the `peer_id` of the
+    // Supplier for devices that use secure tunnels.
+    DevicesReserved {
+        devices: Vec<(DeviceSpec, Option<String>)>,
+    },
+    LeaseRenewed {
+        device: DeviceSpec,
+    },
+    LeaseNotRenewed {
+        reason: Error,
+    },
+    ReservationQueued {
+        position: usize,
+    },
+    ReservationNotQueued {
+        reason: Error,
+    },
+    LeftQueue {
+        removed: usize,
     },
     DeviceReleased,
     DeviceNotReleased {
-        reason: String,
+        reason: Error,
     },
     ConsumerDisconnected,
     AvailableDevices {
@@ -406,15 +731,15 @@ pub enum MarketMakerResponse {
         filter_vec: DeviceFilterVec,
     },
     ErrorGettingDevices {
-        reason: String,
+        reason: Error,
     },
     AllDeviceReleaseSuccess,
     AllDeviceReleaseFailure {
-        reason: String,
+        reason: Error,
     },
     ScrcpyTunnelSuccess,
     ScrcpyTunnelFailure {
-        reason: String,
+        reason: Error,
     },
 
     // Other Responses
@@ -425,6 +750,52 @@ pub enum MarketMakerResponse {
     },
 }
 
+impl MarketMakerResponse {
+    /// Check whether the response reports that the request failed.
+    pub fn is_failure(&self) -> bool {
+        self.failure().is_some()
+    }
+
+    /// The error reported by the response, if the request failed.
+    pub fn failure(&self) -> Option<Error> {
+        match self {
+            MarketMakerResponse::ShutDownFailure { reason }
+            | MarketMakerResponse::WhitelistSupplierFailure { reason }
+            | MarketMakerResponse::WhitelistConsumerFailure { reason }
+            | MarketMakerResponse::UnwhitelistSupplierFailure { reason }
+            | MarketMakerResponse::UnwhitelistConsumerFailure { reason }
+            | MarketMakerResponse::WhitelistStandbyFailure { reason }
+            | MarketMakerResponse::UnwhitelistStandbyFailure { reason }
+            | MarketMakerResponse::GrantRoleFailure { reason }
+            | MarketMakerResponse::RevokeRoleFailure { reason }
+            | MarketMakerResponse::SetQuotaFailure { reason }
+            | MarketMakerResponse::AddToGroupFailure { reason }
+            | MarketMakerResponse::AssignPoolFailure { reason }
+            | MarketMakerResponse::GrantPoolAccessFailure { reason }
+            | MarketMakerResponse::StandbyNotConnected { reason }
+            | MarketMakerResponse::WhitelistPeerFailure { reason }
+            | MarketMakerResponse::UnwhitelistPeerFailure { reason }
+            | MarketMakerResponse::PeerNotConnected { reason }
+            | MarketMakerResponse::SupplierNotConnected { reason }
+            | MarketMakerResponse::DeviceNotReclaimed { reason }
+            | MarketMakerResponse::ConsumerNotConnected { reason }
+            | MarketMakerResponse::DeviceNotReserved { reason }
+            | MarketMakerResponse::LeaseNotRenewed { reason }
+            | MarketMakerResponse::ReservationNotQueued { reason }
+            | MarketMakerResponse::DeviceNotReleased { reason }
+            | MarketMakerResponse::ErrorGettingDevices { reason }
+            | MarketMakerResponse::AllDeviceReleaseFailure { reason }
+            | MarketMakerResponse::ScrcpyTunnelFailure { reason } => Some(reason.clone()),
+            MarketMakerResponse::DeviceBeingUsed { .. } => {
+                Some(Error::DeviceUnavailable(self.to_string()))
+            }
+            MarketMakerResponse::RequestNotAllowed => Some(request_not_allowed()),
+            MarketMakerResponse::InvalidRequest { request } => Some(invalid_request(request)),
+            _ => None,
+        }
+    }
+}
+
 impl Display for MarketMakerResponse {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         match self {
@@ -461,6 +832,77 @@ impl Display for MarketMakerResponse {
             MarketMakerResponse::UnwhitelistConsumerFailure { reason } => {
                 write!(f, "Unwhitelist consumer failure: {}", reason)
             }
+            MarketMakerResponse::WhitelistStandbySuccess => {
+                write!(f, "Whitelist standby success")
+            }
+            MarketMakerResponse::WhitelistStandbyFailure { reason } => {
+                write!(f, "Whitelist standby failure: {}", reason)
+            }
+            MarketMakerResponse::UnwhitelistStandbySuccess => {
+                write!(f, "Unwhitelist standby success")
+            }
+            MarketMakerResponse::UnwhitelistStandbyFailure { reason } => {
+                write!(f, "Unwhitelist standby failure: {}", reason)
+            }
+            MarketMakerResponse::GrantRoleSuccess => write!(f, "Grant role success"),
+            MarketMakerResponse::GrantRoleFailure { reason } => {
+                write!(f, "Grant role failure: {}", reason)
+            }
+            MarketMakerResponse::RevokeRoleSuccess => write!(f, "Revoke role success"),
+            MarketMakerResponse::RevokeRoleFailure { reason } => {
+                write!(f, "Revoke role failure: {}", reason)
+            }
+            MarketMakerResponse::SetQuotaSuccess => write!(f, "Set quota success"),
+            MarketMakerResponse::SetQuotaFailure { reason } => {
+                write!(f, "Set quota failure: {}", reason)
+            }
+            MarketMakerResponse::RemoveQuotaSuccess => write!(f, "Remove quota success"),
+            MarketMakerResponse::AddToGroupSuccess => write!(f, "Add to group success"),
+            MarketMakerResponse::AddToGroupFailure { reason } => {
+                write!(f, "Add to group failure: {}", reason)
+            }
+            MarketMakerResponse::RemoveFromGroupSuccess => {
+                write!(f, "Remove from group success")
+            }
+            MarketMakerResponse::AssignPoolSuccess => write!(f, "Assign pool success"),
+            MarketMakerResponse::AssignPoolFailure { reason } => {
+                write!(f, "Assign pool failure: {}", reason)
+            }
+            MarketMakerResponse::UnassignPoolSuccess => write!(f, "Unassign pool success"),
+            MarketMakerResponse::GrantPoolAccessSuccess => write!(f, "Grant pool access success"),
+            MarketMakerResponse::GrantPoolAccessFailure { reason } => {
+                write!(f, "Grant pool access failure: {}", reason)
+            }
+            MarketMakerResponse::RevokePoolAccessSuccess => {
+                write!(f, "Revoke pool access success")
+            }
+
+            MarketMakerResponse::Subscribed => write!(f, "Subscribed"),
+
+            MarketMakerResponse::StandbyConnected { .. } => write!(f, "Standby connected"),
+            MarketMakerResponse::StandbyNotConnected { reason } => {
+                write!(f, "Standby not connected: {}", reason)
+            }
+            MarketMakerResponse::StateReplicated => write!(f, "State replicated"),
+
+            MarketMakerResponse::WhitelistPeerSuccess => write!(f, "Whitelist peer success"),
+            MarketMakerResponse::WhitelistPeerFailure { reason } => {
+                write!(f, "Whitelist peer failure: {}", reason)
+            }
+            MarketMakerResponse::UnwhitelistPeerSuccess => {
+                write!(f, "Unwhitelist peer success")
+            }
+            MarketMakerResponse::UnwhitelistPeerFailure { reason } => {
+                write!(f, "Unwhitelist peer failure: {}", reason)
+            }
+            MarketMakerResponse::PeerConnected { devices, .. } => {
+                write!(f, "Peer connected. Devices advertised: {}", devices.len())
+            }
+            MarketMakerResponse::PeerNotConnected { reason } => {
+                write!(f, "Peer not connected: {}", reason)
+            }
+            MarketMakerResponse::DevicesAdvertised => write!(f, "Devices advertised"),
+            MarketMakerResponse::ConsumerNotified => write!(f, "Consumer notified"),
 
             MarketMakerResponse::SupplierConnected { supplier, .. } => {
                 write!(f, "Supplier connected:\n{}", supplier)
@@ -501,6 +943,28 @@ impl Display for MarketMakerResponse {
             MarketMakerResponse::DeviceNotReserved { reason } => {
                 write!(f, "Device not reserved: {}", reason)
             }
+            MarketMakerResponse::DevicesReserved { devices } => {
+                writeln!(f, "Devices reserved:").unwrap();
+                for (d, _) in devices {
+                    writeln!(f, "{}", d).unwrap();
+                }
+                write!(f, "")
+            }
+            MarketMakerResponse::LeaseRenewed { device } => {
+                write!(f, "Lease renewed:\n{}", device)
+            }
+            MarketMakerResponse::LeaseNotRenewed { reason } => {
+                write!(f, "Lease not renewed: {}", reason)
+            }
+            MarketMakerResponse::ReservationQueued { position } => {
+                write!(f, "Reservation queued at position: {}", position)
+            }
+            MarketMakerResponse::ReservationNotQueued { reason } => {
+                write!(f, "Reservation not queued: {}", reason)
+            }
+            MarketMakerResponse::LeftQueue { removed } => {
+                write!(f, "Removed {} queued reservation(s)", removed)
+            }
             MarketMakerResponse::DeviceReleased => write!(f, "Device released"),
             MarketMakerResponse::DeviceNotReleased { reason } => {
                 write!(f, "Device not released: {}", reason)
@@ -557,11 +1021,17 @@ pub enum SupplierRequest {
     Status,
     SupplyDevices {
         devices: Option<Vec<String>>,
+        // Pools to assign the supplied devices to.
+        #[serde(default)]
+        pools: HashSet<String>,
     },
     ReclaimDevice {
         device_id: String,
         force: bool,
     },
+    SetAutoSupply {
+        auto_supply: bool,
+    },
 
     // Requests from MarketMaker.
     MarketMakerTerminating,
@@ -604,25 +1074,28 @@ pub enum SupplierResponse {
         failed_devices: Vec<DeviceSpec>,
     },
     DeviceSupplyFailure {
-        reason: String,
+        reason: Error,
     },
     TerminationAcknowledged,
     DeviceReclaimed {
         device_id: String,
     },
     DeviceNotReclaimed {
-        reason: String,
+        reason: Error,
+    },
+    AutoSupplySet {
+        auto_supply: bool,
     },
     SecureTunnelStarted {
         port: u16,
     },
     SecureTunnelStartFailure {
-        reason: String,
+        reason: Error,
     },
     SecureTunnelStopped,
     ScrcpyTunnelSuccess,
     ScrcpyTunnelFailure {
-        reason: String,
+        reason: Error,
     },
 
     RequestNotAllowed,
@@ -631,6 +1104,33 @@ pub enum SupplierResponse {
     },
 }
 
+impl SupplierResponse {
+    /// Check whether the response reports that the request failed.
+    /// Supplying devices fails if none of the devices could be supplied.
+    pub fn is_failure(&self) -> bool {
+        self.failure().is_some()
+    }
+
+    /// The error reported by the response, if the request failed.
+    pub fn failure(&self) -> Option<Error> {
+        match self {
+            SupplierResponse::DevicesSupplied {
+                supplied_devices,
+                failed_devices,
+            } if supplied_devices.is_empty() && !failed_devices.is_empty() => {
+                Some(Error::InvalidInput(self.to_string()))
+            }
+            SupplierResponse::DeviceSupplyFailure { reason }
+            | SupplierResponse::DeviceNotReclaimed { reason }
+            | SupplierResponse::SecureTunnelStartFailure { reason }
+            | SupplierResponse::ScrcpyTunnelFailure { reason } => Some(reason.clone()),
+            SupplierResponse::RequestNotAllowed => Some(request_not_allowed()),
+            SupplierResponse::InvalidRequest { request } => Some(invalid_request(request)),
+            _ => None,
+        }
+    }
+}
+
 impl Display for SupplierResponse {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         match self {
@@ -669,6 +1169,13 @@ impl Display for SupplierResponse {
             SupplierResponse::DeviceNotReclaimed { reason } => {
                 write!(f, "Device reclaimation failure: {}", reason)
             }
+            SupplierResponse::AutoSupplySet { auto_supply } => {
+                write!(
+                    f,
+                    "Auto-supply turned {}",
+                    if *auto_supply { "on" } else { "off" }
+                )
+            }
             SupplierResponse::SecureTunnelStarted { port } => {
                 write!(f, "Secure tunnel started on: {}", port)
             }
@@ -703,7 +1210,23 @@ pub enum ConsumerRequest {
     ReserveDevice {
         device_id: String,
         no_use: bool,
+        lease_secs: Option<u64>,
+    },
+    ReserveAnyDevice {
+        filter_vec: DeviceFilterVec,
+        count: usize,
+        no_use: bool,
+        lease_secs: Option<u64>,
+    },
+    RenewLease {
+        device_id: String,
+        lease_secs: u64,
     },
+    EnqueueReservation {
+        target: WaitTarget,
+        lease_secs: Option<u64>,
+    },
+    LeaveQueue,
     ReleaseDevice {
         device_id: String,
     },
@@ -728,6 +1251,18 @@ pub enum ConsumerRequest {
     SupplierDisconnected {
         device_id: String,
     },
+    LeaseExpired {
+        device_id: String,
+    },
+    // The device was released by an admin of the MarketMaker.
+    DeviceForceReleased {
+        device_id: String,
+    },
+    // A device was reserved for the Consumer from the wait queue.
+    QueuedDeviceReserved {
+        device: Box<DeviceSpec>,
+        peer_id: Option<String>,
+    },
 }
 
 /// Responses from Consumer.
@@ -748,36 +1283,57 @@ pub enum ConsumerResponse {
     DeviceReserved {
         device: DeviceSpec,
     },
+    DevicesReserved {
+        devices: Vec<DeviceSpec>,
+    },
     DeviceNotReserved {
-        reason: String,
+        reason: Error,
+    },
+    LeaseRenewed {
+        device: DeviceSpec,
+    },
+    LeaseNotRenewed {
+        reason: Error,
+    },
+    ReservationQueued {
+        position: usize,
+    },
+    ReservationNotQueued {
+        reason: Error,
+    },
+    LeftQueue {
+        removed: usize,
+    },
+    LeaveQueueFailure {
+        reason: Error,
     },
     DeviceReleased {
         device_id: String,
     },
     DeviceNotReleased {
-        reason: String,
+        reason: Error,
     },
     AllDeviceReleaseSuccess,
     AllDeviceReleaseFailure {
-        reason: String,
+        reason: Error,
     },
     UseDeviceSuccess {
         device_id: String,
     },
     UseDeviceFailure {
-        reason: String,
+        reason: Error,
     },
     StartScrCpySuccess {
         device_id: String,
     },
     StartScrCpyFailure {
-        reason: String,
+        reason: Error,
     },
     StopScrCpySuccess {
         device_id: String,
     },
     StopScrCpyFailure {
-        reason: String,
+        reason: Error,
     },
     ScrCpyDefaultsSet {
         args: Vec<ScrCpyArgs>,
@@ -787,7 +1343,7 @@ pub enum ConsumerResponse {
     },
 
     ErrorGettingDevices {
-        reason: String,
+        reason: Error,
     },
     RequestNotAllowed,
     InvalidRequest {
@@ -795,6 +1351,32 @@ pub enum ConsumerResponse {
     },
 }
 
+impl ConsumerResponse {
+    /// Check whether the response reports that the request failed.
+    pub fn is_failure(&self) -> bool {
+        self.failure().is_some()
+    }
+
+    /// The error reported by the response, if the request failed.
+    pub fn failure(&self) -> Option<Error> {
+        match self {
+            ConsumerResponse::DeviceNotReserved { reason }
+            | ConsumerResponse::LeaseNotRenewed { reason }
+            | ConsumerResponse::ReservationNotQueued { reason }
+            | ConsumerResponse::LeaveQueueFailure { reason }
+            | ConsumerResponse::DeviceNotReleased { reason }
+            | ConsumerResponse::AllDeviceReleaseFailure { reason }
+            | ConsumerResponse::UseDeviceFailure { reason }
+            | ConsumerResponse::StartScrCpyFailure { reason }
+            | ConsumerResponse::StopScrCpyFailure { reason }
+            | ConsumerResponse::ErrorGettingDevices { reason } => Some(reason.clone()),
+            ConsumerResponse::RequestNotAllowed => Some(request_not_allowed()),
+            ConsumerResponse::InvalidRequest { request } => Some(invalid_request(request)),
+            _ => None,
+        }
+    }
+}
+
 impl Display for ConsumerResponse {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         match self {
@@ -830,6 +1412,31 @@ impl Display for ConsumerResponse {
             ConsumerResponse::DeviceNotReserved { reason } => {
                 write!(f, "Device not reserved: {}", reason)
             }
+            ConsumerResponse::DevicesReserved { devices } => {
+                writeln!(f, "Devices reserved:").unwrap();
+                for d in devices {
+                    writeln!(f, "{}", d).unwrap();
+                }
+                write!(f, "")
+            }
+            ConsumerResponse::LeaseRenewed { device } => {
+                write!(f, "Lease renewed:\n{}", device)
+            }
+            ConsumerResponse::LeaseNotRenewed { reason } => {
+                write!(f, "Lease not renewed: {}", reason)
+            }
+            ConsumerResponse::ReservationQueued { position } => {
+                write!(f, "Reservation queued at position: {}", position)
+            }
+            ConsumerResponse::ReservationNotQueued { reason } => {
+                write!(f, "Reservation not queued: {}", reason)
+            }
+            ConsumerResponse::LeftQueue { removed } => {
+                write!(f, "Removed {} queued reservation(s)", removed)
+            }
+            ConsumerResponse::LeaveQueueFailure { reason } => {
+                write!(f, "Error leaving the queue: {}", reason)
+            }
             ConsumerResponse::DeviceReleased { device_id } => {
                 write!(f, "Device released: {}", device_id)
             }
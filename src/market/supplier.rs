@@ -6,6 +6,8 @@ use crate::util::adb_utils;
 use portpicker;
 use request::{MarketMakerRequest, MarketMakerResponse, SupplierRequest, SupplierResponse};
 use std::default::Default;
+use std::sync::mpsc;
+use std::time::Instant;
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub(super) struct Supplier;
@@ -19,6 +21,11 @@ lazy_static! {
 
     // TODO: Replace this static variable with a database.
     static ref SCRCPY_STATE: Mutex<ScrCpyState> = Mutex::new(ScrCpyState::default());
+
+    /// Last health collected for each supplied device.
+    static ref DEVICE_HEALTH: Mutex<HashMap<String, DeviceHealth>> = Mutex::new(HashMap::new());
+    /// Devices whose health is being collected.
+    static ref HEALTH_PENDING: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
 }
 
 impl ScrCpyState {
@@ -84,8 +91,10 @@ pub(super) struct SupplierState {
     mm_port: u16,
     // SocketAddr of the market maker it is connected to.
     mm_addr: Option<SocketAddr>,
-    // HashMap of exposed port numbers and device_details, hashed by device id.
-    ports: HashMap<String, (u16, String)>,
+    // host, port of the market makers to connect to, in order of preference.
+    market_makers: Vec<(String, u16)>,
+    // HashMap of exposed port numbers and device info, hashed by device id.
+    ports: HashMap<String, (u16, DeviceInfo)>,
     // Name of the supplier.
     name: String,
     // Public key of the Market Maker.
@@ -94,6 +103,10 @@ pub(super) struct SupplierState {
     secure_comms: bool,
     // HashMap of PortForwarders, hashed by device id.
     port_forwarders: HashMap<String, PortForwarder>,
+    // HashMap of ANDROID_SERIAL of the supplied devices, hashed by device id.
+    android_serials: HashMap<String, String>,
+    // Supply attached devices and reclaim detached devices automatically.
+    auto_supply: bool,
 }
 
 impl Display for SupplierState {
@@ -127,7 +140,9 @@ pub struct SupplierStateMin {
     // Using secure channel or not.
     secure_comms: bool,
     // HashMap of exposed port numbers, hashed by device id.
-    ports: HashMap<String, (u16, String)>,
+    ports: HashMap<String, (u16, DeviceInfo)>,
+    // Supply attached devices and reclaim detached devices automatically.
+    auto_supply: bool,
 }
 
 impl Display for SupplierStateMin {
@@ -137,10 +152,12 @@ impl Display for SupplierStateMin {
             r"Current Supplier Status:
     Connected to Market Maker    : {}
     Supplier name on network     : {}
-    Number of devices supplied   : {}",
+    Number of devices supplied   : {}
+    Auto-supply                  : {}",
             self.mm_host,
             self.name,
             self.ports.len(),
+            if self.auto_supply { "on" } else { "off" },
         )
         .unwrap();
         if !self.secure_comms {
@@ -173,10 +190,10 @@ WARNING: Not using secure channel for device communications!!
             "Port",
             self.ports
                 .iter()
-                .map(|(key, (port, details))| format!(
+                .map(|(key, (port, info))| format!(
                     "{:^27}|{:^35}|{:^10}",
                     key,
-                    strip_device_details(details),
+                    format!("{} {} {}", info.brand, info.name, info.model),
                     port
                 ))
                 .collect::<Vec<String>>()
@@ -185,17 +202,6 @@ WARNING: Not using secure channel for device communications!!
     }
 }
 
-// Strip device details of the form:
-// Brand: <brand>   Name: <name>   Model: <model>
-// to: <brand> <name> <model>
-fn strip_device_details(details: &str) -> String {
-    details
-        .split("   ")
-        .map(|x| x.split(": ").nth(1).unwrap_or(""))
-        .collect::<Vec<&str>>()
-        .join(" ")
-}
-
 // Keeps accesses to the SUPPLIER_STATE static variable contained in small functions.
 // This is to prevent deadlocks when accessing the SUPPLIER_STATE Mutex in multiple places.
 // We should not need to lock the SUPPLIER_STATE Mutex outside of these functions.
@@ -204,6 +210,12 @@ fn strip_device_details(details: &str) -> String {
 impl SupplierState {
     // Write functions...
 
+    #[inline(always)]
+    fn set_market_makers(market_makers: Vec<(String, u16)>) {
+        let mut state = SUPPLIER_STATE.lock().unwrap();
+        state.market_makers = market_makers;
+    }
+
     #[inline(always)]
     fn update_host_port_name(host: Option<String>, port: Option<u16>, name: Option<String>) {
         let mut state = SUPPLIER_STATE.lock().unwrap();
@@ -231,9 +243,16 @@ impl SupplierState {
     }
 
     #[inline(always)]
-    fn insert_port(device_id: String, port: u16, device_details: String) {
+    fn insert_port(device_id: String, android_serial: String, port: u16, device_info: DeviceInfo) {
         let mut state = SUPPLIER_STATE.lock().unwrap();
-        state.ports.insert(device_id, (port, device_details));
+        state
+            .android_serials
+            .insert(device_id.clone(), android_serial.clone());
+        state.ports.insert(device_id, (port, device_info));
+        drop(state);
+        SavedModes::update_supplier(|supplier| {
+            supplier.android_serials.insert(android_serial);
+        });
     }
 
     #[inline(always)]
@@ -246,6 +265,13 @@ impl SupplierState {
     fn remove_port(device_id: &str) {
         let mut state = SUPPLIER_STATE.lock().unwrap();
         state.ports.remove(device_id);
+        let android_serial = state.android_serials.remove(device_id);
+        drop(state);
+        if let Some(android_serial) = android_serial {
+            SavedModes::update_supplier(|supplier| {
+                supplier.android_serials.remove(&android_serial);
+            });
+        }
     }
 
     #[inline(always)]
@@ -260,8 +286,22 @@ impl SupplierState {
         state.secure_comms = secure_comms;
     }
 
+    #[inline(always)]
+    pub(super) fn set_auto_supply(auto_supply: bool) {
+        let mut state = SUPPLIER_STATE.lock().unwrap();
+        state.auto_supply = auto_supply;
+        drop(state);
+        SavedModes::update_supplier(|supplier| supplier.auto_supply = auto_supply);
+    }
+
     // Read functions...
 
+    #[inline(always)]
+    fn get_market_makers() -> Vec<(String, u16)> {
+        let state = SUPPLIER_STATE.lock().unwrap();
+        state.market_makers.clone()
+    }
+
     #[inline(always)]
     fn get_min_state() -> SupplierStateMin {
         let state = SUPPLIER_STATE.lock().unwrap();
@@ -271,6 +311,7 @@ impl SupplierState {
             name: state.name.clone(),
             secure_comms: state.secure_comms,
             ports: state.ports.clone(),
+            auto_supply: state.auto_supply,
         }
     }
 
@@ -287,7 +328,7 @@ impl SupplierState {
     }
 
     #[inline(always)]
-    fn get_ports() -> HashMap<String, (u16, String)> {
+    fn get_ports() -> HashMap<String, (u16, DeviceInfo)> {
         let state = SUPPLIER_STATE.lock().unwrap();
         state.ports.clone()
     }
@@ -305,6 +346,24 @@ impl SupplierState {
         state.secure_comms
     }
 
+    #[inline(always)]
+    fn get_auto_supply() -> bool {
+        let state = SUPPLIER_STATE.lock().unwrap();
+        state.auto_supply
+    }
+
+    #[inline(always)]
+    fn get_android_serials() -> HashSet<String> {
+        let state = SUPPLIER_STATE.lock().unwrap();
+        state.android_serials.values().cloned().collect()
+    }
+
+    #[inline(always)]
+    fn get_android_serial(device_id: &str) -> Option<String> {
+        let state = SUPPLIER_STATE.lock().unwrap();
+        state.android_serials.get(device_id).cloned()
+    }
+
     #[inline(always)]
     pub(super) fn verify_market_maker(key: &Key) -> bool {
         let state = SUPPLIER_STATE.lock().unwrap();
@@ -349,12 +408,65 @@ lazy_static! {
 }
 
 impl Supplier {
+    /// The Market Makers in `fallbacks` are tried in order, if the Market Maker
+    /// at `mm_host:mm_port` cannot be connected to.
+    /// If `resume` is set, the Supplier resumes the session of its previous run,
+    /// e.g. when its modes are restored after a restart of the system.
     pub(super) fn new(
         mm_host: String,
         mm_port: u16,
+        fallbacks: Vec<(String, u16)>,
         name: Option<String>,
         secure_comms: bool,
-    ) -> io::Result<Supplier> {
+        resume: bool,
+    ) -> Result<Supplier, Error> {
+        let name = name.unwrap_or_else(|| {
+            hostname::get()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string()
+        });
+        let mut market_makers = vec![(mm_host, mm_port)];
+        market_makers.extend(fallbacks);
+        Supplier::connect_any(&market_makers, &name, secure_comms, resume)?;
+        SupplierState::set_market_makers(market_makers);
+        let supplier = Supplier;
+        Supplier::start_heartbeat_thread();
+        Supplier::start_auto_supply_thread();
+        Ok(supplier)
+    }
+
+    /// Connect to the first Market Maker of `market_makers` that accepts the Supplier.
+    /// Returns the error of the first Market Maker, if none accepts the Supplier.
+    fn connect_any(
+        market_makers: &[(String, u16)],
+        name: &str,
+        secure_comms: bool,
+        resume: bool,
+    ) -> Result<(), Error> {
+        let mut first_err = None;
+        for (mm_host, mm_port) in market_makers {
+            let name = name.to_string();
+            match Supplier::connect(mm_host.clone(), *mm_port, name, secure_comms, resume) {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    warn!("Unable to connect to Market Maker {}: {}", mm_host, e);
+                    first_err.get_or_insert(e);
+                }
+            }
+        }
+        Err(first_err
+            .unwrap_or_else(|| Error::InvalidInput("No Market Maker to connect to.".to_string())))
+    }
+
+    /// Connect to the Market Maker as a Supplier, and update the SupplierState.
+    fn connect(
+        mm_host: String,
+        mm_port: u16,
+        name: String,
+        secure_comms: bool,
+        resume: bool,
+    ) -> Result<(), Error> {
         debug!("Checking adb version...");
         let ver_info = adb_utils::get_adb_version()?;
         debug!("ADB_VER_INFO: {}", ver_info);
@@ -363,32 +475,22 @@ impl Supplier {
             debug!("ADB version is compatible.");
         } else {
             error!("ADB version is not compatible.");
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                "ADB version is not compatible.",
-            ));
+            return Err(Error::Adb("ADB version is not compatible.".to_string()));
         }
 
-        let mut supplier_spec = SupplierSpec {
+        let supplier_spec = SupplierSpec {
+            name,
             ver_info,
             secure_comms,
             ..Default::default()
         };
 
-        if let Some(name) = name {
-            supplier_spec.name = name;
-        } else {
-            supplier_spec.name = hostname::get()
-                .unwrap_or_default()
-                .to_string_lossy()
-                .to_string();
-        }
-
         // Unwrapping of serialing/deserializing is safe, because we use request/response objects
         // that are known to be serializable/deserializable.
         let client = TCPClient::new(&mm_host, mm_port)?;
         let supply_request = MarketMakerRequest::SupplierConnect {
             supplier: supplier_spec,
+            resume,
         };
         let response = client.send_request(supply_request, None)?;
 
@@ -408,53 +510,259 @@ impl Supplier {
             SupplierState::update_addr(client.addr);
             SupplierState::set_market_maker_key(mm_pub_key);
             SupplierState::set_secure_comms(secure_comms);
-            let supplier = Supplier;
-            Supplier::start_heartbeat_thread();
-            Ok(supplier)
+            Ok(())
         } else if let MarketMakerResponse::SupplierNotConnected { reason } = response {
             error!("Supplier not connected: {}", reason);
-            Err(io::Error::new(io::ErrorKind::Other, reason))
+            Err(reason)
         } else {
             error!("Unexpected response from Market Maker: {:?}", response);
-            Err(io::Error::new(
-                io::ErrorKind::Other,
-                format!("Unexpected response from Market Maker: {:?}", response),
-            ))
+            Err(Error::Protocol(format!(
+                "Unexpected response from Market Maker: {:?}",
+                response
+            )))
         }
     }
 
-    fn start_heartbeat_thread() {
-        thread::spawn(|| loop {
-            thread::sleep(HEARTBEAT_INTERVAL);
-            // Stop the thread if supplier has terminated.
-            if !SysState::supplier_is_some() {
+    /// Collect the health of the supplied devices, each on a thread of its own, and
+    /// return the last health collected for each device. Waits at most `HEALTH_TIMEOUT`.
+    /// The health of a device is not collected again, until its pending collection
+    /// finishes, so a device that does not respond does not pile up threads.
+    fn collect_device_health() -> HashMap<String, DeviceHealth> {
+        let ports = SupplierState::get_ports();
+        let (tx, rx) = mpsc::channel();
+        let mut started = 0;
+        let mut pending = HEALTH_PENDING.lock().unwrap();
+        for (device_id, (port, _)) in &ports {
+            if !pending.insert(device_id.clone()) {
+                continue;
+            }
+            let device_id = device_id.clone();
+            let port = *port;
+            let tx = tx.clone();
+            thread::spawn(move || {
+                let health = adb_utils::get_device_health(port);
+                DEVICE_HEALTH
+                    .lock()
+                    .unwrap()
+                    .insert(device_id.clone(), health);
+                HEALTH_PENDING.lock().unwrap().remove(&device_id);
+                // The heartbeat may have stopped waiting for the health.
+                let _ = tx.send(());
+            });
+            started += 1;
+        }
+        drop(pending);
+
+        let deadline = Instant::now() + HEALTH_TIMEOUT;
+        for _ in 0..started {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if rx.recv_timeout(remaining).is_err() {
+                warn!("Timed out collecting the health of the supplied devices.");
                 break;
             }
-            let mm_addr = SupplierState::get_addr();
+        }
 
-            if mm_addr.is_none() {
-                warn!("Market Maker address is not set. Skipping heartbeat.");
-                continue;
+        let mut device_health = DEVICE_HEALTH.lock().unwrap();
+        device_health.retain(|device_id, _| ports.contains_key(device_id));
+        device_health.clone()
+    }
+
+    /// Send a heartbeat, with the health of the supplied devices, to the Market Maker.
+    fn send_heartbeat() -> Result<(), Error> {
+        let mm_addr = SupplierState::get_addr()
+            .ok_or_else(|| Error::InvalidState("Market Maker address is not set.".to_string()))?;
+        let client = TCPClient::from(mm_addr);
+        let device_health = Supplier::collect_device_health();
+        let heartbeat_request = MarketMakerRequest::SupplierHeartBeat { device_health };
+        let response = client.send_request(heartbeat_request, None)?;
+        let response = MarketMakerResponse::from_str(&response).unwrap();
+        if let MarketMakerResponse::HeartBeatResponse = response {
+            Ok(())
+        } else {
+            Err(Error::Protocol(format!(
+                "Unexpected response from Market Maker: {:?}",
+                response
+            )))
+        }
+    }
+
+    /// Send heartbeats to the Market Maker. If the Market Maker is unreachable,
+    /// try to reconnect with exponential backoff, until `RECONNECT_TIMEOUT` elapses.
+    fn start_heartbeat_thread() {
+        thread::spawn(|| {
+            let mut backoff: Option<ReconnectBackoff> = None;
+            loop {
+                let delay = match backoff.as_mut() {
+                    None => HEARTBEAT_INTERVAL,
+                    Some(backoff) => match backoff.next_delay() {
+                        Some(delay) => delay,
+                        None => {
+                            error!("Could not reconnect to Market Maker. Stopping supplier.");
+                            System::stop_supplier(true);
+                            break;
+                        }
+                    },
+                };
+                thread::sleep(delay);
+                // Stop the thread if supplier has terminated.
+                if !SysState::supplier_is_some() {
+                    break;
+                }
+                if backoff.is_some() {
+                    match Supplier::reconnect() {
+                        Ok(()) => {
+                            info!("Reconnected to Market Maker.");
+                            backoff = None;
+                        }
+                        Err(e) => warn!("Failed to reconnect to Market Maker: {}", e),
+                    }
+                    continue;
+                }
+                match Supplier::send_heartbeat() {
+                    Ok(()) => debug!("Heartbeat sent successfully."),
+                    Err(e) => {
+                        error!("Failed to send heartbeat to Market Maker: {}", e);
+                        info!("Reconnecting to Market Maker...");
+                        backoff = Some(ReconnectBackoff::new());
+                    }
+                }
             }
+        });
+    }
 
-            let mm_addr = mm_addr.unwrap();
-            let client = TCPClient::from(mm_addr);
-            let heartbeat_request = MarketMakerRequest::SupplierHeartBeat;
-            let response = match client.send_request(heartbeat_request, None) {
-                Ok(response) => response,
-                Err(e) => {
-                    error!("Failed to send heartbeat to Market Maker: {}", e);
-                    System::stop_supplier(true);
+    /// Resume the session with the Market Maker, after it was unreachable.
+    /// If the Market Maker no longer knows the Supplier, e.g. after a restart,
+    /// the Supplier connects again with the same identity and supplies its devices again.
+    fn reconnect() -> Result<(), Error> {
+        if Supplier::send_heartbeat().is_ok() {
+            return Ok(());
+        }
+        let state = SupplierState::get_min_state();
+        // Prefer the Market Maker the Supplier was connected to, e.g. a standby
+        // that took over, before falling back to the others in order.
+        let current = (state.mm_host, state.mm_port);
+        let mut market_makers = SupplierState::get_market_makers();
+        market_makers.retain(|market_maker| *market_maker != current);
+        market_makers.insert(0, current);
+        Supplier::connect_any(
+            &market_makers,
+            &SupplierState::get_name(),
+            SupplierState::get_secure_comms(),
+            false,
+        )?;
+        Supplier::resupply_devices()
+    }
+
+    /// Register the supplied devices with the Market Maker again.
+    /// Devices that the Market Maker already knows are left as they are.
+    fn resupply_devices() -> Result<(), Error> {
+        let devices: Vec<DeviceSpec> = SupplierState::get_ports()
+            .into_iter()
+            .map(|(device_id, (port, device_info))| DeviceSpec {
+                android_serial: SupplierState::get_android_serial(&device_id).unwrap_or_default(),
+                device_id,
+                device_info,
+                available_at_port: port,
+                available_at: base64::encode(SystemKeypair::get_public_key().unwrap()),
+                available_at_name: SupplierState::get_name(),
+                secure_comms: SupplierState::get_secure_comms(),
+                ..Default::default()
+            })
+            .collect();
+        if devices.is_empty() {
+            return Ok(());
+        }
+        let mm_addr = SupplierState::get_addr()
+            .ok_or_else(|| Error::InvalidState("Market Maker address is not set.".to_string()))?;
+        let client = TCPClient::from(mm_addr);
+        let response = client.send_request(MarketMakerRequest::SupplyDevices { devices }, None)?;
+        let response = MarketMakerResponse::from_str(&response).unwrap();
+        if let MarketMakerResponse::DevicesSupplied {
+            supplied_devices, ..
+        } = response
+        {
+            info!(
+                "Supplied {} device(s) again to Market Maker.",
+                supplied_devices.len()
+            );
+            Ok(())
+        } else {
+            Err(Error::Protocol(format!(
+                "Error supplying devices: {}",
+                response
+            )))
+        }
+    }
+
+    /// Supply newly attached devices and reclaim detached devices, while auto-supply is on.
+    /// Detached devices are detected by polling their adb servers, and attached devices
+    /// by listing the USB devices, so that no adb server is started for the detection.
+    /// Devices that the Market Maker refused are not supplied again until they are detached.
+    fn start_auto_supply_thread() {
+        thread::spawn(|| {
+            let mut refused_devices = HashSet::new();
+            loop {
+                thread::sleep(AUTO_SUPPLY_INTERVAL);
+                // Stop the thread if supplier has terminated.
+                if !SysState::supplier_is_some() {
                     break;
                 }
-            };
-            let response = MarketMakerResponse::from_str(&response).unwrap();
-            if let MarketMakerResponse::HeartBeatResponse = response {
-                debug!("Heartbeat sent successfully.");
-            } else {
-                error!("Unexpected response from Market Maker: {:?}", response);
-                System::stop_supplier(true);
-                break;
+                if !SupplierState::get_auto_supply() {
+                    continue;
+                }
+
+                // The adb server of a supplied device serves that device only,
+                // so a detached device is no longer found by its adb server.
+                for (device_id, (port, _)) in SupplierState::get_ports() {
+                    match adb_utils::get_device_state(port) {
+                        Ok(None) => {}
+                        Ok(Some(_)) => continue,
+                        Err(e) => {
+                            warn!("Auto-supply: device {}: {}", device_id, e);
+                            continue;
+                        }
+                    }
+                    info!("Device {} detached. Reclaiming device.", device_id);
+                    let response = Supplier::reclaim_device(device_id, true);
+                    debug!(
+                        "Auto-supply: {}",
+                        SupplierResponse::from_str(&response).unwrap()
+                    );
+                }
+
+                // Only the serials are listed here. The adb server is started, and the
+                // information of a device fetched, when the device is supplied.
+                let connected_devices = match adb_utils::get_attached_serials() {
+                    Some(connected_devices) => connected_devices,
+                    None => continue,
+                };
+                let supplied_devices = SupplierState::get_android_serials();
+                let new_devices = connected_devices
+                    .into_iter()
+                    .filter(|device| !supplied_devices.contains(device))
+                    .collect::<HashSet<_>>();
+                refused_devices.retain(|device| new_devices.contains(device));
+                let new_devices = new_devices
+                    .difference(&refused_devices)
+                    .cloned()
+                    .collect::<Vec<_>>();
+                if new_devices.is_empty() {
+                    continue;
+                }
+                info!("Devices attached: {:?}. Supplying devices.", new_devices);
+                match Supplier::supply_devices(Some(new_devices.clone()), HashSet::new()) {
+                    Ok((_, failed_devices)) => {
+                        refused_devices.extend(
+                            failed_devices
+                                .into_iter()
+                                .map(|device_spec| device_spec.android_serial),
+                        );
+                    }
+                    Err(e) => {
+                        error!("Auto-supply failed to supply devices: {}", e);
+                        refused_devices.extend(new_devices);
+                    }
+                }
             }
         });
     }
@@ -488,10 +796,11 @@ impl Supplier {
         SupplierState::reset_state();
     }
 
-    /// Supply devices to the market maker.
-    fn supply_devices(
+    /// Supply devices to the market maker, assigned to the given pools.
+    pub(super) fn supply_devices(
         devices: Option<Vec<String>>,
-    ) -> io::Result<(Vec<DeviceSpec>, Vec<DeviceSpec>)> {
+        pools: HashSet<String>,
+    ) -> Result<(Vec<DeviceSpec>, Vec<DeviceSpec>), Error> {
         let supply_all = devices.is_none();
         let secure_comms = SupplierState::get_secure_comms();
         let num_devices = devices.as_ref().map(|d| d.len()).unwrap_or(0);
@@ -499,20 +808,11 @@ impl Supplier {
         if port_map.is_none() {
             error!("Couldn't find the device(s) specifed. Please check the device(s) is/are connected.");
             if supply_all {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    "Couldn't find any devices to supply.\n\nPlease check the devices are connected and detected by ADB.",
-                ));
+                return Err(Error::DeviceNotFound("Couldn't find any devices to supply.\n\nPlease check the devices are connected and detected by ADB.".to_string()));
             } else if num_devices > 1 {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    "Couldn't find the specifed devices.\n\nPlease check the devices are connected and detected by ADB.",
-                ));
+                return Err(Error::DeviceNotFound("Couldn't find the specifed devices.\n\nPlease check the devices are connected and detected by ADB.".to_string()));
             } else {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    "Couldn't find the specifed device.\n\nPlease check the device is connected and detected by ADB.",
-                ));
+                return Err(Error::DeviceNotFound("Couldn't find the specifed device.\n\nPlease check the device is connected and detected by ADB.".to_string()));
             }
         }
         // Generate a uuid for each device.
@@ -526,11 +826,12 @@ impl Supplier {
                 DeviceSpec {
                     device_id,
                     android_serial: device.clone(),
-                    device_details: format!("{}", device_info),
+                    device_info: device_info.clone(),
                     available_at_port: *port,
                     available_at: base64::encode(SystemKeypair::get_public_key().unwrap()),
                     available_at_name: SupplierState::get_name(),
                     secure_comms,
+                    pools: pools.clone(),
                     ..Default::default()
                 }
             })
@@ -546,9 +847,8 @@ impl Supplier {
             for (_, _, port) in port_map {
                 adb_utils::kill_adb_server_for_port(port);
             }
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                "Market Maker address is not set. Skipping supply devices.",
+            return Err(Error::InvalidState(
+                "Market Maker address is not set. Skipping supply devices.".to_string(),
             ));
         }
 
@@ -563,9 +863,8 @@ impl Supplier {
             for (_, _, port) in port_map {
                 adb_utils::kill_adb_server_for_port(port);
             }
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                "Failed to send Supply request to Market Maker.",
+            return Err(Error::Unreachable(
+                "Failed to send Supply request to Market Maker.".to_string(),
             ));
         }
         let response = response.unwrap();
@@ -578,8 +877,9 @@ impl Supplier {
             for device_spec in supplied_devices.iter() {
                 SupplierState::insert_port(
                     device_spec.device_id.clone(),
+                    device_spec.android_serial.clone(),
                     device_spec.available_at_port,
-                    device_spec.device_details.clone(),
+                    device_spec.device_info.clone(),
                 );
             }
             for device_spec in failed_devices.iter() {
@@ -591,10 +891,10 @@ impl Supplier {
             for (_, _, port) in port_map {
                 adb_utils::kill_adb_server_for_port(port);
             }
-            Err(io::Error::new(
-                io::ErrorKind::Other,
-                format!("Error supplying devices: {}", response),
-            ))
+            Err(Error::Protocol(format!(
+                "Error supplying devices: {}",
+                response
+            )))
         }
     }
 
@@ -605,7 +905,7 @@ impl Supplier {
         if mm_addr.is_none() {
             error!("Market Maker address is not set. Skipping reclaim device.");
             return MarketMakerResponse::DeviceNotReclaimed {
-                reason: "Fatal: Market Maker address not set.".to_string(),
+                reason: Error::InvalidState("Fatal: Market Maker address not set.".to_string()),
             }
             .to_json();
         }
@@ -619,7 +919,9 @@ impl Supplier {
                 response.err().unwrap()
             );
             return SupplierResponse::DeviceNotReclaimed {
-                reason: "Failed to send ReclaimDevice request to Market Maker".to_string(),
+                reason: Error::Unreachable(
+                    "Failed to send ReclaimDevice request to Market Maker".to_string(),
+                ),
             }
             .to_json();
         }
@@ -632,7 +934,7 @@ impl Supplier {
                     // This should not happen.
                     error!("Device {} not found in port map", device_id);
                     return SupplierResponse::DeviceNotReclaimed {
-                        reason: "Device not found in port map".to_string(),
+                        reason: Error::DeviceNotFound("Device not found in port map".to_string()),
                     }
                     .to_json();
                 }
@@ -649,7 +951,10 @@ impl Supplier {
             _ => {
                 error!("Unexpected response from Market Maker: {:?}", response);
                 SupplierResponse::DeviceNotReclaimed {
-                    reason: format!("Unexpected response from Market Maker: {:?}", response),
+                    reason: Error::Protocol(format!(
+                        "Unexpected response from Market Maker: {:?}",
+                        response
+                    )),
                 }
                 .to_json()
             }
@@ -657,33 +962,28 @@ impl Supplier {
     }
 
     // Start a PortForwarder in Decrypt mode for the given device and return the exposed port.
-    fn start_forwarder(device_id: &str, port: u16, peer_id: String) -> io::Result<u16> {
+    fn start_forwarder(device_id: &str, port: u16, peer_id: String) -> Result<u16, Error> {
         let peer_id = base64::decode(peer_id);
         if peer_id.is_err() {
-            return Err(io::Error::new(io::ErrorKind::Other, "Invalid peer id"));
+            return Err(Error::InvalidInput("Invalid peer id".to_string()));
         }
         let peer_id = peer_id.unwrap();
         let available_at_port = SupplierState::get_port_of_device(device_id);
         if available_at_port.is_none() {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                "Device not found in port map",
+            return Err(Error::DeviceNotFound(
+                "Device not found in port map".to_string(),
             ));
         }
         let available_at_port = available_at_port.unwrap();
         if available_at_port != port {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                "Device port does not match port map",
+            return Err(Error::InvalidInput(
+                "Device port does not match port map".to_string(),
             ));
         }
 
         let exposed_port = portpicker::pick_unused_port();
         if exposed_port.is_none() {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                "Failed to find an available port",
-            ));
+            return Err(Error::Io("Failed to find an available port".to_string()));
         }
         let exposed_port = exposed_port.unwrap();
         let mut forwarder = PortForwarder::try_new(
@@ -722,10 +1022,10 @@ impl Supplier {
             ));
         }
         let mm_addr = mm_addr.unwrap().ip().to_string();
-        let dst_host = if consumer_host != "127.0.0.1" {
-            consumer_host
-        } else {
+        let dst_host = if matches!(consumer_host.parse::<IpAddr>(), Ok(ip) if ip.is_loopback()) {
             mm_addr.as_str()
+        } else {
+            consumer_host
         };
         let mut forwarder = if SupplierState::get_secure_comms() {
             debug!("Starting scrcpy tunnel in Encrypt mode");
@@ -774,18 +1074,15 @@ impl Supplier {
                 SupplierResponse::Status { state }.to_json()
             }
 
-            SupplierRequest::SupplyDevices { devices } if peer_addr.ip().is_loopback() => {
+            SupplierRequest::SupplyDevices { devices, pools } if peer_addr.ip().is_loopback() => {
                 debug!("Supplying devices: {:?}", devices);
-                match Supplier::supply_devices(devices) {
+                match Supplier::supply_devices(devices, pools) {
                     Ok((supplied_devices, failed_devices)) => SupplierResponse::DevicesSupplied {
                         supplied_devices,
                         failed_devices,
                     }
                     .to_json(),
-                    Err(e) => SupplierResponse::DeviceSupplyFailure {
-                        reason: e.to_string(),
-                    }
-                    .to_json(),
+                    Err(e) => SupplierResponse::DeviceSupplyFailure { reason: e }.to_json(),
                 }
             }
 
@@ -794,6 +1091,16 @@ impl Supplier {
                 Supplier::reclaim_device(device_id, force)
             }
 
+            SupplierRequest::SetAutoSupply { auto_supply } if peer_addr.ip().is_loopback() => {
+                debug!("Setting auto-supply: {}", auto_supply);
+                #[cfg(not(target_os = "linux"))]
+                if auto_supply {
+                    warn!("Auto-supply only reclaims detached devices on this platform");
+                }
+                SupplierState::set_auto_supply(auto_supply);
+                SupplierResponse::AutoSupplySet { auto_supply }.to_json()
+            }
+
             SupplierRequest::MarketMakerTerminating if is_market_maker() => {
                 thread::spawn(Supplier::market_maker_terminate);
                 SupplierResponse::TerminationAcknowledged.to_json()
@@ -808,10 +1115,7 @@ impl Supplier {
                 let port = Supplier::start_forwarder(&device_id, port, pub_key);
                 match port {
                     Ok(port) => SupplierResponse::SecureTunnelStarted { port }.to_json(),
-                    Err(e) => SupplierResponse::SecureTunnelStartFailure {
-                        reason: e.to_string(),
-                    }
-                    .to_json(),
+                    Err(e) => SupplierResponse::SecureTunnelStartFailure { reason: e }.to_json(),
                 }
             }
 
@@ -843,11 +1147,11 @@ impl Supplier {
                     }
                     Err(e) if e.kind() == io::ErrorKind::AddrInUse => {
                         SupplierResponse::ScrcpyTunnelFailure {
-                            reason: "Unable to allocate the required port on Supplier side.\nPlease try again.".to_string(),
+                            reason: Error::Io("Unable to allocate the required port on Supplier side.\nPlease try again.".to_string()),
                         }.to_json()
                     }
                     Err(e) => SupplierResponse::ScrcpyTunnelFailure {
-                        reason: e.to_string(),
+                        reason: e.into(),
                     }.to_json(),
                 }
             }
@@ -44,3 +44,27 @@ async fn test_status() {
         }
     }
 }
+
+#[tokio::test]
+#[serial]
+async fn test_set_auto_supply() {
+    test_with_logs();
+    SupplierState::reset_state();
+    assert!(!SupplierState::get_auto_supply());
+
+    let (addr, id) = test_utils::get_peer_with_key(&vec![255u8; 32]);
+    let request = SupplierRequest::SetAutoSupply { auto_supply: true };
+    let response = task::spawn_blocking(move || Supplier::process_request(request, addr, id))
+        .await
+        .unwrap();
+
+    let response = SupplierResponse::from_str(&response).unwrap();
+    assert!(matches!(
+        response,
+        SupplierResponse::AutoSupplySet { auto_supply: true }
+    ));
+    assert!(SupplierState::get_auto_supply());
+    assert!(SupplierState::get_min_state().auto_supply);
+
+    SupplierState::reset_state();
+}
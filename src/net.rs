@@ -2,8 +2,10 @@ use lazy_static::lazy_static;
 use log::{debug, error, info, trace, warn};
 use snow::TransportState;
 use socket2::{Domain, Socket, Type};
+use std::collections::{HashMap, HashSet};
 use std::io;
-use std::net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs};
+use std::str::FromStr;
 use std::sync::{
     mpsc::{self, Receiver, Sender, TryRecvError},
     Arc, Mutex,
@@ -16,59 +18,163 @@ use tokio::{
         tcp::{OwnedReadHalf, OwnedWriteHalf},
         TcpListener, TcpStream,
     },
+    runtime::{Builder, Runtime},
+    sync::Mutex as AsyncMutex,
     task,
     time::timeout,
 };
 
-use crate::market::{request::ToJson, Key, SystemKeypair};
+mod client;
+
+/// Error returned by the typed helpers of the clients. It is [`crate::Error`],
+/// so the category of a failure reported by a node is kept.
+pub use crate::error::Error as AdborcError;
+pub use client::{ConsumerClient, MarketMakerClient, SupplierClient};
+
+use crate::market::{
+    request::{MarketMakerRequest, MarketMakerResponse, Response, ToJson},
+    EventTopic, Key, MarketEvent, SystemKeypair,
+};
 use crate::noise::Noise;
 use crate::util::{ADB_KILL_SERVER_COMMAND, CONNECTION_TIMEOUT};
 
-/// Networking client which is used to send encrypted commands,
-/// receive encrypted responses from the server and decrypt them.
-/// The frontend application will use this to interact with the running system,
-/// and the system will use it to communicate with each other.
-/// NOTE: System above refers to an adborc server operating in any of the modes.
-pub struct TCPClient {
+pub(crate) type StopSender = Mutex<Option<Sender<()>>>;
+/// Sender of the messages streamed to a client. See [`open_stream`].
+pub(crate) type StreamSender = tokio::sync::mpsc::Sender<String>;
+type StreamReceiver = tokio::sync::mpsc::Receiver<String>;
+
+/// Number of messages buffered for a streaming client, before the sender
+/// sees the stream as full.
+pub(crate) const STREAM_CAPACITY: usize = 256;
+
+lazy_static! {
+    static ref STOP_SENDER: StopSender = Mutex::new(None);
+    /// Receivers of the messages to be streamed to clients, keyed by the
+    /// address of the client. See [`open_stream`].
+    static ref STREAMS: Mutex<HashMap<SocketAddr, StreamReceiver>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Keep the connection of the client at `peer_addr` open, after the response
+/// to its current request is sent, and stream the messages sent on the
+/// returned channel to the client. The stream ends when the sender is dropped
+/// or the client closes the connection. At most `STREAM_CAPACITY` messages are
+/// buffered, so a client that stops reading cannot make the server buffer
+/// messages forever.
+pub(crate) fn open_stream(peer_addr: SocketAddr) -> StreamSender {
+    let (tx, rx) = tokio::sync::mpsc::channel(STREAM_CAPACITY);
+    STREAMS.lock().unwrap().insert(peer_addr, rx);
+    tx
+}
+
+fn take_stream(peer_addr: &SocketAddr) -> Option<StreamReceiver> {
+    STREAMS.lock().unwrap().remove(peer_addr)
+}
+
+/// Connection to a server, along with its Noise transport.
+struct Connection {
+    stream: TcpStream,
+    noise: TransportState,
+    buf: Vec<u8>,
+}
+
+impl Connection {
+    /// Connect to the server and perform the Noise handshake.
+    async fn open(addr: SocketAddr) -> io::Result<Connection> {
+        let mut stream = match timeout(CONNECTION_TIMEOUT, TcpStream::connect(addr)).await {
+            Ok(stream) => stream,
+            Err(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "Connection failed/timed out",
+                ))
+            }
+        }?;
+        debug!("Connection established");
+
+        let noise = match SystemKeypair::get_private_key() {
+            Some(private_key) => Noise::build_initiator_with_key(private_key)?,
+            None => Noise::build_initiator()?.0,
+        };
+
+        // Allocate a buffer of 65536 bytes.
+        let mut buf = vec![0u8; 65536];
+        let noise = Noise::initiator_handshake(noise, &mut stream, &mut buf).await?;
+        Ok(Connection { stream, noise, buf })
+    }
+
+    async fn send(&mut self, data: &str) -> io::Result<()> {
+        Noise::encrypt_and_send(
+            &mut self.noise,
+            &mut self.stream,
+            data.as_bytes(),
+            &mut self.buf,
+        )
+        .await
+    }
+
+    async fn recv(&mut self) -> io::Result<String> {
+        Noise::decrypt_message(&mut self.noise, &mut self.stream, &mut self.buf).await
+    }
+
+    /// Check, without waiting, whether the server closed the connection.
+    /// No data is expected on an idle connection, so a connection with data
+    /// to read cannot be reused either.
+    fn is_closed(&self) -> bool {
+        match self.stream.try_read(&mut [0u8; 1]) {
+            Ok(_) => true,
+            Err(e) => e.kind() != io::ErrorKind::WouldBlock,
+        }
+    }
+
+    /// Send the data and wait for the response.
+    async fn request(&mut self, data: &str, linger: Option<Duration>) -> io::Result<String> {
+        self.stream.set_linger(linger)?;
+        self.send(data).await?;
+        self.recv().await
+    }
+}
+
+/// Asynchronous networking client, for applications that already run a Tokio runtime.
+/// Sends the same encrypted commands as [`TCPClient`], but its methods run on the
+/// runtime of the caller. The connection to the server is kept alive, and reused
+/// for the following requests.
+pub struct AsyncClient {
     /// The socket address of the server.
-    pub(crate) addr: SocketAddr,
+    addr: SocketAddr,
     /// Hostname or IP of the server.
     host: String,
     /// Port number of the server.
     port: u16,
+    /// Connection kept alive between requests.
+    connection: AsyncMutex<Option<Connection>>,
 }
 
-pub(crate) type StopSender = Mutex<Option<Sender<()>>>;
-
-lazy_static! {
-    static ref STOP_SENDER: StopSender = Mutex::new(None);
-}
-
-impl From<SocketAddr> for TCPClient {
+impl From<SocketAddr> for AsyncClient {
     fn from(addr: SocketAddr) -> Self {
         Self {
             addr,
             host: addr.ip().to_string(),
             port: addr.port(),
+            connection: AsyncMutex::new(None),
         }
     }
 }
 
-impl TCPClient {
-    /// Create a new TCPClient with the given destination host and port.
-    #[tokio::main]
-    pub async fn new(host: &str, port: u16) -> io::Result<TCPClient> {
+impl AsyncClient {
+    /// Create a new AsyncClient with the given destination host and port.
+    pub async fn new(host: &str, port: u16) -> io::Result<AsyncClient> {
         let addr = parse_addr(host, port, false).await?;
-        Ok(TCPClient {
+        Ok(AsyncClient {
             addr,
             host: host.to_string(),
             port,
+            connection: AsyncMutex::new(None),
         })
     }
 
     /// Check if the destination server is running and return the TCPstream if it is running.
-    #[tokio::main]
-    pub(crate) async fn test_connect(&self) -> io::Result<TcpStream> {
+    pub async fn test_connect(&self) -> io::Result<TcpStream> {
         let timeout_result = timeout(CONNECTION_TIMEOUT, TcpStream::connect(self.addr)).await;
         match timeout_result {
             Ok(stream) => stream,
@@ -80,110 +186,279 @@ impl TCPClient {
     }
 
     /// Send any string to the server with the given timeout and return the response, if received.
-    #[tokio::main]
+    /// The data is sent on the kept alive connection. If the server already closed that
+    /// connection, the data is sent on a new connection instead. The data is never sent
+    /// twice, so an error is returned if the connection fails after the data is sent.
     pub async fn send(&self, data: &str, timeout_in_sec: Option<u64>) -> io::Result<String> {
         debug!(
             "Request received to send data: {}\t\tto host: {}\tat port: {}",
             data, self.host, self.port
         );
-        let mut stream = match timeout(CONNECTION_TIMEOUT, TcpStream::connect(self.addr)).await {
-            Ok(stream) => stream,
-            Err(_) => {
-                return Err(io::Error::new(
-                    io::ErrorKind::TimedOut,
-                    "Connection failed/timed out",
-                ))
+        let linger = timeout_in_sec.map(|t| Duration::new(t, 0));
+        let mut connection = self.connection.lock().await;
+        let mut open_connection = match connection.take() {
+            Some(kept_alive) if !kept_alive.is_closed() => kept_alive,
+            Some(_) => {
+                debug!("Kept alive connection closed by the server");
+                Connection::open(self.addr).await?
             }
-        }?;
-        debug!("Connection established");
-        let timeout = timeout_in_sec.map(|t| Duration::new(t, 0));
-        stream.set_linger(timeout)?;
-
-        let private_key = SystemKeypair::get_private_key();
-        let noise;
-
-        if let Some(private_key) = private_key {
-            noise = Noise::build_initiator_with_key(private_key)?;
-        } else {
-            (noise, _) = Noise::build_initiator()?;
-        }
-
-        // Allocate a buffer of 65536 bytes.
-        let mut buf = [0u8; 65536];
-        let mut noise = Noise::initiator_handshake(noise, &mut stream, &mut buf).await?;
-        Noise::encrypt_and_send(&mut noise, &mut stream, data.as_bytes(), &mut buf).await?;
-        let response = Noise::decrypt_message(&mut noise, &mut stream, &mut buf).await?;
+            None => Connection::open(self.addr).await?,
+        };
+        // A connection that failed is dropped, and a new one is opened for the next request.
+        let response = open_connection.request(data, linger).await?;
+        *connection = Some(open_connection);
         Ok(response)
     }
 
     /// Send a [`crate::market::request::Request`] to the server with the given timeout and
     /// return the response, if received.
-    pub fn send_request<T>(&self, request: T, timeout_in_sec: Option<u64>) -> io::Result<String>
+    pub async fn send_request<T>(
+        &self,
+        request: T,
+        timeout_in_sec: Option<u64>,
+    ) -> io::Result<String>
     where
         T: ToJson,
     {
-        // Unwrapping is safe here because we are using a known enum variant
-        // which is guaranteed to be serializable.
         let request = request.to_json();
-        self.send(request.as_str(), timeout_in_sec)
+        self.send(request.as_str(), timeout_in_sec).await
+    }
+
+    /// Send a [`crate::market::request::Request`] to the server on a new connection, and
+    /// keep that connection open. Returns the response to the request, and the stream
+    /// of the messages sent afterwards by the server.
+    pub async fn open_stream<T>(&self, request: T) -> io::Result<(String, AsyncMessageStream)>
+    where
+        T: ToJson,
+    {
+        let request = request.to_json();
+        debug!(
+            "Opening stream with request: {}\t\tto host: {}\tat port: {}",
+            request, self.host, self.port
+        );
+        let mut connection = Connection::open(self.addr).await?;
+        let response = connection.request(&request, None).await?;
+        Ok((response, AsyncMessageStream { connection }))
+    }
+
+    /// Subscribe to the events of the given topics on the MarketMaker.
+    /// All topics are streamed, if `topics` is empty.
+    pub async fn subscribe(&self, topics: HashSet<EventTopic>) -> io::Result<AsyncEventStream> {
+        let (response, messages) = self
+            .open_stream(MarketMakerRequest::Subscribe { topics })
+            .await?;
+        let response = Response::from_str(&response)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        match response {
+            Response::MarketMaker(MarketMakerResponse::Subscribed) => {
+                Ok(AsyncEventStream { messages })
+            }
+            response => Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                response.to_string(),
+            )),
+        }
     }
 
     /// Send a [`crate::market::request::Request`] to the server without waiting for a response.
-    #[tokio::main]
+    /// The request is sent on a new connection, as the response is never read.
     pub async fn send_no_wait<T>(&self, data: T)
     where
         T: ToJson,
     {
-        // Unwrapping is safe here because we are using a known enum variant
-        // which is guaranteed to be serializable.
         let data = data.to_json();
         debug!(
             "Sending data: {}\t\tto host: {}\tat port: {}",
             data, self.host, self.port
         );
-        let stream = match timeout(CONNECTION_TIMEOUT, TcpStream::connect(self.addr)).await {
-            Ok(stream) => stream,
-            Err(_) => {
-                error!("Connection timed out");
-                return;
-            }
-        };
-        if let Ok(mut stream) = stream {
-            debug!("Connection established");
-            let private_key = SystemKeypair::get_private_key();
-            let noise;
-            if let Some(private_key) = private_key {
-                let result = Noise::build_initiator_with_key(private_key);
-                if result.is_err() {
-                    error!("Error while building initiator: {}", result.err().unwrap());
-                    return;
-                }
-                noise = result.unwrap();
-            } else {
-                let result = Noise::build_initiator();
-                if result.is_err() {
-                    error!("Error while building initiator: {}", result.err().unwrap());
-                    return;
-                }
-                (noise, _) = result.unwrap();
-            }
+        let connection = Connection::open(self.addr).await;
+        if connection.is_err() {
+            error!("Connection failed: {}", connection.err().unwrap());
+            return;
+        }
+        let result = connection.unwrap().send(&data).await;
+        if result.is_err() {
+            error!("Error while sending message: {}", result.err().unwrap());
+        }
+    }
+}
 
-            // Allocate a buffer of 65536 bytes.
-            let mut buf = [0u8; 65536];
-            let noise = Noise::initiator_handshake(noise, &mut stream, &mut buf).await;
-            if noise.is_err() {
-                error!("Error while performing handshake: {}", noise.err().unwrap());
-                return;
+/// Stream of messages sent by the server on a connection kept open
+/// with [`AsyncClient::open_stream`].
+pub struct AsyncMessageStream {
+    connection: Connection,
+}
+
+impl AsyncMessageStream {
+    /// Wait for the next message. Returns `None` when the connection is closed.
+    pub async fn recv(&mut self) -> Option<String> {
+        match self.connection.recv().await {
+            Ok(message) => Some(message),
+            Err(e) => {
+                debug!("Message stream closed: {}", e);
+                None
             }
-            let mut noise = noise.unwrap();
-            let result =
-                Noise::encrypt_and_send(&mut noise, &mut stream, data.as_bytes(), &mut buf).await;
-            if result.is_err() {
-                error!("Error while sending message: {}", result.err().unwrap());
+        }
+    }
+}
+
+/// Stream of the events of a MarketMaker, returned by [`AsyncClient::subscribe`].
+pub struct AsyncEventStream {
+    messages: AsyncMessageStream,
+}
+
+impl AsyncEventStream {
+    /// Wait for the next event. Returns `None` when the MarketMaker closes the connection.
+    pub async fn recv(&mut self) -> Option<MarketEvent> {
+        while let Some(message) = self.messages.recv().await {
+            match MarketEvent::from_str(&message) {
+                Ok(event) => return Some(event),
+                Err(e) => warn!("Skipping unknown event: {}\t{}", message, e),
             }
-        } else {
-            error!("Connection failed");
         }
+        None
+    }
+}
+
+/// Stream of messages sent by the server on a connection kept open
+/// with [`TCPClient::open_stream`]. Iterating blocks until the next message
+/// is received, and ends when the connection is closed.
+pub struct MessageStream {
+    runtime: Runtime,
+    messages: AsyncMessageStream,
+}
+
+impl Iterator for MessageStream {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        self.runtime.block_on(self.messages.recv())
+    }
+}
+
+/// Stream of the events of a MarketMaker, returned by [`TCPClient::subscribe`].
+/// Iterating blocks until the next event is received, and ends when the
+/// MarketMaker closes the connection.
+pub struct EventStream {
+    runtime: Runtime,
+    events: AsyncEventStream,
+}
+
+impl Iterator for EventStream {
+    type Item = MarketEvent;
+
+    fn next(&mut self) -> Option<MarketEvent> {
+        self.runtime.block_on(self.events.recv())
+    }
+}
+
+/// Networking client which is used to send encrypted commands,
+/// receive encrypted responses from the server and decrypt them.
+/// The frontend application will use this to interact with the running system,
+/// and the system will use it to communicate with each other.
+/// NOTE: System above refers to an adborc server operating in any of the modes.
+///
+/// Each call blocks on a runtime of its own, and uses a new connection. Use
+/// [`AsyncClient`] from within a Tokio runtime.
+pub struct TCPClient {
+    /// The socket address of the server.
+    pub(crate) addr: SocketAddr,
+    /// Hostname or IP of the server.
+    host: String,
+    /// Port number of the server.
+    port: u16,
+}
+
+impl From<SocketAddr> for TCPClient {
+    fn from(addr: SocketAddr) -> Self {
+        Self {
+            addr,
+            host: addr.ip().to_string(),
+            port: addr.port(),
+        }
+    }
+}
+
+impl TCPClient {
+    /// Create a new TCPClient with the given destination host and port.
+    #[tokio::main]
+    pub async fn new(host: &str, port: u16) -> io::Result<TCPClient> {
+        let addr = parse_addr(host, port, false).await?;
+        Ok(TCPClient {
+            addr,
+            host: host.to_string(),
+            port,
+        })
+    }
+
+    /// The connection of an [`AsyncClient`] belongs to the runtime it was opened on.
+    /// As every call of the TCPClient runs on a runtime of its own, a new AsyncClient
+    /// is used for every call.
+    fn async_client(&self) -> AsyncClient {
+        AsyncClient {
+            addr: self.addr,
+            host: self.host.clone(),
+            port: self.port,
+            connection: AsyncMutex::new(None),
+        }
+    }
+
+    fn runtime() -> io::Result<Runtime> {
+        Builder::new_current_thread().enable_all().build()
+    }
+
+    /// Check if the destination server is running and return the TCPstream if it is running.
+    #[tokio::main]
+    pub(crate) async fn test_connect(&self) -> io::Result<TcpStream> {
+        self.async_client().test_connect().await
+    }
+
+    /// Send any string to the server with the given timeout and return the response, if received.
+    #[tokio::main]
+    pub async fn send(&self, data: &str, timeout_in_sec: Option<u64>) -> io::Result<String> {
+        self.async_client().send(data, timeout_in_sec).await
+    }
+
+    /// Send a [`crate::market::request::Request`] to the server with the given timeout and
+    /// return the response, if received.
+    pub fn send_request<T>(&self, request: T, timeout_in_sec: Option<u64>) -> io::Result<String>
+    where
+        T: ToJson,
+    {
+        // Unwrapping is safe here because we are using a known enum variant
+        // which is guaranteed to be serializable.
+        let request = request.to_json();
+        self.send(request.as_str(), timeout_in_sec)
+    }
+
+    /// Send a [`crate::market::request::Request`] to the server and keep the connection
+    /// open. Returns the response to the request, and the stream of the messages
+    /// sent afterwards by the server.
+    pub fn open_stream<T>(&self, request: T) -> io::Result<(String, MessageStream)>
+    where
+        T: ToJson,
+    {
+        // The stream outlives this call, so it keeps the runtime it was opened on.
+        let runtime = Self::runtime()?;
+        let (response, messages) = runtime.block_on(self.async_client().open_stream(request))?;
+        Ok((response, MessageStream { runtime, messages }))
+    }
+
+    /// Subscribe to the events of the given topics on the MarketMaker.
+    /// All topics are streamed, if `topics` is empty.
+    pub fn subscribe(&self, topics: HashSet<EventTopic>) -> io::Result<EventStream> {
+        let runtime = Self::runtime()?;
+        let events = runtime.block_on(self.async_client().subscribe(topics))?;
+        Ok(EventStream { runtime, events })
+    }
+
+    /// Send a [`crate::market::request::Request`] to the server without waiting for a response.
+    #[tokio::main]
+    pub async fn send_no_wait<T>(&self, data: T)
+    where
+        T: ToJson,
+    {
+        self.async_client().send_no_wait(data).await
     }
 }
 
@@ -207,17 +482,28 @@ impl CommandServer {
             debug!("Public key is: {}", base64::encode(&keypair.public));
             SystemKeypair::set_keypair(keypair);
         }
-        let socket = Socket::new(Domain::IPV4, Type::STREAM, None)?;
-        socket.set_reuse_address(true)?;
-        socket.set_nonblocking(true)?;
         let address = parse_addr(&self.host, self.port, false).await?;
-        socket.bind(&address.into())?;
-        socket.listen(1024)?;
-        let listener: std::net::TcpListener = socket.into();
-        let listener = TcpListener::from_std(listener)?;
+        let listener = bind_listener(address, 1024)?;
+        // Requests from local clients (e.g. the CLI) are only accepted over loopback,
+        // and local clients connect to 127.0.0.1. If the server is bound to any other
+        // address, e.g. of a VLAN interface or 127.0.0.2, also listen on 127.0.0.1,
+        // so that local clients can still reach the server.
+        let local_address = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), self.port);
+        let local_listener = if address.ip().is_unspecified() || address == local_address {
+            None
+        } else {
+            Some(bind_listener(local_address, 1024)?)
+        };
         debug!("Server started on {}:{}", self.host, self.port);
         loop {
-            if let Ok((stream, _)) = listener.accept().await {
+            let accepted = match &local_listener {
+                Some(local_listener) => tokio::select! {
+                    accepted = listener.accept() => accepted,
+                    accepted = local_listener.accept() => accepted,
+                },
+                None => listener.accept().await,
+            };
+            if let Ok((stream, _)) = accepted {
                 task::spawn(async move {
                     debug!("Connection established");
                     if let Ok(()) = stream.readable().await {
@@ -270,6 +556,10 @@ impl CommandServer {
         let peer_addr = stream
             .peer_addr()
             .expect("Unable to get peer address of stream");
+        // Dual-stack listeners see IPv4 clients as IPv4-mapped IPv6 addresses.
+        // Use the plain IPv4 address, so that loopback checks and peer hosts
+        // work the same for both kinds of listeners.
+        let peer_addr = SocketAddr::new(peer_addr.ip().to_canonical(), peer_addr.port());
         let noise = Noise::responder_handshake(noise, &mut stream, &mut buf, init_msg).await;
         if noise.is_err() {
             error!(
@@ -312,10 +602,58 @@ impl CommandServer {
                     peer_addr,
                     result.err().unwrap()
                 );
+                // Drop the stream opened by the request, if any.
+                take_stream(&peer_addr);
                 return;
             }
             debug!("Response sent to client: {}", peer_addr);
+            if let Some(rx) = take_stream(&peer_addr) {
+                Self::stream_messages(stream, noise, rx, peer_addr).await;
+                return;
+            }
+        }
+    }
+
+    /// Send the messages received on `rx` to the client, until the channel
+    /// or the connection is closed.
+    async fn stream_messages(
+        mut stream: TcpStream,
+        mut noise: TransportState,
+        mut rx: StreamReceiver,
+        peer_addr: SocketAddr,
+    ) {
+        debug!("Streaming messages to client: {}", peer_addr);
+        let mut buf = [0_u8; 65535];
+        // The client does not send anything on a stream. Reading only
+        // detects when the client closes the connection.
+        let mut probe = [0_u8; 1];
+        loop {
+            tokio::select! {
+                message = rx.recv() => {
+                    let message = match message {
+                        Some(message) => message,
+                        None => break,
+                    };
+                    let result =
+                        Noise::encrypt_and_send(&mut noise, &mut stream, message.as_bytes(), &mut buf)
+                            .await;
+                    if result.is_err() {
+                        warn!(
+                            "Error streaming message to client: {}\t{}",
+                            peer_addr,
+                            result.err().unwrap()
+                        );
+                        break;
+                    }
+                }
+                read = stream.read(&mut probe) => {
+                    if !matches!(read, Ok(n) if n > 0) {
+                        break;
+                    }
+                }
+            }
         }
+        debug!("Stream closed for client: {}", peer_addr);
     }
     pub async fn stop(&self) {
         if let Some(tx) = Self::get_tx() {
@@ -324,7 +662,7 @@ impl CommandServer {
                     debug!("Sent stop signal to server");
                     // Server checks for stop signal only when a new connection is received.
                     // Force a new connection to be established to stop the server.
-                    if let Err(e) = TcpStream::connect(format!("127.0.0.1:{}", self.port)).await {
+                    if let Err(e) = connect_local(self.port).await {
                         error!("Error connecting to server: {}", e);
                         error!("Error establishing connection for stopping the server. Maybe the server is already stopped.");
                     };
@@ -411,8 +749,8 @@ impl PortForwarder {
             src_port, dst_host, dst_port
         );
 
-        // In Decrypt and PlainTextAll mode, we are listening on all interfaces because the
-        // source is a remote client sending encrypted data. For both the other
+        // In Decrypt and PlainTextAll mode, we are listening on all interfaces (IPv4 and IPv6)
+        // because the source is a remote client sending encrypted data. For both the other
         // modes, we are listening on localhost only.
         let src_ip = if mode == PortForwardMode::Decrypt || mode == PortForwardMode::PlainTextAll {
             IpAddr::V4(Ipv4Addr::UNSPECIFIED)
@@ -510,10 +848,7 @@ impl PortForwarder {
                     debug!("Sent stop signal to portforwarder");
                     // Portforwader checks for stop signal only when a new connection is received.
                     // Force a new connection to be established to stop the server.
-                    if TcpStream::connect(format!("127.0.0.1:{}", self.src_port))
-                        .await
-                        .is_err()
-                    {
+                    if connect_local(self.src_port).await.is_err() {
                         warn!(
                             "Error establishing connection for stopping the Portforwader. Maybe the server is already stopped.");
                     };
@@ -542,13 +877,7 @@ impl PortForwarder {
         peer_key: Option<Key>,
         mode: PortForwardMode,
     ) -> io::Result<()> {
-        let socket = Socket::new(Domain::IPV4, Type::STREAM, None)?;
-        socket.set_reuse_address(true)?;
-        socket.set_nonblocking(true)?;
-        socket.bind(&src_addr.into())?;
-        socket.listen(128)?;
-        let listener: std::net::TcpListener = socket.into();
-        let listener = TcpListener::from_std(listener)?;
+        let listener = bind_listener(src_addr, 128)?;
         // Let the port forwarder know that the listener is ready.
         started_tx.send(()).map_err(|e| {
             error!("Error sending started signal to portforwarder: {}", e);
@@ -903,23 +1232,66 @@ impl PortForwarder {
     }
 }
 
+/// Bind a listening socket to `address`.
+/// The IPv4 unspecified address (0.0.0.0) binds a dual-stack socket on `[::]`, so that
+/// both IPv4 and IPv6 clients can connect. If IPv6 is not available on the system,
+/// this falls back to listening on IPv4 only.
+fn bind_listener(address: SocketAddr, backlog: i32) -> io::Result<TcpListener> {
+    if address.ip() == IpAddr::V4(Ipv4Addr::UNSPECIFIED) {
+        let dual_stack_address = SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), address.port());
+        match bind_socket(dual_stack_address, backlog) {
+            Ok(listener) => return Ok(listener),
+            Err(e) => debug!(
+                "Unable to listen on {}, using IPv4 only: {}",
+                dual_stack_address, e
+            ),
+        }
+    }
+    bind_socket(address, backlog)
+}
+
+fn bind_socket(address: SocketAddr, backlog: i32) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(address), Type::STREAM, None)?;
+    if address.is_ipv6() && address.ip().is_unspecified() {
+        socket.set_only_v6(false)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&address.into())?;
+    socket.listen(backlog)?;
+    let listener: std::net::TcpListener = socket.into();
+    TcpListener::from_std(listener)
+}
+
+/// Connect to a listener on the local system, over IPv4 or IPv6 loopback.
+async fn connect_local(port: u16) -> io::Result<TcpStream> {
+    match TcpStream::connect((Ipv4Addr::LOCALHOST, port)).await {
+        Ok(stream) => Ok(stream),
+        Err(_) => TcpStream::connect((Ipv6Addr::LOCALHOST, port)).await,
+    }
+}
+
 /// Utility function to convert a string to a socket address.
+/// IPv6 addresses may be enclosed in brackets, e.g. `[2001:db8::1]`.
+/// If a domain name resolves to both IPv4 and IPv6 addresses, IPv4 addresses are tried first.
 pub(crate) async fn parse_addr(host: &str, port: u16, lookup_dst: bool) -> io::Result<SocketAddr> {
+    let host = host
+        .strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+        .unwrap_or(host);
     // Try to parse the host as an IP address.
     if let Ok(ip) = host.parse::<IpAddr>() {
         return Ok(SocketAddr::new(ip, port));
     }
     // Try to resolve the host as a domain name.
     let addr = format!("{}:{}", host, port);
-    let addrs = match addr.to_socket_addrs() {
-        Ok(addrs) => addrs,
+    let mut addrs = match addr.to_socket_addrs() {
+        Ok(addrs) => addrs.collect::<Vec<_>>(),
         Err(e) => return Err(e),
     };
+    addrs.sort_by_key(|addr| addr.is_ipv6());
     let mut last_err = None;
     for addr in addrs {
-        if addr.is_ipv6() {
-            continue;
-        }
         if !lookup_dst {
             return Ok(addr);
         }
@@ -940,6 +1312,41 @@ pub(crate) async fn parse_addr(host: &str, port: u16, lookup_dst: bool) -> io::R
     }))
 }
 
+/// Split an address of the form `host`, `host:port` or `[ipv6]:port` into its host and port.
+/// A bare IPv6 address is taken as a host. `default_port` is used if no port is specified.
+pub fn split_host_port(addr: &str, default_port: u16) -> io::Result<(String, u16)> {
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid address: {}", addr),
+        )
+    };
+    if let Some(rest) = addr.strip_prefix('[') {
+        let (host, port) = rest.split_once(']').ok_or_else(invalid)?;
+        host.parse::<Ipv6Addr>().map_err(|_| invalid())?;
+        let port = match port {
+            "" => default_port,
+            port => port
+                .strip_prefix(':')
+                .and_then(|port| port.parse().ok())
+                .ok_or_else(invalid)?,
+        };
+        return Ok((host.to_string(), port));
+    }
+    if addr.parse::<Ipv6Addr>().is_ok() {
+        return Ok((addr.to_string(), default_port));
+    }
+    match addr.split_once(':') {
+        _ if addr.is_empty() => Err(invalid()),
+        None => Ok((addr.to_string(), default_port)),
+        Some((host, port)) if !host.is_empty() => port
+            .parse()
+            .map(|port| (host.to_string(), port))
+            .map_err(|_| invalid()),
+        Some(_) => Err(invalid()),
+    }
+}
+
 // Unit tests for net module.
 #[cfg(test)]
 mod tests {
@@ -980,6 +1387,89 @@ mod tests {
         }
     }
 
+    // MockStreamingListener. Sends 'Ok' response, followed by a stream of two messages.
+    // This is an encrypted TCP listener.
+    struct MockStreamingListener {
+        listener: CommandServer,
+    }
+
+    impl MockStreamingListener {
+        fn new(host: &str, port: u16) -> MockStreamingListener {
+            MockStreamingListener {
+                listener: CommandServer {
+                    host: host.into(),
+                    port,
+                },
+            }
+        }
+        async fn start(&mut self) -> io::Result<()> {
+            self.listener.start(Self::process_command).await
+        }
+        fn process_command(command: String, peer_addr: SocketAddr, _key: Arc<Key>) -> String {
+            debug!("Command received by MockStreamingListener: {}", command);
+            let tx = open_stream(peer_addr);
+            tx.try_send("one".to_string()).unwrap();
+            tx.try_send("two".to_string()).unwrap();
+            "Ok".to_string()
+        }
+    }
+
+    // MockPeerAddrListener. Sends the address of the client as response.
+    // This is an encrypted TCP listener.
+    struct MockPeerAddrListener {
+        listener: CommandServer,
+    }
+
+    impl MockPeerAddrListener {
+        fn new(host: &str, port: u16) -> MockPeerAddrListener {
+            MockPeerAddrListener {
+                listener: CommandServer {
+                    host: host.into(),
+                    port,
+                },
+            }
+        }
+        async fn start(&mut self) -> io::Result<()> {
+            self.listener.start(Self::process_command).await
+        }
+        fn process_command(command: String, peer_addr: SocketAddr, _key: Arc<Key>) -> String {
+            debug!("Command received by MockPeerAddrListener: {}", command);
+            peer_addr.to_string()
+        }
+    }
+
+    // MockFailingListener. Counts the commands received, and closes the
+    // connection without a response on the command 'fail'.
+    // This is an encrypted TCP listener.
+    struct MockFailingListener {
+        listener: CommandServer,
+    }
+
+    static FAILING_LISTENER_COMMANDS: std::sync::atomic::AtomicUsize =
+        std::sync::atomic::AtomicUsize::new(0);
+
+    impl MockFailingListener {
+        fn new(host: &str, port: u16) -> MockFailingListener {
+            MockFailingListener {
+                listener: CommandServer {
+                    host: host.into(),
+                    port,
+                },
+            }
+        }
+        async fn start(&mut self) -> io::Result<()> {
+            self.listener.start(Self::process_command).await
+        }
+        fn process_command(command: String, _peer_addr: SocketAddr, _key: Arc<Key>) -> String {
+            debug!("Command received by MockFailingListener: {}", command);
+            FAILING_LISTENER_COMMANDS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            if command == "fail" {
+                panic!("Closing the connection without a response");
+            }
+            "Ok".to_string()
+        }
+    }
+
     // MockListener. Always sends 'Ok' response.
     // This is a plaintext TCP listener.
     struct MockListener {
@@ -1089,6 +1579,108 @@ mod tests {
         };
     }
 
+    #[tokio::test]
+    async fn tcpclient_connect_over_ipv6() {
+        test_with_logs();
+        let listen_port = portpicker::pick_unused_port().unwrap();
+        let mut listener = MockEncryptedListener::new(ServerSpec::host(), listen_port);
+
+        task::spawn(async move { listener.start().await.unwrap() });
+
+        // The listener on 0.0.0.0 is dual-stack, so it accepts both IPv4 and IPv6 clients.
+        for host in ["127.0.0.1", "::1", "[::1]"] {
+            let result = task::spawn_blocking(move || {
+                tcp_client_init_send(host, listen_port, "hello", Some(5))
+            })
+            .await
+            .unwrap();
+            match result {
+                Ok(response) => assert_eq!(&response, "Ok"),
+                Err(err) => panic!("TCP send to {} failed with err:\n{}", host, err),
+            };
+        }
+    }
+
+    // Address of an interface other than loopback, if the host has one.
+    fn interface_addr() -> Option<IpAddr> {
+        let socket = std::net::UdpSocket::bind("0.0.0.0:0").ok()?;
+        // No packet is sent. Connecting only selects the interface of the route.
+        socket.connect("192.0.2.1:9").ok()?;
+        let ip = socket.local_addr().ok()?.ip();
+        (!ip.is_loopback()).then_some(ip)
+    }
+
+    #[tokio::test]
+    async fn tcpclient_connect_to_listener_on_interface() {
+        test_with_logs();
+        let mut hosts = interface_addr()
+            .map(|ip| ip.to_string())
+            .into_iter()
+            .collect::<Vec<_>>();
+        if cfg!(target_os = "linux") {
+            hosts.push("127.0.0.2".to_string());
+        }
+        for host in hosts {
+            let listen_port = portpicker::pick_unused_port().unwrap();
+            let mut listener = MockEncryptedListener::new(&host, listen_port);
+            task::spawn(async move { listener.start().await.unwrap() });
+            tokio::time::sleep(Duration::from_millis(100)).await;
+
+            // Local clients connect to 127.0.0.1, whichever address the listener binds.
+            for client_host in [host.clone(), "127.0.0.1".to_string()] {
+                let result = task::spawn_blocking(move || {
+                    tcp_client_init_send(&client_host, listen_port, "hello", Some(5))
+                })
+                .await
+                .unwrap();
+                match result {
+                    Ok(response) => assert_eq!(&response, "Ok"),
+                    Err(err) => panic!("TCP send to listener on {} failed:\n{}", host, err),
+                };
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn parse_ipv6_addr() {
+        let ipv6_loopback = SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), 16063);
+        assert_eq!(
+            parse_addr("::1", 16063, false).await.unwrap(),
+            ipv6_loopback
+        );
+        assert_eq!(
+            parse_addr("[::1]", 16063, false).await.unwrap(),
+            ipv6_loopback
+        );
+        assert_eq!(
+            parse_addr("127.0.0.1", 16063, false).await.unwrap(),
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 16063)
+        );
+    }
+
+    #[test]
+    fn split_host_port_forms() {
+        let split = |addr| split_host_port(addr, 16063).unwrap();
+        assert_eq!(split("10.0.0.5"), ("10.0.0.5".to_string(), 16063));
+        assert_eq!(split("mm.local:17000"), ("mm.local".to_string(), 17000));
+        assert_eq!(split("::1"), ("::1".to_string(), 16063));
+        assert_eq!(split("[::1]"), ("::1".to_string(), 16063));
+        assert_eq!(
+            split("[2001:db8::1]:17000"),
+            ("2001:db8::1".to_string(), 17000)
+        );
+        for addr in [
+            "",
+            ":17000",
+            "mm.local:",
+            "mm.local:port",
+            "[::1",
+            "[::1]17000",
+        ] {
+            assert!(split_host_port(addr, 16063).is_err(), "{}", addr);
+        }
+    }
+
     #[tokio::test]
     async fn tcpclient_send_with_timeout_none() {
         test_with_logs();
@@ -1399,4 +1991,84 @@ mod tests {
             Err(err) => panic!("TCP send failed with err:\n{}", err),
         };
     }
+
+    #[tokio::test]
+    async fn tcpclient_open_stream() {
+        test_with_logs();
+        let listen_port = portpicker::pick_unused_port().unwrap();
+        let mut listener = MockStreamingListener::new(ServerSpec::host(), listen_port);
+
+        task::spawn(async move { listener.start().await.unwrap() });
+
+        let (response, messages) = task::spawn_blocking(move || {
+            let tcp_client = TCPClient::new("localhost", listen_port).unwrap();
+            let (response, stream) = tcp_client
+                .open_stream(MarketMakerRequest::Subscribe {
+                    topics: HashSet::new(),
+                })
+                .unwrap();
+            // The stream ends once the server drops the sender.
+            (response, stream.collect::<Vec<String>>())
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(response, "Ok");
+        assert_eq!(messages, vec!["one".to_string(), "two".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn asyncclient_reuses_connection() {
+        test_with_logs();
+        let listen_port = portpicker::pick_unused_port().unwrap();
+        let mut listener = MockPeerAddrListener::new(ServerSpec::host(), listen_port);
+
+        task::spawn(async move { listener.start().await.unwrap() });
+        // Let the listener start on the runtime of the test.
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        // Runs on the runtime of the test, without spawning a blocking task.
+        let client = AsyncClient::new("localhost", listen_port).await.unwrap();
+        let first = client
+            .send_request(SysStateRequest::GetState, None)
+            .await
+            .unwrap();
+        let second = client
+            .send_request(SysStateRequest::GetState, None)
+            .await
+            .unwrap();
+        // A new client uses a connection of its own.
+        let other = AsyncClient::new("localhost", listen_port)
+            .await
+            .unwrap()
+            .send_request(SysStateRequest::GetState, None)
+            .await
+            .unwrap();
+
+        assert_eq!(first, second);
+        assert_ne!(first, other);
+    }
+
+    #[tokio::test]
+    async fn asyncclient_does_not_resend_request() {
+        test_with_logs();
+        let listen_port = portpicker::pick_unused_port().unwrap();
+        let mut listener = MockFailingListener::new(ServerSpec::host(), listen_port);
+
+        task::spawn(async move { listener.start().await.unwrap() });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let client = AsyncClient::new("localhost", listen_port).await.unwrap();
+        let first = client.send("hello", None).await;
+        // The connection fails after the request is sent.
+        let failed = client.send("fail", None).await;
+        let commands = FAILING_LISTENER_COMMANDS.load(std::sync::atomic::Ordering::SeqCst);
+        // The next request is sent on a new connection.
+        let after = client.send("hello", None).await;
+
+        assert_eq!(first.unwrap(), "Ok");
+        assert!(failed.is_err());
+        assert_eq!(commands, 2);
+        assert_eq!(after.unwrap(), "Ok");
+    }
 }
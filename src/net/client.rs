@@ -0,0 +1,397 @@
+//! Typed helpers on top of [`TCPClient`] and [`AsyncClient`].
+//!
+//! The helpers send the request of a mode, and return the payload of the
+//! successful response. Responses that report a failure are returned as an
+//! [`Error`], so that callers do not need to match on the nested
+//! response enums themselves.
+
+use super::*;
+use crate::error::Error;
+use crate::market::{
+    request::{
+        ConsumerRequest, ConsumerResponse, ParseResponseError, SupplierRequest, SupplierResponse,
+    },
+    ConsumerStateMin, DeviceFilterVec, DeviceSpec, MarketMakerMinState, Role, SupplierStateMin,
+};
+
+fn unexpected(response: Response) -> Error {
+    Error::Protocol(format!("Unexpected response: {}", response))
+}
+
+/// Parse the response, and convert it into an error if it reports a failure.
+fn into_result(response: &str) -> Result<Response, Error> {
+    let response = Response::from_str(response)
+        .map_err(|_| Error::Protocol(ParseResponseError::BadResponse.to_string()))?;
+    match response.failure() {
+        Some(reason) => Err(reason),
+        None => Ok(response),
+    }
+}
+
+impl AsyncClient {
+    /// Send a [`crate::market::request::Request`] to the server and return the parsed
+    /// response. Responses that report a failure are returned as an error.
+    pub async fn request<T>(&self, request: T) -> Result<Response, Error>
+    where
+        T: ToJson,
+    {
+        into_result(&self.send_request(request, None).await?)
+    }
+}
+
+impl TCPClient {
+    /// Send a [`crate::market::request::Request`] to the server and return the parsed
+    /// response. Responses that report a failure are returned as an error.
+    pub fn request<T>(&self, request: T) -> Result<Response, Error>
+    where
+        T: ToJson,
+    {
+        into_result(&self.send_request(request, None)?)
+    }
+
+    /// Typed requests to the MarketMaker.
+    pub fn marketmaker(&self) -> MarketMakerClient<'_> {
+        MarketMakerClient { client: self }
+    }
+
+    /// Typed requests to the Supplier.
+    pub fn supplier(&self) -> SupplierClient<'_> {
+        SupplierClient { client: self }
+    }
+
+    /// Typed requests to the Consumer.
+    pub fn consumer(&self) -> ConsumerClient<'_> {
+        ConsumerClient { client: self }
+    }
+}
+
+/// Typed requests to a MarketMaker, returned by [`TCPClient::marketmaker`].
+/// Requests for Consumers, e.g. `reserve`, require the network_id of the system
+/// to be connected to the MarketMaker as a Consumer.
+pub struct MarketMakerClient<'a> {
+    client: &'a TCPClient,
+}
+
+impl MarketMakerClient<'_> {
+    fn request(&self, request: MarketMakerRequest) -> Result<MarketMakerResponse, Error> {
+        match self.client.request(request)? {
+            Response::MarketMaker(response) => Ok(response),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Get the status of the MarketMaker.
+    pub fn status(&self) -> Result<MarketMakerMinState, Error> {
+        match self.request(MarketMakerRequest::Status)? {
+            MarketMakerResponse::Status { state } => Ok(state),
+            response => Err(unexpected(Response::MarketMaker(response))),
+        }
+    }
+
+    /// Add a Supplier to the whitelist.
+    pub fn add_supplier(&self, key: &str) -> Result<(), Error> {
+        let key = key.to_string();
+        match self.request(MarketMakerRequest::WhitelistSupplier { key })? {
+            MarketMakerResponse::WhitelistSupplierSuccess => Ok(()),
+            response => Err(unexpected(Response::MarketMaker(response))),
+        }
+    }
+
+    /// Add a Consumer to the whitelist.
+    pub fn add_consumer(&self, key: &str) -> Result<(), Error> {
+        let key = key.to_string();
+        match self.request(MarketMakerRequest::WhitelistConsumer { key })? {
+            MarketMakerResponse::WhitelistConsumerSuccess => Ok(()),
+            response => Err(unexpected(Response::MarketMaker(response))),
+        }
+    }
+
+    /// Grant a role to a network_id.
+    pub fn grant_role(&self, key: &str, role: Role) -> Result<(), Error> {
+        let key = key.to_string();
+        match self.request(MarketMakerRequest::GrantRole { key, role })? {
+            MarketMakerResponse::GrantRoleSuccess => Ok(()),
+            response => Err(unexpected(Response::MarketMaker(response))),
+        }
+    }
+
+    /// Revoke a role of a network_id.
+    pub fn revoke_role(&self, key: &str, role: Role) -> Result<(), Error> {
+        let key = key.to_string();
+        match self.request(MarketMakerRequest::RevokeRole { key, role })? {
+            MarketMakerResponse::RevokeRoleSuccess => Ok(()),
+            response => Err(unexpected(Response::MarketMaker(response))),
+        }
+    }
+
+    /// Release a reserved device, regardless of the Consumer using it.
+    pub fn force_release(&self, device_id: &str) -> Result<(), Error> {
+        let device_id = device_id.to_string();
+        match self.request(MarketMakerRequest::ForceReleaseDevice { device_id })? {
+            MarketMakerResponse::DeviceReleased => Ok(()),
+            response => Err(unexpected(Response::MarketMaker(response))),
+        }
+    }
+
+    /// Get the devices available for reservation.
+    pub fn available_devices(&self) -> Result<Vec<DeviceSpec>, Error> {
+        match self.request(MarketMakerRequest::GetAvailableDevices)? {
+            MarketMakerResponse::AvailableDevices { devices } => Ok(devices),
+            response => Err(unexpected(Response::MarketMaker(response))),
+        }
+    }
+
+    /// Reserve the device for the system.
+    pub fn reserve(&self, device_id: &str) -> Result<DeviceSpec, Error> {
+        let device_id = device_id.to_string();
+        let request = MarketMakerRequest::ReserveDevice {
+            device_id,
+            lease_secs: None,
+        };
+        match self.request(request)? {
+            MarketMakerResponse::DeviceReserved { device, .. } => Ok(device),
+            response => Err(unexpected(Response::MarketMaker(response))),
+        }
+    }
+
+    /// Release a device reserved by the system.
+    pub fn release(&self, device_id: &str) -> Result<(), Error> {
+        let device_id = device_id.to_string();
+        match self.request(MarketMakerRequest::ReleaseDevice { device_id })? {
+            MarketMakerResponse::DeviceReleased => Ok(()),
+            response => Err(unexpected(Response::MarketMaker(response))),
+        }
+    }
+}
+
+/// Typed requests to the Supplier on the system, returned by [`TCPClient::supplier`].
+pub struct SupplierClient<'a> {
+    client: &'a TCPClient,
+}
+
+impl SupplierClient<'_> {
+    fn request(&self, request: SupplierRequest) -> Result<SupplierResponse, Error> {
+        match self.client.request(request)? {
+            Response::Supplier(response) => Ok(response),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Get the status of the Supplier.
+    pub fn status(&self) -> Result<SupplierStateMin, Error> {
+        match self.request(SupplierRequest::Status)? {
+            SupplierResponse::Status { state } => Ok(state),
+            response => Err(unexpected(Response::Supplier(response))),
+        }
+    }
+
+    /// Supply the devices with the given serials to the network, or all devices
+    /// detected on `ADB` if `devices` is `None`. Returns the supplied devices,
+    /// and the devices that could not be supplied.
+    pub fn supply(
+        &self,
+        devices: Option<Vec<String>>,
+    ) -> Result<(Vec<DeviceSpec>, Vec<DeviceSpec>), Error> {
+        let request = SupplierRequest::SupplyDevices {
+            devices,
+            pools: HashSet::new(),
+        };
+        match self.request(request)? {
+            SupplierResponse::DevicesSupplied {
+                supplied_devices,
+                failed_devices,
+            } => Ok((supplied_devices, failed_devices)),
+            response => Err(unexpected(Response::Supplier(response))),
+        }
+    }
+
+    /// Reclaim a supplied device from the network. If `force` is set, the device
+    /// is reclaimed even if it is used by a Consumer.
+    pub fn reclaim(&self, device_id: &str, force: bool) -> Result<(), Error> {
+        let device_id = device_id.to_string();
+        match self.request(SupplierRequest::ReclaimDevice { device_id, force })? {
+            SupplierResponse::DeviceReclaimed { .. } => Ok(()),
+            response => Err(unexpected(Response::Supplier(response))),
+        }
+    }
+
+    /// Supply attached devices and reclaim detached devices automatically.
+    pub fn set_auto_supply(&self, auto_supply: bool) -> Result<(), Error> {
+        match self.request(SupplierRequest::SetAutoSupply { auto_supply })? {
+            SupplierResponse::AutoSupplySet { .. } => Ok(()),
+            response => Err(unexpected(Response::Supplier(response))),
+        }
+    }
+}
+
+/// Typed requests to the Consumer on the system, returned by [`TCPClient::consumer`].
+pub struct ConsumerClient<'a> {
+    client: &'a TCPClient,
+}
+
+impl ConsumerClient<'_> {
+    fn request(&self, request: ConsumerRequest) -> Result<ConsumerResponse, Error> {
+        match self.client.request(request)? {
+            Response::Consumer(response) => Ok(response),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Get the status of the Consumer.
+    pub fn status(&self) -> Result<ConsumerStateMin, Error> {
+        match self.request(ConsumerRequest::Status)? {
+            ConsumerResponse::Status { state } => Ok(state),
+            response => Err(unexpected(Response::Consumer(response))),
+        }
+    }
+
+    /// Get the devices available for reservation.
+    pub fn available_devices(&self) -> Result<Vec<DeviceSpec>, Error> {
+        match self.request(ConsumerRequest::GetAvailableDevices)? {
+            ConsumerResponse::AvailableDevices { devices } => Ok(devices),
+            response => Err(unexpected(Response::Consumer(response))),
+        }
+    }
+
+    /// Get the available devices that match the filters.
+    pub fn devices_by_filter(&self, filter_vec: DeviceFilterVec) -> Result<Vec<DeviceSpec>, Error> {
+        match self.request(ConsumerRequest::GetDevicesByFilter { filter_vec })? {
+            ConsumerResponse::DevicesByFilter { devices, .. } => Ok(devices),
+            response => Err(unexpected(Response::Consumer(response))),
+        }
+    }
+
+    /// Reserve the device, and set it as the default device for `adb`.
+    pub fn reserve(&self, device_id: &str) -> Result<DeviceSpec, Error> {
+        self.reserve_with_lease(device_id, None)
+    }
+
+    /// Reserve the device with a lease of `lease_secs` seconds, and set it as the
+    /// default device for `adb`. The reservation does not expire if `lease_secs` is `None`.
+    pub fn reserve_with_lease(
+        &self,
+        device_id: &str,
+        lease_secs: Option<u64>,
+    ) -> Result<DeviceSpec, Error> {
+        let request = ConsumerRequest::ReserveDevice {
+            device_id: device_id.to_string(),
+            no_use: false,
+            lease_secs,
+        };
+        match self.request(request)? {
+            ConsumerResponse::DeviceReserved { device } => Ok(device),
+            response => Err(unexpected(Response::Consumer(response))),
+        }
+    }
+
+    /// Renew the lease of a reserved device. Returns the device with its new lease.
+    pub fn renew_lease(&self, device_id: &str, lease_secs: u64) -> Result<DeviceSpec, Error> {
+        let device_id = device_id.to_string();
+        match self.request(ConsumerRequest::RenewLease {
+            device_id,
+            lease_secs,
+        })? {
+            ConsumerResponse::LeaseRenewed { device } => Ok(device),
+            response => Err(unexpected(Response::Consumer(response))),
+        }
+    }
+
+    /// Get the devices reserved by the Consumer.
+    pub fn list_reserved(&self) -> Result<Vec<DeviceSpec>, Error> {
+        Ok(self.status()?.devices.into_values().collect())
+    }
+
+    /// Set the reserved device as the default device for `adb`.
+    pub fn use_device(&self, device_id: &str) -> Result<(), Error> {
+        let device_id = device_id.to_string();
+        match self.request(ConsumerRequest::UseDevice { device_id })? {
+            ConsumerResponse::UseDeviceSuccess { .. } => Ok(()),
+            response => Err(unexpected(Response::Consumer(response))),
+        }
+    }
+
+    /// Release a reserved device.
+    pub fn release(&self, device_id: &str) -> Result<(), Error> {
+        let device_id = device_id.to_string();
+        match self.request(ConsumerRequest::ReleaseDevice { device_id })? {
+            ConsumerResponse::DeviceReleased { .. } => Ok(()),
+            response => Err(unexpected(Response::Consumer(response))),
+        }
+    }
+
+    /// Release all reserved devices.
+    pub fn release_all(&self) -> Result<(), Error> {
+        match self.request(ConsumerRequest::ReleaseAllDevices)? {
+            ConsumerResponse::AllDeviceReleaseSuccess => Ok(()),
+            response => Err(unexpected(Response::Consumer(response))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::market::request::SysStateResponse;
+
+    #[test]
+    fn failures_into_errors() {
+        let not_reserved = ConsumerResponse::DeviceNotReserved {
+            reason: Error::DeviceUnavailable("Device not available".to_string()),
+        }
+        .to_json();
+        let reserved = ConsumerResponse::DeviceReserved {
+            device: DeviceSpec::default(),
+        }
+        .to_json();
+
+        // The reason of the failure keeps its category.
+        assert!(matches!(
+            into_result(&not_reserved),
+            Err(Error::DeviceUnavailable(reason)) if reason == "Device not available"
+        ));
+        assert!(matches!(
+            into_result(&SysStateResponse::RequestNotAllowed.to_json()),
+            Err(Error::NotAuthorized(_))
+        ));
+        assert!(matches!(
+            into_result(&MarketMakerResponse::InvalidRequest {
+                request: "Reserve".to_string()
+            }
+            .to_json()),
+            Err(Error::InvalidInput(reason)) if reason == "Invalid request: Reserve"
+        ));
+        // Failures without a reason are described by the response.
+        assert!(matches!(
+            into_result(&SysStateResponse::StopMarketMakerFailed.to_json()),
+            Err(Error::InvalidState(_))
+        ));
+        assert!(matches!(
+            into_result(
+                &MarketMakerResponse::DeviceBeingUsed {
+                    device_id: "a".to_string()
+                }
+                .to_json()
+            ),
+            Err(Error::DeviceUnavailable(_))
+        ));
+        // Supplying devices only fails if none of the devices were supplied.
+        let partly_supplied = SupplierResponse::DevicesSupplied {
+            supplied_devices: vec![DeviceSpec::default()],
+            failed_devices: vec![DeviceSpec::default()],
+        };
+        assert_eq!(partly_supplied.failure(), None);
+        assert!(matches!(
+            SupplierResponse::DevicesSupplied {
+                supplied_devices: vec![],
+                failed_devices: vec![DeviceSpec::default()],
+            }
+            .failure(),
+            Some(Error::InvalidInput(_))
+        ));
+        assert!(matches!(into_result("Ok"), Err(Error::Protocol(_))));
+        assert!(matches!(
+            into_result(&reserved),
+            Ok(Response::Consumer(ConsumerResponse::DeviceReserved { .. }))
+        ));
+    }
+}
@@ -1,6 +1,10 @@
 use lazy_static::lazy_static;
 use log::{debug, error};
-use snow::{params::NoiseParams, Builder, HandshakeState, Keypair, TransportState};
+use snow::{
+    params::{DHChoice, NoiseParams},
+    resolvers::{CryptoResolver, DefaultResolver},
+    Builder, HandshakeState, Keypair, TransportState,
+};
 use std::io;
 use tokio::{
     io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
@@ -16,6 +20,30 @@ lazy_static! {
 pub struct Noise;
 
 impl Noise {
+    pub fn generate_keypair() -> io::Result<Keypair> {
+        Builder::new(PARAMS.clone())
+            .generate_keypair()
+            .map_err(transform_error)
+    }
+
+    /// Reconstruct the keypair for the given static private key.
+    pub fn keypair_from_private_key(private_key: &[u8]) -> io::Result<Keypair> {
+        let mut dh = DefaultResolver
+            .resolve_dh(&DHChoice::Curve25519)
+            .ok_or_else(|| io::Error::other("Curve25519 not supported"))?;
+        if private_key.len() != dh.priv_len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Private key must be {} bytes long", dh.priv_len()),
+            ));
+        }
+        dh.set(private_key);
+        Ok(Keypair {
+            private: dh.privkey().to_vec(),
+            public: dh.pubkey().to_vec(),
+        })
+    }
+
     pub fn build_initiator() -> io::Result<(HandshakeState, Keypair)> {
         let builder = Builder::new(PARAMS.clone());
         let keypair = builder.generate_keypair().map_err(transform_error)?;
@@ -1,4 +1,6 @@
-use std::time::Duration;
+use std::io::{self, ErrorKind};
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 /// Constants used by the system.
 pub struct SysStateDefaultConfig;
@@ -11,6 +13,76 @@ impl SysStateDefaultConfig {
     pub const BIND_PORT: u16 = 16063;
 }
 
+/// Address the system listener binds to.
+/// The defaults are taken from [`SysStateDefaultConfig`], and can be overridden
+/// by the node config file, which in turn can be overridden by the
+/// `ADBORC_LISTEN_HOST` and `ADBORC_LISTEN_PORT` environment variables.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ListenerConfig {
+    /// Host address for the system listener.
+    pub host: String,
+    /// Port for the system listener.
+    pub port: u16,
+}
+
+impl Default for ListenerConfig {
+    fn default() -> ListenerConfig {
+        ListenerConfig {
+            host: SysStateDefaultConfig::BIND_HOST.to_string(),
+            port: SysStateDefaultConfig::BIND_PORT,
+        }
+    }
+}
+
+impl ListenerConfig {
+    /// Load the listener config from the node config file in the data directory
+    /// and the environment.
+    pub fn load() -> io::Result<ListenerConfig> {
+        let node_config = config::NodeConfig::load(None)?.unwrap_or_default();
+        ListenerConfig::load_for(&node_config, None)
+    }
+
+    /// Load the listener config for a profile of the node config file.
+    /// The listen address of the profile overrides the one of the node config file,
+    /// and is overridden by the environment.
+    pub fn load_for(
+        node_config: &config::NodeConfig,
+        profile: Option<&config::Profile>,
+    ) -> io::Result<ListenerConfig> {
+        let mut config = ListenerConfig::default();
+        if let Some(host) = &node_config.listen_host {
+            config.host = host.clone();
+        }
+        if let Some(port) = node_config.listen_port {
+            config.port = port;
+        }
+        if let Some(profile) = profile {
+            if let Some(host) = &profile.listen_host {
+                config.host = host.clone();
+            }
+            if let Some(port) = profile.listen_port {
+                config.port = port;
+            }
+        }
+        if let Ok(host) = std::env::var(ADBORC_LISTEN_HOST) {
+            if !host.is_empty() {
+                config.host = host;
+            }
+        }
+        if let Ok(port) = std::env::var(ADBORC_LISTEN_PORT) {
+            if !port.is_empty() {
+                config.port = port.parse().map_err(|_| {
+                    io::Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("Invalid {}: {}", ADBORC_LISTEN_PORT, port),
+                    )
+                })?;
+            }
+        }
+        Ok(config)
+    }
+}
+
 /// Minimum `adb` major version supported by the system.
 pub const MIN_ADB_VER: u8 = 41;
 /// Minimum `adb` revision required for `supplier` mode on the system.
@@ -24,11 +96,44 @@ pub const ADBORC_VERSION: &str = env!("CARGO_PKG_VERSION");
 /// Interval used for sending `heartbeat` messages to the marketmaker.
 /// This is used by both `consumer` and `supplier` modes.
 pub(crate) const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+/// Time for which a `supplier` heartbeat waits for the health of the supplied
+/// devices. Devices whose health is not collected in time are reported with
+/// the last health collected.
+pub(crate) const HEALTH_TIMEOUT: Duration = Duration::from_secs(5);
+/// Interval used by `supplier` in auto-supply mode to check for devices
+/// that have been attached or detached.
+pub(crate) const AUTO_SUPPLY_INTERVAL: Duration = Duration::from_secs(5);
 /// Interval used by marketmaker to check for heartbeat messages from
 /// peer systems. If a system does not send a heartbeat message within
 /// this interval, for three consecutive times, it is considered dead
 /// and removed from the network.
 pub(crate) const UNDERTAKER_INTERVAL: Duration = Duration::from_secs(60);
+/// Delay before the first attempt to reconnect to an unreachable MarketMaker.
+/// The delay doubles after every failed attempt, up to `RECONNECT_MAX_DELAY`.
+pub(crate) const RECONNECT_INITIAL_DELAY: Duration = Duration::from_secs(2);
+/// Maximum delay between two attempts to reconnect to the MarketMaker.
+pub(crate) const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(60);
+/// Time after which `supplier` and `consumer` stop trying to reconnect to an
+/// unreachable MarketMaker, and leave the network.
+pub(crate) const RECONNECT_TIMEOUT: Duration = Duration::from_secs(600);
+/// Interval used by a primary marketmaker to check for state changes to
+/// replicate to its standby.
+pub(crate) const REPLICATION_INTERVAL: Duration = Duration::from_secs(1);
+/// Interval at which a primary marketmaker sends its state to its standby,
+/// even if the state has not changed. This is the heartbeat of the primary.
+pub(crate) const PRIMARY_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+/// Time without a heartbeat from the primary marketmaker, after which
+/// the standby takes over as the primary.
+pub(crate) const STANDBY_TAKEOVER_TIMEOUT: Duration = Duration::from_secs(60);
+/// Interval used by marketmaker to check for changes to its state, to write
+/// them to its state store. Changes made within the interval are written at once.
+pub(crate) const PERSIST_INTERVAL: Duration = Duration::from_millis(500);
+/// Interval used by marketmaker to check for changes to its available devices,
+/// to advertise them to its peers.
+pub(crate) const ADVERTISE_INTERVAL: Duration = Duration::from_secs(1);
+/// Interval at which a marketmaker advertises its available devices to its peers,
+/// even if they have not changed. This is the heartbeat of the peering link.
+pub(crate) const PEER_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
 /// Connection timeout for TCP connections.
 pub(crate) const CONNECTION_TIMEOUT: Duration = Duration::from_secs(3);
 /// Byte representation of the string "0009host:kill".
@@ -44,6 +149,89 @@ pub const STDERR_LOGFILE: &str = "adborc_stderr.log";
 pub const PID_FILE: &str = "adborc.pid";
 /// Environment variable to check, if `adb` messages should be logged.
 pub const ADBORC_LOG_ADB: &str = "ADBORC_LOG_ADB";
+/// Environment variable to override the directory where `adborc` persists its data.
+pub const ADBORC_DATA_DIR: &str = "ADBORC_DATA_DIR";
+/// Environment variable to select the backend used for persisting MarketMaker state.
+/// Supported values are `json` (default) and `none` (state is kept in memory only).
+pub const ADBORC_MM_STORE: &str = "ADBORC_MM_STORE";
+/// File name, inside the data directory, of the key file holding the
+/// base64 encoded private key of the system. The `network_id` of the
+/// system is derived from this key.
+pub const KEY_FILE: &str = "network_key";
+/// Environment variable to override the host address of the system listener.
+pub const ADBORC_LISTEN_HOST: &str = "ADBORC_LISTEN_HOST";
+/// Environment variable to override the port of the system listener.
+pub const ADBORC_LISTEN_PORT: &str = "ADBORC_LISTEN_PORT";
+/// File name, inside the data directory, of the persisted MarketMaker state.
+pub const MARKET_MAKER_STATE_FILE: &str = "marketmaker_state.json";
+/// Environment variable to disable restoring the modes of the previous run,
+/// when the system listener starts. Set to `off` to disable.
+pub const ADBORC_RESTORE_MODES: &str = "ADBORC_RESTORE_MODES";
+/// File name, inside the data directory, of the saved modes of the system.
+pub const MODES_FILE: &str = "modes.json";
+
+/// Get the directory where `adborc` persists its data.
+/// Uses the value of `ADBORC_DATA_DIR`, if set. Otherwise, defaults to
+/// `$HOME/.adborc` on unix and `%APPDATA%\adborc` on windows.
+/// The directory is not created by this function.
+pub fn data_dir() -> PathBuf {
+    if let Ok(dir) = std::env::var(ADBORC_DATA_DIR) {
+        if !dir.is_empty() {
+            return PathBuf::from(dir);
+        }
+    }
+    #[cfg(windows)]
+    let base = std::env::var("APPDATA").map(|dir| PathBuf::from(dir).join("adborc"));
+    #[cfg(not(windows))]
+    let base = std::env::var("HOME").map(|dir| PathBuf::from(dir).join(".adborc"));
+    base.unwrap_or_else(|_| std::env::temp_dir().join("adborc"))
+}
+
+/// Get the current time as seconds since UNIX epoch.
+pub fn unix_time_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// Exponential backoff between attempts to reconnect to the MarketMaker.
+#[derive(Debug)]
+pub(crate) struct ReconnectBackoff {
+    delay: Duration,
+    max_delay: Duration,
+    deadline: Instant,
+}
+
+impl ReconnectBackoff {
+    pub(crate) fn new() -> ReconnectBackoff {
+        ReconnectBackoff::with_limits(
+            RECONNECT_INITIAL_DELAY,
+            RECONNECT_MAX_DELAY,
+            RECONNECT_TIMEOUT,
+        )
+    }
+
+    fn with_limits(delay: Duration, max_delay: Duration, timeout: Duration) -> ReconnectBackoff {
+        ReconnectBackoff {
+            delay,
+            max_delay,
+            deadline: Instant::now() + timeout,
+        }
+    }
+
+    /// Get the delay before the next attempt.
+    /// Returns None once the reconnect timeout has elapsed.
+    pub(crate) fn next_delay(&mut self) -> Option<Duration> {
+        let remaining = self.deadline.checked_duration_since(Instant::now())?;
+        if remaining.is_zero() {
+            return None;
+        }
+        let delay = self.delay.min(remaining);
+        self.delay = (self.delay * 2).min(self.max_delay);
+        Some(delay)
+    }
+}
 
 #[allow(dead_code)]
 pub(crate) fn test_with_logs() {
@@ -56,19 +244,45 @@ pub(crate) fn test_with_logs() {
     }
 }
 
+pub mod config;
 mod scrcpy_utils;
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reconnect_backoff() {
+        let mut backoff = ReconnectBackoff::with_limits(
+            Duration::from_secs(2),
+            Duration::from_secs(10),
+            Duration::from_secs(60),
+        );
+        let delays: Vec<u64> = (0..5)
+            .map(|_| backoff.next_delay().unwrap().as_secs())
+            .collect();
+        assert_eq!(delays, vec![2, 4, 8, 10, 10]);
+
+        let mut backoff = ReconnectBackoff::with_limits(
+            Duration::from_secs(2),
+            Duration::from_secs(10),
+            Duration::ZERO,
+        );
+        assert!(backoff.next_delay().is_none());
+    }
+}
+
 /// Utils related to `adb` and `scrcpy`.
 pub mod adb_utils {
 
     use super::*;
+    use crate::error::Error;
     use lazy_static::lazy_static;
     use log::{debug, error};
     use pathsearch;
     use portpicker;
     use serde::{Deserialize, Serialize};
     use std::fmt::{self, Display, Formatter};
-    use std::io::{self, Error, ErrorKind};
     use std::path::PathBuf;
     use std::process::{Child, Command, Stdio};
     use std::sync::Mutex;
@@ -118,7 +332,7 @@ pub mod adb_utils {
     }
 
     /// Struct to hold information about device.
-    #[derive(Serialize, Deserialize, Debug, Default)]
+    #[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
     pub struct DeviceInfo {
         /// Brand of the device.
         pub brand: String,
@@ -128,6 +342,22 @@ pub mod adb_utils {
         pub model: String,
         /// Android Serial of the device.
         pub android_id: String,
+        /// Manufacturer of the device.
+        pub manufacturer: String,
+        /// Android release version, e.g. `13`.
+        pub android_release: String,
+        /// Android SDK level, e.g. `33`.
+        pub sdk_level: Option<u32>,
+        /// ABIs supported by the device, in order of preference.
+        pub abi_list: Vec<String>,
+        /// Screen resolution (width, height) in pixels.
+        pub screen_resolution: Option<(u32, u32)>,
+        /// Screen density in dpi.
+        pub screen_density: Option<u32>,
+        /// Build fingerprint of the device.
+        pub build_fingerprint: String,
+        /// Security patch level, e.g. `2023-05-05`.
+        pub security_patch: String,
     }
 
     impl Display for DeviceInfo {
@@ -140,27 +370,158 @@ pub mod adb_utils {
         }
     }
 
-    // DeviceInfo from String of the form: "Brand: <brand>   Name: <name>   Model: <model>"
-    // Should return: DeviceInfo {
-    //    brand: <brand>,
-    //    name: <name>,
-    //    model: <model>,
-    //    android_id: ""
-    // }
-    // If the string is not in the expected format, return default DeviceInfo.
-    impl From<String> for DeviceInfo {
-        fn from(s: String) -> Self {
+    // Property names used to report values that are not system properties
+    // in the `getprop` dump collected by `get_device_info`.
+    const ANDROID_ID_PROP: &str = "adborc.android_id";
+    const SCREEN_SIZE_PROP: &str = "adborc.screen_size";
+
+    impl DeviceInfo {
+        /// Parse the output of `getprop`, i.e. lines of the form: `[<property>]: [<value>]`.
+        /// Unknown properties and malformed lines are ignored.
+        pub fn from_getprop(output: &str) -> Self {
             let mut device_info = Self::default();
-            let split = s.split("   ").collect::<Vec<&str>>();
-            if split.len() == 3 {
-                device_info.brand = split[0].split(": ").last().unwrap_or_default().to_string();
-                device_info.name = split[1].split(": ").last().unwrap_or_default().to_string();
-                device_info.model = split[2].split(": ").last().unwrap_or_default().to_string();
+            for line in output.lines() {
+                let (property, value) = match line.trim().split_once("]: [") {
+                    Some((property, value)) => (
+                        property.trim_start_matches('['),
+                        value.trim_end_matches(']').trim(),
+                    ),
+                    None => continue,
+                };
+                match property {
+                    "ro.product.brand" => device_info.brand = value.to_string(),
+                    "ro.product.name" => device_info.name = value.to_string(),
+                    "ro.product.model" => device_info.model = value.to_string(),
+                    "ro.product.manufacturer" => device_info.manufacturer = value.to_string(),
+                    "ro.build.version.release" => device_info.android_release = value.to_string(),
+                    "ro.build.version.sdk" => device_info.sdk_level = value.parse().ok(),
+                    "ro.product.cpu.abilist" => {
+                        device_info.abi_list = value
+                            .split(',')
+                            .filter(|abi| !abi.is_empty())
+                            .map(|abi| abi.to_string())
+                            .collect()
+                    }
+                    "ro.sf.lcd_density" => device_info.screen_density = value.parse().ok(),
+                    "ro.build.fingerprint" => device_info.build_fingerprint = value.to_string(),
+                    "ro.build.version.security_patch" => {
+                        device_info.security_patch = value.to_string()
+                    }
+                    ANDROID_ID_PROP => device_info.android_id = value.to_string(),
+                    // Output of `wm size`, e.g. `Physical size: 1080x2400`.
+                    SCREEN_SIZE_PROP => {
+                        device_info.screen_resolution = value
+                            .rsplit(' ')
+                            .next()
+                            .and_then(|size| size.split_once('x'))
+                            .and_then(|(width, height)| {
+                                Some((width.parse().ok()?, height.parse().ok()?))
+                            })
+                    }
+                    _ => {}
+                }
             }
             device_info
         }
     }
 
+    /// Health of a device, as reported by the Supplier of the device.
+    #[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+    pub struct DeviceHealth {
+        /// State of the device in adb, e.g. `device`, `offline` or `unauthorized`.
+        pub adb_state: String,
+        /// Battery level in percent.
+        pub battery_level: Option<u32>,
+        /// Battery temperature in tenths of a degree Celsius.
+        pub battery_temperature: Option<i32>,
+        /// Whether the device is plugged in to a power source.
+        pub charging: Option<bool>,
+        /// Free storage on the `/data` partition in bytes.
+        pub free_storage: Option<u64>,
+        /// Time since the device booted, in seconds.
+        pub uptime: Option<u64>,
+        /// Time (seconds since UNIX epoch) at which the health was collected.
+        pub reported_at: u64,
+    }
+
+    impl Display for DeviceHealth {
+        fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+            write!(f, "adb: {}", self.adb_state)?;
+            if let Some(battery_level) = self.battery_level {
+                write!(f, "   battery: {}%", battery_level)?;
+                if self.charging == Some(true) {
+                    write!(f, " (charging)")?;
+                }
+            }
+            if let Some(temperature) = self.battery_temperature {
+                write!(f, "   temperature: {:.1}°C", temperature as f32 / 10.0)?;
+            }
+            if let Some(free_storage) = self.free_storage {
+                let free_storage = free_storage as f64 / (1024 * 1024 * 1024) as f64;
+                write!(f, "   free storage: {:.1} GiB", free_storage)?;
+            }
+            if let Some(uptime) = self.uptime {
+                let (days, hours, minutes) =
+                    (uptime / 86400, uptime % 86400 / 3600, uptime % 3600 / 60);
+                write!(f, "   uptime: {}d {}h {}m", days, hours, minutes)?;
+            }
+            Ok(())
+        }
+    }
+
+    impl DeviceHealth {
+        /// Parse the output of the script run by `get_device_health`: the output
+        /// of `dumpsys battery`, followed by `adborc.storage` (last line of `df /data`)
+        /// and `adborc.uptime` (contents of `/proc/uptime`).
+        pub fn from_dumpsys(adb_state: &str, output: &str) -> Self {
+            let mut health = DeviceHealth {
+                adb_state: adb_state.to_string(),
+                reported_at: unix_time_now(),
+                ..Default::default()
+            };
+            let mut plugged = None;
+            for line in output.lines() {
+                let (key, value) = match line.trim().split_once(": ") {
+                    Some((key, value)) => (key, value.trim()),
+                    None => continue,
+                };
+                match key {
+                    "level" => health.battery_level = value.parse().ok(),
+                    "temperature" => health.battery_temperature = value.parse().ok(),
+                    "AC powered" | "USB powered" | "Wireless powered" | "Dock powered" => {
+                        if let Ok(powered) = value.parse::<bool>() {
+                            plugged = Some(plugged.unwrap_or(false) || powered);
+                        }
+                    }
+                    // Columns: Filesystem 1K-blocks Used Available Use% Mounted-on
+                    STORAGE_KEY => {
+                        health.free_storage = value
+                            .split_whitespace()
+                            .nth(3)
+                            .and_then(|available| available.parse::<u64>().ok())
+                            .map(|available| available * 1024)
+                    }
+                    // Columns: uptime idle-time, in seconds.
+                    UPTIME_KEY => {
+                        health.uptime = value
+                            .split_whitespace()
+                            .next()
+                            .and_then(|uptime| uptime.parse::<f64>().ok())
+                            .map(|uptime| uptime as u64)
+                    }
+                    _ => {}
+                }
+            }
+            health.charging = plugged;
+            health
+        }
+    }
+
+    // Keys used to report values that are not part of `dumpsys battery`
+    // in the output collected by `get_device_health`.
+    const STORAGE_KEY: &str = "adborc.storage";
+    const UPTIME_KEY: &str = "adborc.uptime";
+
     #[derive(Default)]
     pub(crate) struct ToolsPath {
         adb: Option<PathBuf>,
@@ -233,67 +594,67 @@ pub mod adb_utils {
         }
     }
 
-    pub(crate) fn set_adb_path(adb_path: PathBuf) -> io::Result<()> {
+    pub(crate) fn set_adb_path(adb_path: PathBuf) -> Result<(), Error> {
         // Check if the path is valid.
         if !adb_path.exists() {
-            return Err(Error::new(
-                ErrorKind::NotFound,
-                format!("Invalid path specified: {}", adb_path.display()),
-            ));
+            return Err(Error::Adb(format!(
+                "Invalid path specified: {}",
+                adb_path.display()
+            )));
         }
         // Check if the path is a directory.
         if adb_path.is_dir() {
-            return Err(Error::new(
-                ErrorKind::InvalidInput,
-                format!("Path is a directory: {}", adb_path.display()),
-            ));
+            return Err(Error::InvalidInput(format!(
+                "Path is a directory: {}",
+                adb_path.display()
+            )));
         }
         // Check if the path is not absolute.
         if !adb_path.is_absolute() {
-            return Err(Error::new(
-                ErrorKind::InvalidInput,
-                format!("Path is not absolute: {}", adb_path.display()),
-            ));
+            return Err(Error::InvalidInput(format!(
+                "Path is not absolute: {}",
+                adb_path.display()
+            )));
         }
         // Check if the path is "adb".
         if adb_path.file_stem().unwrap_or_default() != "adb" {
-            return Err(Error::new(
-                ErrorKind::InvalidInput,
-                format!("Path not pointing to adb: {}", adb_path.display()),
-            ));
+            return Err(Error::InvalidInput(format!(
+                "Path not pointing to adb: {}",
+                adb_path.display()
+            )));
         }
         ToolsPath::set_adb(adb_path);
         Ok(())
     }
 
-    pub(crate) fn set_scrcpy_path(scrcpy_path: PathBuf) -> io::Result<()> {
+    pub(crate) fn set_scrcpy_path(scrcpy_path: PathBuf) -> Result<(), Error> {
         // Check if the path is valid.
         if !scrcpy_path.exists() {
-            return Err(Error::new(
-                ErrorKind::NotFound,
-                format!("Invalid path specified: {}", scrcpy_path.display()),
-            ));
+            return Err(Error::Scrcpy(format!(
+                "Invalid path specified: {}",
+                scrcpy_path.display()
+            )));
         }
         // Check if the path is a directory.
         if scrcpy_path.is_dir() {
-            return Err(Error::new(
-                ErrorKind::InvalidInput,
-                format!("Path is a directory: {}", scrcpy_path.display()),
-            ));
+            return Err(Error::InvalidInput(format!(
+                "Path is a directory: {}",
+                scrcpy_path.display()
+            )));
         }
         // Check if the path is not absolute.
         if !scrcpy_path.is_absolute() {
-            return Err(Error::new(
-                ErrorKind::InvalidInput,
-                format!("Path is not absolute: {}", scrcpy_path.display()),
-            ));
+            return Err(Error::InvalidInput(format!(
+                "Path is not absolute: {}",
+                scrcpy_path.display()
+            )));
         }
         // Check if the path is "scrcpy".
         if scrcpy_path.file_stem().unwrap_or_default() != "scrcpy" {
-            return Err(Error::new(
-                ErrorKind::InvalidInput,
-                format!("Path not pointing to scrcpy: {}", scrcpy_path.display()),
-            ));
+            return Err(Error::InvalidInput(format!(
+                "Path not pointing to scrcpy: {}",
+                scrcpy_path.display()
+            )));
         }
         ToolsPath::set_scrcpy(scrcpy_path);
         Ok(())
@@ -301,14 +662,13 @@ pub mod adb_utils {
 
     /// Get the adb version info.
     /// Returns the version string if successful, otherwise returns an error.
-    pub(crate) fn get_adb_version() -> io::Result<AdbVersionInfo> {
+    pub(crate) fn get_adb_version() -> Result<AdbVersionInfo, Error> {
         let adb_path = match ToolsPath::get_adb() {
             Some(path) => path,
             None => {
                 let adb_path = ToolsPath::find_adb_in_path().ok_or_else(|| {
-                    Error::new(
-                        ErrorKind::NotFound,
-                        "adb not found in PATH. Please install adb and try again.",
+                    Error::Adb(
+                        "adb not found in PATH. Please install adb and try again.".to_string(),
                     )
                 })?;
                 ToolsPath::set_adb(adb_path.clone());
@@ -322,12 +682,10 @@ pub mod adb_utils {
         command.creation_flags(CREATE_NO_WINDOW);
 
         command.arg("version");
-        let output = command
-            .output()
-            .map_err(|e| Error::new(ErrorKind::Other, e))?;
+        let output = command.output().map_err(|e| Error::Adb(e.to_string()))?;
         if output.status.success() {
             let version =
-                String::from_utf8(output.stdout).map_err(|e| Error::new(ErrorKind::Other, e))?;
+                String::from_utf8(output.stdout).map_err(|e| Error::Adb(e.to_string()))?;
             // Version info is of the form:
             // Android Debug Bridge version <version>
             // Version <rev>
@@ -344,9 +702,8 @@ pub mod adb_utils {
                 path: version_info[2].to_string(),
             })
         } else {
-            let stderr =
-                String::from_utf8(output.stderr).map_err(|e| Error::new(ErrorKind::Other, e))?;
-            Err(Error::new(ErrorKind::Other, stderr))
+            let stderr = String::from_utf8(output.stderr).map_err(|e| Error::Adb(e.to_string()))?;
+            Err(Error::Adb(stderr))
         }
     }
 
@@ -381,14 +738,14 @@ pub mod adb_utils {
 
     /// Get the scrcpy version info.
     /// Returns the version string if successful, otherwise returns an error.
-    pub(crate) fn get_scrcpy_version() -> io::Result<ScrcpyVersionInfo> {
+    pub(crate) fn get_scrcpy_version() -> Result<ScrcpyVersionInfo, Error> {
         let scrcpy_path = match ToolsPath::get_scrcpy() {
             Some(path) => path,
             None => {
                 let scrcpy_path = ToolsPath::find_scrcpy_in_path().ok_or_else(|| {
-                    Error::new(
-                        ErrorKind::NotFound,
-                        "scrcpy not found in PATH. Please install scrcpy and try again.",
+                    Error::Scrcpy(
+                        "scrcpy not found in PATH. Please install scrcpy and try again."
+                            .to_string(),
                     )
                 })?;
                 ToolsPath::set_scrcpy(scrcpy_path.clone());
@@ -402,12 +759,10 @@ pub mod adb_utils {
 
         command.arg("--version");
 
-        let output = command
-            .output()
-            .map_err(|e| Error::new(ErrorKind::Other, e))?;
+        let output = command.output().map_err(|e| Error::Scrcpy(e.to_string()))?;
         if output.status.success() {
             let version =
-                String::from_utf8(output.stdout).map_err(|e| Error::new(ErrorKind::Other, e))?;
+                String::from_utf8(output.stdout).map_err(|e| Error::Scrcpy(e.to_string()))?;
             // Version info is of the form:
             // scrcpy 1.17 <https://github.com/Genymobile/scrcpy>
             // ...
@@ -425,13 +780,26 @@ pub mod adb_utils {
             })
         } else {
             let stderr =
-                String::from_utf8(output.stderr).map_err(|e| Error::new(ErrorKind::Other, e))?;
-            Err(Error::new(ErrorKind::Other, stderr))
+                String::from_utf8(output.stderr).map_err(|e| Error::Scrcpy(e.to_string()))?;
+            Err(Error::Scrcpy(stderr))
         }
     }
 
     /// Get the devices connected on the system.
     pub fn get_connected_devices() -> Option<Vec<(String, DeviceInfo)>> {
+        let devices = get_connected_serials()?
+            .into_iter()
+            .map(|device| {
+                let device_info = get_device_info(&device, None);
+                (device, device_info)
+            })
+            .collect();
+        Some(devices)
+    }
+
+    /// Get the serials of the devices connected on the system, without the
+    /// information of the devices. Starts the adb server at the default port.
+    pub(crate) fn get_connected_serials() -> Option<Vec<String>> {
         let stdio = if std::env::var(ADBORC_LOG_ADB).is_ok() {
             Stdio::inherit()
         } else {
@@ -443,12 +811,15 @@ pub mod adb_utils {
         #[cfg(windows)]
         command.creation_flags(CREATE_NO_WINDOW);
 
-        command
+        if let Err(e) = command
             .args(["start-server"])
             .stdout(Stdio::inherit())
             .stderr(stdio)
             .output()
-            .expect("Failed to start adb server");
+        {
+            error!("Failed to start adb server: {}", e);
+            return None;
+        }
 
         let mut command =
             Command::new(ToolsPath::get_adb().unwrap_or_else(|| PathBuf::from("adb")));
@@ -464,20 +835,62 @@ pub mod adb_utils {
                 .iter()
                 .map(|line| line.split('\t').next().unwrap_or_default().to_string())
                 .collect::<Vec<String>>();
-            let mut ret_vec: Vec<(String, DeviceInfo)> = Vec::new();
-            for device in devices_list {
-                let device_info = get_device_info(&device, None);
-
-                ret_vec.push((device, device_info));
-            }
-            Some(ret_vec)
+            Some(devices_list)
         } else {
             None
         }
     }
 
+    /// Get the serials of the USB devices attached to the system that expose an
+    /// adb interface, without starting an adb server. An adb server at the default
+    /// port would claim the devices already served by their own adb servers.
+    /// The devices are listed from sysfs, so this is only supported on linux.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn get_attached_serials() -> Option<Vec<String>> {
+        const USB_DEVICES_DIR: &str = "/sys/bus/usb/devices";
+        let read = |path: PathBuf| {
+            std::fs::read_to_string(path)
+                .map(|contents| contents.trim().to_string())
+                .unwrap_or_default()
+        };
+        let entries = match std::fs::read_dir(USB_DEVICES_DIR) {
+            Ok(entries) => entries,
+            Err(e) => {
+                error!("Failed to list USB devices: {}", e);
+                return None;
+            }
+        };
+        let mut serials = Vec::new();
+        for entry in entries.flatten() {
+            // Interfaces are named `<device>:<config>.<interface>`.
+            let name = entry.file_name().to_string_lossy().to_string();
+            let device = match name.split_once(':') {
+                Some((device, _)) => device.to_string(),
+                None => continue,
+            };
+            let interface = entry.path();
+            // The class, subclass and protocol of the adb interface.
+            if read(interface.join("bInterfaceClass")) != "ff"
+                || read(interface.join("bInterfaceSubClass")) != "42"
+                || read(interface.join("bInterfaceProtocol")) != "01"
+            {
+                continue;
+            }
+            let serial = read(PathBuf::from(USB_DEVICES_DIR).join(device).join("serial"));
+            if !serial.is_empty() && !serials.contains(&serial) {
+                serials.push(serial);
+            }
+        }
+        Some(serials)
+    }
+
+    /// Attached devices cannot be listed without starting an adb server on this platform.
+    #[cfg(not(target_os = "linux"))]
+    pub(crate) fn get_attached_serials() -> Option<Vec<String>> {
+        None
+    }
+
     fn get_device_info(device_id: &str, port: Option<u16>) -> DeviceInfo {
-        let mut device_info = DeviceInfo::default();
         let port = port.unwrap_or(5037);
         let mut command =
             Command::new(ToolsPath::get_adb().unwrap_or_else(|| PathBuf::from("adb")));
@@ -485,6 +898,14 @@ pub mod adb_utils {
         #[cfg(windows)]
         command.creation_flags(CREATE_NO_WINDOW);
 
+        // Collect everything in a single shell session. The android_id and the
+        // screen size are not system properties, so they are appended to the
+        // `getprop` dump in the same format.
+        let script = format!(
+            "getprop; echo \"[{}]: [$(settings get secure android_id)]\"; \
+             echo \"[{}]: [$(wm size | tail -n 1)]\"",
+            ANDROID_ID_PROP, SCREEN_SIZE_PROP
+        );
         let output = command
             .args([
                 "-P",
@@ -492,112 +913,116 @@ pub mod adb_utils {
                 "-s",
                 device_id,
                 "shell",
-                "getprop",
-                "ro.product.brand",
+                script.as_str(),
             ])
             .output();
 
-        if let Ok(output) = output {
-            if output.status.success() {
-                let brand = String::from_utf8_lossy(&output.stdout).to_string();
-                device_info.brand = brand.trim().to_string();
-            } else {
-                error!("`getprop ro.product.brand` returned failure. Failed to get device brand");
+        match output {
+            Ok(output) if output.status.success() => {
+                DeviceInfo::from_getprop(&String::from_utf8_lossy(&output.stdout))
+            }
+            Ok(_) => {
+                error!("`getprop` returned failure. Failed to get device info");
+                DeviceInfo::default()
+            }
+            Err(_) => {
+                error!("Failed to get device info");
+                DeviceInfo::default()
             }
-        } else {
-            error!("Failed to get device brand");
         }
+    }
 
-        let mut command =
-            Command::new(ToolsPath::get_adb().unwrap_or_else(|| PathBuf::from("adb")));
-
-        #[cfg(windows)]
-        command.creation_flags(CREATE_NO_WINDOW);
-
-        let output = command
-            .args([
-                "-P",
-                &port.to_string(),
-                "-s",
-                device_id,
-                "shell",
-                "getprop",
-                "ro.product.name",
-            ])
-            .output();
-
-        if let Ok(output) = output {
-            if output.status.success() {
-                let name = String::from_utf8_lossy(&output.stdout).to_string();
-                device_info.name = name.trim().to_string();
-            } else {
-                error!("`getprop ro.product.name` returned failure. Failed to get device name");
+    /// Get the health of the device served by the adb server at `port`.
+    /// The adb servers started for supplied devices serve a single device each,
+    /// so the device does not need to be specified.
+    pub fn get_device_health(port: u16) -> DeviceHealth {
+        let adb_state = match get_device_state(port) {
+            Ok(Some(adb_state)) => adb_state,
+            Ok(None) => "detached".to_string(),
+            Err(e) => {
+                error!("{}", e);
+                "unknown".to_string()
             }
-        } else {
-            error!("Failed to get device name");
+        };
+        if adb_state != "device" {
+            return DeviceHealth::from_dumpsys(&adb_state, "");
         }
 
+        let port = port.to_string();
         let mut command =
             Command::new(ToolsPath::get_adb().unwrap_or_else(|| PathBuf::from("adb")));
 
         #[cfg(windows)]
         command.creation_flags(CREATE_NO_WINDOW);
 
-        let output = command
-            .args([
-                "-P",
-                &port.to_string(),
-                "-s",
-                device_id,
-                "shell",
-                "getprop",
-                "ro.product.model",
-            ])
-            .output();
-
-        if let Ok(output) = output {
-            if output.status.success() {
-                let model = String::from_utf8_lossy(&output.stdout).to_string();
-                device_info.model = model.trim().to_string();
-            } else {
-                error!("`getprop ro.product.model` returned failure. Failed to get device model");
+        let script = format!(
+            "dumpsys battery; echo \"{}: $(df /data | tail -n 1)\"; \
+             echo \"{}: $(cat /proc/uptime)\"",
+            STORAGE_KEY, UPTIME_KEY
+        );
+        match command
+            .args(["-P", &port, "shell", script.as_str()])
+            .output()
+        {
+            Ok(output) if output.status.success() => {
+                DeviceHealth::from_dumpsys(&adb_state, &String::from_utf8_lossy(&output.stdout))
+            }
+            _ => {
+                error!("Failed to get device health");
+                DeviceHealth::from_dumpsys(&adb_state, "")
             }
-        } else {
-            error!("Failed to get device model");
         }
+    }
 
+    /// Get the adb state (e.g. `device`, `offline`, `unauthorized`) of the device
+    /// served by the adb server at `port`.
+    /// Returns None if the adb server does not find the device, i.e. it has been detached,
+    /// and an error if adb fails without reporting the state of the device.
+    pub fn get_device_state(port: u16) -> Result<Option<String>, Error> {
         let mut command =
             Command::new(ToolsPath::get_adb().unwrap_or_else(|| PathBuf::from("adb")));
 
         #[cfg(windows)]
         command.creation_flags(CREATE_NO_WINDOW);
 
-        let output = command
-            .args([
-                "-s",
-                device_id,
-                "-P",
-                port.to_string().as_str(),
-                "shell",
-                "settings",
-                "get",
-                "secure",
-                "android_id",
-            ])
-            .output();
-
-        if let Ok(output) = output {
-            if output.status.success() {
-                let android_id = String::from_utf8_lossy(&output.stdout).to_string();
-                device_info.android_id = android_id.trim().to_string();
-            } else {
-                error!("`settings get secure android_id` returned failure. Failed to get secure android_id");
-            }
-        } else {
-            error!("Failed to get device secure android id");
+        match command
+            .args(["-P", port.to_string().as_str(), "get-state"])
+            .output()
+        {
+            Ok(output) if output.status.success() => Ok(Some(
+                String::from_utf8_lossy(&output.stdout).trim().to_string(),
+            )),
+            Ok(output) => parse_device_state_error(&String::from_utf8_lossy(&output.stderr)),
+            Err(e) => Err(Error::Adb(format!("Failed to get device state: {}", e))),
         }
+    }
 
-        device_info
+    /// Parse the error of `adb get-state`. adb reports the state in the error for
+    /// devices that are not online, e.g. `error: device offline`, and
+    /// `error: no devices/emulators found` if there is no device.
+    fn parse_device_state_error(stderr: &str) -> Result<Option<String>, Error> {
+        const DEVICE_STATES: [&str; 8] = [
+            "offline",
+            "unauthorized",
+            "authorizing",
+            "connecting",
+            "bootloader",
+            "recovery",
+            "sideload",
+            "rescue",
+        ];
+        let line = stderr.lines().next().unwrap_or_default().trim();
+        if line.contains("no devices") || line.contains("not found") {
+            return Ok(None);
+        }
+        match line.strip_prefix("error: device ").and_then(|state| {
+            state
+                .split(|c: char| !c.is_ascii_alphabetic())
+                .find(|word| DEVICE_STATES.contains(word))
+        }) {
+            Some(state) => Ok(Some(state.to_string())),
+            None => Err(Error::Adb(format!("Failed to get device state: {}", line))),
+        }
     }
 
     /// Restart the adb server for specific devices at different TCP ports.
@@ -610,7 +1035,15 @@ pub mod adb_utils {
         secure_comms: bool,
     ) -> Option<Vec<(String, DeviceInfo, u16)>> {
         // Extract the connected devices in the system into a Vector.
-        let connected_devices = get_connected_devices()?;
+        // The information is only fetched for the devices to restart.
+        let connected_devices = get_connected_serials()?
+            .into_iter()
+            .filter(|device| devices.as_ref().is_none_or(|d| d.contains(device)))
+            .map(|device| {
+                let device_info = get_device_info(&device, None);
+                (device, device_info)
+            })
+            .collect::<Vec<_>>();
 
         debug!("Connected devices: {:?}", connected_devices);
 
@@ -632,18 +1065,9 @@ pub mod adb_utils {
         // Start the adb server for device at the available port.
         let mut ret_vec = Vec::new();
 
-        if let Some(devices) = devices {
-            for (device, device_info) in connected_devices {
-                if devices.contains(&device) {
-                    let port = start_adb_server_for_device(device.clone(), secure_comms);
-                    ret_vec.push((device, device_info, port));
-                }
-            }
-        } else {
-            for (device, device_info) in connected_devices {
-                let port = start_adb_server_for_device(device.clone(), secure_comms);
-                ret_vec.push((device, device_info, port));
-            }
+        for (device, device_info) in connected_devices {
+            let port = start_adb_server_for_device(device.clone(), secure_comms);
+            ret_vec.push((device, device_info, port));
         }
 
         if ret_vec.is_empty() {
@@ -722,20 +1146,20 @@ pub mod adb_utils {
         adb_port: u16,
         port: u16,
         scrcpy_args: Vec<ScrCpyArgs>,
-    ) -> io::Result<Child> {
+    ) -> Result<Child, Error> {
         let scrcpy_verinfo = get_scrcpy_version()?;
         let ver_vec = process_scrcpy_ver_info(&scrcpy_verinfo);
         if ver_vec.len() != 2 {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                format!("Failed to parse scrcpy version: {}", scrcpy_verinfo.version),
-            ));
+            return Err(Error::Scrcpy(format!(
+                "Failed to parse scrcpy version: {}",
+                scrcpy_verinfo.version
+            )));
         }
         if ver_vec[0] != 1 || ver_vec[1] < MIN_SCRCPY_VER {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                format!("Unsupported scrcpy version: {}", scrcpy_verinfo.version),
-            ));
+            return Err(Error::Scrcpy(format!(
+                "Unsupported scrcpy version: {}",
+                scrcpy_verinfo.version
+            )));
         }
         debug!("Port allocated for scrcpy is: {}", port);
         let port_as_str = port.to_string();
@@ -764,6 +1188,7 @@ pub mod adb_utils {
             .args(&args)
             .stderr(Stdio::piped())
             .spawn()
+            .map_err(|e| Error::Scrcpy(format!("Failed to start scrcpy: {}", e)))
     }
 
     #[cfg(test)]
@@ -771,6 +1196,100 @@ pub mod adb_utils {
     mod tests {
         use super::*;
 
+        #[test]
+        fn test_parse_device_state_error() {
+            assert_eq!(
+                parse_device_state_error("error: device offline\n").unwrap(),
+                Some("offline".to_string())
+            );
+            assert_eq!(
+                parse_device_state_error("error: device still authorizing\n").unwrap(),
+                Some("authorizing".to_string())
+            );
+            assert_eq!(
+                parse_device_state_error(
+                    "error: device unauthorized.\nThis adb server's $ADB_VENDOR_KEYS is not set\n"
+                )
+                .unwrap(),
+                Some("unauthorized".to_string())
+            );
+            assert_eq!(
+                parse_device_state_error("error: no devices/emulators found\n").unwrap(),
+                None
+            );
+            assert!(parse_device_state_error("* daemon not running; starting now\n").is_err());
+            assert!(parse_device_state_error("").is_err());
+        }
+
+        #[test]
+        fn test_device_info_from_getprop() {
+            let output = r"[ro.build.fingerprint]: [google/panther/panther:13/TQ2A.230505.002/9891397:user/release-keys]
+[ro.build.version.release]: [13]
+[ro.build.version.sdk]: [33]
+[ro.build.version.security_patch]: [2023-05-05]
+[ro.product.brand]: [google]
+[ro.product.cpu.abilist]: [arm64-v8a,armeabi-v7a,armeabi]
+[ro.product.manufacturer]: [Google]
+[ro.product.model]: [Pixel 7]
+[ro.product.name]: [panther]
+[ro.sf.lcd_density]: [420]
+[adborc.android_id]: [0123456789abcdef]
+[adborc.screen_size]: [Physical size: 1080x2400]
+not a property line";
+            let device_info = DeviceInfo::from_getprop(output);
+            assert_eq!(
+                device_info,
+                DeviceInfo {
+                    brand: "google".to_string(),
+                    name: "panther".to_string(),
+                    model: "Pixel 7".to_string(),
+                    android_id: "0123456789abcdef".to_string(),
+                    manufacturer: "Google".to_string(),
+                    android_release: "13".to_string(),
+                    sdk_level: Some(33),
+                    abi_list: vec![
+                        "arm64-v8a".to_string(),
+                        "armeabi-v7a".to_string(),
+                        "armeabi".to_string()
+                    ],
+                    screen_resolution: Some((1080, 2400)),
+                    screen_density: Some(420),
+                    build_fingerprint:
+                        "google/panther/panther:13/TQ2A.230505.002/9891397:user/release-keys"
+                            .to_string(),
+                    security_patch: "2023-05-05".to_string(),
+                }
+            );
+        }
+
+        #[test]
+        fn test_device_health_from_dumpsys() {
+            let output = r"Current Battery Service state:
+  AC powered: false
+  USB powered: true
+  Wireless powered: false
+  status: 2
+  health: 2
+  present: true
+  level: 3
+  scale: 100
+  temperature: 312
+adborc.storage: /dev/block/dm-48 115249236 20187584 94930580  18% /data
+adborc.uptime: 93784.52 350012.33";
+            let health = DeviceHealth::from_dumpsys("device", output);
+            assert_eq!(health.adb_state, "device");
+            assert_eq!(health.battery_level, Some(3));
+            assert_eq!(health.battery_temperature, Some(312));
+            assert_eq!(health.charging, Some(true));
+            assert_eq!(health.free_storage, Some(94930580 * 1024));
+            assert_eq!(health.uptime, Some(93784));
+
+            let health = DeviceHealth::from_dumpsys("offline", "");
+            assert_eq!(health.adb_state, "offline");
+            assert_eq!(health.battery_level, None);
+            assert_eq!(health.charging, None);
+        }
+
         #[test]
         fn test_get_adb_version() {
             let ver_info = adb_utils::get_adb_version().unwrap();
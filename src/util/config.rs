@@ -0,0 +1,314 @@
+//! Node configuration file, with named profiles.
+//!
+//! The config file (`config.toml` in the data directory, by default) holds the
+//! listener address and declares profiles. A profile may override the listener
+//! address, and holds the paths of `adb` and `scrcpy`, and which modes to start
+//! automatically when the system listener is started with `adborc init`. For example:
+//!
+//! ```toml
+//! default_profile = "lab"
+//! listen_port = 16064
+//!
+//! [profiles.lab]
+//! adb_path = "/opt/platform-tools/adb"
+//!
+//! [profiles.lab.supplier]
+//! remote = "10.0.0.5"
+//! name = "bench-1"
+//! secure = true
+//! supply = true
+//!
+//! [profiles.home.consumer]
+//! remote = "192.168.1.20"
+//! ```
+
+use super::{data_dir, SysStateDefaultConfig};
+use crate::market::Role;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+
+/// Environment variable to select the profile of the config file to use.
+pub const ADBORC_PROFILE: &str = "ADBORC_PROFILE";
+/// File name, inside the data directory, of the node config file.
+pub const NODE_CONFIG_FILE: &str = "config.toml";
+
+/// Contents of the node config file.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct NodeConfig {
+    /// Profile used if no profile is selected.
+    pub default_profile: Option<String>,
+    /// Host address for the system listener, unless set by the selected profile.
+    pub listen_host: Option<String>,
+    /// Port for the system listener, unless set by the selected profile.
+    pub listen_port: Option<u16>,
+    /// Profiles, hashed by name.
+    pub profiles: HashMap<String, Profile>,
+}
+
+/// Configuration of the node for a network.
+/// Modes are started automatically if their section is present.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    /// Host address for the system listener.
+    pub listen_host: Option<String>,
+    /// Port for the system listener.
+    pub listen_port: Option<u16>,
+    /// Path to the `adb` executable.
+    pub adb_path: Option<String>,
+    /// Path to the `scrcpy` executable.
+    pub scrcpy_path: Option<String>,
+    pub marketmaker: Option<MarketMakerProfile>,
+    pub supplier: Option<SupplierProfile>,
+    pub consumer: Option<ConsumerProfile>,
+}
+
+/// MarketMaker mode settings of a profile.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct MarketMakerProfile {
+    /// Require Suppliers and Consumers to be whitelisted.
+    pub use_whitelist: bool,
+    /// network_ids of whitelisted Suppliers.
+    pub supplier_whitelist: Vec<String>,
+    /// network_ids of whitelisted Consumers.
+    pub consumer_whitelist: Vec<String>,
+    /// network_ids of MarketMakers allowed to connect as a standby.
+    pub standby_whitelist: Vec<String>,
+    /// Address (`host` or `host:port`) of the primary MarketMaker, to start as its standby.
+    /// The whitelists of a standby are replicated from the primary.
+    pub standby_of: Option<String>,
+    /// network_ids of MarketMakers allowed to peer.
+    pub peer_whitelist: Vec<String>,
+    /// Addresses (`host` or `host:port`) of peer MarketMakers to connect to.
+    pub peers: Vec<String>,
+    /// Roles granted to network_ids, hashed by network_id.
+    pub roles: HashMap<String, Vec<Role>>,
+}
+
+/// Supplier mode settings of a profile.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct SupplierProfile {
+    /// Hostname or IP address of the MarketMaker.
+    pub remote: String,
+    /// Port of the MarketMaker.
+    pub port: u16,
+    /// Addresses (`host` or `host:port`) of MarketMakers to fall back to, in order.
+    pub fallbacks: Vec<String>,
+    /// Name of the Supplier on the network.
+    pub name: Option<String>,
+    /// Use encrypted communication channels for devices.
+    pub secure: bool,
+    /// Supply devices once the Supplier is started.
+    pub supply: bool,
+    /// `ANDROID_SERIAL`s of the devices to supply. All devices, if not specified.
+    pub devices: Option<Vec<String>>,
+    /// Pools to assign the supplied devices to.
+    pub pools: Option<Vec<String>>,
+    /// Supply attached devices and reclaim detached devices automatically.
+    pub auto_supply: bool,
+}
+
+impl Default for SupplierProfile {
+    fn default() -> SupplierProfile {
+        SupplierProfile {
+            remote: String::new(),
+            port: SysStateDefaultConfig::BIND_PORT,
+            fallbacks: Vec::new(),
+            name: None,
+            secure: false,
+            supply: false,
+            devices: None,
+            pools: None,
+            auto_supply: false,
+        }
+    }
+}
+
+/// Consumer mode settings of a profile.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConsumerProfile {
+    /// Hostname or IP address of the MarketMaker.
+    pub remote: String,
+    /// Port of the MarketMaker.
+    pub port: u16,
+    /// Addresses (`host` or `host:port`) of MarketMakers to fall back to, in order.
+    pub fallbacks: Vec<String>,
+    /// Name of the Consumer on the network.
+    pub name: Option<String>,
+}
+
+impl Default for ConsumerProfile {
+    fn default() -> ConsumerProfile {
+        ConsumerProfile {
+            remote: String::new(),
+            port: SysStateDefaultConfig::BIND_PORT,
+            fallbacks: Vec::new(),
+            name: None,
+        }
+    }
+}
+
+impl NodeConfig {
+    /// Load the node config from `path`, or from `config.toml` in the data directory
+    /// if `path` is not specified.
+    /// Returns None if `path` is not specified and there is no config file.
+    pub fn load(path: Option<&Path>) -> io::Result<Option<NodeConfig>> {
+        let default_path = data_dir().join(NODE_CONFIG_FILE);
+        let path = match path {
+            Some(path) => path,
+            None if default_path.exists() => default_path.as_path(),
+            None => return Ok(None),
+        };
+        let contents = fs::read_to_string(path).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("Unable to read config file {}: {}", path.display(), e),
+            )
+        })?;
+        NodeConfig::parse(&contents)
+            .map(Some)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+    }
+
+    /// Parse the contents of a node config file.
+    pub fn parse(contents: &str) -> io::Result<NodeConfig> {
+        let config: NodeConfig = toml::from_str(contents).map_err(|e| {
+            io::Error::new(
+                ErrorKind::InvalidData,
+                format!("Invalid config file: {}", e),
+            )
+        })?;
+        if let Some(name) = &config.default_profile {
+            if !config.profiles.contains_key(name) {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Default profile `{}` is not defined", name),
+                ));
+            }
+        }
+        for (name, profile) in config.profiles.iter() {
+            let supplier_remote = profile.supplier.as_ref().map(|supplier| &supplier.remote);
+            let consumer_remote = profile.consumer.as_ref().map(|consumer| &consumer.remote);
+            if [supplier_remote, consumer_remote].contains(&Some(&String::new())) {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "Profile `{}`: `remote` of the MarketMaker is required",
+                        name
+                    ),
+                ));
+            }
+        }
+        Ok(config)
+    }
+
+    /// Select a profile by `name`. If `name` is not specified, the profile named by
+    /// `ADBORC_PROFILE` is selected, or else the default profile.
+    /// Returns None if no profile is selected.
+    pub fn profile(&self, name: Option<&str>) -> io::Result<Option<(String, &Profile)>> {
+        let env_name = std::env::var(ADBORC_PROFILE)
+            .ok()
+            .filter(|name| !name.is_empty());
+        let name = match name
+            .map(str::to_string)
+            .or(env_name)
+            .or_else(|| self.default_profile.clone())
+        {
+            Some(name) => name,
+            None => return Ok(None),
+        };
+        match self.profiles.get(&name) {
+            Some(profile) => Ok(Some((name, profile))),
+            None => Err(io::Error::new(
+                ErrorKind::NotFound,
+                format!("Profile `{}` is not defined in the config file", name),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+default_profile = "lab"
+listen_host = "10.0.0.7"
+
+[profiles.lab]
+listen_port = 16064
+adb_path = "/opt/platform-tools/adb"
+
+[profiles.lab.marketmaker]
+use_whitelist = true
+supplier_whitelist = ["c3VwcGxpZXI="]
+
+[profiles.lab.supplier]
+remote = "10.0.0.5"
+name = "bench-1"
+secure = true
+supply = true
+
+[profiles.home.consumer]
+remote = "192.168.1.20"
+port = 17000
+"#;
+
+    #[test]
+    fn parse_node_config() {
+        let config = NodeConfig::parse(CONFIG).unwrap();
+        assert_eq!(config.profiles.len(), 2);
+        assert_eq!(config.listen_host.as_deref(), Some("10.0.0.7"));
+        assert_eq!(config.listen_port, None);
+
+        let (name, lab) = config.profile(None).unwrap().unwrap();
+        assert_eq!(name, "lab");
+        assert_eq!(lab.listen_port, Some(16064));
+        assert_eq!(lab.adb_path.as_deref(), Some("/opt/platform-tools/adb"));
+        assert_eq!(
+            lab.marketmaker,
+            Some(MarketMakerProfile {
+                use_whitelist: true,
+                supplier_whitelist: vec!["c3VwcGxpZXI=".to_string()],
+                ..MarketMakerProfile::default()
+            })
+        );
+        assert_eq!(
+            lab.supplier,
+            Some(SupplierProfile {
+                remote: "10.0.0.5".to_string(),
+                name: Some("bench-1".to_string()),
+                secure: true,
+                supply: true,
+                ..SupplierProfile::default()
+            })
+        );
+        assert!(lab.consumer.is_none());
+
+        let (_, home) = config.profile(Some("home")).unwrap().unwrap();
+        assert!(home.marketmaker.is_none() && home.supplier.is_none());
+        assert_eq!(home.consumer.as_ref().unwrap().port, 17000);
+
+        assert!(config.profile(Some("office")).is_err());
+        assert!(NodeConfig::default().profile(None).unwrap().is_none());
+    }
+
+    #[test]
+    fn parse_invalid_node_config() {
+        // Unknown default profile.
+        assert!(NodeConfig::parse("default_profile = \"lab\"").is_err());
+        // Unknown key.
+        assert!(NodeConfig::parse("[profiles.lab]\nlisten_prot = 16064").is_err());
+        // Missing MarketMaker address.
+        assert!(NodeConfig::parse("[profiles.lab.supplier]\nsecure = true").is_err());
+        // Invalid TOML.
+        assert!(NodeConfig::parse("[profiles.lab").is_err());
+    }
+}